@@ -4,7 +4,7 @@
 // This is required to link libz when libssh2-sys is not included.
 extern crate libz_sys as libz;
 
-use libc::{c_char, c_int, c_uchar, c_uint, c_void, size_t};
+use libc::{c_char, c_int, c_uchar, c_uint, c_ushort, c_void, size_t};
 #[cfg(feature = "ssh")]
 use libssh2_sys as libssh2;
 use std::ffi::CStr;
@@ -15,6 +15,9 @@ pub const GIT_CLONE_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_STASH_APPLY_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_CHECKOUT_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_MERGE_OPTIONS_VERSION: c_uint = 1;
+pub const GIT_MERGE_FILE_INPUT_VERSION: c_uint = 1;
+pub const GIT_MERGE_FILE_OPTIONS_VERSION: c_uint = 1;
+pub const GIT_MERGE_DRIVER_VERSION: c_uint = 1;
 pub const GIT_REMOTE_CALLBACKS_VERSION: c_uint = 1;
 pub const GIT_STATUS_OPTIONS_VERSION: c_uint = 1;
 pub const GIT_BLAME_OPTIONS_VERSION: c_uint = 1;
@@ -93,6 +96,8 @@ pub enum git_worktree {}
 pub enum git_transaction {}
 pub enum git_mailmap {}
 pub enum git_indexer {}
+pub enum git_commit_graph {}
+pub enum git_commit_graph_writer {}
 
 #[repr(C)]
 pub struct git_revspec {
@@ -694,6 +699,8 @@ git_enum! {
         GIT_CHECKOUT_DONT_OVERWRITE_IGNORED = 1 << 19,
         GIT_CHECKOUT_CONFLICT_STYLE_MERGE = 1 << 20,
         GIT_CHECKOUT_CONFLICT_STYLE_DIFF3 = 1 << 21,
+        GIT_CHECKOUT_DRY_RUN = 1 << 24,
+        GIT_CHECKOUT_CONFLICT_STYLE_ZDIFF3 = 1 << 25,
         GIT_CHECKOUT_NONE = 1 << 30,
 
         GIT_CHECKOUT_UPDATE_SUBMODULES = 1 << 16,
@@ -950,6 +957,20 @@ pub const GIT_ATTR_CHECK_INDEX_THEN_FILE: u32 = 1;
 pub const GIT_ATTR_CHECK_INDEX_ONLY: u32 = 2;
 pub const GIT_ATTR_CHECK_NO_SYSTEM: u32 = 1 << 2;
 pub const GIT_ATTR_CHECK_INCLUDE_HEAD: u32 = 1 << 3;
+pub const GIT_ATTR_CHECK_INCLUDE_COMMIT: u32 = 1 << 4;
+
+pub const GIT_ATTR_OPTIONS_VERSION: c_uint = 1;
+
+#[repr(C)]
+pub struct git_attr_options {
+    pub version: c_uint,
+    pub flags: c_uint,
+    pub commit_id: *mut git_oid,
+    pub attr_commit_id: git_oid,
+}
+
+pub type git_attr_foreach_cb =
+    Option<extern "C" fn(name: *const c_char, value: *const c_char, payload: *mut c_void) -> c_int>;
 
 #[repr(C)]
 pub struct git_cred {
@@ -1367,6 +1388,7 @@ git_enum! {
         GIT_MERGE_FAIL_ON_CONFLICT = 1 << 1,
         GIT_MERGE_SKIP_REUC = 1 << 2,
         GIT_MERGE_NO_RECURSIVE = 1 << 3,
+        GIT_MERGE_VIRTUAL_BASE = 1 << 4,
     }
 }
 
@@ -1390,9 +1412,63 @@ git_enum! {
         GIT_MERGE_FILE_IGNORE_WHITESPACE_EOL = 1 << 5,
         GIT_MERGE_FILE_DIFF_PATIENCE = 1 << 6,
         GIT_MERGE_FILE_DIFF_MINIMAL = 1 << 7,
+        GIT_MERGE_FILE_STYLE_ZDIFF3 = 1 << 8,
+        GIT_MERGE_FILE_ACCEPT_CONFLICTS = 1 << 9,
     }
 }
 
+#[repr(C)]
+pub struct git_merge_file_input {
+    pub version: c_uint,
+    pub ptr: *const c_char,
+    pub size: size_t,
+    pub path: *const c_char,
+    pub mode: c_uint,
+}
+
+#[repr(C)]
+pub struct git_merge_file_options {
+    pub version: c_uint,
+    pub ancestor_label: *const c_char,
+    pub our_label: *const c_char,
+    pub their_label: *const c_char,
+    pub favor: git_merge_file_favor_t,
+    pub flags: u32,
+    pub marker_size: c_ushort,
+}
+
+#[repr(C)]
+pub struct git_merge_file_result {
+    pub automergeable: c_uint,
+    pub path: *const c_char,
+    pub mode: c_uint,
+    pub ptr: *const c_char,
+    pub len: size_t,
+}
+
+pub enum git_merge_driver_source {}
+
+pub type git_merge_driver_init_fn = Option<extern "C" fn(*mut git_merge_driver) -> c_int>;
+pub type git_merge_driver_shutdown_fn = Option<extern "C" fn(*mut git_merge_driver)>;
+pub type git_merge_driver_apply_fn = Option<
+    extern "C" fn(
+        *mut git_merge_driver,
+        *mut *const c_char,
+        *mut u32,
+        *mut git_buf,
+        *const c_char,
+        *const git_merge_driver_source,
+    ) -> c_int,
+>;
+
+#[repr(C)]
+pub struct git_merge_driver {
+    pub version: c_uint,
+    pub initialize: git_merge_driver_init_fn,
+    pub shutdown: git_merge_driver_shutdown_fn,
+    pub apply: git_merge_driver_apply_fn,
+}
+
 git_enum! {
     pub enum git_merge_analysis_t {
         GIT_MERGE_ANALYSIS_NONE = 0,
@@ -1581,6 +1657,13 @@ git_enum! {
     }
 }
 
+#[repr(C)]
+pub struct git_odb_expand_id {
+    pub id: git_oid,
+    pub length: c_ushort,
+    pub kind: git_object_t,
+}
+
 #[repr(C)]
 pub struct git_odb_writepack {
     pub backend: *mut git_odb_backend,
@@ -1856,7 +1939,7 @@ pub type git_commit_create_cb = Option<
         *const c_char,
         *const git_tree,
         usize,
-        *const git_commit,
+        *mut *const git_commit,
         *mut c_void,
     ) -> c_int,
 >;
@@ -2056,6 +2139,20 @@ git_enum! {
 
 pub type git_trace_cb = Option<extern "C" fn(level: git_trace_level_t, msg: *const c_char)>;
 
+#[repr(C)]
+pub struct git_allocator {
+    pub gmalloc: Option<extern "C" fn(n: size_t, file: *const c_char, line: c_int) -> *mut c_void>,
+    pub grealloc: Option<
+        extern "C" fn(
+            ptr: *mut c_void,
+            size: size_t,
+            file: *const c_char,
+            line: c_int,
+        ) -> *mut c_void,
+    >,
+    pub gfree: Option<extern "C" fn(ptr: *mut c_void)>,
+}
+
 git_enum! {
     pub enum git_feature_t {
         GIT_FEATURE_THREADS = 1 << 0,
@@ -2092,6 +2189,22 @@ pub struct git_email_create_options {
 
 pub const GIT_EMAIL_CREATE_OPTIONS_VERSION: c_uint = 1;
 
+git_enum! {
+    pub enum git_commit_graph_split_strategy_t {
+        GIT_COMMIT_GRAPH_SPLIT_STRATEGY_SINGLE_FILE = 0,
+    }
+}
+
+#[repr(C)]
+pub struct git_commit_graph_writer_options {
+    pub version: c_uint,
+    pub split_strategy: git_commit_graph_split_strategy_t,
+    pub size_multiple: f32,
+    pub max_commits: size_t,
+}
+
+pub const GIT_COMMIT_GRAPH_WRITER_OPTIONS_VERSION: c_uint = 1;
+
 git_enum! {
     pub enum git_email_create_flags_t {
         GIT_EMAIL_CREATE_DEFAULT = 0,
@@ -2186,6 +2299,8 @@ extern "C" {
         ceiling_dirs: *const c_char,
     ) -> c_int;
     pub fn git_repository_set_odb(repo: *mut git_repository, odb: *mut git_odb) -> c_int;
+    pub fn git_repository_submodule_cache_all(repo: *mut git_repository) -> c_int;
+    pub fn git_repository_submodule_cache_clear(repo: *mut git_repository) -> c_int;
 
     pub fn git_repository_refdb(out: *mut *mut git_refdb, repo: *mut git_repository) -> c_int;
     pub fn git_repository_set_refdb(repo: *mut git_repository, refdb: *mut git_refdb) -> c_int;
@@ -2435,6 +2550,7 @@ extern "C" {
         email: *const c_char,
     ) -> c_int;
     pub fn git_signature_dup(dest: *mut *mut git_signature, sig: *const git_signature) -> c_int;
+    pub fn git_signature_from_buffer(out: *mut *mut git_signature, buf: *const c_char) -> c_int;
 
     // status
     pub fn git_status_list_new(
@@ -2503,6 +2619,7 @@ extern "C" {
         refname: *const c_char,
     ) -> c_int;
     pub fn git_reference_name(r: *const git_reference) -> *const c_char;
+    pub fn git_reference_owner(r: *const git_reference) -> *mut git_repository;
     pub fn git_reference_name_to_id(
         out: *mut git_oid,
         repo: *mut git_repository,
@@ -3018,6 +3135,11 @@ extern "C" {
         repo: *mut git_repository,
         refname: *const c_char,
     ) -> c_int;
+    pub fn git_branch_upstream_merge(
+        out: *mut git_buf,
+        repo: *mut git_repository,
+        refname: *const c_char,
+    ) -> c_int;
 
     // index
     pub fn git_index_version(index: *mut git_index) -> c_uint;
@@ -3207,6 +3329,7 @@ extern "C" {
         value: *const c_char,
     ) -> c_int;
     pub fn git_config_snapshot(out: *mut *mut git_config, config: *mut git_config) -> c_int;
+    pub fn git_config_lock(tx: *mut *mut git_transaction, cfg: *mut git_config) -> c_int;
     pub fn git_config_entry_free(entry: *mut git_config_entry);
     pub fn git_config_multivar_iterator_new(
         out: *mut *mut git_config_iterator,
@@ -3223,7 +3346,45 @@ extern "C" {
         path: *const c_char,
         name: *const c_char,
     ) -> c_int;
+    pub fn git_attr_get_ext(
+        value_out: *mut *const c_char,
+        repo: *mut git_repository,
+        opts: *mut git_attr_options,
+        path: *const c_char,
+        name: *const c_char,
+    ) -> c_int;
+    pub fn git_attr_get_many_ext(
+        values_out: *mut *const c_char,
+        repo: *mut git_repository,
+        opts: *mut git_attr_options,
+        path: *const c_char,
+        num_attr: size_t,
+        names: *mut *const c_char,
+    ) -> c_int;
+    pub fn git_attr_foreach_ext(
+        repo: *mut git_repository,
+        opts: *mut git_attr_options,
+        path: *const c_char,
+        callback: git_attr_foreach_cb,
+        payload: *mut c_void,
+    ) -> c_int;
+    pub fn git_attr_get_many(
+        values_out: *mut *const c_char,
+        repo: *mut git_repository,
+        flags: u32,
+        path: *const c_char,
+        num_attr: size_t,
+        names: *mut *const c_char,
+    ) -> c_int;
+    pub fn git_attr_foreach(
+        repo: *mut git_repository,
+        flags: u32,
+        path: *const c_char,
+        callback: git_attr_foreach_cb,
+        payload: *mut c_void,
+    ) -> c_int;
     pub fn git_attr_value(value: *const c_char) -> git_attr_value_t;
+    pub fn git_attr_cache_flush(repo: *mut git_repository) -> c_int;
 
     // cred
     pub fn git_cred_default_new(out: *mut *mut git_cred) -> c_int;
@@ -3368,6 +3529,44 @@ extern "C" {
     ) -> c_int;
     pub fn git_annotated_commit_free(commit: *mut git_annotated_commit);
     pub fn git_merge_init_options(opts: *mut git_merge_options, version: c_uint) -> c_int;
+    pub fn git_merge_file_input_init(opts: *mut git_merge_file_input, version: c_uint) -> c_int;
+    pub fn git_merge_file_options_init(opts: *mut git_merge_file_options, version: c_uint)
+        -> c_int;
+    pub fn git_merge_file(
+        out: *mut git_merge_file_result,
+        ancestor: *const git_merge_file_input,
+        ours: *const git_merge_file_input,
+        theirs: *const git_merge_file_input,
+        opts: *const git_merge_file_options,
+    ) -> c_int;
+    pub fn git_merge_file_from_index(
+        out: *mut git_merge_file_result,
+        repo: *mut git_repository,
+        ancestor: *const git_index_entry,
+        ours: *const git_index_entry,
+        theirs: *const git_index_entry,
+        opts: *const git_merge_file_options,
+    ) -> c_int;
+    pub fn git_merge_file_result_free(result: *mut git_merge_file_result);
+
+    // merge drivers
+    pub fn git_merge_driver_lookup(name: *const c_char) -> *mut git_merge_driver;
+    pub fn git_merge_driver_register(name: *const c_char, driver: *mut git_merge_driver) -> c_int;
+    pub fn git_merge_driver_unregister(name: *const c_char) -> c_int;
+    pub fn git_merge_driver_source_repo(src: *const git_merge_driver_source)
+        -> *mut git_repository;
+    pub fn git_merge_driver_source_ancestor(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+    pub fn git_merge_driver_source_ours(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+    pub fn git_merge_driver_source_theirs(
+        src: *const git_merge_driver_source,
+    ) -> *const git_index_entry;
+    pub fn git_merge_driver_source_file_options(
+        src: *const git_merge_driver_source,
+    ) -> *const git_merge_file_options;
     pub fn git_merge(
         repo: *mut git_repository,
         their_heads: *mut *const git_annotated_commit,
@@ -3424,6 +3623,35 @@ extern "C" {
         note: *const c_char,
         force: c_int,
     ) -> c_int;
+    pub fn git_note_commit_create(
+        notes_commit_out: *mut git_oid,
+        notes_blob_out: *mut git_oid,
+        repo: *mut git_repository,
+        parent: *mut git_commit,
+        author: *const git_signature,
+        committer: *const git_signature,
+        oid: *const git_oid,
+        note: *const c_char,
+        allow_note_overwrite: c_int,
+    ) -> c_int;
+    pub fn git_note_commit_iterator_new(
+        out: *mut *mut git_note_iterator,
+        notes_commit: *mut git_commit,
+    ) -> c_int;
+    pub fn git_note_commit_read(
+        out: *mut *mut git_note,
+        repo: *mut git_repository,
+        notes_commit: *mut git_commit,
+        oid: *const git_oid,
+    ) -> c_int;
+    pub fn git_note_commit_remove(
+        notes_commit_out: *mut git_oid,
+        repo: *mut git_repository,
+        notes_commit: *mut git_commit,
+        author: *const git_signature,
+        committer: *const git_signature,
+        oid: *const git_oid,
+    ) -> c_int;
     pub fn git_note_default_ref(out: *mut git_buf, repo: *mut git_repository) -> c_int;
     pub fn git_note_free(note: *mut git_note);
     pub fn git_note_id(note: *const git_note) -> *const git_oid;
@@ -3671,6 +3899,11 @@ extern "C" {
         print_cb: git_diff_line_cb,
         payload: *mut c_void,
     ) -> c_int;
+    pub fn git_diff_to_buf(
+        out: *mut git_buf,
+        diff: *mut git_diff,
+        format: git_diff_format_t,
+    ) -> c_int;
     pub fn git_diff_stats_deletions(stats: *const git_diff_stats) -> size_t;
     pub fn git_diff_stats_files_changed(stats: *const git_diff_stats) -> size_t;
     pub fn git_diff_stats_free(stats: *mut git_diff_stats);
@@ -4028,10 +4261,17 @@ extern "C" {
         len: size_t,
     ) -> c_int;
 
+    pub fn git_odb_expand_ids(
+        db: *mut git_odb,
+        ids: *mut git_odb_expand_id,
+        count: size_t,
+    ) -> c_int;
+
     pub fn git_odb_exists(odb: *mut git_odb, oid: *const git_oid) -> c_int;
     pub fn git_odb_exists_ext(odb: *mut git_odb, oid: *const git_oid, flags: c_uint) -> c_int;
 
     pub fn git_odb_refresh(odb: *mut git_odb) -> c_int;
+    pub fn git_odb_write_multi_pack_index(db: *mut git_odb) -> c_int;
 
     pub fn git_odb_object_id(obj: *mut git_odb_object) -> *const git_oid;
     pub fn git_odb_object_size(obj: *mut git_odb_object) -> size_t;
@@ -4076,6 +4316,7 @@ extern "C" {
     ) -> c_int;
 
     pub fn git_odb_backend_malloc(backend: *mut git_odb_backend, len: size_t) -> *mut c_void;
+    pub fn git_odb_backend_data_free(backend: *mut git_odb_backend, data: *mut c_void);
 
     pub fn git_odb_num_backends(odb: *mut git_odb) -> size_t;
     pub fn git_odb_get_backend(
@@ -4093,6 +4334,37 @@ extern "C" {
         backend: *mut git_odb_backend,
     ) -> c_int;
 
+    // commit-graph
+    pub fn git_commit_graph_open(
+        cgraph_out: *mut *mut git_commit_graph,
+        objects_dir: *const c_char,
+    ) -> c_int;
+    pub fn git_commit_graph_free(cgraph: *mut git_commit_graph);
+    pub fn git_commit_graph_writer_options_init(
+        opts: *mut git_commit_graph_writer_options,
+        version: c_uint,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_new(
+        out: *mut *mut git_commit_graph_writer,
+        objects_info_dir: *const c_char,
+        options: *const git_commit_graph_writer_options,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_free(w: *mut git_commit_graph_writer);
+    pub fn git_commit_graph_writer_add_index_file(
+        w: *mut git_commit_graph_writer,
+        repo: *mut git_repository,
+        idx_path: *const c_char,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_add_revwalk(
+        w: *mut git_commit_graph_writer,
+        walk: *mut git_revwalk,
+    ) -> c_int;
+    pub fn git_commit_graph_writer_commit(w: *mut git_commit_graph_writer) -> c_int;
+    pub fn git_commit_graph_writer_dump(
+        buffer: *mut git_buf,
+        w: *mut git_commit_graph_writer,
+    ) -> c_int;
+
     // refdb
     pub fn git_refdb_new(out: *mut *mut git_refdb, repo: *mut git_repository) -> c_int;
     pub fn git_refdb_open(out: *mut *mut git_refdb, repo: *mut git_repository) -> c_int;
@@ -4128,6 +4400,8 @@ extern "C" {
     ) -> *mut git_rebase_operation;
     pub fn git_rebase_orig_head_id(rebase: *mut git_rebase) -> *const git_oid;
     pub fn git_rebase_orig_head_name(rebase: *mut git_rebase) -> *const c_char;
+    pub fn git_rebase_onto_id(rebase: *mut git_rebase) -> *const git_oid;
+    pub fn git_rebase_onto_name(rebase: *mut git_rebase) -> *const c_char;
     pub fn git_rebase_next(
         operation: *mut *mut git_rebase_operation,
         rebase: *mut git_rebase,
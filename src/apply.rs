@@ -1,9 +1,12 @@
 //! git_apply support
 //! see original: <https://github.com/libgit2/libgit2/blob/master/include/git2/apply.h>
 
+use crate::build::CheckoutBuilder;
 use crate::{panic, raw, util::Binding, DiffDelta, DiffHunk};
+use crate::{Delta, Diff, Error, ErrorClass, ErrorCode, Index, IndexEntry, IndexTime};
+use crate::{ObjectType, Oid, Patch, Repository, Tree};
 use libc::c_int;
-use std::{ffi::c_void, mem};
+use std::{ffi::c_void, fs, mem};
 
 /// Possible application locations for git_apply
 /// see <https://libgit2.org/libgit2/#HEAD/type/git_apply_options>
@@ -41,6 +44,9 @@ pub struct ApplyOptions<'cb> {
     raw: raw::git_apply_options,
     hunk_cb: Option<Box<HunkCB<'cb>>>,
     delta_cb: Option<Box<DeltaCB<'cb>>>,
+    three_way: bool,
+    last_delta: Option<usize>,
+    last_hunk: Option<usize>,
 }
 
 type HunkCB<'a> = dyn FnMut(Option<DiffHunk<'_>>) -> bool + 'a;
@@ -51,9 +57,11 @@ extern "C" fn delta_cb_c(delta: *const raw::git_diff_delta, data: *mut c_void) -
         let delta = Binding::from_raw_opt(delta as *mut _);
 
         let payload = &mut *(data as *mut ApplyOptions<'_>);
+        payload.last_delta = Some(payload.last_delta.map_or(0, |i| i + 1));
+        payload.last_hunk = None;
         let callback = match payload.delta_cb {
             Some(ref mut c) => c,
-            None => return -1,
+            None => return 0,
         };
 
         let apply = callback(delta);
@@ -71,9 +79,10 @@ extern "C" fn hunk_cb_c(hunk: *const raw::git_diff_hunk, data: *mut c_void) -> c
         let hunk = Binding::from_raw_opt(hunk);
 
         let payload = &mut *(data as *mut ApplyOptions<'_>);
+        payload.last_hunk = Some(payload.last_hunk.map_or(0, |i| i + 1));
         let callback = match payload.hunk_cb {
             Some(ref mut c) => c,
-            None => return -1,
+            None => return 0,
         };
 
         let apply = callback(hunk);
@@ -93,6 +102,9 @@ impl<'cb> ApplyOptions<'cb> {
             raw: unsafe { mem::zeroed() },
             hunk_cb: None,
             delta_cb: None,
+            three_way: false,
+            last_delta: None,
+            last_hunk: None,
         };
         assert_eq!(
             unsafe { raw::git_apply_options_init(&mut opts.raw, raw::GIT_APPLY_OPTIONS_VERSION) },
@@ -112,10 +124,42 @@ impl<'cb> ApplyOptions<'cb> {
     }
 
     /// Don't actually make changes, just test that the patch applies.
+    ///
+    /// If the patch does not apply, `last_delta` and `last_hunk` tell which
+    /// delta and hunk failed.
     pub fn check(&mut self, check: bool) -> &mut Self {
         self.flag(raw::GIT_APPLY_CHECK, check)
     }
 
+    /// Fall back to a three-way merge when the patch does not apply cleanly,
+    /// like `git apply -3`.
+    ///
+    /// The preimage of each modified file is looked up using the blob id
+    /// recorded in the patch, the patch is applied to it, and the result is
+    /// merged with the current contents. Conflicts are left as conflict
+    /// markers in the working directory and as conflict entries in the index,
+    /// depending on the `ApplyLocation`, instead of failing the apply. Added,
+    /// deleted and renamed files are merged the same way, so for example
+    /// deleting a file that was changed is reported as a conflict.
+    ///
+    /// Every file is merged before anything is written, and nothing is
+    /// written if one of them cannot be merged. With `check` only the merge
+    /// is attempted, and the apply fails with `ErrorCode::MergeConflict` if
+    /// it would leave conflicts. The hunk and delta callbacks are not invoked
+    /// during the fallback.
+    pub fn three_way(&mut self, three_way: bool) -> &mut Self {
+        self.three_way = three_way;
+        self
+    }
+
+    pub(crate) fn is_three_way(&self) -> bool {
+        self.three_way
+    }
+
+    pub(crate) fn is_check(&self) -> bool {
+        self.raw.flags & raw::GIT_APPLY_CHECK as u32 != 0
+    }
+
     /// When applying a patch, callback that will be made per hunk.
     pub fn hunk_callback<F>(&mut self, cb: F) -> &mut Self
     where
@@ -142,12 +186,305 @@ impl<'cb> ApplyOptions<'cb> {
         self
     }
 
+    /// The index of the last delta visited by the most recent apply using
+    /// these options.
+    ///
+    /// When the apply fails this is the delta that could not be applied.
+    pub fn last_delta(&self) -> Option<usize> {
+        self.last_delta
+    }
+
+    /// The index, within `last_delta`, of the last hunk visited by the most
+    /// recent apply using these options.
+    ///
+    /// When the apply fails while applying a hunk this is the hunk that could
+    /// not be applied; it is `None` if the delta failed before any of its
+    /// hunks were applied.
+    pub fn last_hunk(&self) -> Option<usize> {
+        self.last_hunk
+    }
+
     /// Pointer to a raw git_stash_apply_options
+    ///
+    /// This also resets `last_delta` and `last_hunk` for a new apply.
     pub unsafe fn raw(&mut self) -> *const raw::git_apply_options {
+        self.last_delta = None;
+        self.last_hunk = None;
+        self.raw.delta_cb = Some(delta_cb_c);
+        self.raw.hunk_cb = Some(hunk_cb_c);
+        self.raw.payload = self as *mut _ as *mut _;
         &self.raw as *const _
     }
 }
 
+/// The three-way merge of one delta, computed before anything is written.
+struct Merged {
+    paths: Vec<Vec<u8>>,
+    index: Index,
+}
+
+/// Apply `diff` with a three-way merge for each file, after a plain apply
+/// failed.
+///
+/// Every delta is merged before anything is written, so a delta that cannot
+/// be merged leaves the index and working directory untouched. With `check`
+/// nothing is written at all, and an error is returned if any delta would
+/// conflict.
+pub(crate) fn apply_three_way(
+    repo: &Repository,
+    diff: &Diff<'_>,
+    location: ApplyLocation,
+    check: bool,
+) -> Result<(), Error> {
+    let update_index = match location {
+        ApplyLocation::Index | ApplyLocation::Both => true,
+        ApplyLocation::WorkDir => false,
+    };
+    let update_workdir = match location {
+        ApplyLocation::WorkDir | ApplyLocation::Both => true,
+        ApplyLocation::Index => false,
+    };
+    let workdir = match (update_workdir, repo.workdir()) {
+        (true, None) => {
+            return Err(Error::new(
+                ErrorCode::BareRepo,
+                ErrorClass::Repository,
+                "cannot apply to the working directory of a bare repository",
+            ))
+        }
+        (_, workdir) => workdir,
+    };
+    let mut index = repo.index()?;
+
+    let mut merges = Vec::new();
+    for (idx, delta) in diff.deltas().enumerate() {
+        let workdir = workdir.filter(|_| update_workdir);
+        merges.push(merge_delta(repo, diff, &index, workdir, idx, &delta)?);
+    }
+
+    if check {
+        for merged in merges.iter() {
+            if merged.index.has_conflicts() {
+                return Err(Error::new(
+                    ErrorCode::MergeConflict,
+                    ErrorClass::Patch,
+                    format!(
+                        "{}: patch does not merge cleanly",
+                        String::from_utf8_lossy(&merged.paths[0])
+                    ),
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    for mut merged in merges {
+        let conflicts = merged.index.has_conflicts();
+        for path in merged.paths.iter() {
+            let file = crate::util::bytes2path(path);
+            if update_index {
+                match index.remove_path(file) {
+                    Ok(()) => {}
+                    Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            if conflicts {
+                continue;
+            }
+            match merged.index.get_path(file, 0) {
+                Some(entry) => {
+                    if update_index {
+                        index.add(&entry)?;
+                    }
+                    if let Some(workdir) = workdir.filter(|_| update_workdir) {
+                        let blob = repo.find_blob(entry.id)?;
+                        let dest = workdir.join(file);
+                        if let Some(parent) = dest.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        fs::write(dest, blob.content())?;
+                    }
+                }
+                None => {
+                    if let Some(workdir) = workdir.filter(|_| update_workdir) {
+                        match fs::remove_file(workdir.join(file)) {
+                            Ok(()) => {}
+                            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+            }
+        }
+        if conflicts {
+            if update_index {
+                for conflict in merged.index.conflicts()? {
+                    let conflict = conflict?;
+                    index.conflict_add(
+                        conflict.ancestor.as_ref(),
+                        conflict.our.as_ref(),
+                        conflict.their.as_ref(),
+                    )?;
+                }
+            }
+            if update_workdir {
+                let mut checkout = CheckoutBuilder::new();
+                checkout
+                    .force()
+                    .allow_conflicts(true)
+                    .conflict_style_merge(true)
+                    .update_index(false);
+                for path in merged.paths.iter() {
+                    checkout.path(&path[..]);
+                }
+                repo.checkout_index(Some(&mut merged.index), Some(&mut checkout))?;
+            }
+        }
+    }
+    if update_index {
+        index.write()?;
+    }
+    Ok(())
+}
+
+/// Merge the change `delta`, the `idx`th delta of `diff`, into the current
+/// contents of the files it touches.
+///
+/// The preimage is rebuilt from the blob recorded in the patch, the patch is
+/// applied to it, and the result is merged with the index or working
+/// directory contents of both the old and the new path, so that additions,
+/// deletions and renames are merged like modifications.
+fn merge_delta(
+    repo: &Repository,
+    diff: &Diff<'_>,
+    index: &Index,
+    workdir: Option<&std::path::Path>,
+    idx: usize,
+    delta: &DiffDelta<'_>,
+) -> Result<Merged, Error> {
+    let old = delta.old_file();
+    let new = delta.new_file();
+    let path = match delta.status() {
+        Delta::Added => new.path_bytes(),
+        _ => old.path_bytes(),
+    };
+    let path = path.map(|p| String::from_utf8_lossy(p).into_owned());
+    let path = path.as_deref().unwrap_or("<unknown>");
+    match delta.status() {
+        Delta::Modified | Delta::Added | Delta::Deleted | Delta::Renamed | Delta::Copied => {}
+        status => {
+            return Err(Error::new(
+                ErrorCode::ApplyFail,
+                ErrorClass::Patch,
+                format!("{}: cannot three-way merge a {:?} delta", path, status),
+            ))
+        }
+    }
+
+    let mut paths = Vec::new();
+    for file in [&old, &new].iter() {
+        match file.path_bytes() {
+            Some(p) if !paths.iter().any(|q: &Vec<u8>| q[..] == *p) => paths.push(p.to_vec()),
+            _ => {}
+        }
+    }
+    let mode = |file: &crate::DiffFile<'_>| match u32::from(file.mode()) {
+        0 => u32::from(crate::FileMode::Blob),
+        mode => mode,
+    };
+
+    let mut base = Vec::new();
+    if delta.status() != Delta::Added {
+        let mut hex = old.id().to_string();
+        if old.id_abbrev() > 0 {
+            hex.truncate(old.id_abbrev() as usize);
+        }
+        let id = match repo.find_object_by_prefix(&hex, Some(ObjectType::Blob)) {
+            Ok(blob) => blob.id(),
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorCode::ApplyFail,
+                    ErrorClass::Patch,
+                    format!(
+                        "{}: repository lacks blob {} needed for a three-way merge",
+                        path, hex
+                    ),
+                ))
+            }
+        };
+        let old_path = old.path_bytes().unwrap_or_default().to_vec();
+        base.push((old_path, id, mode(&old)));
+    }
+    let base_tree = build_tree(repo, &base)?;
+
+    // Apply a diff of this delta alone: libgit2 removes the files of every
+    // deleted delta from the tree, even the ones a delta callback skips.
+    let single = match Patch::from_diff(diff, idx)? {
+        Some(mut patch) => Diff::from_buffer(&patch.to_buf()?)?,
+        None => {
+            return Err(Error::new(
+                ErrorCode::ApplyFail,
+                ErrorClass::Patch,
+                format!("{}: delta has no patch", path),
+            ))
+        }
+    };
+    let mut applied = repo.apply_to_tree(&base_tree, &single, None)?;
+    let theirs_tree = repo.find_tree(applied.write_tree_to(repo)?)?;
+
+    let mut ours = Vec::new();
+    for p in paths.iter() {
+        let file = crate::util::bytes2path(p);
+        match workdir {
+            Some(workdir) => {
+                let full = workdir.join(file);
+                if full.symlink_metadata().is_ok() {
+                    let mode = match index.get_path(file, 0) {
+                        Some(entry) => entry.mode,
+                        None => base.first().map_or(mode(&new), |b| b.2),
+                    };
+                    ours.push((p.clone(), repo.blob_path(&full)?, mode));
+                }
+            }
+            None => {
+                if let Some(entry) = index.get_path(file, 0) {
+                    ours.push((p.clone(), entry.id, entry.mode));
+                }
+            }
+        }
+    }
+    let ours_tree = build_tree(repo, &ours)?;
+
+    let index = repo.merge_trees(&base_tree, &ours_tree, &theirs_tree, None)?;
+    Ok(Merged { paths, index })
+}
+
+/// Build a tree holding the given blobs, as `(path, id, mode)`.
+fn build_tree<'repo>(
+    repo: &'repo Repository,
+    entries: &[(Vec<u8>, Oid, u32)],
+) -> Result<Tree<'repo>, Error> {
+    let mut index = Index::new()?;
+    for (path, id, mode) in entries {
+        index.add(&IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: *mode,
+            uid: 0,
+            gid: 0,
+            file_size: 0,
+            id: *id,
+            flags: 0,
+            flags_extended: 0,
+            path: path.clone(),
+        })?;
+    }
+    repo.find_tree(index.write_tree_to(repo)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,4 +542,176 @@ mod tests {
         assert_eq!(count_delta, 1);
         assert_eq!(count_hunks, 1);
     }
+
+    #[test]
+    fn three_way() {
+        let path = Path::new("foo.txt");
+        let (td, repo) = crate::test::repo_init();
+        let lines = |changes: &[(usize, &str)]| {
+            (1..=20)
+                .map(|i| match changes.iter().find(|c| c.0 == i) {
+                    Some(c) => format!("{}\n", c.1),
+                    None => format!("{}\n", i),
+                })
+                .collect::<String>()
+        };
+        let write = |content: &str| {
+            t!(t!(File::create(&td.path().join(path))).write_all(content.as_bytes()));
+        };
+        let base = lines(&[]);
+        write(&base);
+        let mut index = t!(repo.index());
+        t!(index.add_path(path));
+        t!(index.write());
+
+        let new = lines(&[(10, "ten")]);
+        let mut patch = t!(crate::Patch::from_buffers(
+            base.as_bytes(),
+            Some(path),
+            new.as_bytes(),
+            Some(path),
+            None
+        ));
+        let diff = t!(crate::Diff::from_buffer(&t!(patch.to_buf())));
+
+        // A change next to the hunk breaks its context, but merges cleanly.
+        write(&lines(&[(12, "twelve")]));
+        let err = repo.apply(&diff, ApplyLocation::WorkDir, None).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::ApplyFail);
+        let mut opts = ApplyOptions::new();
+        opts.three_way(true);
+        t!(repo.apply(&diff, ApplyLocation::WorkDir, Some(&mut opts)));
+        let content = t!(std::fs::read_to_string(td.path().join(path)));
+        assert_eq!(content, lines(&[(10, "ten"), (12, "twelve")]));
+
+        // A change to the same line conflicts.
+        write(&lines(&[(10, "TEN")]));
+        t!(repo.apply(&diff, ApplyLocation::Both, Some(&mut opts)));
+        let content = t!(std::fs::read_to_string(td.path().join(path)));
+        assert!(content.contains("<<<<<<<"));
+        assert!(content.contains("TEN\n"));
+        assert!(content.contains("ten\n"));
+        let index = t!(repo.index());
+        assert!(index.has_conflicts());
+        let conflict = t!(index.conflict_get(path));
+        assert!(conflict.ancestor.is_some());
+        assert!(conflict.our.is_some());
+        assert!(conflict.their.is_some());
+    }
+
+    #[test]
+    fn three_way_adds_and_deletes() {
+        let (td, repo) = crate::test::repo_init();
+        let lines = |changed: Option<(usize, &str)>| {
+            (1..=20)
+                .map(|i| match changed {
+                    Some((n, line)) if n == i => format!("{}\n", line),
+                    _ => format!("{}\n", i),
+                })
+                .collect::<String>()
+        };
+        let tree = |files: &[(&str, &str)]| {
+            let entries = files
+                .iter()
+                .map(|(path, content)| {
+                    let id = t!(repo.blob(content.as_bytes()));
+                    (path.as_bytes().to_vec(), id, 0o100644)
+                })
+                .collect::<Vec<_>>();
+            t!(build_tree(&repo, &entries))
+        };
+        let base = lines(None);
+        let old = tree(&[("baz.txt", &base), ("foo.txt", &base)]);
+        let new = tree(&[("bar.txt", "bar\n"), ("baz.txt", &lines(Some((10, "ten"))))]);
+        let diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+
+        let write = |path: &str, content: &str| {
+            t!(std::fs::write(td.path().join(path), content));
+        };
+        let read = |path: &str| std::fs::read_to_string(td.path().join(path)).ok();
+        write("foo.txt", &base);
+        write("baz.txt", &lines(Some((12, "twelve"))));
+        let mut opts = ApplyOptions::new();
+        opts.three_way(true).check(true);
+        t!(repo.apply(&diff, ApplyLocation::WorkDir, Some(&mut opts)));
+        assert_eq!(read("foo.txt"), Some(base.clone()));
+        assert_eq!(read("bar.txt"), None);
+        assert_eq!(read("baz.txt"), Some(lines(Some((12, "twelve")))));
+
+        opts.check(false);
+        t!(repo.apply(&diff, ApplyLocation::WorkDir, Some(&mut opts)));
+        assert_eq!(read("foo.txt"), None);
+        assert_eq!(read("bar.txt").as_deref(), Some("bar\n"));
+        let merged = read("baz.txt").unwrap();
+        assert!(merged.contains("ten\n") && merged.contains("twelve\n"));
+
+        // Deleting a file that was changed conflicts.
+        write("foo.txt", &lines(Some((5, "five"))));
+        write("baz.txt", &lines(Some((12, "twelve"))));
+        std::fs::remove_file(td.path().join("bar.txt")).unwrap();
+        opts.check(true);
+        let err = repo
+            .apply(&diff, ApplyLocation::WorkDir, Some(&mut opts))
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::MergeConflict);
+        assert_eq!(read("foo.txt"), Some(lines(Some((5, "five")))));
+        assert_eq!(read("bar.txt"), None);
+    }
+
+    #[test]
+    fn check_reports_failed_hunk() {
+        let path = Path::new("foo.txt");
+        let (td, repo) = crate::test::repo_init();
+        let old = (1..=20).map(|i| format!("{}\n", i)).collect::<String>();
+        let new = old
+            .replace("\n2\n", "\ntwo\n")
+            .replace("\n19\n", "\nnineteen\n");
+        let mut patch = t!(crate::Patch::from_buffers(
+            old.as_bytes(),
+            Some(path),
+            new.as_bytes(),
+            Some(path),
+            None
+        ));
+        let diff = t!(crate::Diff::from_buffer(&t!(patch.to_buf())));
+
+        let current = old.replace("\n18\n", "\neighteen\n");
+        t!(t!(File::create(&td.path().join(path))).write_all(current.as_bytes()));
+        let mut opts = ApplyOptions::new();
+        opts.check(true);
+        let err = repo
+            .apply(&diff, ApplyLocation::WorkDir, Some(&mut opts))
+            .unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::ApplyFail);
+        assert_eq!(opts.last_delta(), Some(0));
+        assert_eq!(opts.last_hunk(), Some(1));
+        assert_eq!(t!(std::fs::read_to_string(td.path().join(path))), current);
+
+        t!(t!(File::create(&td.path().join(path))).write_all(old.as_bytes()));
+        t!(repo.apply(&diff, ApplyLocation::WorkDir, Some(&mut opts)));
+        assert_eq!(t!(std::fs::read_to_string(td.path().join(path))), old);
+    }
+
+    #[test]
+    fn apply_to_tree_in_bare_repo() {
+        let td = tempfile::TempDir::new().unwrap();
+        let repo = t!(crate::Repository::init_bare(td.path()));
+        let path = Path::new("dir/foo.txt");
+        let old = t!(repo.blob(b"a\nb\nc\n"));
+        let tree = build_tree(&repo, &[(b"dir/foo.txt".to_vec(), old, 0o100644)]).unwrap();
+
+        let mut patch = t!(crate::Patch::from_buffers(
+            b"a\nb\nc\n",
+            Some(path),
+            b"a\nB\nc\n",
+            Some(path),
+            None
+        ));
+        let diff = t!(crate::Diff::from_buffer(&t!(patch.to_buf())));
+        let mut index = t!(repo.apply_to_tree(&tree, &diff, None));
+        let new_tree = t!(repo.find_tree(t!(index.write_tree_to(&repo))));
+        let entry = t!(new_tree.get_path(path));
+        let blob = t!(repo.find_blob(entry.id()));
+        assert_eq!(blob.content(), b"a\nB\nc\n");
+    }
 }
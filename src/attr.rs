@@ -1,7 +1,19 @@
-use crate::raw;
+use crate::util::Binding;
+use crate::{raw, AttrCheckFlags, Error, ObjectType, Oid, Repository, Signature, Time};
+use std::mem;
 use std::ptr;
 use std::str;
 
+/// Options controlling where attributes are looked up.
+///
+/// Used by [`Repository::get_attr_ext`](crate::Repository::get_attr_ext) and
+/// friends to evaluate attributes as of a given commit or tree, for example
+/// when exporting a historical tree.
+pub struct AttrOptions {
+    raw: raw::git_attr_options,
+    tree: Option<Oid>,
+}
+
 /// All possible states of an attribute.
 ///
 /// This enum is used to interpret the value returned by
@@ -65,6 +77,69 @@ impl<'string> AttrValue<'string> {
     }
 }
 
+impl Default for AttrOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttrOptions {
+    /// Creates a new set of options which checks the working directory, then
+    /// the index.
+    pub fn new() -> AttrOptions {
+        let mut raw: raw::git_attr_options = unsafe { mem::zeroed() };
+        raw.version = raw::GIT_ATTR_OPTIONS_VERSION;
+        AttrOptions { raw, tree: None }
+    }
+
+    /// Set the flags controlling which sources are checked.
+    ///
+    /// Setting the flags clears `INCLUDE_COMMIT` unless it is included again.
+    pub fn flags(&mut self, flags: AttrCheckFlags) -> &mut AttrOptions {
+        self.raw.flags = flags.bits();
+        self
+    }
+
+    /// Also read the gitattributes file from the tree of the given commit.
+    ///
+    /// This sets the `INCLUDE_COMMIT` flag and replaces any tree given with
+    /// `tree`.
+    pub fn commit(&mut self, id: Oid) -> &mut AttrOptions {
+        self.raw.attr_commit_id = unsafe { *id.raw() };
+        self.raw.flags |= AttrCheckFlags::INCLUDE_COMMIT.bits();
+        self.tree = None;
+        self
+    }
+
+    /// Also read the gitattributes file from the given tree.
+    ///
+    /// libgit2 can only read attributes from a commit, so when the options
+    /// are used a commit with a fixed author and date wrapping the tree is
+    /// written to the object database and used instead. It is not referenced
+    /// by anything and will be removed by garbage collection.
+    ///
+    /// This sets the `INCLUDE_COMMIT` flag and replaces any commit given with
+    /// `commit`.
+    pub fn tree(&mut self, id: Oid) -> &mut AttrOptions {
+        self.raw.flags |= AttrCheckFlags::INCLUDE_COMMIT.bits();
+        self.tree = Some(id);
+        self
+    }
+
+    /// Get a mutable pointer to the raw options for use with `repo`, writing
+    /// the commit for a tree source if needed.
+    pub(crate) fn raw(&mut self, repo: &Repository) -> Result<*mut raw::git_attr_options, Error> {
+        if let Some(id) = self.tree {
+            let tree = repo.find_tree(id)?;
+            let sig = Signature::new("git2", "git2", &Time::new(0, 0))?;
+            let buf = repo.commit_create_buffer(&sig, &sig, "attributes\n", &tree, &[])?;
+            let commit = repo.odb()?.write(ObjectType::Commit, &buf)?;
+            self.raw.attr_commit_id = unsafe { *commit.raw() };
+        }
+        Ok(&mut self.raw)
+    }
+}
+
 /// Compare two [`AttrValue`]s.
 ///
 /// Note that this implementation does not differentiate between [`AttrValue::String`] and
@@ -1,18 +1,29 @@
 use crate::util::{self, Binding};
-use crate::{raw, signature, Error, Oid, Repository, Signature};
+use crate::{raw, signature, Commit, Delta, DiffOptions, Error, Oid, Patch, Repository, Signature};
 use libc::c_char;
+use std::ffi::{CStr, CString};
+use std::fs;
 use std::iter::FusedIterator;
 use std::mem;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{marker, ptr};
 
 /// Opaque structure to hold blame results.
 pub struct Blame<'repo> {
     raw: *mut raw::git_blame,
+    hunks: Option<Vec<OwnedHunk>>,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
+/// A copy of a blame hunk owned by git2-rs, used in place of the hunks
+/// computed by libgit2 when they have been adjusted afterwards.
+struct OwnedHunk {
+    raw: raw::git_blame_hunk,
+    _path: Option<CString>,
+    _summary: Option<CString>,
+}
+
 /// Structure that represents a blame hunk.
 pub struct BlameHunk<'blame> {
     raw: *mut raw::git_blame_hunk,
@@ -22,6 +33,16 @@ pub struct BlameHunk<'blame> {
 /// Blame options
 pub struct BlameOptions {
     raw: raw::git_blame_options,
+    ignore_revs: Vec<Oid>,
+    line_ranges: Vec<(usize, usize)>,
+}
+
+/// Progress of an incremental blame, as reported by
+/// `Repository::blame_file_incremental`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlameProgress {
+    lines_attributed: usize,
+    total_lines: usize,
 }
 
 /// An iterator over the hunks in a blame.
@@ -35,6 +56,10 @@ impl<'repo> Blame<'repo> {
     ///
     /// Lines that differ between the buffer and the committed version are
     /// marked as having a zero OID for their final_commit_id.
+    ///
+    /// The buffer is compared against the blame as computed by libgit2, so
+    /// revisions ignored with `BlameOptions::ignore_rev` are not taken into
+    /// account.
     pub fn blame_buffer(&self, buffer: &[u8]) -> Result<Blame<'_>, Error> {
         let mut raw = ptr::null_mut();
 
@@ -51,7 +76,10 @@ impl<'repo> Blame<'repo> {
 
     /// Gets the number of hunks that exist in the blame structure.
     pub fn len(&self) -> usize {
-        unsafe { raw::git_blame_get_hunk_count(self.raw) as usize }
+        match self.hunks {
+            Some(ref hunks) => hunks.len(),
+            None => unsafe { raw::git_blame_get_hunk_count(self.raw) as usize },
+        }
     }
 
     /// Return `true` is there is no hunk in the blame structure.
@@ -61,6 +89,11 @@ impl<'repo> Blame<'repo> {
 
     /// Gets the blame hunk at the given index.
     pub fn get_index(&self, index: usize) -> Option<BlameHunk<'_>> {
+        if let Some(ref hunks) = self.hunks {
+            return hunks
+                .get(index)
+                .map(|h| unsafe { BlameHunk::from_raw_const(&h.raw) });
+        }
         unsafe {
             let ptr = raw::git_blame_get_hunk_byindex(self.raw(), index as u32);
             if ptr.is_null() {
@@ -74,6 +107,15 @@ impl<'repo> Blame<'repo> {
     /// Gets the hunk that relates to the given line number in the newest
     /// commit.
     pub fn get_line(&self, lineno: usize) -> Option<BlameHunk<'_>> {
+        if let Some(ref hunks) = self.hunks {
+            return hunks
+                .iter()
+                .find(|h| {
+                    let start = h.raw.final_start_line_number;
+                    (start..start + h.raw.lines_in_hunk).contains(&lineno)
+                })
+                .map(|h| unsafe { BlameHunk::from_raw_const(&h.raw) });
+        }
         unsafe {
             let ptr = raw::git_blame_get_hunk_byline(self.raw(), lineno);
             if ptr.is_null() {
@@ -180,7 +222,11 @@ impl BlameOptions {
                 0
             );
 
-            Binding::from_raw(&raw as *const _ as *mut _)
+            BlameOptions {
+                raw,
+                ignore_revs: Vec::new(),
+                line_ranges: Vec::new(),
+            }
         }
     }
 
@@ -260,6 +306,120 @@ impl BlameOptions {
         self.raw.max_line = lineno;
         self
     }
+
+    /// Add a range of lines to blame, from `min` to `max` inclusive.
+    ///
+    /// This is the equivalent of passing several `-L` options to
+    /// `git blame`: only the lines in one of the added ranges are blamed, and
+    /// overlapping ranges are merged. Once a range has been added `min_line`
+    /// and `max_line` are ignored.
+    pub fn add_line_range(&mut self, min: usize, max: usize) -> &mut BlameOptions {
+        self.line_ranges.push((min, max));
+        self
+    }
+
+    /// Ignore changes made by the given commit, like
+    /// `git blame --ignore-rev`.
+    ///
+    /// Lines changed by an ignored commit are attributed to the commit that
+    /// last changed the corresponding line before it. A changed line is
+    /// matched with the line at the same offset in the replaced part of the
+    /// file; lines that have no such match, for example because the commit
+    /// only added lines, are still attributed to the ignored commit.
+    pub fn ignore_rev(&mut self, id: Oid) -> &mut BlameOptions {
+        self.ignore_revs.push(id);
+        self
+    }
+
+    /// Ignore all of the commits listed in a file, like
+    /// `git blame --ignore-revs-file`.
+    ///
+    /// The file holds one full commit id per line. Text after a `#` and
+    /// blank lines are skipped.
+    pub fn ignore_revs_file(&mut self, path: &Path) -> Result<&mut BlameOptions, Error> {
+        let content = fs::read_to_string(path).map_err(Error::from)?;
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.len() != raw::GIT_OID_HEXSZ {
+                return Err(Error::from_str(&format!(
+                    "invalid object name '{}' in ignore revs file",
+                    line
+                )));
+            }
+            self.ignore_revs.push(Oid::from_str(line)?);
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn has_ignore_revs(&self) -> bool {
+        !self.ignore_revs.is_empty()
+    }
+
+    pub(crate) fn line_range(&self) -> (usize, usize) {
+        (self.raw.min_line, self.raw.max_line)
+    }
+
+    /// The ranges added with `add_line_range`, sorted and with overlapping
+    /// or adjacent ranges merged.
+    pub(crate) fn line_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges = self.line_ranges.clone();
+        ranges.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (min, max) in ranges {
+            match merged.last_mut() {
+                Some(last) if min <= last.1 + 1 => last.1 = last.1.max(max),
+                _ => merged.push((min, max)),
+            }
+        }
+        merged
+    }
+
+    pub(crate) fn replace_line_ranges(
+        &mut self,
+        ranges: Vec<(usize, usize)>,
+    ) -> Vec<(usize, usize)> {
+        mem::replace(&mut self.line_ranges, ranges)
+    }
+
+    pub(crate) fn newest_commit_id(&self) -> Option<Oid> {
+        let id = unsafe { Oid::from_raw(&self.raw.newest_commit) };
+        if id.is_zero() {
+            None
+        } else {
+            Some(id)
+        }
+    }
+}
+
+impl BlameProgress {
+    pub(crate) fn new(lines_attributed: usize, total_lines: usize) -> BlameProgress {
+        BlameProgress {
+            lines_attributed,
+            total_lines,
+        }
+    }
+
+    /// Number of lines attributed so far.
+    pub fn lines_attributed(&self) -> usize {
+        self.lines_attributed
+    }
+
+    /// Total number of lines being blamed.
+    pub fn total_lines(&self) -> usize {
+        self.total_lines
+    }
+
+    /// Percentage of lines attributed so far, from 0 to 100.
+    pub fn percent(&self) -> f64 {
+        if self.total_lines == 0 {
+            100.0
+        } else {
+            self.lines_attributed as f64 * 100.0 / self.total_lines as f64
+        }
+    }
 }
 
 impl<'repo> Binding for Blame<'repo> {
@@ -268,6 +428,7 @@ impl<'repo> Binding for Blame<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_blame) -> Blame<'repo> {
         Blame {
             raw,
+            hunks: None,
             _marker: marker::PhantomData,
         }
     }
@@ -302,7 +463,11 @@ impl Binding for BlameOptions {
     type Raw = *mut raw::git_blame_options;
 
     unsafe fn from_raw(opts: *mut raw::git_blame_options) -> BlameOptions {
-        BlameOptions { raw: *opts }
+        BlameOptions {
+            raw: *opts,
+            ignore_revs: Vec::new(),
+            line_ranges: Vec::new(),
+        }
     }
 
     fn raw(&self) -> *mut raw::git_blame_options {
@@ -310,6 +475,316 @@ impl Binding for BlameOptions {
     }
 }
 
+impl OwnedHunk {
+    fn new(hunk: &BlameHunk<'_>) -> Result<OwnedHunk, Error> {
+        unsafe {
+            let mut raw = *hunk.raw;
+            let path = owned_cstr(raw.orig_path);
+            let summary = owned_cstr(raw.summary);
+            raw.orig_path = path.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+            raw.summary = summary.as_ref().map_or(ptr::null(), |p| p.as_ptr());
+            // Null out the signatures first so that a failed dup never frees
+            // the ones owned by libgit2.
+            let sigs = [
+                mem::replace(&mut raw.final_signature, ptr::null_mut()),
+                mem::replace(&mut raw.final_committer, ptr::null_mut()),
+                mem::replace(&mut raw.orig_signature, ptr::null_mut()),
+                mem::replace(&mut raw.orig_committer, ptr::null_mut()),
+            ];
+            let mut owned = OwnedHunk {
+                raw,
+                _path: path,
+                _summary: summary,
+            };
+            let dests = [
+                &mut owned.raw.final_signature as *mut _,
+                &mut owned.raw.final_committer as *mut _,
+                &mut owned.raw.orig_signature as *mut _,
+                &mut owned.raw.orig_committer as *mut _,
+            ];
+            for (dest, sig) in dests.iter().zip(sigs.iter()) {
+                if !sig.is_null() {
+                    try_call!(raw::git_signature_dup(*dest, *sig));
+                }
+            }
+            Ok(owned)
+        }
+    }
+
+    /// Whether `next` directly continues this hunk.
+    fn continued_by(&self, next: &OwnedHunk) -> bool {
+        let a = &self.raw;
+        let b = &next.raw;
+        let same_path = match (self._path.as_ref(), next._path.as_ref()) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => true,
+            _ => false,
+        };
+        same_path
+            && a.boundary == b.boundary
+            && a.final_commit_id.id == b.final_commit_id.id
+            && a.orig_commit_id.id == b.orig_commit_id.id
+            && a.final_start_line_number + a.lines_in_hunk == b.final_start_line_number
+            && a.orig_start_line_number + a.lines_in_hunk == b.orig_start_line_number
+    }
+}
+
+unsafe fn owned_cstr(c: *const c_char) -> Option<CString> {
+    if c.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(c).to_owned())
+    }
+}
+
+impl Drop for OwnedHunk {
+    fn drop(&mut self) {
+        unsafe {
+            raw::git_signature_free(self.raw.final_signature);
+            raw::git_signature_free(self.raw.final_committer);
+            raw::git_signature_free(self.raw.orig_signature);
+            raw::git_signature_free(self.raw.orig_committer);
+        }
+    }
+}
+
+/// Implementation of `Repository::blame_file` for options with several line
+/// ranges, blaming each range separately and joining the results.
+pub(crate) fn blame_ranges<'repo>(
+    repo: &'repo Repository,
+    path: &Path,
+    opts: &mut BlameOptions,
+) -> Result<Blame<'repo>, Error> {
+    let ranges = opts.line_ranges();
+    let saved = opts.replace_line_ranges(Vec::new());
+    let (min_line, max_line) = opts.line_range();
+    let result = (|| {
+        let mut blame = None;
+        let mut hunks: Vec<OwnedHunk> = Vec::new();
+        for &(min, max) in &ranges {
+            opts.min_line(min).max_line(max);
+            let range_blame = repo.blame_file(path, Some(&mut *opts))?;
+            for hunk in range_blame.iter() {
+                let owned = OwnedHunk::new(&hunk)?;
+                match hunks.last_mut() {
+                    Some(prev) if prev.continued_by(&owned) => {
+                        prev.raw.lines_in_hunk += owned.raw.lines_in_hunk;
+                    }
+                    _ => hunks.push(owned),
+                }
+            }
+            if blame.is_none() {
+                blame = Some(range_blame);
+            }
+        }
+        let mut blame = blame.expect("at least one line range");
+        blame.hunks = Some(hunks);
+        Ok(blame)
+    })();
+    opts.min_line(min_line).max_line(max_line);
+    opts.replace_line_ranges(saved);
+    result
+}
+
+/// Re-attribute the lines of `blame` that were last changed by one of the
+/// revisions ignored in `opts`.
+pub(crate) fn apply_ignore_revs(
+    repo: &Repository,
+    blame: &mut Blame<'_>,
+    opts: &BlameOptions,
+) -> Result<(), Error> {
+    let mut hunks: Vec<OwnedHunk> = Vec::new();
+    for hunk in blame.iter() {
+        let ignored = !hunk.is_boundary() && opts.ignore_revs.contains(&hunk.final_commit_id());
+        let mut lines = Vec::new();
+        if ignored {
+            let path = hunk.path().map(|p| p.to_path_buf()).unwrap_or_default();
+            let orig_lines = (0..hunk.lines_in_hunk())
+                .map(|k| hunk.orig_start_line() + k)
+                .collect::<Vec<_>>();
+            let reblamed = reblame_lines(repo, opts, hunk.final_commit_id(), &path, &orig_lines)?;
+            for (k, owned) in reblamed.into_iter().enumerate() {
+                let mut owned = match owned {
+                    Some(owned) => owned,
+                    None => {
+                        let mut owned = OwnedHunk::new(&hunk)?;
+                        owned.raw.orig_start_line_number = orig_lines[k];
+                        owned
+                    }
+                };
+                owned.raw.final_start_line_number = hunk.final_start_line() + k;
+                owned.raw.lines_in_hunk = 1;
+                lines.push(owned);
+            }
+        } else {
+            lines.push(OwnedHunk::new(&hunk)?);
+        }
+
+        for owned in lines {
+            match hunks.last_mut() {
+                Some(prev) if prev.continued_by(&owned) => {
+                    prev.raw.lines_in_hunk += owned.raw.lines_in_hunk;
+                }
+                _ => hunks.push(owned),
+            }
+        }
+    }
+    blame.hunks = Some(hunks);
+    Ok(())
+}
+
+/// Find who last changed each of `lines` of `path` before the ignored commit
+/// `id`.
+///
+/// The lines are mapped to the parent of `id` with a single diff and blamed
+/// there with a single blame covering all of them. Lines that land on another
+/// ignored commit are followed further, grouped by that commit.
+fn reblame_lines(
+    repo: &Repository,
+    opts: &BlameOptions,
+    id: Oid,
+    path: &Path,
+    lines: &[usize],
+) -> Result<Vec<Option<OwnedHunk>>, Error> {
+    let mut ret = lines.iter().map(|_| None).collect::<Vec<_>>();
+    let commit = repo.find_commit(id)?;
+    let parent = match commit.parents().next() {
+        Some(parent) => parent,
+        None => return Ok(ret),
+    };
+    let mapped = map_lines_to_parent(repo, &parent, &commit, path, lines)?;
+    let min = mapped.iter().flatten().min();
+    let max = mapped.iter().flatten().max();
+    let (min, max) = match (min, max) {
+        (Some(min), Some(max)) => (*min, *max),
+        _ => return Ok(ret),
+    };
+
+    let mut parent_opts = BlameOptions {
+        raw: opts.raw,
+        ignore_revs: Vec::new(),
+        line_ranges: Vec::new(),
+    };
+    parent_opts
+        .newest_commit(parent.id())
+        .min_line(min)
+        .max_line(max);
+    let blame = repo.blame_file(path, Some(&mut parent_opts))?;
+
+    // Lines blamed on further ignored commits, by commit and path.
+    let mut pending: Vec<(Oid, PathBuf, Vec<usize>, Vec<usize>)> = Vec::new();
+    for (i, line) in mapped.into_iter().enumerate() {
+        let line = match line {
+            Some(line) => line,
+            None => continue,
+        };
+        let hunk = match blame.get_line(line) {
+            Some(hunk) => hunk,
+            None => continue,
+        };
+        let offset = line - hunk.final_start_line();
+        if !hunk.is_boundary() && opts.ignore_revs.contains(&hunk.final_commit_id()) {
+            let id = hunk.final_commit_id();
+            let path = hunk.path().unwrap_or(path);
+            let orig_line = hunk.orig_start_line() + offset;
+            match pending.iter_mut().find(|p| p.0 == id && p.1 == path) {
+                Some(p) => {
+                    p.2.push(orig_line);
+                    p.3.push(i);
+                }
+                None => pending.push((id, path.to_path_buf(), vec![orig_line], vec![i])),
+            }
+            continue;
+        }
+        let mut owned = OwnedHunk::new(&hunk)?;
+        owned.raw.orig_start_line_number += offset;
+        ret[i] = Some(owned);
+    }
+
+    for (id, path, lines, indices) in pending {
+        let reblamed = reblame_lines(repo, opts, id, &path, &lines)?;
+        for (i, owned) in indices.into_iter().zip(reblamed) {
+            ret[i] = owned;
+        }
+    }
+    Ok(ret)
+}
+
+/// Map each of `lines` of `path` in `commit` to the corresponding line in
+/// `parent`.
+///
+/// A line maps to `None` if it was added by `commit` without replacing any
+/// line of the parent.
+fn map_lines_to_parent(
+    repo: &Repository,
+    parent: &Commit<'_>,
+    commit: &Commit<'_>,
+    path: &Path,
+    lines: &[usize],
+) -> Result<Vec<Option<usize>>, Error> {
+    let mut diff_opts = DiffOptions::new();
+    diff_opts.pathspec(path).context_lines(0);
+    let diff = repo.diff_tree_to_tree(
+        Some(&parent.tree()?),
+        Some(&commit.tree()?),
+        Some(&mut diff_opts),
+    )?;
+    let delta = match diff.get_delta(0) {
+        Some(delta) => delta,
+        None => return Ok(lines.iter().map(|line| Some(*line)).collect()),
+    };
+    if delta.status() != Delta::Modified {
+        return Ok(vec![None; lines.len()]);
+    }
+    let patch = match Patch::from_diff(&diff, 0)? {
+        Some(patch) => patch,
+        None => return Ok(vec![None; lines.len()]),
+    };
+    let mut hunks = Vec::new();
+    for i in 0..patch.num_hunks() {
+        let (hunk, _) = patch.hunk(i)?;
+        hunks.push((
+            hunk.old_start() as isize,
+            hunk.old_lines() as isize,
+            hunk.new_start() as isize,
+            hunk.new_lines() as isize,
+        ));
+    }
+
+    // Sweep the lines in order alongside the hunks.
+    let mut order = (0..lines.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| lines[i]);
+    let mut ret = vec![None; lines.len()];
+    let mut next = 0;
+    let mut offset = 0;
+    for i in order {
+        let line = lines[i] as isize;
+        ret[i] = loop {
+            let (old_start, old_lines, new_start, new_lines) = match hunks.get(next) {
+                Some(hunk) => *hunk,
+                None => break Some((line + offset) as usize),
+            };
+            if new_lines == 0 {
+                // Lines were only removed, after line `new_start`.
+                if line <= new_start {
+                    break Some((line + offset) as usize);
+                }
+            } else if line < new_start {
+                break Some((line + offset) as usize);
+            } else if line < new_start + new_lines {
+                if old_lines == 0 {
+                    break None;
+                }
+                let mapped = old_start + (line - new_start).min(old_lines - 1);
+                break Some(mapped as usize);
+            }
+            offset += old_lines - new_lines;
+            next += 1;
+        };
+    }
+    Ok(ret)
+}
+
 impl<'blame> Iterator for BlameIter<'blame> {
     type Item = BlameHunk<'blame>;
     fn next(&mut self) -> Option<BlameHunk<'blame>> {
@@ -333,6 +808,7 @@ impl<'blame> ExactSizeIterator for BlameIter<'blame> {}
 
 #[cfg(test)]
 mod tests {
+    use crate::BlameOptions;
     use std::fs::{self, File};
     use std::path::Path;
 
@@ -376,4 +852,127 @@ mod tests {
         assert_eq!(blame_buffer.iter().count(), 2);
         assert!(line.final_commit_id().is_zero());
     }
+
+    #[test]
+    fn incremental() {
+        let (_td, repo) = crate::test::repo_init();
+        let root = repo.workdir().unwrap();
+        let content = (1..=10).map(|i| format!("{}\n", i)).collect::<String>();
+        fs::write(root.join("foo"), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("foo")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &sig, &sig, "commit", &tree, &[&parent])
+            .unwrap();
+
+        let mut progress = Vec::new();
+        let mut lines = Vec::new();
+        repo.blame_file_incremental(Path::new("foo"), None, 4, |blame, p| {
+            for hunk in blame.iter() {
+                assert_eq!(hunk.final_commit_id(), commit);
+                let start = hunk.final_start_line();
+                lines.extend(start..start + hunk.lines_in_hunk());
+            }
+            progress.push((p.lines_attributed(), p.total_lines()));
+            true
+        })
+        .unwrap();
+        assert_eq!(progress, [(4, 10), (8, 10), (10, 10)]);
+        assert_eq!(lines, (1..=10).collect::<Vec<_>>());
+
+        let mut calls = 0;
+        repo.blame_file_incremental(Path::new("foo"), None, 4, |_, p| {
+            calls += 1;
+            p.percent() < 40.0
+        })
+        .unwrap();
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn ignore_revs() {
+        let (td, repo) = crate::test::repo_init();
+        let root = repo.workdir().unwrap();
+        let sig = repo.signature().unwrap();
+        let commit = |content: &str| {
+            fs::write(root.join("foo"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("foo")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = repo.head().unwrap().peel_to_commit().unwrap();
+            repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &[&parent])
+                .unwrap()
+        };
+        let first = commit("a\nb\nc\n");
+        let reformat = commit("a\nB\nc\n");
+        let last = commit("a\nB\nc\nd\n");
+
+        let blame = repo.blame_file(Path::new("foo"), None).unwrap();
+        assert_eq!(blame.get_line(2).unwrap().final_commit_id(), reformat);
+
+        let mut opts = BlameOptions::new();
+        opts.ignore_rev(reformat);
+        let blame = repo.blame_file(Path::new("foo"), Some(&mut opts)).unwrap();
+        assert_eq!(blame.len(), 2);
+        let hunk = blame.get_index(0).unwrap();
+        assert_eq!(hunk.final_commit_id(), first);
+        assert_eq!(hunk.final_start_line(), 1);
+        assert_eq!(hunk.lines_in_hunk(), 3);
+        assert_eq!(hunk.final_signature().name(), sig.name());
+        assert_eq!(blame.get_line(2).unwrap().final_commit_id(), first);
+        assert_eq!(blame.get_line(4).unwrap().final_commit_id(), last);
+
+        let file = td.path().join("ignore-revs");
+        fs::write(&file, format!("# formatting\n\n{} # reformat\n", reformat)).unwrap();
+        let mut opts = BlameOptions::new();
+        opts.ignore_revs_file(&file).unwrap();
+        let blame = repo.blame_file(Path::new("foo"), Some(&mut opts)).unwrap();
+        assert_eq!(blame.get_line(2).unwrap().final_commit_id(), first);
+
+        fs::write(&file, "1234\n").unwrap();
+        assert!(BlameOptions::new().ignore_revs_file(&file).is_err());
+    }
+
+    #[test]
+    fn line_ranges() {
+        let (_td, repo) = crate::test::repo_init();
+        let root = repo.workdir().unwrap();
+        let content = (1..=10).map(|i| format!("{}\n", i)).collect::<String>();
+        fs::write(root.join("foo"), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("foo")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &sig, &sig, "commit", &tree, &[&parent])
+            .unwrap();
+
+        let mut opts = BlameOptions::new();
+        opts.add_line_range(8, 9)
+            .add_line_range(2, 3)
+            .add_line_range(3, 4);
+        let blame = repo.blame_file(Path::new("foo"), Some(&mut opts)).unwrap();
+        let ranges = blame
+            .iter()
+            .map(|h| {
+                assert_eq!(h.final_commit_id(), commit);
+                (h.final_start_line(), h.lines_in_hunk())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(2, 3), (8, 2)]);
+        assert!(blame.get_line(5).is_none());
+        assert_eq!(blame.get_line(9).unwrap().final_start_line(), 8);
+
+        let mut progress = Vec::new();
+        repo.blame_file_incremental(Path::new("foo"), Some(&mut opts), 2, |_, p| {
+            progress.push(p.lines_attributed());
+            true
+        })
+        .unwrap();
+        assert_eq!(progress, [2, 3, 5]);
+    }
 }
@@ -1,10 +1,13 @@
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::marker;
 use std::ptr;
 use std::str;
 
 use crate::util::Binding;
-use crate::{raw, BranchType, Error, Reference, References};
+use crate::{
+    raw, BranchType, Config, ConfigLevel, Error, Oid, Reference, References, Repository, Sort,
+};
 
 /// A structure to represent a git [branch][1]
 ///
@@ -68,9 +71,35 @@ impl<'repo> Branch<'repo> {
     }
 
     /// Move/rename an existing local branch reference.
+    ///
+    /// Like `git branch -m`, the `branch.<name>` configuration section,
+    /// which holds the upstream of the branch, is moved to the new name, and
+    /// the reflog records the rename. When `force` replaces an existing
+    /// branch, the configuration of the replaced branch is removed so that
+    /// it doesn't get mixed with the one of the renamed branch; it is put
+    /// back if the branch cannot be renamed.
     pub fn rename(&mut self, new_branch_name: &str, force: bool) -> Result<Branch<'repo>, Error> {
-        let mut ret = ptr::null_mut();
+        let mut config = self.local_config()?;
+        let replaced = if force && self.name_bytes()? != new_branch_name.as_bytes() {
+            config.remove_section(&format!("branch.{}", new_branch_name))?
+        } else {
+            Vec::new()
+        };
         let new_branch_name = CString::new(new_branch_name)?;
+        let result = self.move_to(new_branch_name, force);
+        if result.is_err() {
+            // The branch was not replaced after all, so it keeps its
+            // configuration.
+            for (name, value) in &replaced {
+                // A pattern which matches no value, so that the value is added.
+                config.set_multivar(name, "a^", value)?;
+            }
+        }
+        result
+    }
+
+    fn move_to(&mut self, new_branch_name: CString, force: bool) -> Result<Branch<'repo>, Error> {
+        let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_branch_move(
                 &mut ret,
@@ -82,6 +111,18 @@ impl<'repo> Branch<'repo> {
         }
     }
 
+    /// Open the repository-level configuration of the repository of the
+    /// branch, which holds the `branch.<name>` sections.
+    fn local_config(&self) -> Result<Config, Error> {
+        let config = unsafe {
+            let mut raw = ptr::null_mut();
+            let repo = raw::git_reference_owner(&*self.get().raw());
+            try_call!(raw::git_repository_config(&mut raw, repo));
+            Config::from_raw(raw)
+        };
+        config.open_level(ConfigLevel::Local)
+    }
+
     /// Return the name of the given local or remote branch.
     ///
     /// May return `Ok(None)` if the name is not valid utf-8.
@@ -124,6 +165,135 @@ impl<'repo> Branch<'repo> {
     }
 }
 
+/// The bits of `ahead_behind` for commits reachable from the branch and from
+/// the upstream of the `i`th branch, in a bitset of `u64` words.
+fn local_bit(i: usize) -> (usize, u64) {
+    (2 * i / 64, 1 << (2 * i % 64))
+}
+
+fn upstream_bit(i: usize) -> (usize, u64) {
+    ((2 * i + 1) / 64, 1 << ((2 * i + 1) % 64))
+}
+
+/// The state of the walk of `ahead_behind`.
+struct AheadBehindWalk {
+    branches: usize,
+    /// The flags of the commits reached but not walked yet.
+    pending: HashMap<Oid, Vec<u64>>,
+    /// The number of pending commits which are not settled.
+    unsettled: usize,
+}
+
+impl AheadBehindWalk {
+    /// Whether each branch reaches a commit from both sides or from neither,
+    /// in which case its ancestors cannot change any count.
+    fn settled(&self, flags: &[u64]) -> bool {
+        (0..self.branches).all(|i| {
+            let (lw, lb) = local_bit(i);
+            let (uw, ub) = upstream_bit(i);
+            (flags[lw] & lb != 0) == (flags[uw] & ub != 0)
+        })
+    }
+
+    fn reach(&mut self, id: Oid, flags: &[u64]) {
+        let mut entry = match self.pending.remove(&id) {
+            Some(entry) => entry,
+            None => {
+                self.unsettled += 1;
+                vec![0; flags.len()]
+            }
+        };
+        let was_settled = self.settled(&entry);
+        for (to, from) in entry.iter_mut().zip(flags) {
+            *to |= *from;
+        }
+        self.unsettled += was_settled as usize;
+        self.unsettled -= self.settled(&entry) as usize;
+        self.pending.insert(id, entry);
+    }
+
+    fn take(&mut self, id: Oid) -> Option<Vec<u64>> {
+        let flags = self.pending.remove(&id)?;
+        if !self.settled(&flags) {
+            self.unsettled -= 1;
+        }
+        Some(flags)
+    }
+}
+
+/// Implementation of `Repository::branches_ahead_behind`.
+pub(crate) fn ahead_behind(repo: &Repository) -> Result<Vec<(String, usize, usize)>, Error> {
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let upstream = match branch.upstream() {
+            Ok(upstream) => upstream,
+            Err(_) => continue,
+        };
+        let (local, upstream) = match (branch.get().target(), upstream.get().target()) {
+            (Some(local), Some(upstream)) => (local, upstream),
+            _ => continue,
+        };
+        let name = String::from_utf8_lossy(branch.name_bytes()?).into_owned();
+        branches.push((name, local, upstream));
+    }
+    if branches.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Walk the history of all the branches at once, carrying for each commit
+    // which branches and upstreams reach it, until only settled commits are
+    // left. The walk is topological rather than by commit time, so that the
+    // flags of a commit are complete once it is walked even when commit
+    // times are equal or out of order.
+    let words = (2 * branches.len()).div_ceil(64);
+    let mut walk = AheadBehindWalk {
+        branches: branches.len(),
+        pending: HashMap::new(),
+        unsettled: 0,
+    };
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(Sort::TOPOLOGICAL)?;
+    for (i, (_, local, upstream)) in branches.iter().enumerate() {
+        for (id, (word, bit)) in [(*local, local_bit(i)), (*upstream, upstream_bit(i))].iter() {
+            let mut flags = vec![0; words];
+            flags[*word] |= *bit;
+            walk.reach(*id, &flags);
+            revwalk.push(*id)?;
+        }
+    }
+
+    let mut counts = vec![(0, 0); branches.len()];
+    for id in revwalk {
+        if walk.unsettled == 0 {
+            break;
+        }
+        let id = id?;
+        let flags = match walk.take(id) {
+            Some(flags) => flags,
+            None => continue,
+        };
+        for (i, count) in counts.iter_mut().enumerate() {
+            let (lw, lb) = local_bit(i);
+            let (uw, ub) = upstream_bit(i);
+            match (flags[lw] & lb != 0, flags[uw] & ub != 0) {
+                (true, false) => count.0 += 1,
+                (false, true) => count.1 += 1,
+                _ => {}
+            }
+        }
+        for parent in repo.find_commit(id)?.parent_ids() {
+            walk.reach(parent, &flags);
+        }
+    }
+
+    Ok(branches
+        .into_iter()
+        .zip(counts)
+        .map(|((name, _, _), (ahead, behind))| (name, ahead, behind))
+        .collect())
+}
+
 impl<'repo> Branches<'repo> {
     /// Creates a new iterator from the raw pointer given.
     ///
@@ -187,6 +357,73 @@ mod tests {
         b1.delete().unwrap();
     }
 
+    #[test]
+    fn upstream_remote_and_merge() {
+        let (_td, repo) = crate::test::repo_init();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut branch = repo.branch("feature", &commit, false).unwrap();
+        assert!(repo.branch_upstream_merge("refs/heads/feature").is_err());
+
+        branch.set_upstream(Some("main")).unwrap();
+        let remote = repo.branch_upstream_remote("refs/heads/feature").unwrap();
+        assert_eq!(remote.as_str(), Some("."));
+        let merge = repo.branch_upstream_merge("refs/heads/feature").unwrap();
+        assert_eq!(merge.as_str(), Some("refs/heads/main"));
+    }
+
+    #[test]
+    fn rename_moves_config() {
+        let (_td, repo) = crate::test::repo_init();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut old = repo.branch("old", &commit, false).unwrap();
+        old.set_upstream(Some("main")).unwrap();
+        let mut replaced = repo.branch("new", &commit, false).unwrap();
+        replaced.set_upstream(Some("old")).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("branch.new.description", "stale").unwrap();
+
+        let new = old.rename("new", true).unwrap();
+        assert_eq!(new.name().unwrap(), Some("new"));
+        let config = config.snapshot().unwrap();
+        assert_eq!(
+            config.get_str("branch.new.merge").unwrap(),
+            "refs/heads/main"
+        );
+        assert!(config.get_str("branch.new.description").is_err());
+        assert!(config.get_str("branch.old.merge").is_err());
+        let reflog = repo.reflog("refs/heads/new").unwrap();
+        let message = reflog.get(0).unwrap().message().unwrap().to_string();
+        assert!(message.starts_with("branch: renamed refs/heads/old"));
+    }
+
+    #[test]
+    fn ahead_behind() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut behind = repo.branch("behind", &base, false).unwrap();
+        behind.set_upstream(Some("main")).unwrap();
+        repo.branch("untracked", &base, false).unwrap();
+        crate::test::commit(&repo);
+        crate::test::commit(&repo);
+        let tip = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut ahead = repo.branch("ahead", &tip, false).unwrap();
+        ahead.set_upstream(Some("behind")).unwrap();
+
+        let mut counts = repo.branches_ahead_behind().unwrap();
+        counts.sort();
+        assert_eq!(
+            counts,
+            [("ahead".to_string(), 2, 0), ("behind".to_string(), 0, 2)]
+        );
+        for (name, ahead, behind) in counts {
+            let branch = repo.find_branch(&name, BranchType::Local).unwrap();
+            let local = branch.get().target().unwrap();
+            let upstream = branch.upstream().unwrap().get().target().unwrap();
+            let expected = repo.graph_ahead_behind(local, upstream).unwrap();
+            assert_eq!((ahead, behind), expected);
+        }
+    }
+
     #[test]
     fn name_is_valid() {
         assert!(Branch::name_is_valid("foo").unwrap());
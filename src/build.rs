@@ -1,14 +1,16 @@
 //! Builder-pattern objects for configuration various git operations.
 
 use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::collections::HashSet;
 use std::ffi::{CStr, CString};
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 
 use crate::util::{self, Binding};
-use crate::{panic, raw, Error, FetchOptions, IntoCString, Oid, Repository, Tree};
-use crate::{CheckoutNotificationType, DiffFile, FileMode, Remote};
+use crate::{panic, raw, Error, FetchOptions, Index, IntoCString, Oid, Repository, Tree};
+use crate::{CheckoutNotificationType, DiffFile, ErrorCode, FileMode, Remote};
+use crate::{ObjectType, Pathspec, PathspecFlags, TreeWalkMode, TreeWalkResult};
 
 /// A builder struct which is used to build configuration for cloning a new git
 /// repository.
@@ -84,6 +86,7 @@ pub struct CheckoutBuilder<'cb> {
     our_label: Option<CString>,
     ancestor_label: Option<CString>,
     target_dir: Option<CString>,
+    baseline_index: Option<&'cb Index>,
     paths: Vec<CString>,
     path_ptrs: Vec<*const c_char>,
     file_perm: Option<i32>,
@@ -93,6 +96,8 @@ pub struct CheckoutBuilder<'cb> {
     progress: Option<Box<Progress<'cb>>>,
     notify: Option<Box<Notify<'cb>>>,
     notify_flags: CheckoutNotificationType,
+    perfdata: Option<Box<Perfdata<'cb>>>,
+    preview: Option<Vec<CheckoutPreview>>,
 }
 
 /// Checkout progress notification callback.
@@ -117,6 +122,92 @@ pub type Notify<'a> = dyn FnMut(
     ) -> bool
     + 'a;
 
+/// Checkout performance data callback.
+///
+/// The argument holds the counts of filesystem operations performed by the
+/// checkout.
+pub type Perfdata<'a> = dyn FnMut(CheckoutPerfdata) + 'a;
+
+/// Counts of filesystem operations performed during a checkout.
+///
+/// See `CheckoutBuilder::perfdata`.
+#[derive(Clone, Copy, Debug)]
+pub struct CheckoutPerfdata {
+    mkdir_calls: usize,
+    stat_calls: usize,
+    chmod_calls: usize,
+}
+
+impl CheckoutPerfdata {
+    /// Number of directories created.
+    pub fn mkdir_calls(&self) -> usize {
+        self.mkdir_calls
+    }
+
+    /// Number of `stat` calls made on the working directory.
+    pub fn stat_calls(&self) -> usize {
+        self.stat_calls
+    }
+
+    /// Number of file mode changes made.
+    pub fn chmod_calls(&self) -> usize {
+        self.chmod_calls
+    }
+}
+
+/// The action a checkout would take for a path, as recorded in preview mode.
+///
+/// See `CheckoutBuilder::preview`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheckoutAction {
+    /// The file would be created or its contents updated.
+    Update,
+    /// The file would be removed.
+    Remove,
+    /// The file conflicts with the checkout and would be left untouched.
+    Conflict,
+}
+
+/// A path and the action a checkout would take for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheckoutPreview {
+    path: PathBuf,
+    action: CheckoutAction,
+}
+
+impl CheckoutPreview {
+    /// The path, relative to the working directory, that would be affected.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The action that would be taken for the path.
+    pub fn action(&self) -> CheckoutAction {
+        self.action
+    }
+}
+
+/// The paths of the files of `tree`, for `CheckoutBuilder::preview_removals`.
+pub(crate) fn tree_paths(tree: &Tree<'_>) -> Result<HashSet<PathBuf>, Error> {
+    let mut paths = HashSet::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() != Some(ObjectType::Tree) {
+            paths.insert(Path::new(root).join(util::bytes2path(entry.name_bytes())));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(paths)
+}
+
+/// The paths of the entries of `index`, for
+/// `CheckoutBuilder::preview_removals`.
+pub(crate) fn index_paths(index: &Index) -> HashSet<PathBuf> {
+    index
+        .iter()
+        .map(|entry| util::bytes2path(&entry.path).to_path_buf())
+        .collect()
+}
+
 impl<'cb> Default for RepoBuilder<'cb> {
     fn default() -> Self {
         Self::new()
@@ -335,6 +426,7 @@ impl<'cb> CheckoutBuilder<'cb> {
             path_ptrs: Vec::new(),
             paths: Vec::new(),
             target_dir: None,
+            baseline_index: None,
             ancestor_label: None,
             our_label: None,
             their_label: None,
@@ -342,6 +434,8 @@ impl<'cb> CheckoutBuilder<'cb> {
             progress: None,
             notify: None,
             notify_flags: CheckoutNotificationType::empty(),
+            perfdata: None,
+            preview: None,
         }
     }
 
@@ -353,6 +447,27 @@ impl<'cb> CheckoutBuilder<'cb> {
         self
     }
 
+    /// Indicate that this checkout should only analyze what it would do.
+    ///
+    /// Unlike `dry_run`, the checkout runs with the configured strategy
+    /// (`safe`, `force`, etc.) and records the action it would take for every
+    /// affected path, but writes nothing to the working directory or the
+    /// index. Conflicts are recorded instead of failing the checkout. The
+    /// recorded actions are available from `previewed` afterwards, which lets
+    /// a UI ask for confirmation before running a destructive checkout.
+    pub fn preview(&mut self, on: bool) -> &mut CheckoutBuilder<'cb> {
+        self.preview = if on { Some(Vec::new()) } else { None };
+        self
+    }
+
+    /// Returns the actions recorded by the last checkout run in `preview`
+    /// mode.
+    ///
+    /// The list is empty if preview mode is disabled.
+    pub fn previewed(&self) -> &[CheckoutPreview] {
+        self.preview.as_deref().unwrap_or(&[])
+    }
+
     /// Take any action necessary to get the working directory to match the
     /// target including potentially discarding modified files.
     pub fn force(&mut self) -> &mut CheckoutBuilder<'cb> {
@@ -491,6 +606,14 @@ impl<'cb> CheckoutBuilder<'cb> {
         self.flag(raw::GIT_CHECKOUT_CONFLICT_STYLE_DIFF3, on)
     }
 
+    /// Indicates whether to write conflicts in zdiff3 format, which is like
+    /// diff3 but moves lines common to both sides out of the conflict.
+    ///
+    /// Defaults to false.
+    pub fn conflict_style_zdiff3(&mut self, on: bool) -> &mut CheckoutBuilder<'cb> {
+        self.flag(raw::GIT_CHECKOUT_CONFLICT_STYLE_ZDIFF3, on)
+    }
+
     /// Treat paths specified in [`CheckoutBuilder::path`] as exact file paths
     /// instead of as pathspecs.
     pub fn disable_pathspec_match(&mut self, on: bool) -> &mut CheckoutBuilder<'cb> {
@@ -542,6 +665,18 @@ impl<'cb> CheckoutBuilder<'cb> {
         self
     }
 
+    /// Set the index describing the expected contents of the working
+    /// directory.
+    ///
+    /// Normally the checkout compares the working directory against the HEAD
+    /// tree to decide which files are modified. When the baseline is not
+    /// representable as a tree, for example after an in-memory merge, an index
+    /// can be supplied instead.
+    pub fn baseline_index(&mut self, index: &'cb Index) -> &mut CheckoutBuilder<'cb> {
+        self.baseline_index = Some(index);
+        self
+    }
+
     /// The name of the common ancestor side of conflicts
     pub fn ancestor_label(&mut self, label: &str) -> &mut CheckoutBuilder<'cb> {
         self.ancestor_label = Some(CString::new(label).unwrap());
@@ -588,6 +723,85 @@ impl<'cb> CheckoutBuilder<'cb> {
         self
     }
 
+    /// Set a callback to receive performance data about the checkout.
+    ///
+    /// The callback is invoked once the checkout has finished with the number
+    /// of `mkdir`, `stat` and `chmod` calls made, which can help diagnose slow
+    /// checkouts on network filesystems.
+    pub fn perfdata<F>(&mut self, cb: F) -> &mut CheckoutBuilder<'cb>
+    where
+        F: FnMut(CheckoutPerfdata) + 'cb,
+    {
+        self.perfdata = Some(Box::new(cb) as Box<Perfdata<'cb>>);
+        self
+    }
+
+    /// Whether this checkout only records the actions it would take.
+    pub(crate) fn previewing(&self) -> bool {
+        self.preview.is_some()
+    }
+
+    /// Record the files of the baseline missing from `target` which a
+    /// previewed checkout would remove, since libgit2 doesn't notify about
+    /// them. The baseline is the `baseline_index`, or the tree of `HEAD`.
+    pub(crate) fn preview_removals(
+        &mut self,
+        repo: &Repository,
+        target: &HashSet<PathBuf>,
+    ) -> Result<(), Error> {
+        let removes = raw::GIT_CHECKOUT_UPDATE_ONLY | raw::GIT_CHECKOUT_NONE;
+        if self.checkout_opts & removes as u32 != 0 {
+            return Ok(());
+        }
+        let workdir = match (&self.target_dir, repo.workdir()) {
+            (Some(dir), _) => util::bytes2path(dir.as_bytes()).to_path_buf(),
+            (None, Some(workdir)) => workdir.to_path_buf(),
+            (None, None) => return Ok(()),
+        };
+        let baseline = match self.baseline_index {
+            Some(index) => index_paths(index),
+            // Without an index, the checkout starts from nothing.
+            None if !repo.path().join("index").exists() => HashSet::new(),
+            None => match repo.head().and_then(|head| head.peel_to_tree()) {
+                Ok(tree) => tree_paths(&tree)?,
+                Err(e) if e.code() == ErrorCode::UnbornBranch => HashSet::new(),
+                Err(e) if e.code() == ErrorCode::NotFound => HashSet::new(),
+                Err(e) => return Err(e),
+            },
+        };
+        let pathspec = if self.paths.is_empty() {
+            None
+        } else {
+            Some(Pathspec::new(self.paths.iter().cloned())?)
+        };
+        let flags = if self.checkout_opts & raw::GIT_CHECKOUT_DISABLE_PATHSPEC_MATCH as u32 != 0 {
+            PathspecFlags::NO_GLOB
+        } else {
+            PathspecFlags::DEFAULT
+        };
+        let preview = match self.preview.as_mut() {
+            Some(preview) => preview,
+            None => return Ok(()),
+        };
+        let mut removed = baseline
+            .into_iter()
+            .filter(|path| !target.contains(path))
+            .filter(|path| {
+                pathspec
+                    .as_ref()
+                    .is_none_or(|spec| spec.matches_path(path, flags))
+            })
+            .filter(|path| workdir.join(path).symlink_metadata().is_ok())
+            .filter(|path| !preview.iter().any(|p| p.path == *path))
+            .collect::<Vec<_>>();
+        removed.sort();
+        preview.extend(removed.into_iter().map(|path| CheckoutPreview {
+            path,
+            action: CheckoutAction::Remove,
+        }));
+        Ok(())
+    }
+
     /// Configure a raw checkout options based on this configuration.
     ///
     /// This method is unsafe as there is no guarantee that this structure will
@@ -606,6 +820,9 @@ impl<'cb> CheckoutBuilder<'cb> {
         if let Some(ref c) = self.target_dir {
             opts.target_directory = c.as_ptr();
         }
+        if let Some(index) = self.baseline_index {
+            opts.baseline_index = index.raw();
+        }
         if let Some(ref c) = self.ancestor_label {
             opts.ancestor_label = c.as_ptr();
         }
@@ -624,7 +841,24 @@ impl<'cb> CheckoutBuilder<'cb> {
             opts.notify_payload = self as *mut _ as *mut _;
             opts.notify_flags = self.notify_flags.bits() as c_uint;
         }
-        opts.checkout_strategy = self.checkout_opts as c_uint;
+        let mut strategy = self.checkout_opts;
+        if let Some(ref mut preview) = self.preview {
+            preview.clear();
+            strategy |= (raw::GIT_CHECKOUT_DRY_RUN | raw::GIT_CHECKOUT_ALLOW_CONFLICTS) as u32;
+            opts.notify_cb = Some(notify_cb);
+            opts.notify_payload = self as *mut _ as *mut _;
+            opts.notify_flags |= (CheckoutNotificationType::CONFLICT
+                | CheckoutNotificationType::UPDATED
+                | CheckoutNotificationType::DIRTY
+                | CheckoutNotificationType::UNTRACKED
+                | CheckoutNotificationType::IGNORED)
+                .bits() as c_uint;
+        }
+        if self.perfdata.is_some() {
+            opts.perfdata_cb = Some(perfdata_cb);
+            opts.perfdata_payload = self as *mut _ as *mut _;
+        }
+        opts.checkout_strategy = strategy as c_uint;
     }
 }
 
@@ -660,15 +894,49 @@ extern "C" fn notify_cb(
     // pack callback etc
     panic::wrap(|| unsafe {
         let payload = &mut *(data as *mut CheckoutBuilder<'_>);
-        let callback = match payload.notify {
-            Some(ref mut c) => c,
-            None => return 0,
-        };
         let path = if path.is_null() {
             None
         } else {
             Some(util::bytes2path(CStr::from_ptr(path).to_bytes()))
         };
+        let why = CheckoutNotificationType::from_bits_truncate(why as u32);
+
+        if let (Some(preview), Some(path)) = (payload.preview.as_mut(), path) {
+            let strategy = payload.checkout_opts;
+            let removes = |flag: raw::git_checkout_strategy_t| strategy & flag as u32 != 0;
+            let action = if why.is_conflict() {
+                Some(CheckoutAction::Conflict)
+            } else if why.is_updated() {
+                // Deletions are reported with an empty target file.
+                if target.is_null() || (*target).mode == 0 {
+                    Some(CheckoutAction::Remove)
+                } else {
+                    Some(CheckoutAction::Update)
+                }
+            } else if !target.is_null() {
+                // A dirty file of the target is also reported as updated
+                // if the strategy overwrites it.
+                None
+            } else if (why.is_dirty() && removes(raw::GIT_CHECKOUT_FORCE))
+                || (why.is_untracked() && removes(raw::GIT_CHECKOUT_REMOVE_UNTRACKED))
+                || (why.is_ignored() && removes(raw::GIT_CHECKOUT_REMOVE_IGNORED))
+            {
+                Some(CheckoutAction::Remove)
+            } else {
+                None
+            };
+            if let Some(action) = action {
+                preview.push(CheckoutPreview {
+                    path: path.to_path_buf(),
+                    action,
+                });
+            }
+        }
+
+        let callback = match payload.notify {
+            Some(ref mut c) if payload.notify_flags.intersects(why) => c,
+            _ => return 0,
+        };
 
         let baseline = if baseline.is_null() {
             None
@@ -688,7 +956,6 @@ extern "C" fn notify_cb(
             Some(DiffFile::from_raw(workdir))
         };
 
-        let why = CheckoutNotificationType::from_bits_truncate(why as u32);
         let keep_going = callback(why, path, baseline, target, workdir);
         if keep_going {
             0
@@ -699,6 +966,22 @@ extern "C" fn notify_cb(
     .unwrap_or(2)
 }
 
+extern "C" fn perfdata_cb(perfdata: *const raw::git_checkout_perfdata, data: *mut c_void) {
+    panic::wrap(|| unsafe {
+        let payload = &mut *(data as *mut CheckoutBuilder<'_>);
+        let callback = match payload.perfdata {
+            Some(ref mut c) => c,
+            None => return,
+        };
+        let perfdata = &*perfdata;
+        callback(CheckoutPerfdata {
+            mkdir_calls: perfdata.mkdir_calls,
+            stat_calls: perfdata.stat_calls,
+            chmod_calls: perfdata.chmod_calls,
+        })
+    });
+}
+
 unsafe impl Send for TreeUpdateBuilder {}
 
 impl Default for TreeUpdateBuilder {
@@ -772,7 +1055,7 @@ impl TreeUpdateBuilder {
 
 #[cfg(test)]
 mod tests {
-    use super::{CheckoutBuilder, RepoBuilder, TreeUpdateBuilder};
+    use super::{CheckoutAction, CheckoutBuilder, RepoBuilder, TreeUpdateBuilder};
     use crate::{CheckoutNotificationType, FileMode, Repository};
     use std::fs;
     use std::path::Path;
@@ -869,4 +1152,92 @@ mod tests {
         repo.checkout_index(Some(&mut index), Some(&mut checkout_opts))
             .unwrap();
     }
+
+    #[test]
+    fn baseline_index() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join("foo"), "bar").unwrap();
+        let mut baseline = repo.index().unwrap();
+        baseline.add_path(Path::new("foo")).unwrap();
+
+        // Relative to HEAD `foo` is untracked and left alone, but relative to
+        // the baseline it is an unmodified tracked file and gets removed.
+        let head = repo.head().unwrap().peel_to_tree().unwrap();
+        repo.checkout_tree(head.as_object(), None).unwrap();
+        assert!(td.path().join("foo").exists());
+
+        let mut opts = CheckoutBuilder::new();
+        opts.baseline_index(&baseline);
+        repo.checkout_tree(head.as_object(), Some(&mut opts))
+            .unwrap();
+        assert!(!td.path().join("foo").exists());
+    }
+
+    #[test]
+    fn preview() {
+        let (td, repo) = crate::test::repo_init();
+        let (_, tree_id) = crate::test::commit(&repo);
+        repo.index().unwrap().write().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let empty = repo.head().unwrap().peel_to_commit().unwrap();
+        let empty = empty.parent(0).unwrap().tree().unwrap();
+        fs::write(td.path().join("foo"), "modified").unwrap();
+
+        let mut opts = CheckoutBuilder::new();
+        opts.preview(true);
+        repo.checkout_tree(empty.as_object(), Some(&mut opts))
+            .unwrap();
+        assert_eq!(opts.previewed().len(), 1);
+        assert_eq!(opts.previewed()[0].path(), Path::new("foo"));
+        assert_eq!(opts.previewed()[0].action(), CheckoutAction::Conflict);
+
+        opts.force();
+        repo.checkout_tree(empty.as_object(), Some(&mut opts))
+            .unwrap();
+        assert_eq!(opts.previewed().len(), 1);
+        assert_eq!(opts.previewed()[0].action(), CheckoutAction::Remove);
+        assert!(td.path().join("foo").exists());
+
+        fs::remove_file(td.path().join("foo")).unwrap();
+        repo.checkout_tree(tree.as_object(), Some(&mut opts))
+            .unwrap();
+        assert_eq!(opts.previewed().len(), 1);
+        assert_eq!(opts.previewed()[0].action(), CheckoutAction::Update);
+        assert!(!td.path().join("foo").exists());
+
+        // Untracked files are only removed if the strategy says so.
+        fs::write(td.path().join("bar"), "untracked").unwrap();
+        let mut opts = CheckoutBuilder::new();
+        opts.preview(true);
+        repo.checkout_head(Some(&mut opts)).unwrap();
+        assert_eq!(opts.previewed().len(), 0);
+        opts.remove_untracked(true);
+        repo.checkout_head(Some(&mut opts)).unwrap();
+        assert_eq!(opts.previewed().len(), 1);
+        assert_eq!(opts.previewed()[0].path(), Path::new("bar"));
+        assert_eq!(opts.previewed()[0].action(), CheckoutAction::Remove);
+        assert!(td.path().join("bar").exists());
+    }
+
+    #[test]
+    fn perfdata_callback() {
+        let (td, repo) = crate::test::repo_init();
+        fs::create_dir(td.path().join("dir")).unwrap();
+        fs::write(td.path().join("dir/file"), "foo").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("dir/file")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        fs::remove_dir_all(td.path().join("dir")).unwrap();
+
+        let mut called = false;
+        let mut opts = CheckoutBuilder::new();
+        opts.force().perfdata(|perf| {
+            called = true;
+            assert!(perf.mkdir_calls() > 0);
+        });
+        repo.checkout_tree(tree.as_object(), Some(&mut opts))
+            .unwrap();
+        drop(opts);
+        assert!(called);
+    }
 }
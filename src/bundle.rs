@@ -0,0 +1,340 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str;
+
+use crate::{Error, ErrorClass, ErrorCode, ObjectType, Oid, Repository};
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorCode::Invalid, ErrorClass::Invalid, msg)
+}
+
+/// The header of a git bundle file, as written by `git bundle create` or
+/// `BundleBuilder`.
+///
+/// A bundle holds a set of references along with a packfile of the objects
+/// they need, which lets repositories be transferred without a network
+/// connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bundle {
+    version: u32,
+    capabilities: Vec<String>,
+    prerequisites: Vec<(Oid, String)>,
+    refs: Vec<(String, Oid)>,
+}
+
+/// A builder for bundle files.
+pub struct BundleBuilder<'repo> {
+    repo: &'repo Repository,
+    refs: Vec<(String, Oid)>,
+    exclude: Vec<Oid>,
+    version: u32,
+}
+
+impl Bundle {
+    /// Read the header of a bundle.
+    ///
+    /// Both version 2 and version 3 bundles are supported. On success the
+    /// reader is left at the start of the packfile of the bundle.
+    pub fn from_reader<R: BufRead>(reader: &mut R) -> Result<Bundle, Error> {
+        let mut line = Vec::new();
+        let mut next_line = |line: &mut Vec<u8>| -> Result<bool, Error> {
+            line.clear();
+            if reader.read_until(b'\n', line)? == 0 {
+                return Ok(false);
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            Ok(true)
+        };
+
+        next_line(&mut line)?;
+        let version = match &line[..] {
+            b"# v2 git bundle" => 2,
+            b"# v3 git bundle" => 3,
+            _ => return Err(invalid("not a git bundle")),
+        };
+        let mut bundle = Bundle {
+            version,
+            capabilities: Vec::new(),
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+        };
+        loop {
+            if !next_line(&mut line)? {
+                return Err(invalid("truncated bundle header"));
+            }
+            if line.is_empty() {
+                return Ok(bundle);
+            }
+            let line = str::from_utf8(&line).map_err(|_| invalid("invalid bundle header"))?;
+            if let Some(capability) = line.strip_prefix('@') {
+                if version == 2 {
+                    return Err(invalid("capabilities are not allowed in v2 bundles"));
+                }
+                if let Some(format) = capability.strip_prefix("object-format=") {
+                    if format != "sha1" {
+                        return Err(invalid("unsupported bundle object format"));
+                    }
+                }
+                bundle.capabilities.push(capability.to_string());
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let (id, comment) = split_id(prerequisite)?;
+                bundle.prerequisites.push((id, comment.to_string()));
+            } else {
+                let (id, name) = split_id(line)?;
+                bundle.refs.push((name.to_string(), id));
+            }
+        }
+    }
+
+    /// The version of the bundle format, `2` or `3`.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The capabilities of a version 3 bundle, such as `object-format=sha1`
+    /// or `filter=blob:none`.
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    /// The commits a repository must have for the bundle to be unbundled
+    /// into it, along with their comment, usually the commit summary.
+    pub fn prerequisites(&self) -> &[(Oid, String)] {
+        &self.prerequisites
+    }
+
+    /// The references of the bundle and the objects they point to.
+    pub fn refs(&self) -> &[(String, Oid)] {
+        &self.refs
+    }
+
+    /// Create or update references of `repo` for the references of the
+    /// bundle.
+    ///
+    /// `map` is called with the name of each reference of the bundle and
+    /// returns the name of the reference to write, or `None` to skip it.
+    /// Existing references are only overwritten if `force` is true. Returns
+    /// the names of the references written.
+    pub fn update_refs<F>(
+        &self,
+        repo: &Repository,
+        force: bool,
+        mut map: F,
+    ) -> Result<Vec<String>, Error>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut updated = Vec::new();
+        for (name, id) in &self.refs {
+            let target = match map(name) {
+                Some(target) => target,
+                None => continue,
+            };
+            repo.reference(&target, *id, force, "bundle: storing head")?;
+            updated.push(target);
+        }
+        Ok(updated)
+    }
+}
+
+fn split_id(line: &str) -> Result<(Oid, &str), Error> {
+    let (id, rest) = match line.find(' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    };
+    if id.len() != 40 {
+        return Err(invalid("invalid object id in bundle header"));
+    }
+    Ok((Oid::from_str(id)?, rest))
+}
+
+/// Implementation of `Repository::unbundle`.
+pub(crate) fn unbundle<R: Read>(repo: &Repository, reader: R) -> Result<Bundle, Error> {
+    let mut reader = BufReader::new(reader);
+    let bundle = Bundle::from_reader(&mut reader)?;
+    let odb = repo.odb()?;
+    for (id, _) in &bundle.prerequisites {
+        if !odb.exists(*id) {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Odb,
+                format!("missing prerequisite commit {} of the bundle", id),
+            ));
+        }
+    }
+
+    // A pack without objects has nothing to index.
+    let header = reader.fill_buf()?;
+    if header.len() >= 12 && header[8..12] == [0, 0, 0, 0] {
+        return Ok(bundle);
+    }
+    let mut writer = odb.packwriter()?;
+    io::copy(&mut reader, &mut writer)?;
+    writer.commit()?;
+    Ok(bundle)
+}
+
+impl<'repo> BundleBuilder<'repo> {
+    /// Create a builder for a bundle of objects of `repo`.
+    pub fn new(repo: &'repo Repository) -> BundleBuilder<'repo> {
+        BundleBuilder {
+            repo,
+            refs: Vec::new(),
+            exclude: Vec::new(),
+            version: 2,
+        }
+    }
+
+    /// Add a reference to the bundle, along with all the objects it needs.
+    ///
+    /// `name` is the full name of a reference, such as `refs/heads/main`, or
+    /// `HEAD`.
+    pub fn reference(&mut self, name: &str) -> Result<&mut BundleBuilder<'repo>, Error> {
+        let id = self.repo.refname_to_id(name)?;
+        self.refs.push((name.to_string(), id));
+        Ok(self)
+    }
+
+    /// Leave out a commit and its history from the bundle.
+    ///
+    /// The commits of the bundle whose parents are left out are recorded as
+    /// prerequisites, which the receiving repository must already have. This
+    /// is how incremental bundles, for the `A..B` range, are created.
+    pub fn exclude(&mut self, id: Oid) -> &mut BundleBuilder<'repo> {
+        self.exclude.push(id);
+        self
+    }
+
+    /// The version of the bundle format to write, `2` or `3`. Defaults to
+    /// `2`, which is understood by all versions of git.
+    pub fn version(&mut self, version: u32) -> &mut BundleBuilder<'repo> {
+        self.version = version;
+        self
+    }
+
+    /// Write the bundle to `w`.
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        if self.version != 2 && self.version != 3 {
+            return Err(invalid("unsupported bundle version"));
+        }
+        if self.refs.is_empty() {
+            return Err(invalid("refusing to create an empty bundle"));
+        }
+
+        let repo = self.repo;
+        let mut walk = repo.revwalk()?;
+        let mut builder = repo.packbuilder()?;
+        for (_, id) in &self.refs {
+            let object = repo.find_object(*id, None)?;
+            match object.kind() {
+                Some(ObjectType::Commit) => walk.push(*id)?,
+                Some(ObjectType::Tag) => {
+                    builder.insert_object(*id, None)?;
+                    match object.peel(ObjectType::Commit) {
+                        Ok(commit) => walk.push(commit.id())?,
+                        Err(_) => {
+                            builder.insert_recursive(object.peel(ObjectType::Any)?.id(), None)?
+                        }
+                    }
+                }
+                _ => builder.insert_recursive(*id, None)?,
+            }
+        }
+        for id in &self.exclude {
+            walk.hide(*id)?;
+        }
+        let commits = walk.collect::<Result<Vec<_>, _>>()?;
+        let included = commits.iter().cloned().collect::<HashSet<_>>();
+        let mut prerequisites = Vec::new();
+        let mut seen = HashSet::new();
+        for id in &commits {
+            for parent in repo.find_commit(*id)?.parents() {
+                if !included.contains(&parent.id()) && seen.insert(parent.id()) {
+                    prerequisites.push(parent);
+                }
+            }
+        }
+
+        let mut walk = repo.revwalk()?;
+        for id in &commits {
+            walk.push(*id)?;
+        }
+        for commit in &prerequisites {
+            walk.hide(commit.id())?;
+        }
+        builder.insert_walk(&mut walk)?;
+
+        let mut header = format!("# v{} git bundle\n", self.version);
+        if self.version == 3 {
+            header.push_str("@object-format=sha1\n");
+        }
+        for commit in &prerequisites {
+            header.push_str(&format!(
+                "-{} {}\n",
+                commit.id(),
+                commit.summary().unwrap_or("")
+            ));
+        }
+        for (name, id) in &self.refs {
+            header.push_str(&format!("{} {}\n", id, name));
+        }
+        header.push('\n');
+        w.write_all(header.as_bytes())?;
+        builder.write_to(w)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bundle, BundleBuilder};
+    use crate::Oid;
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = t!(repo.refname_to_id("HEAD"));
+        let (tip, _tree) = crate::test::commit(&repo);
+
+        let mut full = Vec::new();
+        let mut builder = BundleBuilder::new(&repo);
+        t!(builder.reference("refs/heads/main"));
+        t!(builder.write(&mut full));
+        let bundle = t!(Bundle::from_reader(&mut &full[..]));
+        assert_eq!(bundle.version(), 2);
+        assert_eq!(bundle.prerequisites(), []);
+        assert_eq!(bundle.refs(), [("refs/heads/main".to_string(), tip)]);
+
+        let mut incremental = Vec::new();
+        t!(builder.exclude(base).version(3).write(&mut incremental));
+        let bundle = t!(Bundle::from_reader(&mut &incremental[..]));
+        assert_eq!(bundle.version(), 3);
+        assert_eq!(bundle.capabilities(), ["object-format=sha1".to_string()]);
+        assert_eq!(bundle.prerequisites(), [(base, "initial".to_string())]);
+
+        // The incremental bundle needs the base commit. A repository from
+        // `repo_init` could already have it, as its initial commit is
+        // created the same way.
+        let td = t!(tempfile::TempDir::new());
+        let empty = t!(crate::Repository::init(td.path()));
+        let err = empty.unbundle(&incremental[..]).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::NotFound);
+        assert!(err.message().contains(&base.to_string()));
+
+        let (_td, clone) = crate::test::repo_init();
+        let bundle = t!(clone.unbundle(&full[..]));
+        let updated = t!(bundle.update_refs(&clone, false, |name| {
+            name.strip_prefix("refs/heads/")
+                .map(|branch| format!("refs/remotes/bundle/{}", branch))
+        }));
+        assert_eq!(updated, ["refs/remotes/bundle/main"]);
+        assert_eq!(t!(clone.refname_to_id("refs/remotes/bundle/main")), tip);
+        t!(clone.find_commit(tip));
+
+        assert!(Bundle::from_reader(&mut &b"PACK"[..]).is_err());
+        let bad = format!("# v2 git bundle\n{} refs/heads/main\n", Oid::zero());
+        assert!(Bundle::from_reader(&mut bad.as_bytes()).is_err());
+    }
+}
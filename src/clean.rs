@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::util;
+use crate::{Error, Index, IntoCString, Repository, StatusOptions};
+
+/// Options which can be used to configure how `Repository::clean` removes
+/// files from the working directory.
+///
+/// By default only untracked files are removed, leaving untracked directories
+/// and ignored files alone, like `git clean -f`.
+#[derive(Default)]
+pub struct CleanOptions {
+    dry_run: bool,
+    directories: bool,
+    ignored: bool,
+    ignored_only: bool,
+    nested_repositories: bool,
+    pathspec: Vec<CString>,
+}
+
+impl CleanOptions {
+    /// Creates a new set of clean options with the default configuration.
+    pub fn new() -> CleanOptions {
+        CleanOptions::default()
+    }
+
+    /// Only report what would be removed without deleting anything.
+    ///
+    /// This is the equivalent of `git clean -n`. Defaults to false.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut CleanOptions {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Also remove untracked directories.
+    ///
+    /// Without this, files inside directories that contain no tracked files
+    /// are left alone. This is the equivalent of `git clean -d`. Defaults to
+    /// false.
+    pub fn directories(&mut self, directories: bool) -> &mut CleanOptions {
+        self.directories = directories;
+        self
+    }
+
+    /// Also remove ignored files.
+    ///
+    /// This is the equivalent of `git clean -x`. Defaults to false.
+    pub fn ignored(&mut self, ignored: bool) -> &mut CleanOptions {
+        self.ignored = ignored;
+        self
+    }
+
+    /// Remove only ignored files, leaving other untracked files alone.
+    ///
+    /// This is the equivalent of `git clean -X`. Defaults to false.
+    pub fn ignored_only(&mut self, ignored_only: bool) -> &mut CleanOptions {
+        self.ignored_only = ignored_only;
+        self
+    }
+
+    /// Also remove untracked directories that are git repositories of their
+    /// own.
+    ///
+    /// Nested repositories are only removed if `directories` is also set.
+    /// This is the equivalent of `git clean -ff`. Defaults to false.
+    pub fn nested_repositories(&mut self, remove: bool) -> &mut CleanOptions {
+        self.nested_repositories = remove;
+        self
+    }
+
+    /// Add a path pattern limiting which files are removed.
+    ///
+    /// If no patterns are given the entire working directory is cleaned.
+    pub fn pathspec<T: IntoCString>(&mut self, pathspec: T) -> &mut CleanOptions {
+        self.pathspec
+            .push(util::cstring_to_repo_path(pathspec).unwrap());
+        self
+    }
+}
+
+/// Implementation of `Repository::clean`.
+pub(crate) fn clean(repo: &Repository, opts: &CleanOptions) -> Result<Vec<PathBuf>, Error> {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Err(Error::from_str("cannot clean a bare repository")),
+    };
+    let index = repo.index()?;
+
+    let mut status_opts = StatusOptions::new();
+    status_opts
+        .include_untracked(!opts.ignored_only)
+        .recurse_untracked_dirs(true)
+        .include_ignored(opts.ignored || opts.ignored_only)
+        .recurse_ignored_dirs(true)
+        .exclude_submodules(true);
+    for pathspec in &opts.pathspec {
+        status_opts.pathspec(pathspec.clone());
+    }
+
+    let mut removed = Vec::new();
+    let mut removed_set = HashSet::new();
+    let mut dirs = HashSet::new();
+    for entry in repo.statuses(Some(&mut status_opts))?.iter() {
+        let status = entry.status();
+        let selected = if status.is_ignored() {
+            opts.ignored || opts.ignored_only
+        } else {
+            status.is_wt_new() && !opts.ignored_only
+        };
+        if !selected {
+            continue;
+        }
+
+        let path = util::bytes2path(entry.path_bytes());
+        let full = workdir.join(path);
+        if entry.path_bytes().ends_with(b"/") {
+            // Untracked directories are only reported without recursing into
+            // them if they are repositories of their own.
+            if !opts.directories || !opts.nested_repositories {
+                continue;
+            }
+            if !opts.dry_run {
+                fs::remove_dir_all(&full).map_err(Error::from)?;
+            }
+        } else {
+            if !opts.directories && !is_tracked_dir(&index, path.parent())? {
+                continue;
+            }
+            if !opts.dry_run {
+                fs::remove_file(&full).map_err(Error::from)?;
+            }
+        }
+
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|p| *p != Path::new("")) {
+            if !dirs.insert(dir.to_path_buf()) {
+                break;
+            }
+            parent = dir.parent();
+        }
+        removed_set.insert(path.to_path_buf());
+        removed.push(path.to_path_buf());
+    }
+
+    if opts.directories {
+        // Remove the directories that were left empty, deepest first, and
+        // report them instead of their contents like `git clean` does.
+        let mut dirs = dirs.into_iter().collect::<Vec<_>>();
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        let mut removed_dirs = Vec::new();
+        for dir in dirs {
+            if is_tracked_dir(&index, Some(&dir))? {
+                continue;
+            }
+            let full = workdir.join(&dir);
+            let mut empty = true;
+            for child in fs::read_dir(&full).map_err(Error::from)? {
+                let child = child.map_err(Error::from)?;
+                if opts.dry_run && removed_set.contains(&dir.join(child.file_name())) {
+                    continue;
+                }
+                empty = false;
+                break;
+            }
+            if !empty {
+                continue;
+            }
+            if !opts.dry_run {
+                fs::remove_dir(&full).map_err(Error::from)?;
+            }
+            removed_set.insert(dir.clone());
+            removed_dirs.push(dir);
+        }
+
+        removed.retain(|p| {
+            !p.ancestors()
+                .skip(1)
+                .any(|a| removed_dirs.iter().any(|d| d == a))
+        });
+        removed.extend(removed_dirs);
+        removed.sort();
+    }
+
+    Ok(removed)
+}
+
+/// Returns whether the index tracks any file under `dir`.
+///
+/// The root of the working directory always counts as tracked.
+fn is_tracked_dir(index: &Index, dir: Option<&Path>) -> Result<bool, Error> {
+    let dir = match dir {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => return Ok(true),
+    };
+    let mut prefix = dir.into_c_string()?.into_bytes();
+    prefix.push(b'/');
+    let prefix = util::cstring_to_repo_path(prefix)?;
+    match index.find_prefix(prefix.as_bytes()) {
+        Ok(pos) => match index.get(pos) {
+            Some(entry) => Ok(entry.path.starts_with(prefix.as_bytes())),
+            None => Ok(false),
+        },
+        Err(ref e) if e.code() == crate::ErrorCode::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CleanOptions;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        crate::test::commit(&repo);
+        t!(t!(repo.index()).write());
+        t!(fs::write(td.path().join(".gitignore"), "*.log\n"));
+        t!(fs::write(td.path().join("untracked"), ""));
+        t!(fs::write(td.path().join("build.log"), ""));
+        t!(fs::create_dir(td.path().join("dir")));
+        t!(fs::write(td.path().join("dir/file"), ""));
+        t!(fs::create_dir(td.path().join("nested")));
+        t!(crate::Repository::init(td.path().join("nested")));
+
+        let mut opts = CleanOptions::new();
+        opts.dry_run(true);
+        let removed = t!(repo.clean(&opts));
+        assert_eq!(removed, [PathBuf::from(".gitignore"), "untracked".into()]);
+        assert!(td.path().join("untracked").exists());
+
+        opts.dry_run(false).directories(true).ignored_only(true);
+        let removed = t!(repo.clean(&opts));
+        assert_eq!(removed, [PathBuf::from("build.log")]);
+        assert!(!td.path().join("build.log").exists());
+
+        opts.ignored_only(false).pathspec("dir");
+        let removed = t!(repo.clean(&opts));
+        assert_eq!(removed, [PathBuf::from("dir")]);
+        assert!(!td.path().join("dir").exists());
+        assert!(td.path().join("untracked").exists());
+        assert!(td.path().join("nested").exists());
+
+        let removed = t!(repo.clean(CleanOptions::new().directories(true)));
+        assert_eq!(removed, [PathBuf::from(".gitignore"), "untracked".into()]);
+        assert!(td.path().join("nested").exists());
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::iter::FusedIterator;
 use std::marker;
 use std::mem;
@@ -32,6 +33,20 @@ pub struct ParentIds<'commit> {
     commit: &'commit Commit<'commit>,
 }
 
+/// An iterator over the fields of the header of a commit, in order.
+///
+/// Created by `Commit::header_fields`.
+pub struct CommitHeaderFields<'commit> {
+    rest: &'commit [u8],
+}
+
+/// A field of the header of a commit, such as `tree`, `parent` or `gpgsig`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitHeaderField<'commit> {
+    key: &'commit [u8],
+    value: Cow<'commit, [u8]>,
+}
+
 impl<'repo> Commit<'repo> {
     /// Get the id (SHA1) of a repository commit
     pub fn id(&self) -> Oid {
@@ -119,6 +134,18 @@ impl<'repo> Commit<'repo> {
         Ok(buf)
     }
 
+    /// Get the fields of the commit header, in the order they are written.
+    ///
+    /// Keys which appear several times, such as `parent`, are returned once
+    /// for each value. Values spanning several lines, such as `gpgsig`, are
+    /// returned with their continuation lines joined by newlines, as with
+    /// `header_field_bytes`.
+    pub fn header_fields(&self) -> CommitHeaderFields<'_> {
+        CommitHeaderFields {
+            rest: self.raw_header_bytes(),
+        }
+    }
+
     /// Get the full raw text of the commit header.
     pub fn raw_header_bytes(&self) -> &[u8] {
         unsafe { crate::opt_bytes(self, raw::git_commit_raw_header(&*self.raw)).unwrap() }
@@ -406,6 +433,66 @@ impl<'commit> FusedIterator for ParentIds<'commit> {}
 
 impl<'commit> ExactSizeIterator for ParentIds<'commit> {}
 
+impl<'commit> CommitHeaderField<'commit> {
+    /// The key of the field.
+    ///
+    /// Returns `None` if the key is not valid utf-8.
+    pub fn key(&self) -> Option<&str> {
+        str::from_utf8(self.key).ok()
+    }
+
+    /// The key of the field, as a byte array.
+    pub fn key_bytes(&self) -> &[u8] {
+        self.key
+    }
+
+    /// The value of the field.
+    ///
+    /// Returns `None` if the value is not valid utf-8.
+    pub fn value(&self) -> Option<&str> {
+        str::from_utf8(&self.value).ok()
+    }
+
+    /// The value of the field, as a byte array.
+    pub fn value_bytes(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+fn split_line(s: &[u8]) -> (&[u8], &[u8]) {
+    match s.iter().position(|&b| b == b'\n') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => (s, &[]),
+    }
+}
+
+impl<'commit> Iterator for CommitHeaderFields<'commit> {
+    type Item = CommitHeaderField<'commit>;
+    fn next(&mut self) -> Option<CommitHeaderField<'commit>> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let (line, rest) = split_line(self.rest);
+        self.rest = rest;
+        let (key, value) = match line.iter().position(|&b| b == b' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, &[][..]),
+        };
+        let mut value = Cow::Borrowed(value);
+        // Continuation lines start with a space.
+        while self.rest.first() == Some(&b' ') {
+            let (line, rest) = split_line(self.rest);
+            self.rest = rest;
+            let value = value.to_mut();
+            value.push(b'\n');
+            value.extend_from_slice(&line[1..]);
+        }
+        Some(CommitHeaderField { key, value })
+    }
+}
+
+impl<'commit> FusedIterator for CommitHeaderFields<'commit> {}
+
 impl<'repo> Clone for Commit<'repo> {
     fn clone(&self) -> Self {
         self.as_object().clone().into_commit().ok().unwrap()
@@ -443,6 +530,10 @@ mod tests {
             crate::Oid::from_str(tree_header_bytes.as_str().unwrap()).unwrap(),
             commit.tree_id()
         );
+        let fields = commit.header_fields().collect::<Vec<_>>();
+        assert_eq!(fields[0].key(), Some("tree"));
+        assert_eq!(fields[0].value_bytes(), tree_header_bytes.as_ref());
+        assert!(fields.iter().any(|f| f.key() == Some("author")));
         assert_eq!(commit.author().name(), Some("name"));
         assert_eq!(commit.author().email(), Some("email"));
         assert_eq!(commit.committer().name(), Some("name"));
@@ -469,4 +560,68 @@ mod tests {
             .ok()
             .unwrap();
     }
+
+    #[test]
+    fn header_fields() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+        let buf = repo
+            .commit_create_buffer(&sig, &sig, "merge", &tree, &[&head, &head])
+            .unwrap();
+        let signature = "-----BEGIN SIGNATURE-----\n\nabc\n-----END SIGNATURE-----";
+        let id = repo
+            .commit_signed(buf.as_str().unwrap(), signature, None)
+            .unwrap();
+        let commit = repo.find_commit(id).unwrap();
+
+        let fields = commit.header_fields().collect::<Vec<_>>();
+        let keys = fields.iter().map(|f| f.key().unwrap()).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            ["tree", "parent", "parent", "author", "committer", "gpgsig"]
+        );
+        assert_eq!(fields[1].value(), Some(&*head.id().to_string()));
+        assert_eq!(fields[5].value(), Some(signature));
+        assert_eq!(
+            fields[5].value_bytes(),
+            &*commit.header_field_bytes("gpgsig").unwrap()
+        );
+    }
+
+    #[test]
+    fn commit_signed_create() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+
+        let id = repo
+            .commit_signed_create(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                "signed\n\nbody",
+                &tree,
+                &[&head],
+                |content| {
+                    assert!(content.contains("signed"));
+                    Ok("signature".to_string())
+                },
+            )
+            .unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(id));
+        let (signature, _) = repo.extract_signature(&id, None).unwrap();
+        assert_eq!(signature.as_str(), Some("signature"));
+        let reflog = repo.reflog("refs/heads/main").unwrap();
+        assert_eq!(reflog.get(0).unwrap().message(), Some("commit: signed"));
+
+        // The reference no longer points to the parent.
+        assert!(repo
+            .commit_signed_create(Some("HEAD"), &sig, &sig, "stale", &tree, &[&head], |_| {
+                Ok("signature".to_string())
+            })
+            .is_err());
+    }
 }
@@ -0,0 +1,148 @@
+use std::path::Path;
+use std::ptr;
+
+use crate::util::{Binding, IntoCString};
+use crate::{raw, Buf, Error, Repository, Revwalk};
+
+/// A commit-graph file, as read by libgit2.
+///
+/// The commit-graph stores the parents, root tree, commit time and
+/// generation number of commits in a single file, so that revision walks,
+/// merge-base computations and ahead/behind counts don't need to parse the
+/// commits themselves. libgit2 picks up the commit-graph of a repository
+/// automatically, this type can be used to check that one exists and is
+/// valid.
+pub struct CommitGraph {
+    raw: *mut raw::git_commit_graph,
+}
+
+/// A writer for commit-graph files.
+///
+/// Commits are added to the writer from pack indexes or revision walks, and
+/// the graph covering all of them is written at once. libgit2 only writes
+/// single-file commit-graphs, so updating a graph with new commits means
+/// writing it again with all of them.
+pub struct CommitGraphWriter {
+    raw: *mut raw::git_commit_graph_writer,
+}
+
+impl CommitGraph {
+    /// Open and validate the commit-graph file of an objects directory,
+    /// that is `objects_dir/info/commit-graph`.
+    ///
+    /// Fails if the file does not exist or is corrupt.
+    pub fn open<P: AsRef<Path>>(objects_dir: P) -> Result<CommitGraph, Error> {
+        crate::init();
+        let objects_dir = objects_dir.as_ref().into_c_string()?;
+        let mut out = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_commit_graph_open(&mut out, objects_dir));
+            Ok(CommitGraph { raw: out })
+        }
+    }
+}
+
+impl Drop for CommitGraph {
+    fn drop(&mut self) {
+        unsafe { raw::git_commit_graph_free(self.raw) }
+    }
+}
+
+impl CommitGraphWriter {
+    /// Create a writer for the commit-graph of an objects directory.
+    ///
+    /// `objects_info_dir` is the `info` directory of the objects directory,
+    /// such as `.git/objects/info`, in which the `commit-graph` file is
+    /// written.
+    pub fn new<P: AsRef<Path>>(objects_info_dir: P) -> Result<CommitGraphWriter, Error> {
+        crate::init();
+        let objects_info_dir = objects_info_dir.as_ref().into_c_string()?;
+        let mut out = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_commit_graph_writer_new(
+                &mut out,
+                objects_info_dir,
+                ptr::null()
+            ));
+            Ok(CommitGraphWriter { raw: out })
+        }
+    }
+
+    /// Add all the commits stored in a packfile to the graph.
+    ///
+    /// `idx_path` is the path of the index of the packfile.
+    pub fn add_index_file<P: AsRef<Path>>(
+        &mut self,
+        repo: &Repository,
+        idx_path: P,
+    ) -> Result<(), Error> {
+        let idx_path = idx_path.as_ref().into_c_string()?;
+        unsafe {
+            try_call!(raw::git_commit_graph_writer_add_index_file(
+                self.raw,
+                repo.raw(),
+                idx_path
+            ));
+        }
+        Ok(())
+    }
+
+    /// Add all the commits of a revision walk to the graph.
+    ///
+    /// The walk is consumed in the process.
+    pub fn add_revwalk(&mut self, walk: &mut Revwalk<'_>) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_commit_graph_writer_add_revwalk(
+                self.raw,
+                walk.raw()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the commit-graph file to the objects info directory, replacing
+    /// any existing one.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_commit_graph_writer_commit(self.raw));
+        }
+        Ok(())
+    }
+
+    /// Write the commit-graph to a buffer instead of the objects info
+    /// directory.
+    pub fn dump(&mut self) -> Result<Buf, Error> {
+        let buf = Buf::new();
+        unsafe {
+            try_call!(raw::git_commit_graph_writer_dump(buf.raw(), self.raw));
+        }
+        Ok(buf)
+    }
+}
+
+impl Drop for CommitGraphWriter {
+    fn drop(&mut self) {
+        unsafe { raw::git_commit_graph_writer_free(self.raw) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommitGraph, CommitGraphWriter};
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let objects = repo.path().join("objects");
+        assert!(CommitGraph::open(&objects).is_err());
+
+        let mut writer = t!(CommitGraphWriter::new(objects.join("info")));
+        let mut walk = t!(repo.revwalk());
+        t!(walk.push_head());
+        t!(writer.add_revwalk(&mut walk));
+        let buf = t!(writer.dump());
+        assert!(buf.starts_with(b"CGPH"));
+        t!(writer.commit());
+        t!(CommitGraph::open(&objects));
+    }
+}
@@ -5,7 +5,7 @@ use std::ptr;
 use std::str;
 
 use crate::util::{self, Binding};
-use crate::{raw, Buf, ConfigLevel, Error, IntoCString};
+use crate::{raw, Buf, ConfigLevel, ConfigSection, Error, FromConfig, IntoCString};
 
 /// A structure representing a git configuration key/value store
 pub struct Config {
@@ -307,6 +307,12 @@ impl Config {
         }
     }
 
+    /// Read a section of the configuration, such as `core` or
+    /// `remote.origin`, into a type implementing `FromConfig`.
+    pub fn get_section<T: FromConfig>(&self, name: &str) -> Result<T, Error> {
+        T::from_config(&ConfigSection::new(self, name))
+    }
+
     /// Iterate over all the config variables
     ///
     /// If `glob` is `Some`, then the iterator will only iterate over all
@@ -465,6 +471,67 @@ impl Config {
         }
     }
 
+    /// Update the configuration atomically.
+    ///
+    /// The highest-priority writable configuration file is locked, and the
+    /// changes made by `f` are only written to it, all at once, if `f`
+    /// returns successfully. If `f` fails or panics, none of them are
+    /// written. Other processes can't change the file in the meantime.
+    ///
+    /// Values set by `f` can't be read back before this returns.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Config) -> Result<T, Error>,
+    {
+        struct Lock(*mut raw::git_transaction);
+        impl Drop for Lock {
+            fn drop(&mut self) {
+                unsafe { raw::git_transaction_free(self.0) }
+            }
+        }
+
+        let mut tx = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_config_lock(&mut tx, self.raw));
+        }
+        let lock = Lock(tx);
+        let ret = f(self)?;
+        unsafe {
+            try_call!(raw::git_transaction_commit(lock.0));
+        }
+        Ok(ret)
+    }
+
+    /// Remove all the keys of a section, such as `branch.main`, returning
+    /// the names and values of the removed entries.
+    pub(crate) fn remove_section(&mut self, section: &str) -> Result<Vec<(String, String)>, Error> {
+        let mut pattern = String::from("^");
+        for c in section.chars() {
+            if "\\.^$*+?()[]{}|".contains(c) {
+                pattern.push('\\');
+            }
+            pattern.push(c);
+        }
+        // Keys have no dots, unlike subsections such as branch names.
+        pattern.push_str("\\.[^.]+$");
+        let mut removed = Vec::new();
+        let mut entries = self.entries(Some(&pattern))?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            if let (Some(name), Some(value)) = (entry.name(), entry.value()) {
+                removed.push((name.to_string(), value.to_string()));
+            }
+        }
+        drop(entries);
+        let mut names = removed.iter().map(|(name, _)| name).collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        for name in names {
+            self.remove_multivar(name, ".*")?;
+        }
+        Ok(removed)
+    }
+
     /// Parse a string as a bool.
     ///
     /// Interprets "true", "yes", "on", 1, or any non-zero number as true.
@@ -569,6 +636,28 @@ impl<'cfg> ConfigEntry<'cfg> {
     pub fn include_depth(&self) -> u32 {
         unsafe { (*self.raw).include_depth as u32 }
     }
+
+    /// Gets the path of the file this entry was read from, like
+    /// `git config --show-origin`.
+    ///
+    /// Returns `None` if the entry doesn't come from a file, or if the path
+    /// is not valid utf-8.
+    pub fn origin_path(&self) -> Option<&str> {
+        self.origin_path_bytes()
+            .and_then(|s| str::from_utf8(s).ok())
+    }
+
+    /// Gets the path of the file this entry was read from, as a byte slice.
+    pub fn origin_path_bytes(&self) -> Option<&[u8]> {
+        unsafe { crate::opt_bytes(self, (*self.raw).origin_path) }
+    }
+
+    /// Gets the type of the backend this entry was read from, such as
+    /// `file` or `memory`.
+    pub fn backend_type(&self) -> Option<&str> {
+        unsafe { crate::opt_bytes(self, (*self.raw).backend_type) }
+            .and_then(|s| str::from_utf8(s).ok())
+    }
 }
 
 impl<'cfg> Binding for ConfigEntry<'cfg> {
@@ -779,4 +868,52 @@ mod tests {
         assert_eq!(Config::parse_i64("1G").unwrap(), 1024 * 1024 * 1024);
         assert_eq!(Config::parse_i64("100G").unwrap(), 100 * 1024 * 1024 * 1024);
     }
+
+    #[test]
+    fn transaction() {
+        let td = TempDir::new().unwrap();
+        let path = td.path().join("foo");
+        File::create(&path).unwrap();
+        let mut cfg = Config::open(&path).unwrap();
+
+        cfg.transaction(|cfg| {
+            cfg.set_str("remote.origin.url", "https://example.com/repo")?;
+            cfg.set_str("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")
+        })
+        .unwrap();
+        let err = cfg
+            .transaction(|cfg| {
+                cfg.set_str("remote.other.url", "https://example.com/other")?;
+                Err::<(), _>(crate::Error::from_str("bad refspec"))
+            })
+            .unwrap_err();
+        assert_eq!(err.message(), "bad refspec");
+
+        let cfg = Config::open(&path).unwrap().snapshot().unwrap();
+        assert_eq!(
+            cfg.get_str("remote.origin.url").unwrap(),
+            "https://example.com/repo"
+        );
+        assert!(cfg.get_str("remote.origin.fetch").is_ok());
+        assert!(cfg.get_str("remote.other.url").is_err());
+    }
+
+    #[test]
+    fn entry_origin() {
+        let td = TempDir::new().unwrap();
+        let path = td.path().join("config");
+        let included = td.path().join("included");
+        std::fs::write(&included, "[foo]\n\tincluded = 1\n").unwrap();
+        std::fs::write(&path, "[foo]\n\tbar = 1\n[include]\n\tpath = included\n").unwrap();
+        let cfg = Config::open(&path).unwrap();
+
+        let entry = cfg.get_entry("foo.bar").unwrap();
+        assert_eq!(entry.backend_type(), Some("file"));
+        assert_eq!(entry.origin_path(), Some(path.to_str().unwrap()));
+        assert_eq!(entry.include_depth(), 0);
+
+        let entry = cfg.get_entry("foo.included").unwrap();
+        assert!(entry.origin_path().unwrap().ends_with("included"));
+        assert_eq!(entry.include_depth(), 1);
+    }
 }
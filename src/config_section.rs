@@ -0,0 +1,390 @@
+use std::path::PathBuf;
+
+use crate::{Config, Error, ErrorClass, ErrorCode};
+
+/// A type which can be read from a section of the configuration, with
+/// `Config::get_section`.
+///
+/// ```
+/// use git2::{Config, ConfigSection, Error, FromConfig};
+///
+/// struct Core {
+///     bare: bool,
+///     abbrev: i64,
+///     editor: Option<String>,
+/// }
+///
+/// impl FromConfig for Core {
+///     fn from_config(section: &ConfigSection<'_>) -> Result<Core, Error> {
+///         Ok(Core {
+///             bare: section.bool("bare")?.unwrap_or(false),
+///             abbrev: section.i64("abbrev")?.unwrap_or(7),
+///             editor: section.string("editor")?,
+///         })
+///     }
+/// }
+///
+/// let config = Config::new()?;
+/// let core: Core = config.get_section("core")?;
+/// assert_eq!(core.abbrev, 7);
+/// # Ok::<(), Error>(())
+/// ```
+pub trait FromConfig: Sized {
+    /// Read the value from a section of the configuration.
+    fn from_config(section: &ConfigSection<'_>) -> Result<Self, Error>;
+}
+
+/// A section of the configuration, such as `core` or `remote.origin`, from
+/// which typed values are read.
+///
+/// Each getter returns `None` if the key is not set, so that defaults can
+/// be applied with `unwrap_or`, and fails with an error naming the key if
+/// the value can't be converted to the requested type.
+pub struct ConfigSection<'cfg> {
+    config: &'cfg Config,
+    name: String,
+}
+
+/// A color, as configured by `color.*` settings such as `red bold` or
+/// `#ff0000 ul`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigColor {
+    foreground: Option<ConfigColorValue>,
+    background: Option<ConfigColorValue>,
+    attributes: Vec<String>,
+}
+
+/// One of the colors of a `ConfigColor`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigColorValue {
+    /// `normal`, leaving the color unchanged.
+    Normal,
+    /// `default`, the default color of the terminal.
+    Default,
+    /// A color of the 256-color ANSI palette. The named colors `black` to
+    /// `white` are 0 to 7 and their `bright` variants 8 to 15.
+    Ansi(u8),
+    /// A 24-bit color, written `#rrggbb` or `#rgb`.
+    Rgb(u8, u8, u8),
+}
+
+fn invalid(key: &str, value: &str, what: &str) -> Error {
+    Error::new(
+        ErrorCode::Invalid,
+        ErrorClass::Config,
+        format!("invalid {} '{}' for config key '{}'", what, value, key),
+    )
+}
+
+impl<'cfg> ConfigSection<'cfg> {
+    /// Create a reader for the section `name` of `config`.
+    pub fn new(config: &'cfg Config, name: &str) -> ConfigSection<'cfg> {
+        ConfigSection {
+            config,
+            name: name.to_string(),
+        }
+    }
+
+    /// The name of the section.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The full name of a key of the section.
+    pub fn key(&self, key: &str) -> String {
+        format!("{}.{}", self.name, key)
+    }
+
+    /// Read a string.
+    pub fn string(&self, key: &str) -> Result<Option<String>, Error> {
+        let key = self.key(key);
+        let entry = match self.config.get_entry(&key) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !entry.has_value() {
+            return Ok(Some(String::new()));
+        }
+        match entry.value() {
+            Some(value) => Ok(Some(value.to_string())),
+            None => Err(invalid(&key, "<non-utf8>", "string")),
+        }
+    }
+
+    /// Read all the values of a key which can be set several times, such as
+    /// `remote.origin.fetch`.
+    pub fn strings(&self, key: &str) -> Result<Vec<String>, Error> {
+        let key = self.key(key);
+        let mut values = Vec::new();
+        let mut entries = self.config.multivar(&key, None)?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            if !entry.has_value() {
+                values.push(String::new());
+                continue;
+            }
+            match entry.value() {
+                Some(value) => values.push(value.to_string()),
+                None => return Err(invalid(&key, "<non-utf8>", "string")),
+            }
+        }
+        Ok(values)
+    }
+
+    /// Read a boolean, such as `true`, `no` or `1`. A key without a value is
+    /// true.
+    pub fn bool(&self, key: &str) -> Result<Option<bool>, Error> {
+        self.parse(key, "boolean", |s| Config::parse_bool(s))
+    }
+
+    /// Read an integer, with an optional `k`, `m` or `g` suffix.
+    pub fn i64(&self, key: &str) -> Result<Option<i64>, Error> {
+        self.parse(key, "integer", |s| Config::parse_i64(s))
+    }
+
+    /// Read an integer fitting in 32 bits, with an optional `k`, `m` or `g`
+    /// suffix.
+    pub fn i32(&self, key: &str) -> Result<Option<i32>, Error> {
+        self.parse(key, "integer", |s| Config::parse_i32(s))
+    }
+
+    /// Read a path, expanding a leading `~`.
+    pub fn path(&self, key: &str) -> Result<Option<PathBuf>, Error> {
+        let full = self.key(key);
+        let value = match self.raw(key)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match self.config.get_path(&full) {
+            Ok(path) => Ok(Some(path)),
+            // libgit2 reports paths it cannot expand as config errors.
+            Err(e) if e.class() == ErrorClass::Config => Err(invalid(&full, &value, "path")),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a color.
+    pub fn color(&self, key: &str) -> Result<Option<ConfigColor>, Error> {
+        let full = self.key(key);
+        match self.raw(key)? {
+            Some(value) => match ConfigColor::parse(&value) {
+                Some(color) => Ok(Some(color)),
+                None => Err(invalid(&full, &value, "color")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Read the value of a key as it is written, or `None` if it is not set.
+    /// A key without a value reads as `true`.
+    fn raw(&self, key: &str) -> Result<Option<String>, Error> {
+        let full = self.key(key);
+        let entry = match self.config.get_entry(&full) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !entry.has_value() {
+            return Ok(Some("true".to_string()));
+        }
+        match entry.value() {
+            Some(value) => Ok(Some(value.to_string())),
+            None => Err(invalid(&full, "<non-utf8>", "value")),
+        }
+    }
+
+    fn parse<T>(
+        &self,
+        key: &str,
+        what: &str,
+        parse: impl Fn(&str) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        match self.raw(key)? {
+            Some(value) => match parse(&value) {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(invalid(&self.key(key), &value, what)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl ConfigColor {
+    /// Parse a color as written in the configuration, such as `red`,
+    /// `bold blue black`, `#ff8800 ul` or `nobold`.
+    ///
+    /// The first color is the foreground and the second one the background.
+    /// Returns `None` if the color is invalid.
+    pub fn parse(s: &str) -> Option<ConfigColor> {
+        const ATTRIBUTES: &[&str] = &["bold", "dim", "ul", "blink", "reverse", "italic", "strike"];
+        let mut color = ConfigColor {
+            foreground: None,
+            background: None,
+            attributes: Vec::new(),
+        };
+        for word in s.split_whitespace() {
+            let word = word.to_ascii_lowercase();
+            if let Some(value) = ConfigColorValue::parse(&word) {
+                if color.foreground.is_none() {
+                    color.foreground = Some(value);
+                } else if color.background.is_none() {
+                    color.background = Some(value);
+                } else {
+                    return None;
+                }
+                continue;
+            }
+            let attribute = word
+                .strip_prefix("no-")
+                .or_else(|| word.strip_prefix("no"))
+                .unwrap_or(&word);
+            if !ATTRIBUTES.contains(&attribute) {
+                return None;
+            }
+            color.attributes.push(word);
+        }
+        Some(color)
+    }
+
+    /// The foreground color, if any.
+    pub fn foreground(&self) -> Option<ConfigColorValue> {
+        self.foreground
+    }
+
+    /// The background color, if any.
+    pub fn background(&self) -> Option<ConfigColorValue> {
+        self.background
+    }
+
+    /// The attributes of the color, such as `bold` or `noul`, lowercased.
+    pub fn attributes(&self) -> &[String] {
+        &self.attributes
+    }
+}
+
+impl ConfigColorValue {
+    fn parse(s: &str) -> Option<ConfigColorValue> {
+        const NAMES: &[&str] = &[
+            "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+        ];
+        match s {
+            "normal" => return Some(ConfigColorValue::Normal),
+            "default" => return Some(ConfigColorValue::Default),
+            _ => {}
+        }
+        if let Some(hex) = s.strip_prefix('#') {
+            let digits = hex
+                .chars()
+                .map(|c| c.to_digit(16).map(|d| d as u8))
+                .collect::<Option<Vec<_>>>()?;
+            return match digits[..] {
+                [r1, r2, g1, g2, b1, b2] => Some(ConfigColorValue::Rgb(
+                    r1 << 4 | r2,
+                    g1 << 4 | g2,
+                    b1 << 4 | b2,
+                )),
+                // `#rgb` is short for `#rrggbb`.
+                [r, g, b] => Some(ConfigColorValue::Rgb(r * 0x11, g * 0x11, b * 0x11)),
+                _ => None,
+            };
+        }
+        if let Ok(n) = s.parse::<u8>() {
+            return Some(ConfigColorValue::Ansi(n));
+        }
+        let (name, offset) = match s.strip_prefix("bright") {
+            Some(name) => (name, 8),
+            None => (s, 0),
+        };
+        let i = NAMES.iter().position(|n| *n == name)?;
+        Some(ConfigColorValue::Ansi(i as u8 + offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigColor, ConfigColorValue, ConfigSection, FromConfig};
+    use crate::{Config, Error};
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    struct Remote {
+        url: String,
+        fetch: Vec<String>,
+        prune: bool,
+        timeout: i64,
+        key: Option<PathBuf>,
+    }
+
+    impl FromConfig for Remote {
+        fn from_config(section: &ConfigSection<'_>) -> Result<Remote, Error> {
+            Ok(Remote {
+                url: section.string("url")?.unwrap_or_default(),
+                fetch: section.strings("fetch")?,
+                prune: section.bool("prune")?.unwrap_or(false),
+                timeout: section.i64("timeout")?.unwrap_or(30),
+                key: section.path("key")?,
+            })
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let td = TempDir::new().unwrap();
+        let path = td.path().join("config");
+        fs::write(
+            &path,
+            "[remote \"origin\"]\n\
+             \turl = https://example.com/repo\n\
+             \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+             \tfetch = +refs/tags/*:refs/tags/*\n\
+             \tprune\n\
+             \ttimeout = 2k\n\
+             [remote \"bad\"]\n\
+             \tprune = maybe\n\
+             [color \"diff\"]\n\
+             \tmeta = \"bold #ff8800 brightblue\"\n\
+             \told = purple\n",
+        )
+        .unwrap();
+        let cfg = Config::open(&path).unwrap().snapshot().unwrap();
+
+        let remote: Remote = cfg.get_section("remote.origin").unwrap();
+        assert_eq!(remote.url, "https://example.com/repo");
+        assert_eq!(remote.fetch.len(), 2);
+        assert!(remote.prune);
+        assert_eq!(remote.timeout, 2048);
+        assert_eq!(remote.key, None);
+
+        let err = cfg.get_section::<Remote>("remote.bad").err().unwrap();
+        assert!(err.message().contains("'remote.bad.prune'"));
+
+        let section = ConfigSection::new(&cfg, "color.diff");
+        let meta = section.color("meta").unwrap().unwrap();
+        assert_eq!(
+            meta.foreground(),
+            Some(ConfigColorValue::Rgb(0xff, 0x88, 0))
+        );
+        assert_eq!(meta.background(), Some(ConfigColorValue::Ansi(12)));
+        assert_eq!(meta.attributes(), ["bold".to_string()]);
+        assert!(section.color("old").is_err());
+        assert_eq!(section.color("new").unwrap(), None);
+        assert!(ConfigColor::parse("red blue green").is_none());
+        assert_eq!(
+            ConfigColor::parse("nobold 208").unwrap().foreground(),
+            Some(ConfigColorValue::Ansi(208))
+        );
+        assert_eq!(
+            ConfigColor::parse("#FF8800").unwrap().foreground(),
+            Some(ConfigColorValue::Rgb(0xff, 0x88, 0))
+        );
+        assert_eq!(
+            ConfigColor::parse("#f80 #0a0").unwrap().background(),
+            Some(ConfigColorValue::Rgb(0, 0xaa, 0))
+        );
+        for invalid in &["#", "#ff88", "#ff880", "#ff88000", "#gg8800", "#+f8800"] {
+            assert!(ConfigColor::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+}
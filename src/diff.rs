@@ -1,16 +1,17 @@
 use libc::{c_char, c_int, c_void, size_t};
-use std::ffi::CString;
+use std::any::Any;
+use std::ffi::{CStr, CString};
 use std::iter::FusedIterator;
 use std::marker;
 use std::mem;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::slice;
 
 use crate::util::{self, Binding};
 use crate::{panic, raw, Buf, Delta, DiffFormat, Error, FileMode, Oid, Repository};
-use crate::{DiffFlags, DiffStatsFormat, IntoCString};
+use crate::{DiffFlags, DiffStatsFormat, IntoCString, Patch};
 
 /// The diff object that contains all individual file deltas.
 ///
@@ -46,12 +47,58 @@ pub struct DiffOptions {
     pathspec_ptrs: Vec<*const c_char>,
     old_prefix: Option<CString>,
     new_prefix: Option<CString>,
+    callbacks: Option<Box<DiffOptionsCallbacks>>,
     raw: raw::git_diff_options,
 }
 
+/// A callback used to filter the deltas of a diff as it is generated.
+///
+/// Used by `DiffOptions::notify_callback`. The first argument is the delta
+/// about to be added to the diff and the second is the pathspec that matched
+/// it, if any. Return 0 to include the delta, > 0 to skip it, and < 0 to
+/// abort generating the diff.
+pub type DiffNotifyCb = dyn FnMut(DiffDelta<'_>, Option<&[u8]>) -> i32;
+
+/// A callback used to report progress while a diff is generated.
+///
+/// Used by `DiffOptions::progress_callback`. The arguments are the old and new
+/// paths of the file being examined. Return `false` to abort generating the
+/// diff.
+pub type DiffProgressCb = dyn FnMut(Option<&Path>, Option<&Path>) -> bool;
+
+#[derive(Default)]
+struct DiffOptionsCallbacks {
+    notify: Option<Box<DiffNotifyCb>>,
+    progress: Option<Box<DiffProgressCb>>,
+}
+
 /// Control behavior of rename and copy detection
 pub struct DiffFindOptions {
     raw: raw::git_diff_find_options,
+    metric: Option<Box<SimilarityMetric>>,
+}
+
+type SignatureCb = dyn FnMut(&DiffFile<'_>, &[u8]) -> Box<dyn Any>;
+type SimilarityCb = dyn FnMut(&dyn Any, &dyn Any) -> u8;
+
+struct SimilarityMetric {
+    raw: raw::git_diff_similarity_metric,
+    signature: Box<SignatureCb>,
+    similarity: Box<SimilarityCb>,
+}
+
+/// Insertion and deletion counts for a single file in a diff.
+///
+/// Produced by `Diff::file_stats`, this holds the same data as a line of
+/// `git diff --numstat`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffFileStats {
+    old_path: Option<PathBuf>,
+    new_path: Option<PathBuf>,
+    status: Delta,
+    insertions: usize,
+    deletions: usize,
+    binary: bool,
 }
 
 /// Control behavior of formatting emails
@@ -182,6 +229,43 @@ impl<'repo> Diff<'repo> {
         }
     }
 
+    /// Select the deltas for which `f` returns `true`, along with their
+    /// patches.
+    ///
+    /// This narrows an already computed diff without diffing again, so one
+    /// diff can serve several views. libgit2 cannot remove deltas from a diff,
+    /// so the diff itself is left unchanged; the selected deltas are returned
+    /// in order instead, each with its patch, which is `None` for deltas
+    /// without text content such as binary or unmodified files.
+    pub fn filter_deltas<F>(
+        &self,
+        mut f: F,
+    ) -> Result<Vec<(DiffDelta<'_>, Option<Patch<'repo>>)>, Error>
+    where
+        F: FnMut(&DiffDelta<'_>) -> bool,
+    {
+        let mut ret = Vec::new();
+        for (idx, delta) in self.deltas().enumerate() {
+            if f(&delta) {
+                ret.push((delta, Patch::from_diff(self, idx)?));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Produce the text of a diff in the given format as a Buf.
+    ///
+    /// For binary files to be included in a patch in a way that can be
+    /// applied again, the diff must have been created with
+    /// `DiffOptions::show_binary` set.
+    pub fn to_buf(&self, format: DiffFormat) -> Result<Buf, Error> {
+        let buf = Buf::new();
+        unsafe {
+            try_call!(raw::git_diff_to_buf(buf.raw(), self.raw, format));
+        }
+        Ok(buf)
+    }
+
     /// Loop over all deltas in a diff issuing callbacks.
     ///
     /// Returning `false` from any callback will terminate the iteration and
@@ -238,6 +322,24 @@ impl<'repo> Diff<'repo> {
         }
     }
 
+    /// Accumulate insertion and deletion counts for each file in the diff.
+    ///
+    /// Unlike `stats`, which only offers totals, this returns one entry per
+    /// delta, in the same order as `deltas`.
+    pub fn file_stats(&self) -> Result<Vec<DiffFileStats>, Error> {
+        let mut stats = Vec::with_capacity(self.deltas().len());
+        for i in 0..self.deltas().len() {
+            match Patch::from_diff(self, i)? {
+                Some(patch) => stats.push(DiffFileStats::from_patch(&patch)?),
+                None => {
+                    let delta = self.get_delta(i).unwrap();
+                    stats.push(DiffFileStats::new(&delta, 0, 0));
+                }
+            }
+        }
+        Ok(stats)
+    }
+
     /// Transform a diff marking file renames, copies, etc.
     ///
     /// This modifies a diff in place, replacing old entries that look like
@@ -312,6 +414,10 @@ impl Diff<'static> {
     /// two trees, however there may be subtle differences. For example,
     /// a patch file likely contains abbreviated object IDs, so the
     /// object IDs parsed by this function will also be abbreviated.
+    ///
+    /// Any text before the first file header is skipped, so the output of
+    /// `git format-patch` can be parsed directly. The resulting diff can be
+    /// applied with [`Repository::apply`](crate::Repository::apply).
     pub fn from_buffer(buffer: &[u8]) -> Result<Diff<'static>, Error> {
         crate::init();
         let mut diff: *mut raw::git_diff = std::ptr::null_mut();
@@ -498,10 +604,13 @@ impl<'a> DiffDelta<'a> {
         result
     }
 
-    // TODO: expose when diffs are more exposed
-    // pub fn similarity(&self) -> u16 {
-    //     unsafe { (*self.raw).similarity }
-    // }
+    /// Returns the similarity score of this delta, from 0 to 100.
+    ///
+    /// This is only meaningful for renamed and copied deltas, where it records
+    /// how similar the old and new files were found to be.
+    pub fn similarity(&self) -> u16 {
+        unsafe { (*self.raw).similarity }
+    }
 
     /// Returns the number of files in this delta.
     pub fn nfiles(&self) -> u16 {
@@ -578,6 +687,15 @@ impl<'a> DiffFile<'a> {
         unsafe { Binding::from_raw(&(*self.raw).id as *const _) }
     }
 
+    /// Returns the number of hex digits of the Oid that are known.
+    ///
+    /// Diffs parsed from a patch with `Diff::from_buffer` usually only carry
+    /// abbreviated object ids, in which case the rest of the Oid returned by
+    /// `id` is zeroes.
+    pub fn id_abbrev(&self) -> u16 {
+        unsafe { (*self.raw).id_abbrev }
+    }
+
     /// Returns the path, in bytes, of the entry relative to the working
     /// directory of the repository.
     pub fn path_bytes(&self) -> Option<&'a [u8]> {
@@ -676,6 +794,7 @@ impl DiffOptions {
             raw: unsafe { mem::zeroed() },
             old_prefix: None,
             new_prefix: None,
+            callbacks: None,
         };
         assert_eq!(unsafe { raw::git_diff_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -853,6 +972,10 @@ impl DiffOptions {
 
     /// Include the necessary deflate/delta information so that `git-apply` can
     /// apply given diff information to binary files.
+    ///
+    /// Patches printed from such a diff contain `GIT binary patch` sections,
+    /// which `Diff::from_buffer` parses back so that the changes can be
+    /// applied with `Repository::apply`.
     pub fn show_binary(&mut self, show: bool) -> &mut DiffOptions {
         self.flag(raw::GIT_DIFF_SHOW_BINARY, show)
     }
@@ -943,7 +1066,94 @@ impl DiffOptions {
         &self.raw as *const _
     }
 
-    // TODO: expose ignore_submodules, notify_cb/notify_payload
+    /// Set a callback invoked for each delta before it is added to the diff.
+    ///
+    /// This can be used to skip specific deltas while the diff is generated;
+    /// see `DiffNotifyCb` for the meaning of the return value. Aborting fails
+    /// the diff with an error whose code is `ErrorCode::User`.
+    pub fn notify_callback<F>(&mut self, cb: F) -> &mut DiffOptions
+    where
+        F: FnMut(DiffDelta<'_>, Option<&[u8]>) -> i32 + 'static,
+    {
+        self.callbacks().notify = Some(Box::new(cb));
+        self.raw.notify_cb = Some(diff_notify_cb);
+        self
+    }
+
+    /// Set a callback invoked as each file is examined while the diff is
+    /// generated, for example to show progress on diffs of large trees.
+    ///
+    /// Returning `false` aborts the diff with an error whose code is
+    /// `ErrorCode::User`.
+    pub fn progress_callback<F>(&mut self, cb: F) -> &mut DiffOptions
+    where
+        F: FnMut(Option<&Path>, Option<&Path>) -> bool + 'static,
+    {
+        self.callbacks().progress = Some(Box::new(cb));
+        self.raw.progress_cb = Some(diff_progress_cb);
+        self
+    }
+
+    fn callbacks(&mut self) -> &mut DiffOptionsCallbacks {
+        let callbacks = self.callbacks.get_or_insert_with(Default::default);
+        self.raw.payload = &mut **callbacks as *mut DiffOptionsCallbacks as *mut c_void;
+        callbacks
+    }
+
+    // TODO: expose ignore_submodules
+}
+
+extern "C" fn diff_notify_cb(
+    _diff: *const raw::git_diff,
+    delta: *const raw::git_diff_delta,
+    matched_pathspec: *const c_char,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let callbacks = &mut *(payload as *mut DiffOptionsCallbacks);
+        let cb = match callbacks.notify {
+            Some(ref mut cb) => cb,
+            None => return 0,
+        };
+        let matched = if matched_pathspec.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(matched_pathspec).to_bytes())
+        };
+        match cb(Binding::from_raw(delta as *mut _), matched) {
+            n if n < 0 => raw::GIT_EUSER,
+            n => n,
+        }
+    })
+    .unwrap_or(raw::GIT_EUSER)
+}
+
+extern "C" fn diff_progress_cb(
+    _diff: *const raw::git_diff,
+    old_path: *const c_char,
+    new_path: *const c_char,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let callbacks = &mut *(payload as *mut DiffOptionsCallbacks);
+        let cb = match callbacks.progress {
+            Some(ref mut cb) => cb,
+            None => return 0,
+        };
+        let path = |p: *const c_char| {
+            if p.is_null() {
+                None
+            } else {
+                Some(util::bytes2path(CStr::from_ptr(p).to_bytes()))
+            }
+        };
+        if cb(path(old_path), path(new_path)) {
+            0
+        } else {
+            raw::GIT_EUSER
+        }
+    })
+    .unwrap_or(raw::GIT_EUSER)
 }
 
 impl<'diff> Iterator for Deltas<'diff> {
@@ -1207,6 +1417,56 @@ impl DiffStats {
     }
 }
 
+impl DiffFileStats {
+    pub(crate) fn new(delta: &DiffDelta<'_>, insertions: usize, deletions: usize) -> DiffFileStats {
+        DiffFileStats {
+            old_path: delta.old_file().path().map(|p| p.to_path_buf()),
+            new_path: delta.new_file().path().map(|p| p.to_path_buf()),
+            status: delta.status(),
+            insertions,
+            deletions,
+            binary: delta.flags().is_binary(),
+        }
+    }
+
+    pub(crate) fn from_patch(patch: &Patch<'_>) -> Result<DiffFileStats, Error> {
+        let (_, insertions, deletions) = patch.line_stats()?;
+        // Generating the patch loads the file contents, which is what
+        // determines whether the delta is binary.
+        Ok(DiffFileStats::new(&patch.delta(), insertions, deletions))
+    }
+
+    /// Path of the file on the old side of the diff, if any.
+    pub fn old_path(&self) -> Option<&Path> {
+        self.old_path.as_deref()
+    }
+
+    /// Path of the file on the new side of the diff, if any.
+    pub fn new_path(&self) -> Option<&Path> {
+        self.new_path.as_deref()
+    }
+
+    /// Status of the file, which tells whether it was renamed or copied.
+    pub fn status(&self) -> Delta {
+        self.status
+    }
+
+    /// Number of lines added to the file.
+    pub fn insertions(&self) -> usize {
+        self.insertions
+    }
+
+    /// Number of lines removed from the file.
+    pub fn deletions(&self) -> usize {
+        self.deletions
+    }
+
+    /// Whether the file is binary, in which case no lines are counted.
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+}
+
 impl Binding for DiffStats {
     type Raw = *mut raw::git_diff_stats;
 
@@ -1334,6 +1594,7 @@ impl DiffFindOptions {
     pub fn new() -> DiffFindOptions {
         let mut opts = DiffFindOptions {
             raw: unsafe { mem::zeroed() },
+            metric: None,
         };
         assert_eq!(
             unsafe { raw::git_diff_find_init_options(&mut opts.raw, 1) },
@@ -1486,7 +1747,45 @@ impl DiffFindOptions {
         self
     }
 
-    // TODO: expose git_diff_similarity_metric
+    /// Use a custom similarity metric for rename and copy detection.
+    ///
+    /// `signature` is called once for each file considered, with the file's
+    /// contents, to compute a signature of any type. `similarity` is then
+    /// called with pairs of signatures and should return a score between 0
+    /// (completely different) and 100 (identical); larger values are clamped
+    /// to 100. The scores are compared against the thresholds configured on
+    /// these options.
+    pub fn similarity_metric<T, S, F>(
+        &mut self,
+        mut signature: S,
+        mut similarity: F,
+    ) -> &mut DiffFindOptions
+    where
+        T: 'static,
+        S: FnMut(&DiffFile<'_>, &[u8]) -> T + 'static,
+        F: FnMut(&T, &T) -> u8 + 'static,
+    {
+        let mut metric = Box::new(SimilarityMetric {
+            raw: raw::git_diff_similarity_metric {
+                file_signature: Some(file_signature_cb),
+                buffer_signature: Some(buffer_signature_cb),
+                free_signature: Some(free_signature_cb),
+                similarity: Some(similarity_cb),
+                payload: ptr::null_mut(),
+            },
+            signature: Box::new(move |file, content| Box::new(signature(file, content))),
+            similarity: Box::new(move |a, b| {
+                match (a.downcast_ref::<T>(), b.downcast_ref::<T>()) {
+                    (Some(a), Some(b)) => similarity(a, b),
+                    _ => 0,
+                }
+            }),
+        });
+        metric.raw.payload = &mut *metric as *mut SimilarityMetric as *mut c_void;
+        self.raw.metric = &mut metric.raw;
+        self.metric = Some(metric);
+        self
+    }
 
     /// Acquire a pointer to the underlying raw options.
     pub unsafe fn raw(&mut self) -> *const raw::git_diff_find_options {
@@ -1494,6 +1793,79 @@ impl DiffFindOptions {
     }
 }
 
+fn store_signature(
+    out: *mut *mut c_void,
+    file: *const raw::git_diff_file,
+    content: &[u8],
+    payload: *mut c_void,
+) {
+    unsafe {
+        let metric = &mut *(payload as *mut SimilarityMetric);
+        let signature = (metric.signature)(&Binding::from_raw(file), content);
+        *out = Box::into_raw(Box::new(signature)) as *mut c_void;
+    }
+}
+
+extern "C" fn file_signature_cb(
+    out: *mut *mut c_void,
+    file: *const raw::git_diff_file,
+    fullpath: *const c_char,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let path = util::bytes2path(CStr::from_ptr(fullpath).to_bytes());
+        match std::fs::read(path) {
+            Ok(content) => {
+                store_signature(out, file, &content, payload);
+                0
+            }
+            Err(_) => -1,
+        }
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn buffer_signature_cb(
+    out: *mut *mut c_void,
+    file: *const raw::git_diff_file,
+    buf: *const c_char,
+    buflen: size_t,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let content = if buflen == 0 {
+            &[][..]
+        } else {
+            slice::from_raw_parts(buf as *const u8, buflen)
+        };
+        store_signature(out, file, content, payload);
+        0
+    })
+    .unwrap_or(-1)
+}
+
+extern "C" fn free_signature_cb(sig: *mut c_void, _payload: *mut c_void) {
+    if !sig.is_null() {
+        unsafe { drop(Box::from_raw(sig as *mut Box<dyn Any>)) }
+    }
+}
+
+extern "C" fn similarity_cb(
+    score: *mut c_int,
+    siga: *mut c_void,
+    sigb: *mut c_void,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let metric = &mut *(payload as *mut SimilarityMetric);
+        let a = &**(siga as *const Box<dyn Any>);
+        let b = &**(sigb as *const Box<dyn Any>);
+        *score = (metric.similarity)(a, b).min(100) as c_int;
+        0
+    })
+    .unwrap_or(-1)
+}
+
 impl Default for DiffFormatEmailOptions {
     fn default() -> Self {
         Self::new()
@@ -1558,7 +1930,7 @@ mod tests {
     use std::borrow::Borrow;
     use std::fs::File;
     use std::io::Write;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     #[test]
     fn smoke() {
@@ -1860,4 +2232,212 @@ mod tests {
 
         assert_eq!(result.unwrap_err().code(), crate::ErrorCode::User);
     }
+
+    #[test]
+    fn filter_deltas() {
+        let (td, repo) = crate::test::repo_init();
+        for name in &["a", "b", "c"] {
+            t!(t!(File::create(&td.path().join(name))).write_all(name.as_bytes()));
+        }
+        t!(t!(File::create(&td.path().join("d"))).write_all(b"\0binary"));
+        let mut opts = DiffOptions::new();
+        opts.include_untracked(true).show_untracked_content(true);
+        let diff = t!(repo.diff_index_to_workdir(None, Some(&mut opts)));
+        assert_eq!(diff.deltas().len(), 4);
+
+        let selected =
+            t!(diff.filter_deltas(|delta| delta.new_file().path() != Some(Path::new("b"))));
+        let paths = selected
+            .iter()
+            .map(|(d, _)| d.new_file().path().unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [PathBuf::from("a"), PathBuf::from("c"), PathBuf::from("d")]
+        );
+        assert!(selected
+            .iter()
+            .all(|(d, _)| d.status() == crate::Delta::Untracked));
+        assert_eq!(t!(selected[0].1.as_ref().unwrap().line_stats()).1, 1);
+        assert_eq!(diff.deltas().len(), 4);
+
+        assert!(t!(diff.filter_deltas(|_| false)).is_empty());
+    }
+
+    #[test]
+    fn binary_patch_round_trip() {
+        let (td, repo) = crate::test::repo_init();
+        let original = (0..=255u8).cycle().take(2048).collect::<Vec<u8>>();
+        let mut modified = original.clone();
+        modified[100] = 0;
+        modified.extend_from_slice(&[0, 1, 2, 3]);
+        let bin_path = Path::new("bin");
+        t!(t!(File::create(&td.path().join(bin_path))).write_all(&original));
+        let mut index = t!(repo.index());
+        t!(index.add_path(bin_path));
+        t!(index.write());
+
+        t!(t!(File::create(&td.path().join(bin_path))).write_all(&modified));
+        let mut opts = DiffOptions::new();
+        opts.show_binary(true);
+        let diff = t!(repo.diff_index_to_workdir(None, Some(&mut opts)));
+        let text = t!(diff.to_buf(crate::DiffFormat::Patch));
+        assert!(text.as_str().unwrap().contains("GIT binary patch"));
+        let mut patch = t!(crate::Patch::from_diff(&diff, 0)).unwrap();
+        assert_eq!(&*t!(patch.to_buf()), &*text);
+
+        t!(t!(File::create(&td.path().join(bin_path))).write_all(&original));
+        let parsed = t!(crate::Diff::from_buffer(&text));
+        t!(repo.apply(&parsed, crate::ApplyLocation::WorkDir, None));
+        assert_eq!(t!(std::fs::read(td.path().join(bin_path))), modified);
+    }
+
+    #[test]
+    fn from_buffer_patch_email() {
+        let path = Path::new("foo");
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(File::create(&td.path().join(path))).write_all(b"a\nb\nc\n"));
+
+        let email = "\
+From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001
+From: Someone <someone@example.com>
+Subject: [PATCH] change foo
+
+---
+ foo | 2 +-
+ 1 file changed, 1 insertion(+), 1 deletion(-)
+
+diff --git a/foo b/foo
+--- a/foo
++++ b/foo
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+--
+2.40.0
+";
+        let diff = t!(crate::Diff::from_buffer(email.as_bytes()));
+        assert_eq!(diff.deltas().len(), 1);
+        assert_eq!(diff.get_delta(0).unwrap().new_file().path(), Some(path));
+        t!(repo.apply(&diff, crate::ApplyLocation::WorkDir, None));
+        assert_eq!(t!(std::fs::read(td.path().join(path))), b"a\nB\nc\n");
+    }
+
+    #[test]
+    fn similarity_metric() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree_with = |name: &str, content: &[u8]| {
+            let blob = t!(repo.blob(content));
+            let mut builder = t!(repo.treebuilder(None));
+            t!(builder.insert(name, blob, 0o100644));
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let old = tree_with("a.rs", b"// header\nfn old() {}\n");
+        let new = tree_with(
+            "b.rs",
+            b"// header\nstruct Completely;\nimpl Different {}\n",
+        );
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        let mut opts = crate::DiffFindOptions::new();
+        opts.renames(true);
+        t!(diff.find_similar(Some(&mut opts)));
+        assert_eq!(diff.deltas().len(), 2);
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        opts.similarity_metric(
+            |_file, content| {
+                content
+                    .split(|b| *b == b'\n')
+                    .next()
+                    .unwrap_or(&[])
+                    .to_vec()
+            },
+            |a: &Vec<u8>, b: &Vec<u8>| if a == b { 100 } else { 0 },
+        );
+        t!(diff.find_similar(Some(&mut opts)));
+        assert_eq!(diff.deltas().len(), 1);
+        let delta = diff.get_delta(0).unwrap();
+        assert_eq!(delta.status(), crate::Delta::Renamed);
+        assert_eq!(delta.similarity(), 100);
+    }
+
+    #[test]
+    fn notify_and_progress_callbacks() {
+        let (td, repo) = crate::test::repo_init();
+        for name in ["a", "b", "c"].iter() {
+            t!(t!(File::create(&td.path().join(name))).write_all(b"foo\n"));
+        }
+
+        let progress = std::rc::Rc::new(std::cell::Cell::new(0));
+        let calls = progress.clone();
+        let mut opts = DiffOptions::new();
+        opts.include_untracked(true)
+            .notify_callback(|delta: crate::DiffDelta<'_>, _| {
+                if delta.new_file().path() == Some(Path::new("b")) {
+                    1
+                } else {
+                    0
+                }
+            })
+            .progress_callback(move |_old, new| {
+                assert!(new.is_some());
+                calls.set(calls.get() + 1);
+                true
+            });
+        let diff = t!(repo.diff_index_to_workdir(None, Some(&mut opts)));
+        let paths = diff
+            .deltas()
+            .map(|d| d.new_file().path().unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [PathBuf::from("a"), PathBuf::from("c")]);
+        assert!(progress.get() > 0);
+
+        let mut opts = DiffOptions::new();
+        opts.include_untracked(true).notify_callback(|_, _| -1);
+        let result = repo.diff_index_to_workdir(None, Some(&mut opts));
+        match result {
+            Ok(_) => panic!("diff should have been aborted"),
+            Err(e) => assert_eq!(e.code(), crate::ErrorCode::User),
+        }
+    }
+
+    #[test]
+    fn file_stats() {
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(File::create(&td.path().join("a"))).write_all(b"1\n2\n3\n4\n5\n6\n"));
+        t!(t!(File::create(&td.path().join("b"))).write_all(b"\0binary"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("a")));
+        t!(index.add_path(Path::new("b")));
+        let old = t!(repo.find_tree(t!(index.write_tree())));
+
+        t!(std::fs::remove_file(td.path().join("a")));
+        t!(t!(File::create(&td.path().join("c"))).write_all(b"1\n2\n3\n4\n5\nsix\n7\n"));
+        t!(t!(File::create(&td.path().join("b"))).write_all(b"\0binary2"));
+        t!(index.remove_path(Path::new("a")));
+        t!(index.add_path(Path::new("b")));
+        t!(index.add_path(Path::new("c")));
+        let new = t!(repo.find_tree(t!(index.write_tree())));
+
+        let mut diff = t!(repo.diff_tree_to_tree(Some(&old), Some(&new), None));
+        t!(diff.find_similar(None));
+        let stats = t!(diff.file_stats());
+        assert_eq!(stats.len(), 2);
+
+        let renamed = stats.iter().find(|s| s.status() == crate::Delta::Renamed);
+        let renamed = renamed.unwrap();
+        assert_eq!(renamed.old_path(), Some(Path::new("a")));
+        assert_eq!(renamed.new_path(), Some(Path::new("c")));
+        assert_eq!((renamed.insertions(), renamed.deletions()), (2, 1));
+        assert!(!renamed.is_binary());
+
+        let binary = stats.iter().find(|s| s.status() == crate::Delta::Modified);
+        let binary = binary.unwrap();
+        assert_eq!(binary.new_path(), Some(Path::new("b")));
+        assert_eq!((binary.insertions(), binary.deletions()), (0, 0));
+        assert!(binary.is_binary());
+    }
 }
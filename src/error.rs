@@ -1,18 +1,55 @@
 use libc::c_int;
+use std::cell::RefCell;
 use std::env::JoinPathsError;
 use std::error;
 use std::ffi::{CStr, CString, NulError};
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::str;
 
-use crate::{raw, ErrorClass, ErrorCode};
+use crate::{raw, ErrorClass, ErrorCode, Oid};
 
 /// A structure to represent errors coming out of libgit2.
-#[derive(Debug, PartialEq)]
+///
+/// Besides the libgit2 code, class and message, an error can carry the
+/// operation which failed and the path, reference or object involved, which
+/// are included in its `Display` output, as well as the underlying error,
+/// available through `std::error::Error::source`.
+#[derive(Debug)]
 pub struct Error {
     code: c_int,
     klass: c_int,
     message: Box<str>,
+    context: Option<Box<Context>>,
+    source: Option<Source>,
+}
+
+type Source = Box<dyn error::Error + Send + Sync>;
+
+thread_local!(static PENDING_SOURCE: RefCell<Option<(String, Source)>> = {
+    RefCell::new(None)
+});
+
+/// Keep `source` to be attached to the next error read from libgit2 if its
+/// message is `message`, for errors which pass through libgit2 from a
+/// callback, such as the IO errors of custom transports.
+pub(crate) fn set_pending_source(message: &str, source: Source) {
+    PENDING_SOURCE.with(|slot| *slot.borrow_mut() = Some((message.to_string(), source)));
+}
+
+/// Drop the source kept by `set_pending_source`, once a later callback has
+/// succeeded and libgit2 can't be failing with its error anymore.
+pub(crate) fn clear_pending_source() {
+    PENDING_SOURCE.with(|slot| *slot.borrow_mut() = None);
+}
+
+#[derive(Debug, Default)]
+struct Context {
+    operation: Option<String>,
+    path: Option<PathBuf>,
+    refspec: Option<String>,
+    oid: Option<Oid>,
 }
 
 impl Error {
@@ -51,13 +88,18 @@ impl Error {
             // Additionally if `git_error_last` returns null then we returned a
             // canned error out.
             let ptr = raw::git_error_last();
-            let err = if ptr.is_null() {
+            let mut err = if ptr.is_null() {
                 let mut error = Error::from_str("an unknown git error occurred");
                 error.code = code;
                 error
             } else {
                 Error::from_raw(code, ptr)
             };
+            if let Some((message, source)) = PENDING_SOURCE.with(|slot| slot.borrow_mut().take()) {
+                if *err.message == message {
+                    err.source = Some(source);
+                }
+            }
             raw::git_error_clear();
             err
         }
@@ -70,6 +112,8 @@ impl Error {
             code,
             klass: (*ptr).klass,
             message,
+            context: None,
+            source: None,
         }
     }
 
@@ -82,6 +126,8 @@ impl Error {
             code: raw::GIT_ERROR as c_int,
             klass: raw::GIT_ERROR_NONE as c_int,
             message: s.into(),
+            context: None,
+            source: None,
         }
     }
 
@@ -124,6 +170,9 @@ impl Error {
             raw::GIT_EAPPLYFAIL => super::ErrorCode::ApplyFail,
             raw::GIT_EOWNER => super::ErrorCode::Owner,
             raw::GIT_TIMEOUT => super::ErrorCode::Timeout,
+            raw::GIT_EUNCHANGED => super::ErrorCode::Unchanged,
+            raw::GIT_ENOTSUPPORTED => super::ErrorCode::NotSupported,
+            raw::GIT_EREADONLY => super::ErrorCode::ReadOnly,
             _ => super::ErrorCode::GenericError,
         }
     }
@@ -162,6 +211,9 @@ impl Error {
             ErrorCode::ApplyFail => raw::GIT_EAPPLYFAIL,
             ErrorCode::Owner => raw::GIT_EOWNER,
             ErrorCode::Timeout => raw::GIT_TIMEOUT,
+            ErrorCode::Unchanged => raw::GIT_EUNCHANGED,
+            ErrorCode::NotSupported => raw::GIT_ENOTSUPPORTED,
+            ErrorCode::ReadOnly => raw::GIT_EREADONLY,
         };
     }
 
@@ -286,6 +338,8 @@ impl Error {
             GIT_EEOF,
             GIT_EINVALID,
             GIT_EUNCOMMITTED,
+            GIT_EDIRECTORY,
+            GIT_EMERGECONFLICT,
             GIT_PASSTHROUGH,
             GIT_ITEROVER,
             GIT_RETRY,
@@ -294,6 +348,9 @@ impl Error {
             GIT_EAPPLYFAIL,
             GIT_EOWNER,
             GIT_TIMEOUT,
+            GIT_EUNCHANGED,
+            GIT_ENOTSUPPORTED,
+            GIT_EREADONLY,
         )
     }
 
@@ -349,6 +406,68 @@ impl Error {
         &self.message
     }
 
+    /// Record the high-level operation which failed, such as
+    /// `"find reference"`.
+    pub fn with_operation(mut self, operation: &str) -> Error {
+        self.context_mut().operation = Some(operation.to_string());
+        self
+    }
+
+    /// Record the path involved in the failed operation.
+    pub fn with_path(mut self, path: &Path) -> Error {
+        self.context_mut().path = Some(path.to_path_buf());
+        self
+    }
+
+    /// Record the reference name, refspec or revision involved in the failed
+    /// operation.
+    pub fn with_refspec(mut self, refspec: &str) -> Error {
+        self.context_mut().refspec = Some(refspec.to_string());
+        self
+    }
+
+    /// Record the id of the object involved in the failed operation.
+    pub fn with_oid(mut self, oid: Oid) -> Error {
+        self.context_mut().oid = Some(oid);
+        self
+    }
+
+    /// Record the error which caused this one, returned by
+    /// `std::error::Error::source`.
+    pub fn with_source<E>(mut self, source: E) -> Error
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Return the high-level operation which failed, if known.
+    pub fn operation(&self) -> Option<&str> {
+        self.context.as_ref()?.operation.as_deref()
+    }
+
+    /// Return the path involved in the failed operation, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.context.as_ref()?.path.as_deref()
+    }
+
+    /// Return the reference name, refspec or revision involved in the failed
+    /// operation, if known.
+    pub fn refspec(&self) -> Option<&str> {
+        self.context.as_ref()?.refspec.as_deref()
+    }
+
+    /// Return the id of the object involved in the failed operation, if
+    /// known.
+    pub fn oid(&self) -> Option<Oid> {
+        self.context.as_ref()?.oid
+    }
+
+    fn context_mut(&mut self) -> &mut Context {
+        self.context.get_or_insert_with(Default::default)
+    }
+
     /// A low-level convenience to call [`raw::git_error_set_str`] with the
     /// information from this error.
     ///
@@ -361,10 +480,35 @@ impl Error {
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(&**source),
+            None => None,
+        }
+    }
+}
+
+impl PartialEq for Error {
+    /// Compares the code, class and message of the errors, but not their
+    /// context or sources.
+    fn eq(&self, other: &Error) -> bool {
+        self.code == other.code && self.klass == other.klass && self.message == other.message
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(context) = &self.context {
+            let mut parts = Vec::new();
+            parts.extend(context.operation.clone());
+            parts.extend(context.refspec.as_ref().map(|s| format!("'{}'", s)));
+            parts.extend(context.path.as_ref().map(|p| format!("'{}'", p.display())));
+            parts.extend(context.oid.map(|oid| oid.to_string()));
+            if !parts.is_empty() {
+                write!(f, "{}: ", parts.join(" "))?;
+            }
+        }
         write!(f, "{}", self.message)?;
         match self.class() {
             ErrorClass::None => {}
@@ -387,6 +531,14 @@ impl From<NulError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        let mut err = Error::from_str(&e.to_string());
+        err.set_class(ErrorClass::Os);
+        err.with_source(e)
+    }
+}
+
 impl From<JoinPathsError> for Error {
     fn from(e: JoinPathsError) -> Error {
         Error::from_str(&e.to_string())
@@ -405,4 +557,136 @@ mod tests {
         assert_eq!(err.code(), ErrorCode::NotFound);
         assert_eq!(err.class(), ErrorClass::Submodule);
     }
+
+    #[test]
+    fn code_round_trip() {
+        let codes = [
+            ErrorCode::GenericError,
+            ErrorCode::NotFound,
+            ErrorCode::Exists,
+            ErrorCode::Ambiguous,
+            ErrorCode::BufSize,
+            ErrorCode::User,
+            ErrorCode::BareRepo,
+            ErrorCode::UnbornBranch,
+            ErrorCode::Unmerged,
+            ErrorCode::NotFastForward,
+            ErrorCode::InvalidSpec,
+            ErrorCode::Conflict,
+            ErrorCode::Locked,
+            ErrorCode::Modified,
+            ErrorCode::Auth,
+            ErrorCode::Certificate,
+            ErrorCode::Applied,
+            ErrorCode::Peel,
+            ErrorCode::Eof,
+            ErrorCode::Invalid,
+            ErrorCode::Uncommitted,
+            ErrorCode::Directory,
+            ErrorCode::MergeConflict,
+            ErrorCode::HashsumMismatch,
+            ErrorCode::IndexDirty,
+            ErrorCode::ApplyFail,
+            ErrorCode::Owner,
+            ErrorCode::Timeout,
+            ErrorCode::Unchanged,
+            ErrorCode::NotSupported,
+            ErrorCode::ReadOnly,
+        ];
+        for code in codes.iter() {
+            // Keep this list in sync with the variants.
+            match code {
+                ErrorCode::GenericError
+                | ErrorCode::NotFound
+                | ErrorCode::Exists
+                | ErrorCode::Ambiguous
+                | ErrorCode::BufSize
+                | ErrorCode::User
+                | ErrorCode::BareRepo
+                | ErrorCode::UnbornBranch
+                | ErrorCode::Unmerged
+                | ErrorCode::NotFastForward
+                | ErrorCode::InvalidSpec
+                | ErrorCode::Conflict
+                | ErrorCode::Locked
+                | ErrorCode::Modified
+                | ErrorCode::Auth
+                | ErrorCode::Certificate
+                | ErrorCode::Applied
+                | ErrorCode::Peel
+                | ErrorCode::Eof
+                | ErrorCode::Invalid
+                | ErrorCode::Uncommitted
+                | ErrorCode::Directory
+                | ErrorCode::MergeConflict
+                | ErrorCode::HashsumMismatch
+                | ErrorCode::IndexDirty
+                | ErrorCode::ApplyFail
+                | ErrorCode::Owner
+                | ErrorCode::Timeout
+                | ErrorCode::Unchanged
+                | ErrorCode::NotSupported
+                | ErrorCode::ReadOnly => {}
+            }
+
+            let mut err = crate::Error::from_str("test");
+            err.set_code(*code);
+            assert_eq!(err.code(), *code);
+            let raw = err.raw_code();
+            let from_raw = crate::Error::last_error(raw as libc::c_int);
+            assert_eq!(from_raw.code(), *code);
+        }
+
+        let err = crate::Error::last_error(crate::raw::GIT_EREADONLY as libc::c_int);
+        assert_eq!(err.code(), ErrorCode::ReadOnly);
+        assert_eq!(err.raw_code(), crate::raw::GIT_EREADONLY);
+    }
+
+    #[test]
+    fn context() {
+        use std::error::Error as _;
+
+        let (_td, repo) = crate::test::repo_init();
+
+        let err = repo.find_reference("refs/heads/missing").err().unwrap();
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert_eq!(err.operation(), Some("find reference"));
+        assert_eq!(err.refspec(), Some("refs/heads/missing"));
+        assert!(err
+            .to_string()
+            .starts_with("find reference 'refs/heads/missing': "));
+
+        let oid = crate::Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let err = repo.find_commit(oid).err().unwrap();
+        assert_eq!(err.oid(), Some(oid));
+        assert!(err.source().is_none());
+
+        let io = std::io::Error::new(std::io::ErrorKind::Other, "disk on fire");
+        let err = crate::Error::from(io).with_operation("write index");
+        assert_eq!(err.class(), ErrorClass::Os);
+        assert_eq!(err.to_string(), "write index: disk on fire; class=Os (2)");
+        assert_eq!(err.source().unwrap().to_string(), "disk on fire");
+    }
+
+    #[test]
+    fn pending_source() {
+        use std::error::Error as _;
+
+        let (_td, repo) = crate::test::repo_init();
+        let io = || Box::new(std::io::Error::new(std::io::ErrorKind::Other, "x"));
+
+        // An empty message doesn't match every error.
+        super::set_pending_source("", io());
+        let err = repo.find_reference("refs/heads/missing").err().unwrap();
+        assert!(err.source().is_none());
+
+        super::set_pending_source(err.message(), io());
+        super::clear_pending_source();
+        let err = repo.find_reference("refs/heads/missing").err().unwrap();
+        assert!(err.source().is_none());
+
+        super::set_pending_source(err.message(), io());
+        let err = repo.find_reference("refs/heads/missing").err().unwrap();
+        assert_eq!(err.source().unwrap().to_string(), "x");
+    }
 }
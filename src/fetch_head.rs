@@ -0,0 +1,45 @@
+use std::str;
+
+use crate::Oid;
+
+/// An entry of the `FETCH_HEAD` file, recording a reference fetched by the
+/// last fetch, as returned by `Repository::fetch_head`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchHeadEntry {
+    pub(crate) ref_name: String,
+    pub(crate) remote_url: Vec<u8>,
+    pub(crate) id: Oid,
+    pub(crate) is_merge: bool,
+}
+
+impl FetchHeadEntry {
+    /// The name of the fetched reference on the remote, such as
+    /// `refs/heads/main`.
+    pub fn ref_name(&self) -> &str {
+        &self.ref_name
+    }
+
+    /// The URL of the remote the reference was fetched from.
+    ///
+    /// Returns `None` if the URL is not valid utf-8.
+    pub fn remote_url(&self) -> Option<&str> {
+        str::from_utf8(&self.remote_url).ok()
+    }
+
+    /// The URL of the remote the reference was fetched from, as a byte
+    /// array.
+    pub fn remote_url_bytes(&self) -> &[u8] {
+        &self.remote_url
+    }
+
+    /// The id the reference pointed to.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// Whether the reference is to be merged by `git pull`, as opposed to
+    /// `not-for-merge` entries fetched alongside it.
+    pub fn is_merge(&self) -> bool {
+        self.is_merge
+    }
+}
@@ -0,0 +1,732 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::Binding;
+use crate::{
+    raw, Error, ErrorClass, ErrorCode, ObjectType, Oid, ReflogExpireOptions, Repository, Time,
+};
+
+/// The mode of the index and tree entries of submodules, whose commits live
+/// in another repository.
+const GITLINK: u32 = 0o160000;
+
+/// A loose object file of the `objects` directory.
+struct LooseObject {
+    id: Oid,
+    path: PathBuf,
+    mtime: SystemTime,
+}
+
+/// List the loose objects of an `objects` directory.
+fn loose_objects(objects: &Path) -> Result<Vec<LooseObject>, Error> {
+    let mut out = Vec::new();
+    let entries = match fs::read_dir(objects) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(out),
+        Err(e) => return Err(e.into()),
+    };
+    for dir in entries {
+        let dir = dir?;
+        let prefix = match dir.file_name().into_string() {
+            Ok(name) if name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) => name,
+            _ => continue,
+        };
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let rest = match file.file_name().into_string() {
+                Ok(name) if name.len() == 38 && name.bytes().all(|b| b.is_ascii_hexdigit()) => name,
+                // Temporary files of objects being written.
+                _ => continue,
+            };
+            let mtime = file.metadata().and_then(|m| m.modified())?;
+            out.push(LooseObject {
+                id: Oid::from_str(&format!("{}{}", prefix, rest))?,
+                path: file.path(),
+                mtime,
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Compute the set of objects reachable from the references, reflogs,
+/// `HEAD`s and indexes of a repository and its worktrees.
+pub(crate) fn reachable(repo: &Repository) -> Result<HashSet<Oid>, Error> {
+    let mut roots = Vec::new();
+    let mut names = vec!["HEAD".to_string()];
+    for reference in repo.references()? {
+        let reference = reference?;
+        roots.extend(reference.target());
+        if let Some(name) = reference.name() {
+            names.push(name.to_string());
+        }
+    }
+    add_head_and_index(repo, &mut roots)?;
+    for name in &names {
+        for entry in repo.reflog(name)?.iter() {
+            roots.push(entry.id_old());
+            roots.push(entry.id_new());
+        }
+    }
+    for name in repo.worktrees()?.iter().flatten() {
+        let worktree = repo.find_worktree(name)?;
+        if worktree.validate().is_err() {
+            continue;
+        }
+        let wt = Repository::open_from_worktree(&worktree)?;
+        add_head_and_index(&wt, &mut roots)?;
+        for entry in wt.reflog("HEAD")?.iter() {
+            roots.push(entry.id_old());
+            roots.push(entry.id_new());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    while let Some(id) = roots.pop() {
+        if id.is_zero() || seen.contains(&id) {
+            continue;
+        }
+        // Objects which are referenced but missing are not reachable.
+        let object = match repo.find_object(id, None) {
+            Ok(object) => object,
+            Err(e) if e.code() == ErrorCode::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        seen.insert(id);
+        match object.kind() {
+            Some(ObjectType::Commit) => {
+                let commit = object.peel_to_commit()?;
+                roots.push(commit.tree_id());
+                roots.extend(commit.parent_ids());
+            }
+            Some(ObjectType::Tag) => {
+                roots.push(object.peel_to_tag()?.target_id());
+            }
+            Some(ObjectType::Tree) => {
+                let tree = object.peel_to_tree()?;
+                roots.extend(
+                    tree.iter()
+                        .filter(|e| e.filemode() as u32 != GITLINK)
+                        .map(|e| e.id()),
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(seen)
+}
+
+fn add_head_and_index(repo: &Repository, roots: &mut Vec<Oid>) -> Result<(), Error> {
+    match repo.refname_to_id("HEAD") {
+        Ok(id) => roots.push(id),
+        Err(e) if e.code() == ErrorCode::NotFound || e.code() == ErrorCode::UnbornBranch => {}
+        Err(e) => return Err(e),
+    }
+    if repo.is_bare() {
+        return Ok(());
+    }
+    let index = repo.index()?;
+    roots.extend(index.iter().filter(|e| e.mode != GITLINK).map(|e| e.id));
+    Ok(())
+}
+
+/// Implementation of `Repository::prune`.
+pub(crate) fn prune(repo: &Repository, expire: Time, dry_run: bool) -> Result<Vec<Oid>, Error> {
+    prune_with(repo, expire, dry_run, None)
+}
+
+fn prune_with(
+    repo: &Repository,
+    expire: Time,
+    dry_run: bool,
+    reachable_set: Option<&HashSet<Oid>>,
+) -> Result<Vec<Oid>, Error> {
+    let objects = repo.commondir().join("objects");
+    let expire = expire.seconds();
+    let candidates = loose_objects(&objects)?
+        .into_iter()
+        .filter(|o| match o.mtime.duration_since(UNIX_EPOCH) {
+            Ok(age) => (age.as_secs() as i64) < expire,
+            Err(_) => true,
+        })
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let computed;
+    let reachable = match reachable_set {
+        Some(set) => set,
+        None => {
+            computed = reachable(repo)?;
+            &computed
+        }
+    };
+    let mut pruned = Vec::new();
+    for object in candidates {
+        if reachable.contains(&object.id) {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_file(&object.path)?;
+            if let Some(dir) = object.path.parent() {
+                // Only succeeds once the fan-out directory is empty.
+                let _ = fs::remove_dir(dir);
+            }
+        }
+        pruned.push(object.id);
+    }
+    pruned.sort();
+    Ok(pruned)
+}
+
+/// Statistics about how the references of a repository are stored, as
+/// returned by `Repository::ref_stats`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefStats {
+    loose: usize,
+    packed: usize,
+    packed_size: u64,
+}
+
+impl RefStats {
+    /// The number of loose reference files under `refs/`, including the
+    /// per-worktree references of linked worktrees.
+    ///
+    /// Each loose reference is a file of its own, so a large number of them
+    /// makes listing references slow; `Repository::pack_refs` moves them
+    /// into the `packed-refs` file, except for per-worktree references,
+    /// which are never packed.
+    pub fn loose(&self) -> usize {
+        self.loose
+    }
+
+    /// The number of references in the `packed-refs` file. References which
+    /// also have a loose file are counted in both.
+    pub fn packed(&self) -> usize {
+        self.packed
+    }
+
+    /// The size of the `packed-refs` file in bytes.
+    pub fn packed_size(&self) -> u64 {
+        self.packed_size
+    }
+}
+
+fn count_loose_refs(dir: &Path) -> Result<usize, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut count = 0;
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            count += count_loose_refs(&entry.path())?;
+        } else if !entry.file_name().to_string_lossy().ends_with(".lock") {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Implementation of `Repository::ref_stats`.
+pub(crate) fn ref_stats(repo: &Repository) -> Result<RefStats, Error> {
+    let dir = repo.commondir();
+    let mut stats = RefStats {
+        loose: count_loose_refs(&dir.join("refs"))?,
+        ..RefStats::default()
+    };
+    // The per-worktree references of linked worktrees, such as those under
+    // `refs/bisect`, live in their own directory.
+    match fs::read_dir(dir.join("worktrees")) {
+        Ok(entries) => {
+            for entry in entries {
+                stats.loose += count_loose_refs(&entry?.path().join("refs"))?;
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    match fs::read(dir.join("packed-refs")) {
+        Ok(data) => {
+            stats.packed_size = data.len() as u64;
+            stats.packed = data
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.is_empty() && line[0] != b'#' && line[0] != b'^')
+                .count();
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(stats)
+}
+
+/// The stages of `Repository::gc`, in the order in which they run.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GcStage {
+    /// Removing old entries from the reflogs.
+    ExpireReflogs,
+    /// Moving loose references to the `packed-refs` file.
+    PackRefs,
+    /// Packing all reachable objects into a single packfile.
+    Repack,
+    /// Deleting unreachable loose objects.
+    PruneObjects,
+    /// Deleting the metadata of worktrees that no longer exist.
+    PruneWorktrees,
+}
+
+/// Callback for the progress of `Repository::gc`.
+///
+/// It is called with the current stage along with the number of items
+/// processed so far and the total number of items of the stage. Returning
+/// `false` cancels the collection.
+pub type GcProgress<'a> = dyn FnMut(GcStage, usize, usize) -> bool + 'a;
+
+/// Options for `Repository::gc`.
+pub struct GcOptions<'a> {
+    reflog_expire: Option<Time>,
+    prune_expire: Option<Time>,
+    expire_reflogs: bool,
+    pack_refs: bool,
+    repack: bool,
+    prune: bool,
+    prune_worktrees: bool,
+    threads: u32,
+    progress: Option<Box<GcProgress<'a>>>,
+}
+
+impl<'a> Default for GcOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> GcOptions<'a> {
+    /// Creates a new set of options, with every stage enabled.
+    pub fn new() -> GcOptions<'a> {
+        GcOptions {
+            reflog_expire: None,
+            prune_expire: None,
+            expire_reflogs: true,
+            pack_refs: true,
+            repack: true,
+            prune: true,
+            prune_worktrees: true,
+            threads: 1,
+            progress: None,
+        }
+    }
+
+    /// Whether to remove old reflog entries, so that the objects they
+    /// reference can be pruned.
+    pub fn expire_reflogs(&mut self, enabled: bool) -> &mut GcOptions<'a> {
+        self.expire_reflogs = enabled;
+        self
+    }
+
+    /// Reflog entries older than this are removed. By default, the cutoffs
+    /// of `gc.reflogExpire` and `gc.reflogExpireUnreachable` are used, see
+    /// `ReflogExpireOptions`.
+    pub fn reflog_expire(&mut self, expire: Time) -> &mut GcOptions<'a> {
+        self.reflog_expire = Some(expire);
+        self
+    }
+
+    /// Whether to move loose references to the `packed-refs` file.
+    pub fn pack_refs(&mut self, enabled: bool) -> &mut GcOptions<'a> {
+        self.pack_refs = enabled;
+        self
+    }
+
+    /// Whether to pack all reachable objects into a single new packfile,
+    /// replacing the existing packfiles and loose copies of the objects.
+    ///
+    /// Packfiles with a `.keep` file are left untouched. The replaced
+    /// packfiles are only deleted once the new one has been flushed to disk.
+    /// Like `git gc`, their unreachable objects are made loose, so that they
+    /// are pruned once they expire, unless the packfile holding them is
+    /// already older than `prune_expire`, in which case they are dropped.
+    /// When pruning is disabled, they are always kept.
+    pub fn repack(&mut self, enabled: bool) -> &mut GcOptions<'a> {
+        self.repack = enabled;
+        self
+    }
+
+    /// Whether to delete unreachable loose objects, as `Repository::prune`
+    /// does.
+    pub fn prune(&mut self, enabled: bool) -> &mut GcOptions<'a> {
+        self.prune = enabled;
+        self
+    }
+
+    /// Unreachable loose objects older than this are deleted. Defaults to
+    /// two weeks ago, like `gc.pruneExpire`.
+    pub fn prune_expire(&mut self, expire: Time) -> &mut GcOptions<'a> {
+        self.prune_expire = Some(expire);
+        self
+    }
+
+    /// Whether to delete the metadata of worktrees whose working directory
+    /// is gone and which are not locked.
+    pub fn prune_worktrees(&mut self, enabled: bool) -> &mut GcOptions<'a> {
+        self.prune_worktrees = enabled;
+        self
+    }
+
+    /// The number of threads used to search for deltas while repacking, `0`
+    /// meaning one per CPU. Defaults to `1`.
+    pub fn threads(&mut self, threads: u32) -> &mut GcOptions<'a> {
+        self.threads = threads;
+        self
+    }
+
+    /// Set the callback to be called with the progress of the collection.
+    pub fn progress<F>(&mut self, cb: F) -> &mut GcOptions<'a>
+    where
+        F: FnMut(GcStage, usize, usize) -> bool + 'a,
+    {
+        self.progress = Some(Box::new(cb) as Box<GcProgress<'a>>);
+        self
+    }
+
+    fn report(&mut self, stage: GcStage, current: usize, total: usize) -> Result<(), Error> {
+        if let Some(ref mut cb) = self.progress {
+            if !cb(stage, current, total) {
+                return Err(Error::new(
+                    ErrorCode::User,
+                    ErrorClass::Callback,
+                    "gc was cancelled by the callback",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn days_ago(days: u64) -> Time {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Time::new(now.saturating_sub(days * 24 * 60 * 60) as i64, 0)
+}
+
+/// Implementation of `Repository::gc`.
+pub(crate) fn gc(repo: &Repository, opts: &mut GcOptions<'_>) -> Result<(), Error> {
+    if opts.expire_reflogs {
+        expire_reflogs(repo, opts)?;
+    }
+    if opts.pack_refs {
+        opts.report(GcStage::PackRefs, 0, 1)?;
+        pack_refs(repo)?;
+        opts.report(GcStage::PackRefs, 1, 1)?;
+    }
+    let reachable_set = if opts.repack || opts.prune {
+        Some(reachable(repo)?)
+    } else {
+        None
+    };
+    let expire = opts.prune_expire.unwrap_or_else(|| days_ago(14));
+    if opts.repack {
+        let expire = if opts.prune { Some(expire) } else { None };
+        repack(repo, reachable_set.as_ref().unwrap(), expire, opts)?;
+    }
+    if opts.prune {
+        opts.report(GcStage::PruneObjects, 0, 1)?;
+        prune_with(repo, expire, false, reachable_set.as_ref())?;
+        opts.report(GcStage::PruneObjects, 1, 1)?;
+    }
+    if opts.prune_worktrees {
+        let names = repo.worktrees()?;
+        let names = names.iter().flatten().collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            opts.report(GcStage::PruneWorktrees, i, names.len())?;
+            let worktree = repo.find_worktree(name)?;
+            if worktree.is_prunable(None)? {
+                worktree.prune(None)?;
+            }
+        }
+        opts.report(GcStage::PruneWorktrees, names.len(), names.len())?;
+    }
+    Ok(())
+}
+
+fn expire_reflogs(repo: &Repository, opts: &mut GcOptions<'_>) -> Result<(), Error> {
+    let mut expire = ReflogExpireOptions::new();
+    if let Some(time) = opts.reflog_expire {
+        expire.expire(Some(time)).expire_unreachable(Some(time));
+    }
+    let mut names = vec!["HEAD".to_string()];
+    for name in repo.references()?.names() {
+        names.push(name?.to_string());
+    }
+    for (i, name) in names.iter().enumerate() {
+        opts.report(GcStage::ExpireReflogs, i, names.len())?;
+        repo.reflog_expire(name, Some(&expire))?;
+    }
+    opts.report(GcStage::ExpireReflogs, names.len(), names.len())
+}
+
+fn repack(
+    repo: &Repository,
+    reachable: &HashSet<Oid>,
+    expire: Option<Time>,
+    opts: &mut GcOptions<'_>,
+) -> Result<(), Error> {
+    let objects = repo.commondir().join("objects");
+    let dir = objects.join("pack");
+    let old = crate::odb::packfiles(&dir)?
+        .into_iter()
+        .filter(|p| !p.path().with_extension("keep").exists())
+        .collect::<Vec<_>>();
+    let total = reachable.len();
+    opts.report(GcStage::Repack, 0, total)?;
+    if total == 0 {
+        return Ok(());
+    }
+
+    let mut builder = repo.packbuilder()?;
+    builder.set_threads(opts.threads);
+    // Walking the history first orders the objects by recency, which gives
+    // the best deltas.
+    let mut walk = repo.revwalk()?;
+    walk.push_glob("*")?;
+    match walk.push_head() {
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {}
+        res => res?,
+    }
+    builder.insert_walk(&mut walk)?;
+    for id in reachable {
+        builder.insert_object(*id, None)?;
+    }
+    fs::create_dir_all(&dir)?;
+    builder.write(&dir, 0)?;
+    let name = match builder.name() {
+        Some(name) => format!("pack-{}", name),
+        None => return Err(Error::from_str("the new packfile has no name")),
+    };
+    // The old packs are only deleted once the new one is safely on disk.
+    for ext in &["pack", "idx"] {
+        fs::File::open(dir.join(&name).with_extension(ext))?.sync_all()?;
+    }
+    sync_dir(&dir)?;
+    let packed = crate::odb::pack_index_ids(&dir.join(&name).with_extension("idx"))?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    opts.report(GcStage::Repack, total, total)?;
+
+    // Like `git repack -A --unpack-unreachable`, the unreachable objects of
+    // the replaced packs are made loose so that pruning decides when they
+    // go, except those of packs that are older than the prune expiry.
+    let odb = repo.odb()?;
+    let loose = crate::odb::loose_odb(&objects)?;
+    let old = old
+        .into_iter()
+        .filter(|p| format!("{}.pack", name) != p.name())
+        .collect::<Vec<_>>();
+    for pack in &old {
+        let expired = match expire {
+            Some(expire) => {
+                let mtime = fs::metadata(pack.path())?.modified()?;
+                match mtime.duration_since(UNIX_EPOCH) {
+                    Ok(age) => (age.as_secs() as i64) < expire.seconds(),
+                    Err(_) => true,
+                }
+            }
+            None => false,
+        };
+        if expired {
+            continue;
+        }
+        for id in crate::odb::pack_index_ids(&pack.path().with_extension("idx"))? {
+            if packed.contains(&id) || reachable.contains(&id) {
+                continue;
+            }
+            let object = odb.read(id)?;
+            loose.write(object.kind(), object.data())?;
+        }
+    }
+    // Packs that are mapped can't be deleted on Windows, so let go of the
+    // handles opened here first.
+    drop(loose);
+    drop(odb);
+
+    // The new pack replaces the old ones, the multi-pack-index covering
+    // them, and the loose copies of the objects it holds.
+    for pack in &old {
+        for ext in &["pack", "idx", "rev", "bitmap"] {
+            match fs::remove_file(pack.path().with_extension(ext)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+    match fs::remove_file(dir.join("multi-pack-index")) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    for object in loose_objects(&objects)? {
+        if packed.contains(&object.id) {
+            fs::remove_file(&object.path)?;
+            let _ = fs::remove_dir(object.path.parent().unwrap());
+        }
+    }
+    repo.odb()?.refresh()
+}
+
+/// Flush the entries of the directory `dir` to disk, where the platform
+/// supports it.
+fn sync_dir(dir: &Path) -> Result<(), Error> {
+    if cfg!(unix) {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Implementation of `Repository::pack_refs`.
+pub(crate) fn pack_refs(repo: &Repository) -> Result<(), Error> {
+    unsafe {
+        let mut refdb = ptr::null_mut();
+        try_call!(raw::git_repository_refdb(&mut refdb, repo.raw()));
+        let rc = raw::git_refdb_compress(refdb);
+        raw::git_refdb_free(refdb);
+        if rc < 0 {
+            return Err(Error::last_error(rc));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GcOptions, GcStage};
+    use crate::{ObjectType, Time};
+
+    #[test]
+    fn prune() {
+        let (td, repo) = crate::test::repo_init();
+        let odb = t!(repo.odb());
+        let dangling = t!(odb.write(ObjectType::Blob, b"dangling"));
+        let (commit, tree) = crate::test::commit(&repo);
+        let future = Time::new(i64::from(i32::MAX), 0);
+
+        // Nothing is old enough yet.
+        assert_eq!(t!(repo.prune(Time::new(0, 0), false)), []);
+        assert_eq!(t!(repo.prune(future, true)), [dangling]);
+        assert!(t!(repo.find_blob(dangling)).size() > 0);
+
+        assert_eq!(t!(repo.prune(future, false)), [dangling]);
+        let hex = dangling.to_string();
+        let path = td.path().join(".git/objects").join(&hex[..2]);
+        assert!(!path.join(&hex[2..]).exists());
+        t!(repo.find_commit(commit));
+        t!(repo.find_tree(tree));
+    }
+
+    #[test]
+    fn gc() {
+        let (td, repo) = crate::test::repo_init();
+        let odb = t!(repo.odb());
+        let dangling = t!(odb.write(ObjectType::Blob, b"dangling"));
+        let (commit, _tree) = crate::test::commit(&repo);
+        let head = t!(repo.find_commit(commit));
+        t!(repo.branch("topic", &head, false));
+
+        let mut stages = Vec::new();
+        let mut opts = GcOptions::new();
+        opts.prune_expire(Time::new(i64::from(i32::MAX), 0))
+            .progress(|stage, _, _| {
+                if stages.last() != Some(&stage) {
+                    stages.push(stage);
+                }
+                true
+            });
+        t!(repo.gc(Some(&mut opts)));
+        drop(opts);
+        assert_eq!(
+            stages,
+            [
+                GcStage::ExpireReflogs,
+                GcStage::PackRefs,
+                GcStage::Repack,
+                GcStage::PruneObjects,
+                GcStage::PruneWorktrees,
+            ]
+        );
+
+        // Everything reachable is in a single pack, nothing is left loose.
+        let packs = t!(repo.packfiles());
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].object_count(), 5);
+        assert!(!td.path().join(".git/refs/heads/topic").exists());
+        let hex = commit.to_string();
+        assert!(!td.path().join(".git/objects").join(&hex[..2]).exists());
+        assert!(repo.find_blob(dangling).is_err());
+        t!(repo.find_commit(commit));
+        t!(repo.find_branch("topic", crate::BranchType::Local));
+
+        let mut opts = GcOptions::new();
+        opts.progress(|_, _, _| false);
+        let err = repo.gc(Some(&mut opts)).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::User);
+    }
+
+    #[test]
+    fn repack_keeps_unreachable() {
+        let (_td, repo) = crate::test::repo_init();
+        let odb = t!(repo.odb());
+        let dangling = t!(odb.write(ObjectType::Blob, b"dangling"));
+        let mut builder = t!(repo.packbuilder());
+        t!(builder.insert_object(dangling, None));
+        t!(builder.write(&repo.path().join("objects/pack"), 0));
+        t!(repo.prune(Time::new(i64::from(i32::MAX), 0), false));
+        t!(odb.refresh());
+
+        let mut opts = GcOptions::new();
+        opts.prune(false);
+        t!(repo.gc(Some(&mut opts)));
+        let packs = t!(repo.packfiles());
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].object_count(), 2);
+        assert_eq!(t!(repo.find_blob(dangling)).content(), b"dangling");
+
+        let mut opts = GcOptions::new();
+        opts.prune_expire(Time::new(i64::from(i32::MAX), 0));
+        t!(repo.gc(Some(&mut opts)));
+        // The open repository may still see the pruned object.
+        let repo = t!(crate::Repository::open(repo.path()));
+        assert!(repo.find_blob(dangling).is_err());
+    }
+
+    #[test]
+    fn ref_stats() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = t!(repo.refname_to_id("HEAD"));
+        for i in 0..3 {
+            t!(repo.reference(&format!("refs/tags/t{}", i), head, false, ""));
+        }
+        let stats = t!(repo.ref_stats());
+        assert_eq!(stats.loose(), 4);
+        assert_eq!(stats.packed(), 0);
+
+        let refs = repo.path().join("worktrees/wt/refs/bisect");
+        t!(std::fs::create_dir_all(&refs));
+        t!(std::fs::write(refs.join("bad"), format!("{}\n", head)));
+        assert_eq!(t!(repo.ref_stats()).loose(), 5);
+        t!(std::fs::remove_dir_all(repo.path().join("worktrees")));
+
+        t!(repo.pack_refs());
+        let stats = t!(repo.ref_stats());
+        assert_eq!(stats.loose(), 0);
+        assert_eq!(stats.packed(), 4);
+        assert!(stats.packed_size() > 0);
+        assert_eq!(t!(repo.refname_to_id("refs/tags/t1")), head);
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+
+use crate::{Error, ObjectType, Oid};
+
+/// A writer computing the id of an object as its content is written.
+///
+/// The content is not retained, so the id of arbitrarily large files can be
+/// computed with constant memory and without writing them to an object
+/// database. Since the size of an object is part of what is hashed, it must
+/// be known up front.
+///
+/// ```
+/// use git2::{HashingWriter, ObjectType, Oid};
+/// use std::io::Write;
+///
+/// let mut writer = HashingWriter::new(ObjectType::Blob, 11).unwrap();
+/// writer.write_all(b"hello ").unwrap();
+/// writer.write_all(b"world").unwrap();
+/// let id = writer.finish().unwrap();
+/// assert_eq!(id, Oid::hash_object(ObjectType::Blob, b"hello world").unwrap());
+/// ```
+pub struct HashingWriter {
+    sha1: Sha1,
+    size: u64,
+    written: u64,
+}
+
+impl HashingWriter {
+    /// Create a writer for an object of type `kind` holding `size` bytes.
+    ///
+    /// `kind` must be one of the types of objects stored in a repository:
+    /// blob, tree, commit or tag.
+    pub fn new(kind: ObjectType, size: u64) -> Result<HashingWriter, Error> {
+        if !kind.is_loose() {
+            return Err(Error::from_str("invalid object type for hashing"));
+        }
+        let mut sha1 = Sha1::new();
+        sha1.update(format!("{} {}\0", kind.str(), size).as_bytes());
+        Ok(HashingWriter {
+            sha1,
+            size,
+            written: 0,
+        })
+    }
+
+    /// The number of bytes written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Return the id of the object, once all of its content has been
+    /// written.
+    pub fn finish(self) -> Result<Oid, Error> {
+        if self.written != self.size {
+            return Err(Error::from_str(&format!(
+                "object size mismatch: expected {} bytes, got {}",
+                self.size, self.written
+            )));
+        }
+        Oid::from_bytes(&self.sha1.finish())
+    }
+}
+
+impl io::Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more data written than the object size",
+            ));
+        }
+        self.sha1.update(buf);
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A SHA-1 hasher, for the hashes libgit2 does not expose.
+pub(crate) struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    filled: usize,
+    len: u64,
+}
+
+impl Sha1 {
+    pub(crate) fn new() -> Sha1 {
+        Sha1 {
+            state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            block: [0; 64],
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 20] {
+        let bits = self.len * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0; 20];
+        for (chunk, word) in digest.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashingWriter;
+    use crate::{ObjectType, Oid};
+    use std::io::Write;
+
+    #[test]
+    fn smoke() {
+        let data = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let mut writer = t!(HashingWriter::new(ObjectType::Blob, data.len() as u64));
+        for chunk in data.chunks(777) {
+            t!(writer.write_all(chunk));
+        }
+        assert_eq!(writer.written(), data.len() as u64);
+        let expected = t!(Oid::hash_object(ObjectType::Blob, &data));
+        assert_eq!(t!(writer.finish()), expected);
+
+        let writer = t!(HashingWriter::new(ObjectType::Tree, 0));
+        let empty_tree = t!(Oid::from_str("4b825dc642cb6eb9a060e54bf8d69288fbee4904"));
+        assert_eq!(t!(writer.finish()), empty_tree);
+
+        let mut writer = t!(HashingWriter::new(ObjectType::Blob, 2));
+        assert!(writer.write_all(b"abc").is_err());
+        assert!(writer.finish().is_err());
+        assert!(HashingWriter::new(ObjectType::Any, 0).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Commit, Delta, DiffFindOptions, DiffOptions, Error, Oid, Repository, Revwalk, Sort};
+
+/// An iterator over the commits that changed a single file, following it
+/// across renames and copies.
+///
+/// Created by `Repository::file_history`, this is the equivalent of
+/// `git log --follow`. Each item is a commit that changed the file, the path
+/// of the file in that commit, and how the commit changed it. Once the file
+/// is found to have been renamed or copied, older commits are searched for
+/// its previous path instead. Merge commits are skipped.
+pub struct FileHistory<'repo> {
+    repo: &'repo Repository,
+    walk: Revwalk<'repo>,
+    path: Option<PathBuf>,
+}
+
+impl<'repo> FileHistory<'repo> {
+    pub(crate) fn new(
+        repo: &'repo Repository,
+        start: Oid,
+        path: &Path,
+    ) -> Result<FileHistory<'repo>, Error> {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(start)?;
+        Ok(FileHistory {
+            repo,
+            walk,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// The path of the file in the commits not yet returned, or `None` once
+    /// the commit adding the file has been reached.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn check(
+        &mut self,
+        id: Oid,
+        path: &Path,
+    ) -> Result<Option<(Commit<'repo>, PathBuf, Delta)>, Error> {
+        let commit = self.repo.find_commit(id)?;
+        if commit.parent_count() > 1 {
+            return Ok(None);
+        }
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let tree = commit.tree()?;
+
+        let mut opts = DiffOptions::new();
+        opts.pathspec(path).disable_pathspec_match(true);
+        let diff =
+            self.repo
+                .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut opts))?;
+        let status = match diff.get_delta(0) {
+            Some(delta) => delta.status(),
+            None => return Ok(None),
+        };
+        if status != Delta::Added || parent_tree.is_none() {
+            if status == Delta::Added {
+                self.path = None;
+            }
+            return Ok(Some((commit, path.to_path_buf(), status)));
+        }
+
+        // The file appeared in this commit, so look at the whole diff for
+        // where it came from.
+        let mut diff = self
+            .repo
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true)))?;
+        for delta in diff.deltas() {
+            if delta.new_file().path() != Some(path) {
+                continue;
+            }
+            match delta.status() {
+                Delta::Renamed | Delta::Copied => {
+                    self.path = delta.old_file().path().map(|p| p.to_path_buf());
+                    return Ok(Some((commit, path.to_path_buf(), delta.status())));
+                }
+                _ => {}
+            }
+        }
+        self.path = None;
+        Ok(Some((commit, path.to_path_buf(), Delta::Added)))
+    }
+}
+
+impl<'repo> Iterator for FileHistory<'repo> {
+    type Item = Result<(Commit<'repo>, PathBuf, Delta), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let path = self.path.clone()?;
+            let id = match self.walk.next()? {
+                Ok(id) => id,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.check(id, &path) {
+                Ok(Some(item)) => return Some(Ok(item)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Delta, Oid, Repository};
+    use std::path::{Path, PathBuf};
+
+    fn commit(repo: &Repository, files: &[(&str, Option<&str>)], message: &str) -> Oid {
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        for (name, content) in files {
+            match content {
+                Some(content) => {
+                    let blob = repo.blob(content.as_bytes()).unwrap();
+                    builder.insert(name, blob, 0o100644).unwrap();
+                }
+                None => builder.remove(name).unwrap(),
+            }
+        }
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])
+            .unwrap()
+    }
+
+    #[test]
+    fn follows_renames() {
+        let (_td, repo) = crate::test::repo_init();
+        let content = (1..=20)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let added = commit(&repo, &[("old", Some(&content))], "add");
+        let changed = commit(
+            &repo,
+            &[("old", Some(&(content.clone() + "more\n")))],
+            "change",
+        );
+        commit(&repo, &[("other", Some("other\n"))], "unrelated");
+        let renamed = commit(
+            &repo,
+            &[("old", None), ("new", Some(&(content + "more\n")))],
+            "rename",
+        );
+
+        let head = repo.head().unwrap().target().unwrap();
+        let mut history = repo.file_history(head, Path::new("new")).unwrap();
+        let items = history
+            .by_ref()
+            .map(|item| {
+                let (commit, path, status) = item.unwrap();
+                (commit.id(), path, status)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                (renamed, PathBuf::from("new"), Delta::Renamed),
+                (changed, PathBuf::from("old"), Delta::Modified),
+                (added, PathBuf::from("old"), Delta::Added),
+            ]
+        );
+        assert_eq!(history.path(), None);
+    }
+}
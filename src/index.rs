@@ -9,7 +9,9 @@ use libc::{c_char, c_int, c_uint, c_void, size_t};
 
 use crate::util::{self, path_to_repo_path, Binding};
 use crate::IntoCString;
-use crate::{panic, raw, Error, IndexAddOption, IndexTime, Oid, Repository, Tree};
+use crate::{
+    panic, raw, Error, ErrorClass, ErrorCode, IndexAddOption, IndexTime, Oid, Repository, Tree,
+};
 
 /// A structure to represent a git [index][1]
 ///
@@ -49,6 +51,21 @@ pub struct IndexConflict {
 /// the operation on the item, > 0 to skip the item, and < 0 to abort the scan.
 pub type IndexMatchedPath<'a> = dyn FnMut(&Path, &[u8]) -> i32 + 'a;
 
+/// A callback function to filter index matches while reporting progress.
+///
+/// Used by `Index::add_all_with_progress`. The arguments are the same as for
+/// `IndexMatchedPath` followed by the progress of the operation. Return 0 to
+/// add the item, > 0 to skip the item, and < 0 to cancel the operation.
+pub type IndexAddProgressCb<'a> = dyn FnMut(&Path, &[u8], IndexAddProgress) -> i32 + 'a;
+
+/// Progress of an `Index::add_all_with_progress` operation.
+#[derive(Copy, Clone, Debug)]
+pub struct IndexAddProgress {
+    scanned: usize,
+    added: usize,
+    total: usize,
+}
+
 /// A structure to represent an entry or a file inside of an index.
 ///
 /// All fields of an entry are public for modification and inspection. This is
@@ -146,39 +163,8 @@ impl Index {
     /// will be added.
     pub fn add(&mut self, entry: &IndexEntry) -> Result<(), Error> {
         let path = CString::new(&entry.path[..])?;
-
-        // libgit2 encodes the length of the path in the lower bits of the
-        // `flags` entry, so mask those out and recalculate here to ensure we
-        // don't corrupt anything.
-        let mut flags = entry.flags & !raw::GIT_INDEX_ENTRY_NAMEMASK;
-
-        if entry.path.len() < raw::GIT_INDEX_ENTRY_NAMEMASK as usize {
-            flags |= entry.path.len() as u16;
-        } else {
-            flags |= raw::GIT_INDEX_ENTRY_NAMEMASK;
-        }
-
         unsafe {
-            let raw = raw::git_index_entry {
-                dev: entry.dev,
-                ino: entry.ino,
-                mode: entry.mode,
-                uid: entry.uid,
-                gid: entry.gid,
-                file_size: entry.file_size,
-                id: *entry.id.raw(),
-                flags,
-                flags_extended: entry.flags_extended,
-                path: path.as_ptr(),
-                mtime: raw::git_index_time {
-                    seconds: entry.mtime.seconds(),
-                    nanoseconds: entry.mtime.nanoseconds(),
-                },
-                ctime: raw::git_index_time {
-                    seconds: entry.ctime.seconds(),
-                    nanoseconds: entry.ctime.nanoseconds(),
-                },
-            };
+            let raw = entry.to_raw(&path);
             try_call!(raw::git_index_add(self.raw, &raw));
             Ok(())
         }
@@ -203,40 +189,8 @@ impl Index {
     /// moved to the "resolve undo" (REUC) section.
     pub fn add_frombuffer(&mut self, entry: &IndexEntry, data: &[u8]) -> Result<(), Error> {
         let path = CString::new(&entry.path[..])?;
-
-        // libgit2 encodes the length of the path in the lower bits of the
-        // `flags` entry, so mask those out and recalculate here to ensure we
-        // don't corrupt anything.
-        let mut flags = entry.flags & !raw::GIT_INDEX_ENTRY_NAMEMASK;
-
-        if entry.path.len() < raw::GIT_INDEX_ENTRY_NAMEMASK as usize {
-            flags |= entry.path.len() as u16;
-        } else {
-            flags |= raw::GIT_INDEX_ENTRY_NAMEMASK;
-        }
-
         unsafe {
-            let raw = raw::git_index_entry {
-                dev: entry.dev,
-                ino: entry.ino,
-                mode: entry.mode,
-                uid: entry.uid,
-                gid: entry.gid,
-                file_size: entry.file_size,
-                id: *entry.id.raw(),
-                flags,
-                flags_extended: entry.flags_extended,
-                path: path.as_ptr(),
-                mtime: raw::git_index_time {
-                    seconds: entry.mtime.seconds(),
-                    nanoseconds: entry.mtime.nanoseconds(),
-                },
-                ctime: raw::git_index_time {
-                    seconds: entry.ctime.seconds(),
-                    nanoseconds: entry.ctime.nanoseconds(),
-                },
-            };
-
+            let raw = entry.to_raw(&path);
             let ptr = data.as_ptr() as *const c_void;
             let len = data.len() as size_t;
             try_call!(raw::git_index_add_frombuffer(self.raw, &raw, ptr, len));
@@ -338,6 +292,80 @@ impl Index {
         Ok(())
     }
 
+    /// Add or update index entries matching files in the working directory,
+    /// reporting progress as it goes.
+    ///
+    /// This behaves like `add_all`, except that the callback is also given
+    /// the number of matching items seen and added so far along with the
+    /// total number of items to process, so that callers can report a
+    /// percentage for large trees. Computing the total requires an extra
+    /// scan of the working directory before any entries are added.
+    ///
+    /// If the callback returns a negative value the operation is cancelled
+    /// and an error with the code `ErrorCode::User` is returned. Entries added
+    /// before the cancellation remain in the in-memory index.
+    pub fn add_all_with_progress<T, I>(
+        &mut self,
+        pathspecs: I,
+        flag: IndexAddOption,
+        cb: &mut IndexAddProgressCb<'_>,
+    ) -> Result<(), Error>
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let pathspecs = pathspecs
+            .into_iter()
+            .map(|p| p.into_c_string())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut total = 0;
+        self.add_all(
+            pathspecs.iter().cloned(),
+            flag,
+            Some(&mut |_: &Path, _: &[u8]| {
+                total += 1;
+                1
+            }),
+        )?;
+
+        let mut progress = IndexAddProgress {
+            scanned: 0,
+            added: 0,
+            total,
+        };
+        let mut cancelled = false;
+        let result = self.add_all(
+            pathspecs.iter().cloned(),
+            flag,
+            Some(&mut |path: &Path, matched: &[u8]| {
+                if cancelled {
+                    return -1;
+                }
+                progress.scanned += 1;
+                match cb(path, matched, progress) {
+                    0 => {
+                        progress.added += 1;
+                        0
+                    }
+                    ret if ret > 0 => 1,
+                    _ => {
+                        cancelled = true;
+                        -1
+                    }
+                }
+            }),
+        );
+        if cancelled {
+            return Err(Error::new(
+                ErrorCode::User,
+                ErrorClass::Callback,
+                "add_all was cancelled by the callback",
+            ));
+        }
+        result
+    }
+
     /// Clear the contents (all the entries) of an index object.
     ///
     /// This clears the index object in memory; changes must be explicitly
@@ -412,6 +440,71 @@ impl Index {
         unsafe { raw::git_index_has_conflicts(self.raw) == 1 }
     }
 
+    /// Get the conflict entries for a path in the index.
+    ///
+    /// The returned `IndexConflict` holds the entries at stage 1 (ancestor),
+    /// stage 2 (ours), and stage 3 (theirs) for `path`, any of which may be
+    /// absent. Returns an error with code `NotFound` if the path is not in
+    /// conflict.
+    pub fn conflict_get(&self, path: &Path) -> Result<IndexConflict, Error> {
+        let path = path_to_repo_path(path)?;
+        let mut ancestor = ptr::null();
+        let mut our = ptr::null();
+        let mut their = ptr::null();
+        unsafe {
+            try_call!(raw::git_index_conflict_get(
+                &mut ancestor,
+                &mut our,
+                &mut their,
+                self.raw,
+                path
+            ));
+            Ok(IndexConflict::from_raw_entries(ancestor, our, their))
+        }
+    }
+
+    /// Add or update index entries to represent a conflict.
+    ///
+    /// Any staged entries that exist at the given paths will be removed. The
+    /// entries are written at stage 1 (ancestor), stage 2 (ours), and stage 3
+    /// (theirs) regardless of the stage recorded in their `flags`. At least
+    /// one entry must be provided, and all provided entries must share the
+    /// same path.
+    pub fn conflict_add(
+        &mut self,
+        ancestor: Option<&IndexEntry>,
+        our: Option<&IndexEntry>,
+        their: Option<&IndexEntry>,
+    ) -> Result<(), Error> {
+        let ancestor_path = ancestor.map(|e| CString::new(&e.path[..])).transpose()?;
+        let our_path = our.map(|e| CString::new(&e.path[..])).transpose()?;
+        let their_path = their.map(|e| CString::new(&e.path[..])).transpose()?;
+        unsafe {
+            let ancestor = ancestor.map(|e| e.to_raw(ancestor_path.as_ref().unwrap()));
+            let our = our.map(|e| e.to_raw(our_path.as_ref().unwrap()));
+            let their = their.map(|e| e.to_raw(their_path.as_ref().unwrap()));
+            try_call!(raw::git_index_conflict_add(
+                self.raw,
+                ancestor.as_ref().map_or(ptr::null(), |e| e as *const _),
+                our.as_ref().map_or(ptr::null(), |e| e as *const _),
+                their.as_ref().map_or(ptr::null(), |e| e as *const _)
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove all conflicts in the index for the given path.
+    ///
+    /// This removes the entries at stages 1, 2, and 3, leaving any entry at
+    /// stage 0 untouched.
+    pub fn conflict_remove(&mut self, path: &Path) -> Result<(), Error> {
+        let path = path_to_repo_path(path)?;
+        unsafe {
+            try_call!(raw::git_index_conflict_remove(self.raw, path));
+        }
+        Ok(())
+    }
+
     /// Get the full path to the index file on disk.
     ///
     /// Returns `None` if this is an in-memory index.
@@ -614,6 +707,23 @@ impl Index {
     }
 }
 
+impl IndexAddProgress {
+    /// Number of matching items seen so far, including the current one.
+    pub fn scanned(&self) -> usize {
+        self.scanned
+    }
+
+    /// Number of items added to the index so far.
+    pub fn added(&self) -> usize {
+        self.added
+    }
+
+    /// Total number of matching items to process.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
 impl Binding for Index {
     type Raw = *mut raw::git_index;
     unsafe fn from_raw(raw: *mut raw::git_index) -> Index {
@@ -687,20 +797,87 @@ impl<'index> Iterator for IndexConflicts<'index> {
                 &mut their,
                 self.conflict_iter
             ));
-            Some(Ok(IndexConflict {
-                ancestor: match ancestor.is_null() {
-                    false => Some(IndexEntry::from_raw(*ancestor)),
-                    true => None,
-                },
-                our: match our.is_null() {
-                    false => Some(IndexEntry::from_raw(*our)),
-                    true => None,
-                },
-                their: match their.is_null() {
-                    false => Some(IndexEntry::from_raw(*their)),
-                    true => None,
-                },
-            }))
+            Some(Ok(IndexConflict::from_raw_entries(ancestor, our, their)))
+        }
+    }
+}
+
+impl IndexConflict {
+    unsafe fn from_raw_entries(
+        ancestor: *const raw::git_index_entry,
+        our: *const raw::git_index_entry,
+        their: *const raw::git_index_entry,
+    ) -> IndexConflict {
+        IndexConflict {
+            ancestor: match ancestor.is_null() {
+                false => Some(IndexEntry::from_raw(*ancestor)),
+                true => None,
+            },
+            our: match our.is_null() {
+                false => Some(IndexEntry::from_raw(*our)),
+                true => None,
+            },
+            their: match their.is_null() {
+                false => Some(IndexEntry::from_raw(*their)),
+                true => None,
+            },
+        }
+    }
+}
+
+impl IndexEntry {
+    /// Returns the stage of this entry.
+    ///
+    /// Stage 0 is a normal, unconflicted entry. During a conflict, stage 1
+    /// holds the common ancestor, stage 2 holds "our" side, and stage 3 holds
+    /// "their" side.
+    pub fn stage(&self) -> i32 {
+        ((self.flags & raw::GIT_INDEX_ENTRY_STAGEMASK) >> raw::GIT_INDEX_ENTRY_STAGESHIFT) as i32
+    }
+
+    /// Set the stage of this entry.
+    ///
+    /// The stage is stored in the `flags` field; only the low two bits of
+    /// `stage` are used. See `stage` for the meaning of each value.
+    pub fn set_stage(&mut self, stage: i32) {
+        self.flags = (self.flags & !raw::GIT_INDEX_ENTRY_STAGEMASK)
+            | (((stage as u16) << raw::GIT_INDEX_ENTRY_STAGESHIFT)
+                & raw::GIT_INDEX_ENTRY_STAGEMASK);
+    }
+
+    /// Builds the raw representation of this entry, borrowing `path` for the
+    /// path pointer.
+    pub(crate) unsafe fn to_raw(&self, path: &CString) -> raw::git_index_entry {
+        // libgit2 encodes the length of the path in the lower bits of the
+        // `flags` entry, so mask those out and recalculate here to ensure we
+        // don't corrupt anything.
+        let mut flags = self.flags & !raw::GIT_INDEX_ENTRY_NAMEMASK;
+
+        if self.path.len() < raw::GIT_INDEX_ENTRY_NAMEMASK as usize {
+            flags |= self.path.len() as u16;
+        } else {
+            flags |= raw::GIT_INDEX_ENTRY_NAMEMASK;
+        }
+
+        raw::git_index_entry {
+            dev: self.dev,
+            ino: self.ino,
+            mode: self.mode,
+            uid: self.uid,
+            gid: self.gid,
+            file_size: self.file_size,
+            id: *self.id.raw(),
+            flags,
+            flags_extended: self.flags_extended,
+            path: path.as_ptr(),
+            mtime: raw::git_index_time {
+                seconds: self.mtime.seconds(),
+                nanoseconds: self.mtime.nanoseconds(),
+            },
+            ctime: raw::git_index_time {
+                seconds: self.ctime.seconds(),
+                nanoseconds: self.ctime.nanoseconds(),
+            },
         }
     }
 }
@@ -792,6 +969,64 @@ mod tests {
         index.write_tree_to(&repo).unwrap();
     }
 
+    #[test]
+    fn add_all_with_progress() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut index = repo.index().unwrap();
+
+        let root = repo.path().parent().unwrap();
+        fs::create_dir(&root.join("foo")).unwrap();
+        for name in ["a", "b", "c"].iter() {
+            File::create(&root.join("foo").join(name)).unwrap();
+        }
+        let mut seen = Vec::new();
+        index
+            .add_all_with_progress(
+                ["foo"].iter(),
+                crate::IndexAddOption::DEFAULT,
+                &mut |path: &Path, _: &[u8], progress| {
+                    seen.push((progress.scanned(), progress.added(), progress.total()));
+                    if path == Path::new("foo/b") {
+                        1
+                    } else {
+                        0
+                    }
+                },
+            )
+            .unwrap();
+        assert_eq!(seen, [(1, 0, 3), (2, 1, 3), (3, 1, 3)]);
+        assert!(index.get_path(Path::new("foo/a"), 0).is_some());
+        assert!(index.get_path(Path::new("foo/b"), 0).is_none());
+
+        index.clear().unwrap();
+        let err = index
+            .add_all_with_progress(
+                ["foo"].iter(),
+                crate::IndexAddOption::DEFAULT,
+                &mut |_: &Path, _: &[u8], progress| if progress.scanned() == 2 { -1 } else { 0 },
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::User);
+        assert_eq!(err.class(), crate::ErrorClass::Callback);
+        assert_eq!(index.len(), 1);
+
+        index.clear().unwrap();
+        let mut calls = 0;
+        let err = index
+            .add_all_with_progress(
+                ["foo"].iter(),
+                crate::IndexAddOption::DEFAULT,
+                &mut |_: &Path, _: &[u8], _| {
+                    calls += 1;
+                    i32::MIN
+                },
+            )
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::User);
+        assert_eq!(calls, 1);
+        assert!(index.is_empty());
+    }
+
     #[test]
     fn add_all() {
         let (_td, repo) = crate::test::repo_init();
@@ -910,6 +1145,38 @@ mod tests {
         assert_eq!(b.content(), content);
     }
 
+    #[test]
+    fn stages_and_conflicts() {
+        let mut index = Index::new().unwrap();
+        let mut e = entry();
+        e.path = b"foo".to_vec();
+        assert_eq!(e.stage(), 0);
+        e.set_stage(2);
+        assert_eq!(e.stage(), 2);
+        index.add(&e).unwrap();
+        assert_eq!(index.get_path(Path::new("foo"), 2).unwrap().stage(), 2);
+        assert!(index.get_path(Path::new("foo"), 0).is_none());
+
+        let mut ancestor = entry();
+        ancestor.path = b"bar".to_vec();
+        let mut their = entry();
+        their.path = b"bar".to_vec();
+        index
+            .conflict_add(Some(&ancestor), None, Some(&their))
+            .unwrap();
+        assert!(index.has_conflicts());
+        let conflict = index.conflict_get(Path::new("bar")).unwrap();
+        assert_eq!(conflict.ancestor.unwrap().stage(), 1);
+        assert!(conflict.our.is_none());
+        assert_eq!(conflict.their.unwrap().stage(), 3);
+
+        index.conflict_remove(Path::new("bar")).unwrap();
+        assert_eq!(
+            index.conflict_get(Path::new("bar")).err().unwrap().code(),
+            ErrorCode::NotFound
+        );
+    }
+
     fn entry() -> IndexEntry {
         IndexEntry {
             ctime: IndexTime::new(0, 0),
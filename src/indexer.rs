@@ -1,5 +1,6 @@
 use std::ffi::CStr;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::{io, marker, mem, ptr};
 
 use libc::c_void;
@@ -109,6 +110,9 @@ pub struct Indexer<'odb> {
     raw: *mut raw::git_indexer,
     progress: raw::git_indexer_progress,
     progress_payload_ptr: *mut OdbPackwriterCb<'odb>,
+    path: PathBuf,
+    keep: Option<String>,
+    trailer: Vec<u8>,
 }
 
 impl<'a> Indexer<'a> {
@@ -121,7 +125,9 @@ impl<'a> Indexer<'a> {
     /// `mode` is the permissions to use for the output files, use `0` for defaults.
     ///
     /// If `verify` is `false`, the indexer will bypass object connectivity checks.
+    /// The checksum at the end of the pack is always verified.
     pub fn new(odb: Option<&Odb<'a>>, path: &Path, mode: u32, verify: bool) -> Result<Self, Error> {
+        let dir = path.to_path_buf();
         let path = path.into_c_string()?;
 
         let odb = odb.map(Binding::raw).unwrap_or_else(ptr::null_mut);
@@ -148,16 +154,100 @@ impl<'a> Indexer<'a> {
             raw: out,
             progress: Default::default(),
             progress_payload_ptr,
+            path: dir,
+            keep: None,
+            trailer: Vec::new(),
         })
     }
 
+    /// Write a `.keep` file along with the pack when it is committed.
+    ///
+    /// The `.keep` file holds `reason` and prevents `git gc` and `git repack`
+    /// from deleting or repacking the pack, for example while the references
+    /// pointing into it are being updated. It is written before the pack and
+    /// its index are moved into place, so the pack is never visible without
+    /// it. It is not written by default.
+    pub fn keep(&mut self, reason: &str) -> &mut Self {
+        self.keep = Some(reason.to_string());
+        self
+    }
+
+    /// Append all the data of `reader` to the pack.
+    ///
+    /// This reads the pack incrementally, so it can be used to index a pack
+    /// arriving over a socket or any other stream without buffering it, such
+    /// as a pack downloaded over HTTP from a CDN. Returns the number of bytes read.
+    pub fn append_from<R: io::Read>(&mut self, reader: &mut R) -> Result<u64, Error> {
+        let mut buf = vec![0; 64 * 1024];
+        let mut total = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => return Ok(total),
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::from(e)),
+            };
+            unsafe {
+                try_call!(raw::git_indexer_append(
+                    self.raw,
+                    buf.as_ptr() as *const c_void,
+                    n,
+                    &mut self.progress
+                ));
+            }
+            self.track_trailer(&buf[..n]);
+            total += n as u64;
+        }
+    }
+
     /// Finalize the pack and index
     ///
     /// Resolves any pending deltas and writes out the index file. The returned
     /// string is the hexadecimal checksum of the packfile, which is also used
     /// to name the pack and index files (`pack-<checksum>.pack` and
     /// `pack-<checksum>.idx` respectively).
+    ///
+    /// If requested with `keep`, the `.keep` file is written first, named
+    /// after the checksum that ends the received data. libgit2 rewrites that
+    /// checksum when it completes a thin pack with local objects, in which
+    /// case the `.keep` file is renamed once the pack is in place.
     pub fn commit(mut self) -> Result<String, Error> {
+        let early_keep = match self.keep {
+            Some(ref reason) if self.trailer.len() == raw::GIT_OID_RAWSZ => {
+                let checksum = self
+                    .trailer
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                let keep = self.path.join(format!("pack-{}.keep", checksum));
+                fs::write(&keep, format!("{}\n", reason))?;
+                Some(keep)
+            }
+            _ => None,
+        };
+        let name = match self.finalize() {
+            Ok(name) => name,
+            Err(e) => {
+                if let Some(ref keep) = early_keep {
+                    let _ = fs::remove_file(keep);
+                }
+                return Err(e);
+            }
+        };
+        if let Some(ref reason) = self.keep {
+            let keep = self.path.join(format!("pack-{}.keep", name));
+            match early_keep {
+                Some(ref early) if *early == keep => {}
+                Some(ref early) => fs::rename(early, keep)?,
+                None => fs::write(keep, format!("{}\n", reason))?,
+            }
+        }
+        Ok(name)
+    }
+
+    /// Resolve pending deltas, write the index and move the pack and index
+    /// into place, returning the name of the pack.
+    fn finalize(&mut self) -> Result<String, Error> {
         unsafe {
             try_call!(raw::git_indexer_commit(self.raw, &mut self.progress));
 
@@ -166,6 +256,20 @@ impl<'a> Indexer<'a> {
         }
     }
 
+    /// Remember the last bytes appended, which end up holding the checksum
+    /// of the pack.
+    fn track_trailer(&mut self, data: &[u8]) {
+        let len = raw::GIT_OID_RAWSZ;
+        if data.len() >= len {
+            self.trailer.clear();
+            self.trailer.extend_from_slice(&data[data.len() - len..]);
+        } else {
+            self.trailer.extend_from_slice(data);
+            let excess = self.trailer.len().saturating_sub(len);
+            self.trailer.drain(..excess);
+        }
+    }
+
     /// The callback through which progress is monitored. Be aware that this is
     /// called inline, so performance may be affected.
     pub fn progress<F>(&mut self, cb: F) -> &mut Self
@@ -188,11 +292,11 @@ impl io::Write for Indexer<'_> {
 
             let res = raw::git_indexer_append(self.raw, ptr, len, &mut self.progress);
             if res < 0 {
-                Err(io::Error::new(io::ErrorKind::Other, Error::last_error(res)))
-            } else {
-                Ok(buf.len())
+                return Err(io::Error::new(io::ErrorKind::Other, Error::last_error(res)));
             }
         }
+        self.track_trailer(buf);
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -249,4 +353,36 @@ mod tests {
         assert_eq!(commit_target.id(), commit_source_id);
         assert!(progress_called);
     }
+
+    #[test]
+    fn append_from_keep() {
+        let (_td, repo_source) = crate::test::repo_init();
+        let (_td, repo_target) = crate::test::repo_init();
+
+        let mut builder = t!(repo_source.packbuilder());
+        let mut buf = Buf::new();
+        let (commit_source_id, _tree) = crate::test::commit(&repo_source);
+        // Pack the whole history, so that the verified pack is complete.
+        let mut walk = t!(repo_source.revwalk());
+        t!(walk.push(commit_source_id));
+        t!(builder.insert_walk(&mut walk));
+        t!(builder.write_buf(&mut buf));
+
+        let dir = repo_target.path().join("objects").join("pack");
+        let mut indexer = t!(Indexer::new(None, &dir, 0, true));
+        indexer.keep("receive-pack");
+        let read = t!(indexer.append_from(&mut &buf[..]));
+        assert_eq!(read, buf.len() as u64);
+        let name = t!(indexer.commit());
+
+        let keep = t!(std::fs::read_to_string(
+            dir.join(format!("pack-{}.keep", name))
+        ));
+        assert_eq!(keep, "receive-pack\n");
+        t!(repo_target.find_commit(commit_source_id));
+
+        let mut indexer = t!(Indexer::new(None, &dir, 0, true));
+        t!(indexer.append_from(&mut &buf[..buf.len() - 1]));
+        assert!(indexer.commit().is_err());
+    }
 }
@@ -80,46 +80,65 @@ use std::str;
 use std::sync::Once;
 
 pub use crate::apply::{ApplyLocation, ApplyOptions};
-pub use crate::attr::AttrValue;
-pub use crate::blame::{Blame, BlameHunk, BlameIter, BlameOptions};
+pub use crate::attr::{AttrOptions, AttrValue};
+pub use crate::blame::{Blame, BlameHunk, BlameIter, BlameOptions, BlameProgress};
 pub use crate::blob::{Blob, BlobWriter};
 pub use crate::branch::{Branch, Branches};
 pub use crate::buf::Buf;
+pub use crate::bundle::{Bundle, BundleBuilder};
 pub use crate::cherrypick::CherrypickOptions;
-pub use crate::commit::{Commit, Parents};
+pub use crate::clean::CleanOptions;
+pub use crate::commit::{Commit, CommitHeaderField, CommitHeaderFields, Parents};
+pub use crate::commit_graph::{CommitGraph, CommitGraphWriter};
 pub use crate::config::{Config, ConfigEntries, ConfigEntry};
+pub use crate::config_section::{ConfigColor, ConfigColorValue, ConfigSection, FromConfig};
 pub use crate::cred::{Cred, CredentialHelper};
 pub use crate::describe::{Describe, DescribeFormatOptions, DescribeOptions};
-pub use crate::diff::{Deltas, Diff, DiffDelta, DiffFile, DiffOptions};
+pub use crate::diff::{Deltas, Diff, DiffDelta, DiffFile, DiffFileStats, DiffOptions};
 pub use crate::diff::{DiffBinary, DiffBinaryFile, DiffBinaryKind, DiffPatchidOptions};
 pub use crate::diff::{DiffFindOptions, DiffHunk, DiffLine, DiffLineType, DiffStats};
+pub use crate::diff::{DiffNotifyCb, DiffProgressCb};
 pub use crate::email::{Email, EmailCreateOptions};
 pub use crate::error::Error;
+pub use crate::fetch_head::FetchHeadEntry;
+pub use crate::gc::{GcOptions, GcProgress, GcStage, RefStats};
+pub use crate::hash::HashingWriter;
+pub use crate::history::FileHistory;
 pub use crate::index::{
-    Index, IndexConflict, IndexConflicts, IndexEntries, IndexEntry, IndexMatchedPath,
+    Index, IndexAddProgress, IndexAddProgressCb, IndexConflict, IndexConflicts, IndexEntries,
+    IndexEntry, IndexMatchedPath,
 };
 pub use crate::indexer::{Indexer, IndexerProgress, Progress};
-pub use crate::mailmap::Mailmap;
+pub use crate::mailmap::{Mailmap, MailmapEntry};
 pub use crate::mempack::Mempack;
-pub use crate::merge::{AnnotatedCommit, MergeOptions};
+pub use crate::merge::{
+    AnnotatedCommit, MergeBaseTree, MergeFileOptions, MergeFileResult, MergeOptions,
+    MergeRenamePrediction,
+};
 pub use crate::message::{
-    message_prettify, message_trailers_bytes, message_trailers_strs, MessageTrailersBytes,
-    MessageTrailersBytesIterator, MessageTrailersStrs, MessageTrailersStrsIterator,
+    message_prettify, message_trailers_bytes, message_trailers_strs, MessageTrailerEditor,
+    MessageTrailersBytes, MessageTrailersBytesIterator, MessageTrailersStrs,
+    MessageTrailersStrsIterator, TrailerIfExists, TrailerIfMissing, TrailerWhere,
     DEFAULT_COMMENT_CHAR,
 };
-pub use crate::note::{Note, Notes};
+pub use crate::note::{Note, Notes, NotesMergeOutcome, NotesMergeStrategy};
 pub use crate::object::Object;
-pub use crate::odb::{Odb, OdbObject, OdbPackwriter, OdbReader, OdbWriter};
+pub use crate::odb::{Odb, OdbObject, OdbObjects, OdbPackwriter, OdbReader, OdbWriter, Packfile};
+pub use crate::odb_backend::{DiskBackend, LayeredBackend, OdbBackend, OdbLayer, OdbOperation};
 pub use crate::oid::Oid;
 pub use crate::packbuilder::{PackBuilder, PackBuilderStage};
-pub use crate::patch::Patch;
+pub use crate::patch::{Patch, SideBySideKind, SideBySideLine, SideBySideRow};
 pub use crate::pathspec::{Pathspec, PathspecFailedEntries, PathspecMatchList};
 pub use crate::pathspec::{PathspecDiffEntries, PathspecEntries};
+pub use crate::pickaxe::{PickaxeMatch, PickaxeOptions};
 pub use crate::proxy_options::ProxyOptions;
 pub use crate::push_update::PushUpdate;
-pub use crate::rebase::{Rebase, RebaseOperation, RebaseOperationType, RebaseOptions};
+pub use crate::rebase::{
+    CommitCreateCb, Rebase, RebaseOperation, RebaseOperationType, RebaseOptions, RebaseTodo,
+    RebaseTodoItem, RebasedCommits,
+};
 pub use crate::reference::{Reference, ReferenceNames, References};
-pub use crate::reflog::{Reflog, ReflogEntry, ReflogIter};
+pub use crate::reflog::{Reflog, ReflogEntry, ReflogExpireOptions, ReflogIter};
 pub use crate::refspec::Refspec;
 pub use crate::remote::{
     FetchOptions, PushOptions, Refspecs, Remote, RemoteConnection, RemoteHead, RemoteRedirect,
@@ -127,22 +146,36 @@ pub use crate::remote::{
 pub use crate::remote_callbacks::{CertificateCheckStatus, Credentials, RemoteCallbacks};
 pub use crate::remote_callbacks::{TransportMessage, UpdateTips};
 pub use crate::repo::{Repository, RepositoryInitOptions};
+pub use crate::rerere::RerereStatus;
 pub use crate::revert::RevertOptions;
 pub use crate::revspec::Revspec;
 pub use crate::revwalk::Revwalk;
+pub use crate::rewrite::{CommitRewrite, HistoryRewrite};
+pub use crate::sequencer::Sequencer;
 pub use crate::signature::Signature;
 pub use crate::stash::{StashApplyOptions, StashApplyProgressCb, StashCb, StashSaveOptions};
 pub use crate::status::{StatusEntry, StatusIter, StatusOptions, StatusShow, Statuses};
-pub use crate::submodule::{Submodule, SubmoduleUpdateOptions};
-pub use crate::tag::Tag;
+pub use crate::submodule::{
+    Submodule, SubmoduleAddOptions, SubmoduleStatusEntry, SubmoduleUpdateAllOptions,
+    SubmoduleUpdateOptions, SubmoduleUpdateProgress, SubmoduleUpdateResults,
+};
+pub use crate::tag::{Tag, TagInfo};
 pub use crate::time::{IndexTime, Time};
+#[cfg(feature = "tracing")]
+pub use crate::tracing::trace_set_tracing;
 pub use crate::tracing::{trace_set, TraceLevel};
-pub use crate::transaction::Transaction;
+pub use crate::transaction::{RefUpdates, Transaction};
 pub use crate::tree::{Tree, TreeEntry, TreeIter, TreeWalkMode, TreeWalkResult};
 pub use crate::treebuilder::TreeBuilder;
 pub use crate::util::IntoCString;
+#[cfg(feature = "signature-commands")]
+pub use crate::verify::{GpgVerifier, SshVerifier};
+pub use crate::verify::{SignatureVerdict, SignatureVerification, SignatureVerifier};
 pub use crate::version::Version;
-pub use crate::worktree::{Worktree, WorktreeAddOptions, WorktreeLockStatus, WorktreePruneOptions};
+pub use crate::worktree::{
+    Worktree, WorktreeAddOptions, WorktreeHead, WorktreeInfo, WorktreeLockStatus,
+    WorktreePruneOptions,
+};
 
 // Create a convinience method on bitflag struct which checks the given flag
 macro_rules! is_bit_set {
@@ -220,6 +253,12 @@ pub enum ErrorCode {
     Owner,
     /// Timeout
     Timeout,
+    /// There were no changes
+    Unchanged,
+    /// An option is not supported
+    NotSupported,
+    /// The subject is read-only
+    ReadOnly,
 }
 
 /// An enumeration of possible categories of things that can have
@@ -688,6 +727,7 @@ mod util;
 
 pub mod build;
 pub mod cert;
+pub mod merge_driver;
 pub mod oid_array;
 pub mod opts;
 pub mod string_array;
@@ -698,14 +738,22 @@ mod blame;
 mod blob;
 mod branch;
 mod buf;
+mod bundle;
 mod cherrypick;
+mod clean;
 mod commit;
+mod commit_graph;
 mod config;
+mod config_section;
 mod cred;
 mod describe;
 mod diff;
 mod email;
 mod error;
+mod fetch_head;
+mod gc;
+mod hash;
+mod history;
 mod index;
 mod indexer;
 mod mailmap;
@@ -715,10 +763,12 @@ mod message;
 mod note;
 mod object;
 mod odb;
+mod odb_backend;
 mod oid;
 mod packbuilder;
 mod patch;
 mod pathspec;
+mod pickaxe;
 mod proxy_options;
 mod push_update;
 mod rebase;
@@ -728,9 +778,12 @@ mod refspec;
 mod remote;
 mod remote_callbacks;
 mod repo;
+mod rerere;
 mod revert;
 mod revspec;
 mod revwalk;
+mod rewrite;
+mod sequencer;
 mod signature;
 mod stash;
 mod status;
@@ -742,6 +795,7 @@ mod tracing;
 mod transaction;
 mod tree;
 mod treebuilder;
+mod verify;
 mod version;
 mod worktree;
 
@@ -1529,6 +1583,13 @@ bitflags! {
         const INDEX_ONLY = raw::GIT_ATTR_CHECK_INDEX_ONLY as u32;
         /// Do not use the system gitattributes file.
         const NO_SYSTEM = raw::GIT_ATTR_CHECK_NO_SYSTEM as u32;
+        /// Also check the gitattributes file in the tree of HEAD.
+        const INCLUDE_HEAD = raw::GIT_ATTR_CHECK_INCLUDE_HEAD as u32;
+        /// Also check the gitattributes file in the tree of a given commit.
+        ///
+        /// The commit is given with `AttrOptions::commit`, or a tree with
+        /// `AttrOptions::tree`, either of which sets this flag.
+        const INCLUDE_COMMIT = raw::GIT_ATTR_CHECK_INCLUDE_COMMIT as u32;
     }
 }
 
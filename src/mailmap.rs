@@ -1,21 +1,39 @@
 use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::mem;
 use std::ptr;
 
 use crate::util::Binding;
-use crate::{raw, Error, Signature};
+use crate::{raw, Error, ErrorCode, ObjectType, Repository, Signature};
 
 /// A structure to represent a repository's .mailmap file.
 ///
-/// The representation cannot be written to disk.
+/// Entries can be added, removed and merged from other mailmaps, and the
+/// result written back with `to_buffer`.
 pub struct Mailmap {
     raw: *mut raw::git_mailmap,
+    entries: Vec<MailmapEntry>,
+}
+
+/// An entry of a `Mailmap`, mapping a name and email address as found in
+/// commits to the real ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MailmapEntry {
+    real_name: Option<String>,
+    real_email: Option<String>,
+    replace_name: Option<String>,
+    replace_email: String,
 }
 
 impl Binding for Mailmap {
     type Raw = *mut raw::git_mailmap;
 
     unsafe fn from_raw(ptr: *mut raw::git_mailmap) -> Mailmap {
-        Mailmap { raw: ptr }
+        Mailmap {
+            raw: ptr,
+            entries: Vec::new(),
+        }
     }
 
     fn raw(&self) -> *mut raw::git_mailmap {
@@ -47,14 +65,21 @@ impl Mailmap {
         crate::init();
         let mut ret = ptr::null_mut();
         let len = buf.len();
-        let buf = CString::new(buf)?;
+        let cbuf = CString::new(buf)?;
         unsafe {
-            try_call!(raw::git_mailmap_from_buffer(&mut ret, buf, len));
-            Ok(Binding::from_raw(ret))
+            try_call!(raw::git_mailmap_from_buffer(&mut ret, cbuf, len));
+        }
+        let mut mailmap: Mailmap = unsafe { Binding::from_raw(ret) };
+        for entry in parse(buf) {
+            mailmap.record(entry);
         }
+        Ok(mailmap)
     }
 
     /// Adds a new entry to this in-memory mailmap object.
+    ///
+    /// An entry with the same `replace_name` and `replace_email` is
+    /// replaced.
     pub fn add_entry(
         &mut self,
         real_name: Option<&str>,
@@ -62,19 +87,84 @@ impl Mailmap {
         replace_name: Option<&str>,
         replace_email: &str,
     ) -> Result<(), Error> {
-        let real_name = crate::opt_cstr(real_name)?;
-        let real_email = crate::opt_cstr(real_email)?;
-        let replace_name = crate::opt_cstr(replace_name)?;
-        let replace_email = CString::new(replace_email)?;
+        let entry = MailmapEntry {
+            real_name: real_name.filter(|s| !s.is_empty()).map(String::from),
+            real_email: real_email.filter(|s| !s.is_empty()).map(String::from),
+            replace_name: replace_name.filter(|s| !s.is_empty()).map(String::from),
+            replace_email: replace_email.to_string(),
+        };
+        add_raw(self.raw, &entry)?;
+        self.record(entry);
+        Ok(())
+    }
+
+    /// Removes the entry for `replace_name` and `replace_email`.
+    ///
+    /// Returns whether there was such an entry.
+    pub fn remove_entry(
+        &mut self,
+        replace_name: Option<&str>,
+        replace_email: &str,
+    ) -> Result<bool, Error> {
+        let replace_name = replace_name.filter(|s| !s.is_empty());
+        let index = self.entries.iter().position(|e| {
+            e.replace_name.as_deref() == replace_name && e.replace_email == replace_email
+        });
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+
+        // libgit2 can't remove entries, so build the map again without it.
+        let mut ret = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_mailmap_add_entry(
-                self.raw,
-                real_name,
-                real_email,
-                replace_name,
-                replace_email
-            ));
-            Ok(())
+            try_call!(raw::git_mailmap_new(&mut ret));
+        }
+        let mut mailmap: Mailmap = unsafe { Binding::from_raw(ret) };
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i != index {
+                add_raw(mailmap.raw, entry)?;
+            }
+        }
+        mem::swap(&mut self.raw, &mut mailmap.raw);
+        self.entries.remove(index);
+        Ok(true)
+    }
+
+    /// Adds all the entries of `other` to this mailmap, replacing the ones
+    /// with the same `replace_name` and `replace_email`.
+    pub fn merge(&mut self, other: &Mailmap) -> Result<(), Error> {
+        for entry in &other.entries {
+            add_raw(self.raw, entry)?;
+            self.record(entry.clone());
+        }
+        Ok(())
+    }
+
+    /// Returns the entries of this mailmap.
+    pub fn entries(&self) -> &[MailmapEntry] {
+        &self.entries
+    }
+
+    /// Serializes this mailmap in the format of a `.mailmap` file.
+    ///
+    /// Comments and the layout of the file this mailmap was read from are
+    /// not kept.
+    pub fn to_buffer(&self) -> String {
+        let mut buf = String::new();
+        for entry in &self.entries {
+            buf.push_str(&entry.to_string());
+            buf.push('\n');
+        }
+        buf
+    }
+
+    fn record(&mut self, entry: MailmapEntry) {
+        match self.entries.iter_mut().find(|e| {
+            e.replace_name == entry.replace_name && e.replace_email == entry.replace_email
+        }) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
         }
     }
 
@@ -92,6 +182,163 @@ impl Mailmap {
     }
 }
 
+impl MailmapEntry {
+    /// The real name, or `None` to keep the name found in commits.
+    pub fn real_name(&self) -> Option<&str> {
+        self.real_name.as_deref()
+    }
+
+    /// The real email address, or `None` to keep the address found in
+    /// commits.
+    pub fn real_email(&self) -> Option<&str> {
+        self.real_email.as_deref()
+    }
+
+    /// The name found in commits, or `None` to match any name.
+    pub fn replace_name(&self) -> Option<&str> {
+        self.replace_name.as_deref()
+    }
+
+    /// The email address found in commits.
+    pub fn replace_email(&self) -> &str {
+        &self.replace_email
+    }
+}
+
+impl fmt::Display for MailmapEntry {
+    /// Formats this entry as a line of a `.mailmap` file.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.real_name {
+            write!(f, "{} ", name)?;
+        }
+        // A second name can only be given after a real email, so use the
+        // replaced one, which amounts to the same.
+        match (&self.real_email, &self.replace_name) {
+            (Some(email), _) => write!(f, "<{}> ", email)?,
+            (None, Some(_)) => write!(f, "<{}> ", self.replace_email)?,
+            (None, None) => {}
+        }
+        if let Some(name) = &self.replace_name {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "<{}>", self.replace_email)
+    }
+}
+
+fn add_raw(mailmap: *mut raw::git_mailmap, entry: &MailmapEntry) -> Result<(), Error> {
+    let real_name = crate::opt_cstr(entry.real_name.as_deref())?;
+    let real_email = crate::opt_cstr(entry.real_email.as_deref())?;
+    let replace_name = crate::opt_cstr(entry.replace_name.as_deref())?;
+    let replace_email = CString::new(&entry.replace_email[..])?;
+    unsafe {
+        try_call!(raw::git_mailmap_add_entry(
+            mailmap,
+            real_name,
+            real_email,
+            replace_name,
+            replace_email
+        ));
+    }
+    Ok(())
+}
+
+/// Parse the entries of a `.mailmap` file the way libgit2 does, skipping
+/// invalid lines.
+fn parse(buf: &str) -> Vec<MailmapEntry> {
+    fn name_and_email(s: &str) -> Option<(Option<String>, String, &str)> {
+        let start = s.find('<')?;
+        let end = start + s[start..].find('>')?;
+        let name = s[..start].trim();
+        let name = if name.is_empty() {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        Some((name, s[start + 1..end].to_string(), &s[end + 1..]))
+    }
+
+    let mut entries = Vec::new();
+    for line in buf.lines() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (real_name, real_email, rest) = match name_and_email(line) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let entry = match name_and_email(rest) {
+            Some((replace_name, replace_email, _)) => MailmapEntry {
+                real_name,
+                real_email: Some(real_email).filter(|s| !s.is_empty()),
+                replace_name,
+                replace_email,
+            },
+            None => MailmapEntry {
+                real_name,
+                real_email: None,
+                replace_name: None,
+                replace_email: real_email,
+            },
+        };
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Loads the mailmap of `repo`, from the same sources as libgit2: the
+/// `.mailmap` file of the working directory, the blob named by
+/// `mailmap.blob` (`HEAD:.mailmap` in bare repositories) and the file
+/// named by `mailmap.file`.
+///
+/// libgit2 loads the sources, which are then read again to list their
+/// entries. Like libgit2, sources which can't be read are skipped.
+pub(crate) fn from_repository(repo: &Repository) -> Result<Mailmap, Error> {
+    let mut ret = ptr::null_mut();
+    unsafe {
+        try_call!(raw::git_mailmap_from_repository(&mut ret, repo.raw()));
+    }
+    let mut mailmap: Mailmap = unsafe { Binding::from_raw(ret) };
+
+    let mut bufs = Vec::new();
+    if let Some(workdir) = repo.workdir() {
+        bufs.extend(fs::read(workdir.join(".mailmap")).ok());
+    }
+    let config = repo.config()?;
+    let blob = match config.get_string("mailmap.blob") {
+        Ok(blob) => Some(blob),
+        Err(e) if e.code() == ErrorCode::NotFound && repo.is_bare() => {
+            Some("HEAD:.mailmap".to_string())
+        }
+        Err(_) => None,
+    };
+    if let Some(blob) = blob {
+        let blob = repo
+            .revparse_single(&blob)
+            .and_then(|object| object.peel(ObjectType::Blob));
+        if let Some(blob) = blob.ok().and_then(|object| object.into_blob().ok()) {
+            bufs.push(blob.content().to_vec());
+        }
+    }
+    if let Ok(path) = config.get_path("mailmap.file") {
+        let path = match repo.workdir() {
+            Some(workdir) => workdir.join(path),
+            None => path,
+        };
+        bufs.extend(fs::read(path).ok());
+    }
+    // libgit2 parses the raw bytes, while the listed entries are decoded
+    // lossily. Adding them again replaces the entries of libgit2 with the
+    // same keys, so that `entries` lists what signatures are resolved with.
+    for buf in bufs {
+        for entry in parse(&String::from_utf8_lossy(&buf)) {
+            add_raw(mailmap.raw, &entry)?;
+            mailmap.record(entry);
+        }
+    }
+    Ok(mailmap)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -131,4 +378,59 @@ mod tests {
         assert_eq!(mailmapped_sig.name(), Some("name"));
         assert_eq!(mailmapped_sig.email(), Some("prøper@emæil"));
     }
+
+    #[test]
+    fn edit() {
+        let buf = "# comment\n\
+                   Real Name <real@email> <old@email>\n\
+                   <real@email> Old Name <other@email>\n\
+                   Name Only <name@email>\n";
+        let mut mm = t!(Mailmap::from_buffer(buf));
+        assert_eq!(mm.entries().len(), 3);
+        assert_eq!(mm.entries()[1].real_name(), None);
+        assert_eq!(mm.entries()[1].replace_name(), Some("Old Name"));
+        assert_eq!(
+            mm.to_buffer(),
+            "Real Name <real@email> <old@email>\n\
+             <real@email> Old Name <other@email>\n\
+             Name Only <name@email>\n"
+        );
+
+        let sig = t!(Signature::now("Old Name", "other@email"));
+        assert_eq!(t!(mm.resolve_signature(&sig)).email(), Some("real@email"));
+        assert!(t!(mm.remove_entry(Some("Old Name"), "other@email")));
+        assert!(!t!(mm.remove_entry(None, "other@email")));
+        assert_eq!(t!(mm.resolve_signature(&sig)).email(), Some("other@email"));
+
+        let mut other = t!(Mailmap::new());
+        t!(other.add_entry(Some("New Name"), None, Some("Old Name"), "other@email"));
+        t!(other.add_entry(Some("Name"), None, None, "name@email"));
+        t!(mm.merge(&other));
+        assert_eq!(
+            mm.to_buffer(),
+            "Real Name <real@email> <old@email>\n\
+             Name <name@email>\n\
+             New Name <other@email> Old Name <other@email>\n"
+        );
+        assert_eq!(t!(mm.resolve_signature(&sig)).name(), Some("New Name"));
+
+        let reparsed = t!(Mailmap::from_buffer(&mm.to_buffer()));
+        assert_eq!(reparsed.to_buffer(), mm.to_buffer());
+    }
+
+    #[test]
+    fn from_repository() {
+        let (td, repo) = crate::test::repo_init();
+        t!(fs::write(
+            td.path().join(".mailmap"),
+            b"N\xe4me <real@email> <old@email>\n"
+        ));
+        let mm = t!(repo.mailmap());
+        assert_eq!(mm.to_buffer(), "N\u{fffd}me <real@email> <old@email>\n");
+
+        let sig = t!(Signature::now("Old", "old@email"));
+        let resolved = t!(mm.resolve_signature(&sig));
+        assert_eq!(resolved.name(), mm.entries()[0].real_name());
+        assert_eq!(resolved.email(), Some("real@email"));
+    }
 }
@@ -1,3 +1,4 @@
+use std::io::Write;
 use std::marker;
 
 use crate::util::Binding;
@@ -39,6 +40,25 @@ impl<'odb> Mempack<'odb> {
         Ok(())
     }
 
+    /// Write the objects of the mempack to a new packfile of `odb`, along with
+    /// its index, and then clear the mempack.
+    ///
+    /// This is the usual way to commit the objects accumulated in memory to
+    /// the repository, usually into its `objects/pack` directory. `repo` must
+    /// be the repository whose object database holds the mempack. Nothing is
+    /// written if the mempack is empty.
+    pub fn write_to_odb(&self, repo: &Repository, odb: &Odb<'_>) -> Result<(), Error> {
+        let mut buf = Buf::new();
+        self.dump(repo, &mut buf)?;
+        // A pack without objects is just its header and trailer.
+        if buf.get(8..12) != Some(&[0, 0, 0, 0][..]) {
+            let mut writer = odb.packwriter()?;
+            writer.write_all(&buf).map_err(Error::from)?;
+            writer.commit()?;
+        }
+        self.reset()
+    }
+
     /// Clears all data in the mempack.
     pub fn reset(&self) -> Result<(), Error> {
         unsafe {
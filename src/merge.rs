@@ -1,11 +1,16 @@
-use libc::c_uint;
+use libc::{c_uint, c_ushort};
+use std::collections::HashSet;
+use std::ffi::CString;
 use std::marker;
 use std::mem;
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
 use std::str;
 
 use crate::call::Convert;
 use crate::util::Binding;
-use crate::{raw, Commit, FileFavor, Oid};
+use crate::{raw, Commit, Error, ErrorCode, FileFavor, IntoCString, Oid, Repository, Tree};
 
 /// A structure to represent an annotated commit, the input to merge and rebase.
 ///
@@ -22,6 +27,55 @@ pub struct MergeOptions {
     raw: raw::git_merge_options,
 }
 
+/// The merge base that a merge of two commits is performed against.
+///
+/// Produced by `Repository::merge_base_tree`. When the commits have more than
+/// one merge base, as after criss-cross merges, the bases are merged together
+/// into a virtual base the same way a recursive merge does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeBaseTree {
+    tree: Option<Oid>,
+    bases: Vec<Oid>,
+    is_virtual: bool,
+    conflicts: Vec<PathBuf>,
+}
+
+/// A merge base being computed, either a commit or a virtual commit made by
+/// merging several of them, like libgit2's annotated commits.
+enum Base {
+    Commit(Oid),
+    Virtual { tree: Oid, parents: Vec<Oid> },
+}
+
+/// A prediction of how rename detection behaves when merging a set of trees.
+///
+/// Produced by `Repository::predict_merge_renames`. Inexact rename detection
+/// compares every added file with every deleted file, so libgit2 skips it
+/// when a merge changes more paths than the target limit, silently leaving
+/// renamed files as a deletion and an addition. libgit2 does not report
+/// whether that happened, so this is computed from the trees and options
+/// the way libgit2 decides it, without running the merge.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MergeRenamePrediction {
+    find_renames: bool,
+    threshold: u32,
+    target_limit: u32,
+    changed_paths: usize,
+}
+
+/// Options for merging the contents of a single file.
+pub struct MergeFileOptions {
+    ancestor_label: Option<CString>,
+    our_label: Option<CString>,
+    their_label: Option<CString>,
+    raw: raw::git_merge_file_options,
+}
+
+/// The result of merging the contents of a single file.
+pub struct MergeFileResult {
+    raw: raw::git_merge_file_result,
+}
+
 impl<'repo> AnnotatedCommit<'repo> {
     /// Gets the commit ID that the given git_annotated_commit refers to
     pub fn id(&self) -> Oid {
@@ -66,7 +120,11 @@ impl MergeOptions {
         self
     }
 
-    /// Detect file renames
+    /// Detect file renames.
+    ///
+    /// This is enabled by default. Disabling it skips rename detection
+    /// entirely, which makes merges with many changed paths much faster but
+    /// turns renames into a deletion and an addition.
     pub fn find_renames(&mut self, find: bool) -> &mut MergeOptions {
         self.flag(raw::GIT_MERGE_FIND_RENAMES as u32, find)
     }
@@ -79,7 +137,7 @@ impl MergeOptions {
 
     /// Do not write the REUC extension on the generated index
     pub fn skip_reuc(&mut self, skip: bool) -> &mut MergeOptions {
-        self.flag(raw::GIT_MERGE_FAIL_ON_CONFLICT as u32, skip)
+        self.flag(raw::GIT_MERGE_SKIP_REUC as u32, skip)
     }
 
     /// If the commits being merged have multiple merge bases, do not build a
@@ -95,10 +153,14 @@ impl MergeOptions {
         self
     }
 
-    ///  Maximum similarity sources to examine for renames (default 200).
-    /// If the number of rename candidates (add / delete pairs) is greater
-    /// than this value, inexact rename detection is aborted. This setting
-    /// overrides the `merge.renameLimit` configuration value.
+    /// Maximum number of changed paths for which inexact rename detection
+    /// is performed, which is git's rename limit.
+    ///
+    /// If a merge changes more paths than this, only exact renames are
+    /// detected; `Repository::predict_merge_renames` predicts whether that
+    /// happens. This setting overrides the `merge.renameLimit` and
+    /// `diff.renameLimit` configuration values, and defaults to 1000 when
+    /// neither is set.
     pub fn target_limit(&mut self, limit: u32) -> &mut MergeOptions {
         self.raw.target_limit = limit as c_uint;
         self
@@ -138,6 +200,12 @@ impl MergeOptions {
         self.file_flag(raw::GIT_MERGE_FILE_STYLE_DIFF3 as u32, diff3)
     }
 
+    /// Create zdiff3-style file, which moves lines common to both sides out
+    /// of the conflict
+    pub fn zdiff3_style(&mut self, zdiff3: bool) -> &mut MergeOptions {
+        self.file_flag(raw::GIT_MERGE_FILE_STYLE_ZDIFF3 as u32, zdiff3)
+    }
+
     /// Condense non-alphanumeric regions for simplified diff file
     pub fn simplify_alnum(&mut self, simplify: bool) -> &mut MergeOptions {
         self.file_flag(raw::GIT_MERGE_FILE_SIMPLIFY_ALNUM as u32, simplify)
@@ -174,6 +242,461 @@ impl MergeOptions {
     }
 }
 
+impl Default for MergeFileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeFileOptions {
+    /// Creates a default set of file merge options.
+    pub fn new() -> MergeFileOptions {
+        let mut opts = MergeFileOptions {
+            ancestor_label: None,
+            our_label: None,
+            their_label: None,
+            raw: unsafe { mem::zeroed() },
+        };
+        assert_eq!(
+            unsafe {
+                raw::git_merge_file_options_init(&mut opts.raw, raw::GIT_MERGE_FILE_OPTIONS_VERSION)
+            },
+            0
+        );
+        opts
+    }
+
+    /// Label for the ancestor file side of the conflict which will be
+    /// prepended to labels in diff3-format merge files.
+    pub fn ancestor_label<T: IntoCString>(&mut self, t: T) -> &mut MergeFileOptions {
+        self.ancestor_label = Some(t.into_c_string().unwrap());
+        self
+    }
+
+    /// Label for our file side of the conflict which will be prepended to
+    /// labels in merge files.
+    pub fn our_label<T: IntoCString>(&mut self, t: T) -> &mut MergeFileOptions {
+        self.our_label = Some(t.into_c_string().unwrap());
+        self
+    }
+
+    /// Label for their file side of the conflict which will be prepended to
+    /// labels in merge files.
+    pub fn their_label<T: IntoCString>(&mut self, t: T) -> &mut MergeFileOptions {
+        self.their_label = Some(t.into_c_string().unwrap());
+        self
+    }
+
+    /// Specify a side to favor for resolving conflicts
+    pub fn favor(&mut self, favor: FileFavor) -> &mut MergeFileOptions {
+        self.raw.favor = favor.convert();
+        self
+    }
+
+    fn flag(&mut self, opt: raw::git_merge_file_flag_t, val: bool) -> &mut MergeFileOptions {
+        if val {
+            self.raw.flags |= opt as u32;
+        } else {
+            self.raw.flags &= !opt as u32;
+        }
+        self
+    }
+
+    /// Create standard conflicted merge files
+    pub fn style_standard(&mut self, standard: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_STYLE_MERGE, standard)
+    }
+
+    /// Create diff3-style file
+    pub fn style_diff3(&mut self, diff3: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_STYLE_DIFF3, diff3)
+    }
+
+    /// Create zdiff3-style file, which moves lines common to both sides out
+    /// of the conflict
+    pub fn style_zdiff3(&mut self, zdiff3: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_STYLE_ZDIFF3, zdiff3)
+    }
+
+    /// Condense non-alphanumeric regions for simplified diff file
+    pub fn simplify_alnum(&mut self, simplify: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_SIMPLIFY_ALNUM, simplify)
+    }
+
+    /// Ignore all whitespace
+    pub fn ignore_whitespace(&mut self, ignore: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_IGNORE_WHITESPACE, ignore)
+    }
+
+    /// Ignore changes in amount of whitespace
+    pub fn ignore_whitespace_change(&mut self, ignore: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_IGNORE_WHITESPACE_CHANGE, ignore)
+    }
+
+    /// Ignore whitespace at end of line
+    pub fn ignore_whitespace_eol(&mut self, ignore: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_IGNORE_WHITESPACE_EOL, ignore)
+    }
+
+    /// Use the "patience diff" algorithm
+    pub fn patience(&mut self, patience: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_DIFF_PATIENCE, patience)
+    }
+
+    /// Take extra time to find minimal diff
+    pub fn minimal(&mut self, minimal: bool) -> &mut MergeFileOptions {
+        self.flag(raw::GIT_MERGE_FILE_DIFF_MINIMAL, minimal)
+    }
+
+    /// The size of conflict markers (eg, "<<<<<<<"). Default is 7.
+    pub fn marker_size(&mut self, size: u16) -> &mut MergeFileOptions {
+        self.raw.marker_size = size as c_ushort;
+        self
+    }
+
+    /// Acquire a pointer to the underlying raw options.
+    ///
+    /// The pointer is only valid as long as these options are not modified
+    /// or dropped.
+    pub(crate) unsafe fn raw(&mut self) -> *const raw::git_merge_file_options {
+        self.raw.ancestor_label = crate::call::convert(&self.ancestor_label);
+        self.raw.our_label = crate::call::convert(&self.our_label);
+        self.raw.their_label = crate::call::convert(&self.their_label);
+        &self.raw as *const _
+    }
+}
+
+impl MergeFileResult {
+    /// True if the output was automerged, false if the output contains
+    /// conflict markers.
+    pub fn is_automergeable(&self) -> bool {
+        self.raw.automergeable > 0
+    }
+
+    /// The path that the resultant merge file should use.
+    ///
+    /// returns `None` if a filename conflict would occur, or if the path is
+    /// not valid utf-8
+    pub fn path(&self) -> Option<&str> {
+        self.path_bytes()
+            .and_then(|bytes| str::from_utf8(bytes).ok())
+    }
+
+    /// Gets the path as a byte slice.
+    pub fn path_bytes(&self) -> Option<&[u8]> {
+        unsafe { crate::opt_bytes(self, self.raw.path) }
+    }
+
+    /// The mode that the resultant merge file should use.
+    pub fn mode(&self) -> u32 {
+        self.raw.mode as u32
+    }
+
+    /// The contents of the merge.
+    pub fn content(&self) -> &[u8] {
+        if self.raw.ptr.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.raw.ptr as *const u8, self.raw.len) }
+    }
+}
+
+impl Binding for MergeFileResult {
+    type Raw = raw::git_merge_file_result;
+    unsafe fn from_raw(raw: raw::git_merge_file_result) -> MergeFileResult {
+        MergeFileResult { raw }
+    }
+    fn raw(&self) -> raw::git_merge_file_result {
+        unsafe { ptr::read(&self.raw) }
+    }
+}
+
+impl Drop for MergeFileResult {
+    fn drop(&mut self) {
+        unsafe { raw::git_merge_file_result_free(&mut self.raw) }
+    }
+}
+
+impl std::fmt::Debug for MergeFileResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut ds = f.debug_struct("MergeFileResult");
+        if let Some(path) = &self.path() {
+            ds.field("path", path);
+        }
+        ds.field("automergeable", &self.is_automergeable());
+        ds.field("mode", &self.mode());
+        ds.finish()
+    }
+}
+
+/// Merge the contents of three buffers, without involving a repository.
+pub(crate) fn merge_buffers(
+    ancestor: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+) -> Result<MergeFileResult, Error> {
+    crate::init();
+    unsafe {
+        let input = |data: &[u8]| {
+            let mut input: raw::git_merge_file_input = mem::zeroed();
+            raw::git_merge_file_input_init(&mut input, raw::GIT_MERGE_FILE_INPUT_VERSION);
+            input.ptr = data.as_ptr() as *const _;
+            input.size = data.len();
+            input
+        };
+        let (ancestor, ours, theirs) = (input(ancestor), input(ours), input(theirs));
+        let mut ret = mem::zeroed();
+        try_call!(raw::git_merge_file(
+            &mut ret,
+            &ancestor,
+            &ours,
+            &theirs,
+            ptr::null()
+        ));
+        Ok(Binding::from_raw(ret))
+    }
+}
+
+impl MergeRenamePrediction {
+    /// Whether rename detection is enabled at all.
+    pub fn find_renames(&self) -> bool {
+        self.find_renames
+    }
+
+    /// The similarity threshold used to consider a file renamed.
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    /// The maximum number of changed paths for which inexact rename
+    /// detection is performed.
+    pub fn target_limit(&self) -> u32 {
+        self.target_limit
+    }
+
+    /// The number of paths changed by either side of the merge.
+    pub fn changed_paths(&self) -> usize {
+        self.changed_paths
+    }
+
+    /// Whether inexact rename detection is expected to be skipped because
+    /// more paths changed than the target limit allows.
+    pub fn limit_exceeded(&self) -> bool {
+        self.find_renames && self.threshold < 100 && self.changed_paths > self.target_limit as usize
+    }
+}
+
+/// Implementation of `Repository::predict_merge_renames`, mirroring how
+/// libgit2 normalizes merge options.
+pub(crate) fn predict_merge_renames(
+    repo: &Repository,
+    ancestor: &Tree<'_>,
+    ours: &Tree<'_>,
+    theirs: &Tree<'_>,
+    opts: Option<&MergeOptions>,
+) -> Result<MergeRenamePrediction, Error> {
+    let default_opts = MergeOptions::new();
+    let raw = &opts.unwrap_or(&default_opts).raw;
+    let find_renames = raw.flags & raw::GIT_MERGE_FIND_RENAMES as u32 != 0;
+    let threshold = match raw.rename_threshold {
+        0 => 50,
+        threshold => threshold,
+    };
+    let target_limit = match raw.target_limit {
+        0 => {
+            let config = repo.config()?;
+            let limit = |name: &str| match config.get_i32(name) {
+                Ok(limit) => Ok(limit),
+                Err(ref e) if e.code() == ErrorCode::NotFound => Ok(0),
+                Err(e) => Err(e),
+            };
+            match limit("merge.renameLimit")? {
+                0 => limit("diff.renameLimit")?,
+                limit => limit,
+            }
+        }
+        limit => limit as i32,
+    };
+    let target_limit = if target_limit <= 0 {
+        1000
+    } else {
+        target_limit as u32
+    };
+
+    let mut paths = HashSet::new();
+    for side in [ours, theirs].iter() {
+        let diff = repo.diff_tree_to_tree(Some(ancestor), Some(side), None)?;
+        for delta in diff.deltas() {
+            paths.extend(delta.old_file().path_bytes().map(|p| p.to_vec()));
+            paths.extend(delta.new_file().path_bytes().map(|p| p.to_vec()));
+        }
+    }
+    Ok(MergeRenamePrediction {
+        find_renames,
+        threshold,
+        target_limit,
+        changed_paths: paths.len(),
+    })
+}
+
+impl MergeBaseTree {
+    /// The id of the tree used as the base of the merge, or `None` if the
+    /// commits have no common ancestor.
+    pub fn tree_id(&self) -> Option<Oid> {
+        self.tree
+    }
+
+    /// The merge bases of the two commits, best first.
+    pub fn bases(&self) -> &[Oid] {
+        &self.bases
+    }
+
+    /// Whether the base tree was created by merging several merge bases
+    /// rather than taken from a single commit.
+    ///
+    /// This is `false` if the recursion limit or `MergeOptions::no_recursive`
+    /// stopped the bases from being merged.
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+
+    /// The paths that conflicted while merging the merge bases without
+    /// leaving conflict markers, such as files modified on one side and
+    /// deleted on the other.
+    ///
+    /// Like libgit2, which reads a virtual base without its conflicts, these
+    /// paths are left out of the base tree.
+    pub fn conflicts(&self) -> &[PathBuf] {
+        &self.conflicts
+    }
+}
+
+impl Base {
+    /// The commits standing in for this base when looking for merge bases.
+    fn head_ids(&self) -> Vec<Oid> {
+        match self {
+            Base::Commit(id) => vec![*id],
+            Base::Virtual { parents, .. } => parents.clone(),
+        }
+    }
+
+    fn tree_id(&self, repo: &Repository) -> Result<Oid, Error> {
+        match self {
+            Base::Commit(id) => Ok(repo.find_commit(*id)?.tree_id()),
+            Base::Virtual { tree, .. } => Ok(*tree),
+        }
+    }
+}
+
+/// Implementation of `Repository::merge_base_tree`.
+pub(crate) fn merge_base_tree(
+    repo: &Repository,
+    one: Oid,
+    two: Oid,
+    opts: Option<&MergeOptions>,
+) -> Result<MergeBaseTree, Error> {
+    let default_opts = MergeOptions::new();
+    let opts = opts.unwrap_or(&default_opts);
+    let mut conflicts = Vec::new();
+    let (base, bases) = compute_base(repo, &Base::Commit(one), two, opts, 0, &mut conflicts)?;
+    Ok(MergeBaseTree {
+        tree: base.as_ref().map(|b| b.tree_id(repo)).transpose()?,
+        bases,
+        is_virtual: matches!(base, Some(Base::Virtual { .. })),
+        conflicts,
+    })
+}
+
+/// Find the merge base of `one` and `two`, merging several bases into a
+/// virtual one as libgit2's `compute_base` does.
+///
+/// Also returns the merge bases found, best first.
+fn compute_base(
+    repo: &Repository,
+    one: &Base,
+    two: Oid,
+    opts: &MergeOptions,
+    mut level: u32,
+    conflicts: &mut Vec<PathBuf>,
+) -> Result<(Option<Base>, Vec<Oid>), Error> {
+    // `one` may be virtual and stand for several commits, so it goes after
+    // `two`, which is always a single commit.
+    let mut ids = vec![two];
+    ids.extend(one.head_ids());
+    let found = match repo.merge_bases_many(&ids) {
+        Ok(bases) => bases.to_vec(),
+        Err(ref e) if e.code() == ErrorCode::NotFound => return Ok((None, Vec::new())),
+        Err(e) => return Err(e),
+    };
+
+    let no_recursive = opts.raw.flags & raw::GIT_MERGE_NO_RECURSIVE as u32 != 0;
+    let mut bases = found.clone();
+    let count = if no_recursive { 0 } else { bases.len() };
+    if count > 0 {
+        bases.reverse();
+    }
+    let mut base = Base::Commit(bases[0]);
+    for other in bases.iter().take(count).skip(1) {
+        level += 1;
+        if opts.raw.recursion_limit != 0 && level > opts.raw.recursion_limit {
+            break;
+        }
+        base = create_virtual_base(repo, base, *other, opts, level, conflicts)?;
+    }
+    Ok((Some(base), found))
+}
+
+/// Merge `one` and `two` into a virtual base, as libgit2's
+/// `create_virtual_base` does.
+fn create_virtual_base(
+    repo: &Repository,
+    one: Base,
+    two: Oid,
+    opts: &MergeOptions,
+    level: u32,
+    conflicts: &mut Vec<PathBuf>,
+) -> Result<Base, Error> {
+    // Conflicts in a virtual base are kept as conflict markers, like git does.
+    let mut virtual_opts = MergeOptions::new();
+    virtual_opts.raw = unsafe { ptr::read(&opts.raw) };
+    virtual_opts.raw.flags &= !(raw::GIT_MERGE_FAIL_ON_CONFLICT as u32);
+    virtual_opts.raw.flags |= raw::GIT_MERGE_VIRTUAL_BASE as u32;
+
+    let (ancestor, _) = compute_base(repo, &one, two, &virtual_opts, level + 1, conflicts)?;
+    let ancestor = match ancestor {
+        Some(base) => repo.find_tree(base.tree_id(repo)?)?,
+        None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+    };
+    let ours = repo.find_tree(one.tree_id(repo)?)?;
+    let theirs = repo.find_commit(two)?.tree()?;
+    let mut index = repo.merge_trees(&ancestor, &ours, &theirs, Some(&virtual_opts))?;
+
+    // What still conflicts has no conflict markers to keep; libgit2 reads
+    // the virtual base without its conflicts, so leave those paths out.
+    let found = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+    for conflict in found {
+        let entries = [conflict.our, conflict.their, conflict.ancestor];
+        for entry in entries.iter().flatten() {
+            let path = crate::util::bytes2path(&entry.path).to_path_buf();
+            match index.conflict_remove(&path) {
+                Ok(()) => {}
+                Err(ref e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            if !conflicts.contains(&path) {
+                conflicts.push(path);
+            }
+        }
+    }
+
+    let mut parents = one.head_ids();
+    parents.push(two);
+    Ok(Base::Virtual {
+        tree: index.write_tree_to(repo)?,
+        parents,
+    })
+}
+
 impl<'repo> Binding for AnnotatedCommit<'repo> {
     type Raw = *mut raw::git_annotated_commit;
     unsafe fn from_raw(raw: *mut raw::git_annotated_commit) -> AnnotatedCommit<'repo> {
@@ -0,0 +1,230 @@
+//! Interfaces for adding custom merge drivers to libgit2
+
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::marker;
+use std::mem::{self, ManuallyDrop};
+use std::ptr;
+
+use crate::util::Binding;
+use crate::{panic, raw, Error, IndexEntry, Repository};
+
+/// A custom merge driver, used to merge the contents of files whose
+/// `merge` attribute names the driver.
+///
+/// Once registered with `register`, the driver is used by every operation
+/// that merges file contents, including `Repository::merge`,
+/// `Repository::cherrypick`, `Repository::merge_trees` and rebases.
+pub trait MergeDriver: Send + Sync + 'static {
+    /// Merge the contents of a conflicting file.
+    ///
+    /// Return `Ok(Some(content))` with the merged contents to resolve the
+    /// file, or `Ok(None)` to let the default text driver merge it instead.
+    /// To leave the file conflicted, return an error with the code
+    /// `ErrorCode::MergeConflict`. Any other error aborts the merge.
+    fn apply(&self, source: &MergeDriverSource<'_>) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// The files being merged by a `MergeDriver`.
+pub struct MergeDriverSource<'a> {
+    raw: *const raw::git_merge_driver_source,
+    repo: ManuallyDrop<Repository>,
+    name: &'a str,
+    _marker: marker::PhantomData<&'a raw::git_merge_driver_source>,
+}
+
+/// Instance of a `git_merge_driver`, must use `#[repr(C)]` to ensure that the
+/// C fields come first.
+#[repr(C)]
+struct RawMergeDriver {
+    raw: raw::git_merge_driver,
+    name: String,
+    obj: Box<dyn MergeDriver>,
+}
+
+/// Add a custom merge driver under the given name.
+///
+/// Files are merged with the driver when their `merge` attribute is set to
+/// `name`, for example with a `*.json merge=json` line in `.gitattributes`.
+/// The driver is kept for the rest of the life of the process, even if it is
+/// later unregistered.
+pub fn register<D: MergeDriver>(name: &str, driver: D) -> Result<(), Error> {
+    crate::init();
+    let c_name = CString::new(name)?;
+    let mut data = Box::new(RawMergeDriver {
+        raw: raw::git_merge_driver {
+            version: raw::GIT_MERGE_DRIVER_VERSION,
+            initialize: None,
+            shutdown: None,
+            apply: Some(driver_apply),
+        },
+        name: name.to_string(),
+        obj: Box::new(driver),
+    });
+    unsafe {
+        let datap = (&mut *data) as *mut RawMergeDriver as *mut raw::git_merge_driver;
+        try_call!(raw::git_merge_driver_register(c_name, datap));
+    }
+    mem::forget(data);
+    Ok(())
+}
+
+/// Remove the merge driver registered under the given name.
+///
+/// Files naming the driver in their `merge` attribute are merged with the
+/// default text driver afterwards.
+pub fn unregister(name: &str) -> Result<(), Error> {
+    crate::init();
+    let name = CString::new(name)?;
+    unsafe {
+        try_call!(raw::git_merge_driver_unregister(name));
+    }
+    Ok(())
+}
+
+impl<'a> MergeDriverSource<'a> {
+    /// The repository in which the merge is taking place.
+    pub fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// The name the driver was registered under.
+    pub fn driver_name(&self) -> &str {
+        self.name
+    }
+
+    /// The index entry of the common ancestor, if the file existed there.
+    pub fn ancestor(&self) -> Option<IndexEntry> {
+        unsafe { entry(raw::git_merge_driver_source_ancestor(self.raw)) }
+    }
+
+    /// The index entry of our side of the merge, if the file exists there.
+    pub fn ours(&self) -> Option<IndexEntry> {
+        unsafe { entry(raw::git_merge_driver_source_ours(self.raw)) }
+    }
+
+    /// The index entry of their side of the merge, if the file exists there.
+    pub fn theirs(&self) -> Option<IndexEntry> {
+        unsafe { entry(raw::git_merge_driver_source_theirs(self.raw)) }
+    }
+
+    /// Read the contents of one of the entries of this source.
+    pub fn content(&self, entry: &IndexEntry) -> Result<Vec<u8>, Error> {
+        Ok(self.repo.find_blob(entry.id)?.content().to_vec())
+    }
+}
+
+unsafe fn entry(raw: *const raw::git_index_entry) -> Option<IndexEntry> {
+    if raw.is_null() {
+        None
+    } else {
+        // The entries handed to merge drivers don't encode the length of
+        // their path in `flags` the way index entries do, so fill it in
+        // before the path is read back.
+        let mut raw = ptr::read(raw);
+        let len = CStr::from_ptr(raw.path).to_bytes().len();
+        let mask = raw::GIT_INDEX_ENTRY_NAMEMASK;
+        raw.flags = (raw.flags & !mask) | len.min(mask as usize) as u16;
+        Some(Binding::from_raw(raw))
+    }
+}
+
+extern "C" fn driver_apply(
+    driver: *mut raw::git_merge_driver,
+    path_out: *mut *const c_char,
+    mode_out: *mut u32,
+    merged_out: *mut raw::git_buf,
+    _filter_name: *const c_char,
+    src: *const raw::git_merge_driver_source,
+) -> c_int {
+    let ret = panic::wrap(|| unsafe {
+        let data = &*(driver as *const RawMergeDriver);
+        let source = MergeDriverSource {
+            raw: src,
+            repo: ManuallyDrop::new(Binding::from_raw(raw::git_merge_driver_source_repo(src))),
+            name: &data.name,
+            _marker: marker::PhantomData,
+        };
+        let content = match data.obj.apply(&source) {
+            Ok(Some(content)) => content,
+            Ok(None) => return raw::GIT_PASSTHROUGH as c_int,
+            Err(e) => return e.raw_set_git_error(),
+        };
+
+        // The merged file takes the path and mode of whichever side still
+        // has it, preferring ours.
+        let side = match raw::git_merge_driver_source_ours(src) {
+            ours if !ours.is_null() => ours,
+            _ => raw::git_merge_driver_source_theirs(src),
+        };
+        if side.is_null() {
+            return raw::GIT_PASSTHROUGH as c_int;
+        }
+        *path_out = (*side).path;
+        *mode_out = (*side).mode;
+        raw::git_buf_set(merged_out, content.as_ptr() as *const c_void, content.len())
+    });
+    ret.unwrap_or(-1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergeDriver, MergeDriverSource};
+    use crate::{Error, ErrorClass, ErrorCode};
+    use std::fs;
+
+    struct Union;
+
+    impl MergeDriver for Union {
+        fn apply(&self, source: &MergeDriverSource<'_>) -> Result<Option<Vec<u8>>, Error> {
+            let (ours, theirs) = match (source.ours(), source.theirs()) {
+                (Some(ours), Some(theirs)) => (ours, theirs),
+                _ => return Ok(None),
+            };
+            if ours.path == b"conflict" {
+                return Err(Error::new(
+                    ErrorCode::MergeConflict,
+                    ErrorClass::Merge,
+                    "left conflicted",
+                ));
+            }
+            let mut content = source.content(&ours)?;
+            content.extend(source.content(&theirs)?);
+            Ok(Some(content))
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let (td, repo) = crate::test::repo_init();
+        t!(fs::write(
+            td.path().join(".gitattributes"),
+            "* merge=git2-rs-union\n"
+        ));
+        if let Err(e) = super::register("git2-rs-union", Union) {
+            panic!("failed to register the git2-rs-union driver: {}", e);
+        }
+
+        let tree_with = |content: &str| {
+            let mut builder = t!(repo.treebuilder(None));
+            for name in &["file", "conflict"] {
+                let blob = t!(repo.blob(content.as_bytes()));
+                t!(builder.insert(name, blob, 0o100644));
+            }
+            t!(repo.find_tree(t!(builder.write())))
+        };
+        let ancestor = tree_with("base\n");
+        let ours = tree_with("ours\n");
+        let theirs = tree_with("theirs\n");
+        let index = t!(repo.merge_trees(&ancestor, &ours, &theirs, None));
+        t!(super::unregister("git2-rs-union"));
+
+        let conflicts = t!(index.conflicts())
+            .map(|c| t!(c).our.unwrap().path)
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts, [b"conflict".to_vec()]);
+        let entry = index.get_path("file".as_ref(), 0).unwrap();
+        let blob = t!(repo.find_blob(entry.id));
+        assert_eq!(blob.content(), b"ours\ntheirs\n");
+    }
+}
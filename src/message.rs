@@ -7,7 +7,7 @@ use std::ptr;
 use libc::{c_char, c_int};
 
 use crate::util::Binding;
-use crate::{raw, Buf, Error, IntoCString};
+use crate::{raw, Buf, Config, Error, ErrorClass, ErrorCode, IntoCString};
 
 /// Clean up a message, removing extraneous whitespace, and ensure that the
 /// message ends with a newline. If `comment_char` is `Some`, also remove comment
@@ -242,6 +242,414 @@ fn to_bytes_tuple(trailers: &MessageTrailers, index: usize) -> (&[u8], &[u8]) {
     }
 }
 
+/// Where `MessageTrailerEditor` puts new trailers, as configured by
+/// `trailer.where`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrailerWhere {
+    /// After all the existing trailers. This is the default.
+    End,
+    /// Before all the existing trailers.
+    Start,
+    /// After the last trailer with the same key, or at the end.
+    After,
+    /// Before the first trailer with the same key, or at the start.
+    Before,
+}
+
+/// What `MessageTrailerEditor` does when adding a trailer whose key is
+/// already present, as configured by `trailer.ifExists`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrailerIfExists {
+    /// Add the trailer unless the trailer next to where it would be added
+    /// is the same. This is the default.
+    AddIfDifferentNeighbor,
+    /// Add the trailer unless the same trailer is already present.
+    AddIfDifferent,
+    /// Always add the trailer.
+    Add,
+    /// Replace the existing trailers with the same key.
+    Replace,
+    /// Leave the message unchanged.
+    DoNothing,
+}
+
+/// What `MessageTrailerEditor` does when adding a trailer whose key is not
+/// present yet, as configured by `trailer.ifMissing`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrailerIfMissing {
+    /// Add the trailer. This is the default.
+    Add,
+    /// Leave the message unchanged.
+    DoNothing,
+}
+
+enum TrailerEdit {
+    Add(String, String),
+    Replace(String, String),
+    Remove(String),
+}
+
+/// Adds, replaces and removes the trailers of commit messages, such as
+/// `Signed-off-by`, like `git interpret-trailers`.
+///
+/// Trailers are written in the trailer block at the end of the message,
+/// which is created if needed. Anything from a line starting with `---` and
+/// whitespace, such as the notes of a patch, is left untouched.
+pub struct MessageTrailerEditor {
+    edits: Vec<TrailerEdit>,
+    position: TrailerWhere,
+    if_exists: TrailerIfExists,
+    if_missing: TrailerIfMissing,
+    separators: String,
+}
+
+/// The beginnings of the trailers which git adds itself, any of which makes
+/// the last paragraph a trailer block even if it isn't only trailers.
+const GIT_GENERATED_TRAILERS: &[&str] = &["Signed-off-by: ", "(cherry picked from commit "];
+
+/// A trailer of the message being edited, along with its continuation
+/// lines.
+struct Trailer {
+    key: String,
+    value: String,
+    lines: Vec<String>,
+}
+
+impl MessageTrailerEditor {
+    /// Creates an editor with the default settings of git.
+    pub fn new() -> MessageTrailerEditor {
+        MessageTrailerEditor {
+            edits: Vec::new(),
+            position: TrailerWhere::End,
+            if_exists: TrailerIfExists::AddIfDifferentNeighbor,
+            if_missing: TrailerIfMissing::Add,
+            separators: ":".to_string(),
+        }
+    }
+
+    /// Creates an editor with the settings of the `trailer.where`,
+    /// `trailer.ifExists`, `trailer.ifMissing` and `trailer.separators`
+    /// configuration keys.
+    ///
+    /// The per-trailer `trailer.<token>.*` keys are not supported: they
+    /// neither override these settings for their key nor make a paragraph
+    /// count as a trailer block.
+    pub fn from_config(config: &Config) -> Result<MessageTrailerEditor, Error> {
+        let mut editor = MessageTrailerEditor::new();
+        let get = |key: &str| match config.get_string(key) {
+            Ok(value) => Ok(Some(value.to_ascii_lowercase())),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+        let invalid = |key: &str, value: &str| {
+            Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Config,
+                format!("invalid value '{}' for config key '{}'", value, key),
+            )
+        };
+        if let Some(value) = get("trailer.where")? {
+            editor.position = match &value[..] {
+                "end" => TrailerWhere::End,
+                "start" => TrailerWhere::Start,
+                "after" => TrailerWhere::After,
+                "before" => TrailerWhere::Before,
+                _ => return Err(invalid("trailer.where", &value)),
+            };
+        }
+        if let Some(value) = get("trailer.ifexists")? {
+            editor.if_exists = match &value[..] {
+                "addifdifferentneighbor" => TrailerIfExists::AddIfDifferentNeighbor,
+                "addifdifferent" => TrailerIfExists::AddIfDifferent,
+                "add" => TrailerIfExists::Add,
+                "replace" => TrailerIfExists::Replace,
+                "donothing" => TrailerIfExists::DoNothing,
+                _ => return Err(invalid("trailer.ifExists", &value)),
+            };
+        }
+        if let Some(value) = get("trailer.ifmissing")? {
+            editor.if_missing = match &value[..] {
+                "add" => TrailerIfMissing::Add,
+                "donothing" => TrailerIfMissing::DoNothing,
+                _ => return Err(invalid("trailer.ifMissing", &value)),
+            };
+        }
+        if let Some(value) = get("trailer.separators")? {
+            if !value.is_empty() {
+                editor.separators = value;
+            }
+        }
+        Ok(editor)
+    }
+
+    /// Where new trailers are added.
+    pub fn position(&mut self, position: TrailerWhere) -> &mut MessageTrailerEditor {
+        self.position = position;
+        self
+    }
+
+    /// What to do when adding a trailer whose key is already present.
+    pub fn if_exists(&mut self, if_exists: TrailerIfExists) -> &mut MessageTrailerEditor {
+        self.if_exists = if_exists;
+        self
+    }
+
+    /// What to do when adding a trailer whose key is not present yet.
+    pub fn if_missing(&mut self, if_missing: TrailerIfMissing) -> &mut MessageTrailerEditor {
+        self.if_missing = if_missing;
+        self
+    }
+
+    /// The characters which separate the key of a trailer from its value,
+    /// defaulting to `:`. The first one is used for new trailers.
+    pub fn separators(&mut self, separators: &str) -> &mut MessageTrailerEditor {
+        if !separators.is_empty() {
+            self.separators = separators.to_string();
+        }
+        self
+    }
+
+    /// Add a trailer, such as `Signed-off-by: Name <email>`, following the
+    /// `position`, `if_exists` and `if_missing` settings.
+    pub fn add(&mut self, key: &str, value: &str) -> &mut MessageTrailerEditor {
+        self.edits
+            .push(TrailerEdit::Add(key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Replace the trailers with the key `key` by a single one, in place of
+    /// the first of them, or add it if there is none.
+    pub fn replace(&mut self, key: &str, value: &str) -> &mut MessageTrailerEditor {
+        self.edits
+            .push(TrailerEdit::Replace(key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Remove all the trailers with the key `key`.
+    pub fn remove(&mut self, key: &str) -> &mut MessageTrailerEditor {
+        self.edits.push(TrailerEdit::Remove(key.to_string()));
+        self
+    }
+
+    /// Apply the edits, in the order they were made, to `message` and
+    /// return the rewritten message.
+    ///
+    /// Keys are compared case-insensitively.
+    pub fn apply(&self, message: &str) -> String {
+        let mut lines = message.lines().collect::<Vec<_>>();
+        // Anything after a `---` line is not part of the message proper.
+        let divider = lines
+            .iter()
+            .position(|line| {
+                line.strip_prefix("---")
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            })
+            .unwrap_or(lines.len());
+        let rest = lines.split_off(divider);
+        while matches!(lines.last(), Some(line) if line.trim().is_empty()) {
+            lines.pop();
+        }
+
+        // The trailer block is the last paragraph, if it isn't the only one
+        // and is made of trailers only.
+        let start = lines
+            .iter()
+            .rposition(|line| line.trim().is_empty())
+            .map_or(lines.len(), |i| i + 1);
+        let mut trailers = Vec::new();
+        let mut body_end = lines.len();
+        if start < lines.len() && start > 0 {
+            if let Some(parsed) = self.parse_trailers(&lines[start..]) {
+                trailers = parsed;
+                body_end = start - 1;
+            }
+        }
+
+        for edit in &self.edits {
+            match edit {
+                TrailerEdit::Add(key, value) => self.add_trailer(&mut trailers, key, value),
+                TrailerEdit::Replace(key, value) => self.replace_trailer(&mut trailers, key, value),
+                TrailerEdit::Remove(key) => {
+                    trailers.retain(|t| !t.key.eq_ignore_ascii_case(key));
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for line in &lines[..body_end] {
+            out.push_str(line);
+            out.push('\n');
+        }
+        if !trailers.is_empty() {
+            if body_end > 0 {
+                out.push('\n');
+            }
+            for trailer in &trailers {
+                for line in &trailer.lines {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+        }
+        for line in &rest {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Parse the last paragraph of a message as a trailer block, like git
+    /// does: it must be made of trailers only, or contain a trailer which
+    /// git generates and be at least a quarter trailers.
+    fn parse_trailers(&self, lines: &[&str]) -> Option<Vec<Trailer>> {
+        let is_continuation = |line: &str| line.starts_with(char::is_whitespace);
+        let (mut trailer_lines, mut other_lines, mut continuations) = (0, 0, 0);
+        let mut generated = false;
+        for line in lines.iter().rev() {
+            if GIT_GENERATED_TRAILERS.iter().any(|p| line.starts_with(p)) {
+                trailer_lines += 1;
+                continuations = 0;
+                generated = true;
+            } else if self.separator_index(line).is_some() && !is_continuation(line) {
+                trailer_lines += 1;
+                continuations = 0;
+            } else if is_continuation(line) {
+                continuations += 1;
+            } else {
+                other_lines += 1 + continuations;
+                continuations = 0;
+            }
+        }
+        other_lines += continuations;
+        if trailer_lines == 0
+            || (other_lines > 0 && !(generated && trailer_lines * 3 >= other_lines))
+        {
+            return None;
+        }
+
+        // Lines of the block which aren't trailers are kept as they are,
+        // with an empty key.
+        let mut trailers: Vec<Trailer> = Vec::new();
+        for line in lines {
+            if let (true, Some(trailer)) = (is_continuation(line), trailers.last_mut()) {
+                trailer.value.push(' ');
+                trailer.value.push_str(line.trim());
+                trailer.lines.push(line.to_string());
+                continue;
+            }
+            let (key, value) = match self.separator_index(line) {
+                Some(i) => (line[..i].trim_end(), line[i + 1..].trim()),
+                None => ("", ""),
+            };
+            trailers.push(Trailer {
+                key: key.to_string(),
+                value: value.to_string(),
+                lines: vec![line.to_string()],
+            });
+        }
+        Some(trailers)
+    }
+
+    /// The position of the separator of a trailer line, which must follow a
+    /// key made of alphanumeric characters and dashes, and optionally
+    /// whitespace.
+    fn separator_index(&self, line: &str) -> Option<usize> {
+        let mut whitespace = false;
+        for (i, c) in line.char_indices() {
+            if self.separators.contains(c) {
+                return Some(i).filter(|&i| i > 0);
+            }
+            if !whitespace && (c.is_alphanumeric() || c == '-') {
+                continue;
+            }
+            if i > 0 && (c == ' ' || c == '\t') {
+                whitespace = true;
+                continue;
+            }
+            break;
+        }
+        None
+    }
+
+    fn new_trailer(&self, key: &str, value: &str) -> Trailer {
+        let separator = self.separators.chars().next().unwrap_or(':');
+        Trailer {
+            key: key.to_string(),
+            value: value.to_string(),
+            lines: vec![format!("{}{} {}", key, separator, value)],
+        }
+    }
+
+    fn add_trailer(&self, trailers: &mut Vec<Trailer>, key: &str, value: &str) {
+        let same_key = |t: &Trailer| t.key.eq_ignore_ascii_case(key);
+        let same = |t: &Trailer| same_key(t) && t.value == value;
+        let exists = trailers.iter().any(same_key);
+        if !exists && self.if_missing == TrailerIfMissing::DoNothing {
+            return;
+        }
+        let index = match self.position {
+            TrailerWhere::End => trailers.len(),
+            TrailerWhere::Start => 0,
+            TrailerWhere::After => trailers
+                .iter()
+                .rposition(same_key)
+                .map_or(trailers.len(), |i| i + 1),
+            TrailerWhere::Before => trailers.iter().position(same_key).unwrap_or(0),
+        };
+        if exists {
+            match self.if_exists {
+                TrailerIfExists::AddIfDifferentNeighbor => {
+                    let before = index.checked_sub(1).and_then(|i| trailers.get(i));
+                    let after = trailers.get(index);
+                    if before.into_iter().chain(after).any(same) {
+                        return;
+                    }
+                }
+                TrailerIfExists::AddIfDifferent => {
+                    if trailers.iter().any(same) {
+                        return;
+                    }
+                }
+                TrailerIfExists::Add => {}
+                TrailerIfExists::Replace => return self.replace_trailer(trailers, key, value),
+                TrailerIfExists::DoNothing => return,
+            }
+        }
+        trailers.insert(index, self.new_trailer(key, value));
+    }
+
+    fn replace_trailer(&self, trailers: &mut Vec<Trailer>, key: &str, value: &str) {
+        match trailers
+            .iter()
+            .position(|t| t.key.eq_ignore_ascii_case(key))
+        {
+            Some(first) => {
+                trailers[first] = self.new_trailer(key, value);
+                let mut i = 0;
+                trailers.retain(|t| {
+                    i += 1;
+                    i - 1 == first || !t.key.eq_ignore_ascii_case(key)
+                });
+            }
+            None => {
+                if self.if_missing == TrailerIfMissing::Add {
+                    let index = match self.position {
+                        TrailerWhere::Start | TrailerWhere::Before => 0,
+                        TrailerWhere::End | TrailerWhere::After => trailers.len(),
+                    };
+                    trailers.insert(index, self.new_trailer(key, value));
+                }
+            }
+        }
+    }
+}
+
+impl Default for MessageTrailerEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -346,4 +754,73 @@ Signed-off-by: Lieutenant \xe2\x28\xa1prout
             map
         }
     }
+
+    #[test]
+    fn edit_trailers() {
+        use crate::{MessageTrailerEditor, TrailerIfExists, TrailerWhere};
+
+        let message = "subject\n\nbody\n\nSigned-off-by: A <a@example.com>\n";
+        let mut editor = MessageTrailerEditor::new();
+        editor.add("Reviewed-by", "B <b@example.com>");
+        assert_eq!(
+            editor.apply(message),
+            "subject\n\nbody\n\n\
+             Signed-off-by: A <a@example.com>\n\
+             Reviewed-by: B <b@example.com>\n"
+        );
+
+        // The same trailer isn't added twice in a row.
+        let mut editor = MessageTrailerEditor::new();
+        editor.add("signed-off-by", "A <a@example.com>");
+        assert_eq!(editor.apply(message), message);
+
+        // A trailer block is created after the body, before any patch notes.
+        let mut editor = MessageTrailerEditor::new();
+        editor.add("Signed-off-by", "A <a@example.com>");
+        assert_eq!(
+            editor.apply("subject\n\nbody\n---\nnotes\n"),
+            "subject\n\nbody\n\nSigned-off-by: A <a@example.com>\n---\nnotes\n"
+        );
+        assert_eq!(
+            editor.apply("subject: not a trailer"),
+            "subject: not a trailer\n\nSigned-off-by: A <a@example.com>\n"
+        );
+
+        let message = "subject\n\nFixes: #1\nSigned-off-by: A\nFixes: #2\n";
+        let mut editor = MessageTrailerEditor::new();
+        editor.replace("Fixes", "#3");
+        assert_eq!(
+            editor.apply(message),
+            "subject\n\nFixes: #3\nSigned-off-by: A\n"
+        );
+        let mut editor = MessageTrailerEditor::new();
+        editor.remove("fixes").remove("Signed-off-by");
+        assert_eq!(editor.apply(message), "subject\n");
+        let mut editor = MessageTrailerEditor::new();
+        editor
+            .position(TrailerWhere::After)
+            .if_exists(TrailerIfExists::AddIfDifferent)
+            .add("Fixes", "#1")
+            .add("Fixes", "#4");
+        assert_eq!(
+            editor.apply(message),
+            "subject\n\nFixes: #1\nSigned-off-by: A\nFixes: #2\nFixes: #4\n"
+        );
+
+        // A paragraph with a trailer added by git only needs to be a quarter
+        // trailers, and its other lines are kept.
+        let message = "subject\n\nsee\nthe\nbug\nSigned-off-by: A\n";
+        let mut editor = MessageTrailerEditor::new();
+        editor.add("Fixes", "#1");
+        assert_eq!(editor.apply(message), format!("{}Fixes: #1\n", message));
+        let message = "subject\n\nsee the\nbug\nFixes: #1\n";
+        assert_eq!(editor.apply(message), format!("{}\nFixes: #1\n", message));
+
+        // Only `---` followed by whitespace starts the patch notes.
+        let message = "subject\n\n---- not notes\n--- \nnotes\n";
+        assert_eq!(
+            editor.apply(message),
+            "subject\n\n---- not notes\n\nFixes: #1\n--- \nnotes\n"
+        );
+    }
 }
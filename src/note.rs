@@ -1,8 +1,10 @@
+use std::collections::{BTreeSet, HashMap};
 use std::marker;
 use std::str;
 
 use crate::util::Binding;
-use crate::{raw, signature, Error, Oid, Repository, Signature};
+use crate::{raw, signature, Error, ErrorCode, Oid, Repository, Signature};
+use crate::{ObjectType, Tree, TreeWalkMode, TreeWalkResult};
 
 /// A structure representing a [note][note] in git.
 ///
@@ -22,6 +24,37 @@ pub struct Notes<'repo> {
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
+/// How `Repository::notes_merge` resolves the notes of an object changed on
+/// both sides, like the strategies of `git notes merge`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NotesMergeStrategy {
+    /// Leave the conflicts to be resolved by the caller.
+    Manual,
+    /// Keep the local notes.
+    Ours,
+    /// Keep the notes being merged in.
+    Theirs,
+    /// Concatenate the local notes and the notes being merged in.
+    Union,
+    /// Concatenate both notes, then sort their lines and remove duplicate
+    /// and empty lines.
+    CatSortUniq,
+}
+
+/// The result of `Repository::notes_merge`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotesMergeOutcome {
+    /// The local notes already contain the notes being merged in.
+    UpToDate,
+    /// The local notes reference was fast-forwarded to this commit.
+    FastForward(Oid),
+    /// The notes were merged into this new merge commit.
+    Merged(Oid),
+    /// The notes of these objects were changed on both sides, and nothing
+    /// was written since the strategy is `NotesMergeStrategy::Manual`.
+    Conflicts(Vec<Oid>),
+}
+
 impl<'repo> Note<'repo> {
     /// Get the note author
     pub fn author(&self) -> Signature<'_> {
@@ -49,6 +82,188 @@ impl<'repo> Note<'repo> {
     }
 }
 
+/// Read the notes of a notes commit, mapping annotated objects to notes.
+fn notes_map(repo: &Repository, commit: Option<Oid>) -> Result<HashMap<Oid, Oid>, Error> {
+    let mut notes = HashMap::new();
+    let commit = match commit {
+        Some(commit) => repo.find_commit(commit)?,
+        None => return Ok(notes),
+    };
+    // Notes may be fanned out in subdirectories named after the first
+    // digits of the annotated object.
+    commit.tree()?.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            let name = format!("{}{}", root.replace('/', ""), entry.name().unwrap_or(""));
+            if let Ok(id) = Oid::from_str(&name) {
+                if name.len() == 40 {
+                    notes.insert(id, entry.id());
+                }
+            }
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(notes)
+}
+
+fn combine_notes(
+    repo: &Repository,
+    ours: Oid,
+    theirs: Oid,
+    strategy: NotesMergeStrategy,
+) -> Result<Oid, Error> {
+    let ours = repo.find_blob(ours)?;
+    let theirs = repo.find_blob(theirs)?;
+    let mut content = Vec::new();
+    if strategy == NotesMergeStrategy::CatSortUniq {
+        let lines = ours
+            .content()
+            .split(|&b| b == b'\n')
+            .chain(theirs.content().split(|&b| b == b'\n'))
+            .filter(|line| !line.is_empty())
+            .collect::<BTreeSet<_>>();
+        for line in lines {
+            content.extend_from_slice(line);
+            content.push(b'\n');
+        }
+    } else {
+        content.extend_from_slice(ours.content());
+        if !content.ends_with(b"\n") {
+            content.push(b'\n');
+        }
+        content.push(b'\n');
+        content.extend_from_slice(theirs.content());
+    }
+    repo.blob(&content)
+}
+
+/// Set or remove the note of the object `hex` in a notes tree, returning
+/// the id of the edited tree.
+///
+/// Like libgit2, an existing note is edited where it is found, and a new
+/// note goes into the fanout subdirectory for its digits if there is one.
+fn edit_notes_tree(
+    repo: &Repository,
+    tree: &Tree<'_>,
+    hex: &str,
+    note: Option<Oid>,
+) -> Result<Oid, Error> {
+    let mut builder = repo.treebuilder(Some(tree))?;
+    let is_blob = |name: &str| {
+        tree.get_name(name)
+            .is_some_and(|entry| entry.kind() == Some(ObjectType::Blob))
+    };
+    let subtree = match hex.get(..2) {
+        Some(dir) if hex.len() > 2 && !is_blob(hex) => tree
+            .get_name(dir)
+            .filter(|entry| entry.kind() == Some(ObjectType::Tree))
+            .map(|entry| (dir, entry.id())),
+        _ => None,
+    };
+    match (subtree, note) {
+        (Some((dir, id)), note) => {
+            let edited = edit_notes_tree(repo, &repo.find_tree(id)?, &hex[2..], note)?;
+            if repo.find_tree(edited)?.is_empty() {
+                builder.remove(dir)?;
+            } else {
+                builder.insert(dir, edited, 0o040000)?;
+            }
+        }
+        (None, Some(note)) => {
+            builder.insert(hex, note, 0o100644)?;
+        }
+        (None, None) => {
+            if is_blob(hex) {
+                builder.remove(hex)?;
+            }
+        }
+    }
+    builder.write()
+}
+
+/// Implementation of `Repository::notes_merge`.
+pub(crate) fn merge(
+    repo: &Repository,
+    notes_ref: Option<&str>,
+    other: &str,
+    signature: &Signature<'_>,
+    strategy: NotesMergeStrategy,
+) -> Result<NotesMergeOutcome, Error> {
+    let notes_ref = match notes_ref {
+        Some(notes_ref) => notes_ref.to_string(),
+        None => repo.note_default_ref()?,
+    };
+    let remote = repo.refname_to_id(other)?;
+    let local = match repo.refname_to_id(&notes_ref) {
+        Ok(local) => local,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            repo.reference(&notes_ref, remote, false, "notes: Fast-forward")?;
+            return Ok(NotesMergeOutcome::FastForward(remote));
+        }
+        Err(e) => return Err(e),
+    };
+    if local == remote || repo.graph_descendant_of(local, remote)? {
+        return Ok(NotesMergeOutcome::UpToDate);
+    }
+    if repo.graph_descendant_of(remote, local)? {
+        repo.reference_matching(&notes_ref, remote, true, local, "notes: Fast-forward")?;
+        return Ok(NotesMergeOutcome::FastForward(remote));
+    }
+
+    let base = match repo.merge_base(local, remote) {
+        Ok(base) => Some(base),
+        Err(e) if e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let base_notes = notes_map(repo, base)?;
+    let ours = notes_map(repo, Some(local))?;
+    let theirs = notes_map(repo, Some(remote))?;
+    let mut changes = Vec::new();
+    let mut conflicts = Vec::new();
+    for id in ours.keys().chain(theirs.keys()).collect::<BTreeSet<_>>() {
+        let base = base_notes.get(id).cloned();
+        let (l, r) = (ours.get(id).cloned(), theirs.get(id).cloned());
+        let note = if l == r || r == base {
+            l
+        } else if l == base {
+            r
+        } else {
+            match strategy {
+                NotesMergeStrategy::Manual => {
+                    conflicts.push(*id);
+                    continue;
+                }
+                NotesMergeStrategy::Ours => l,
+                NotesMergeStrategy::Theirs => r,
+                NotesMergeStrategy::Union | NotesMergeStrategy::CatSortUniq => match (l, r) {
+                    (Some(l), Some(r)) => Some(combine_notes(repo, l, r, strategy)?),
+                    // Changed on one side and removed on the other.
+                    (l, r) => l.or(r),
+                },
+            }
+        };
+        if note != ours.get(id).cloned() {
+            changes.push((*id, note));
+        }
+    }
+    if !conflicts.is_empty() {
+        return Ok(NotesMergeOutcome::Conflicts(conflicts));
+    }
+
+    // Edit the local notes tree rather than rebuilding it, so that its
+    // fanout and any entries which are not notes are kept.
+    let local_commit = repo.find_commit(local)?;
+    let mut tree = local_commit.tree()?;
+    for (id, note) in changes {
+        let edited = edit_notes_tree(repo, &tree, &id.to_string(), note)?;
+        tree = repo.find_tree(edited)?;
+    }
+    let message = format!("notes: Merged notes from {} into {}", other, notes_ref);
+    let parents = [&local_commit, &repo.find_commit(remote)?];
+    let id = repo.commit(None, signature, signature, &message, &tree, &parents)?;
+    repo.reference_matching(&notes_ref, id, true, local, &message)?;
+    Ok(NotesMergeOutcome::Merged(id))
+}
+
 impl<'repo> Binding for Note<'repo> {
     type Raw = *mut raw::git_note;
     unsafe fn from_raw(raw: *mut raw::git_note) -> Note<'repo> {
@@ -144,4 +359,138 @@ mod tests {
         assert_eq!(sig.name(), note_obj.committer().name());
         assert!(sig.when() == note_obj.committer().when());
     }
+
+    #[test]
+    fn merge() {
+        use crate::{NotesMergeOutcome, NotesMergeStrategy};
+
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let (other, _) = crate::test::commit(&repo);
+        let theirs = Some("refs/notes/theirs");
+
+        repo.note(&sig, &sig, None, head, "base\n", false).unwrap();
+        let base = repo.refname_to_id("refs/notes/commits").unwrap();
+        repo.reference("refs/notes/theirs", base, false, "")
+            .unwrap();
+        repo.note(&sig, &sig, None, head, "ours\n", true).unwrap();
+        repo.note(&sig, &sig, theirs, head, "theirs\n", true)
+            .unwrap();
+        repo.note(&sig, &sig, theirs, other, "new\n", false)
+            .unwrap();
+
+        let outcome = repo
+            .notes_merge(None, "refs/notes/theirs", &sig, NotesMergeStrategy::Manual)
+            .unwrap();
+        assert_eq!(outcome, NotesMergeOutcome::Conflicts(vec![head]));
+
+        let outcome = repo
+            .notes_merge(
+                None,
+                "refs/notes/theirs",
+                &sig,
+                NotesMergeStrategy::CatSortUniq,
+            )
+            .unwrap();
+        let merged = match outcome {
+            NotesMergeOutcome::Merged(id) => id,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        assert_eq!(repo.refname_to_id("refs/notes/commits").unwrap(), merged);
+        let note = repo.find_note(None, head).unwrap();
+        assert_eq!(note.message(), Some("ours\ntheirs\n"));
+        assert_eq!(
+            repo.find_note(None, other).unwrap().message(),
+            Some("new\n")
+        );
+
+        let outcome = repo
+            .notes_merge(None, "refs/notes/theirs", &sig, NotesMergeStrategy::Ours)
+            .unwrap();
+        assert_eq!(outcome, NotesMergeOutcome::UpToDate);
+        let outcome = repo
+            .notes_merge(theirs, "refs/notes/commits", &sig, NotesMergeStrategy::Ours)
+            .unwrap();
+        assert_eq!(outcome, NotesMergeOutcome::FastForward(merged));
+    }
+
+    #[test]
+    fn merge_keeps_fanout() {
+        use crate::{NotesMergeOutcome, NotesMergeStrategy};
+        use std::path::Path;
+
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let (other, _) = crate::test::commit(&repo);
+        let hex = head.to_string();
+
+        let mut fanout = repo.treebuilder(None).unwrap();
+        let note = repo.blob(b"base\n").unwrap();
+        fanout.insert(&hex[2..], note, 0o100644).unwrap();
+        let fanout = fanout.write().unwrap();
+        let mut root = repo.treebuilder(None).unwrap();
+        root.insert(&hex[..2], fanout, 0o040000).unwrap();
+        let readme = repo.blob(b"notes\n").unwrap();
+        root.insert("README", readme, 0o100644).unwrap();
+        let tree = repo.find_tree(root.write().unwrap()).unwrap();
+        let base = repo
+            .commit(Some("refs/notes/commits"), &sig, &sig, "base", &tree, &[])
+            .unwrap();
+        repo.reference("refs/notes/theirs", base, false, "")
+            .unwrap();
+        repo.note(&sig, &sig, None, head, "ours\n", true).unwrap();
+        repo.note(&sig, &sig, Some("refs/notes/theirs"), other, "new\n", false)
+            .unwrap();
+
+        let outcome = repo
+            .notes_merge(None, "refs/notes/theirs", &sig, NotesMergeStrategy::Manual)
+            .unwrap();
+        let merged = match outcome {
+            NotesMergeOutcome::Merged(id) => id,
+            outcome => panic!("unexpected outcome {:?}", outcome),
+        };
+        let tree = repo.find_commit(merged).unwrap().tree().unwrap();
+        assert_eq!(tree.get_name("README").unwrap().id(), readme);
+        let path = Path::new(&hex[..2]).join(&hex[2..]);
+        let entry = tree.get_path(&path).unwrap();
+        assert_eq!(repo.find_blob(entry.id()).unwrap().content(), b"ours\n");
+        assert_eq!(
+            repo.find_note(None, other).unwrap().message(),
+            Some("new\n")
+        );
+    }
+
+    #[test]
+    fn notes_commit() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let (other, _) = crate::test::commit(&repo);
+
+        let (first, blob) = repo
+            .note_commit_create(None, &sig, &sig, head, "foo", false)
+            .unwrap();
+        let first = repo.find_commit(first).unwrap();
+        assert_eq!(first.parent_count(), 0);
+        let note = repo.find_note_in_commit(&first, head).unwrap();
+        assert_eq!(note.id(), blob);
+        assert_eq!(note.message(), Some("foo"));
+
+        let (second, _) = repo
+            .note_commit_create(Some(&first), &sig, &sig, other, "bar", false)
+            .unwrap();
+        let second = repo.find_commit(second).unwrap();
+        assert_eq!(second.parent_id(0).unwrap(), first.id());
+        assert_eq!(repo.notes_in_commit(&second).unwrap().count(), 2);
+
+        let third = repo.note_commit_remove(&second, &sig, &sig, head).unwrap();
+        let third = repo.find_commit(third).unwrap();
+        assert!(repo.find_note_in_commit(&third, head).is_err());
+        assert_eq!(repo.notes_in_commit(&third).unwrap().count(), 1);
+
+        // No reference was touched.
+        assert!(repo.find_note(None, other).is_err());
+    }
 }
@@ -1,16 +1,21 @@
-use std::io;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
 use std::marker;
 use std::ptr;
 use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
-use std::ffi::CString;
+use std::path::{Path, PathBuf};
 
 use libc::{c_char, c_int, c_uint, c_void, size_t};
 
 use crate::panic;
 use crate::util::Binding;
 use crate::{
-    raw, Error, IndexerProgress, Mempack, Object, ObjectType, OdbLookupFlags, Oid, Progress,
+    raw, Error, ErrorCode, IndexerProgress, IntoCString, Mempack, Object, ObjectType, OdbBackend,
+    OdbLookupFlags, Oid, Progress,
 };
 
 /// A structure to represent a git object database
@@ -114,6 +119,76 @@ impl<'repo> Odb<'repo> {
         }
     }
 
+    /// Iterate over all objects in the object database along with their
+    /// type and size.
+    ///
+    /// The ids of all objects are listed when this is called, while the
+    /// headers of the objects are only read as the iterator advances, so
+    /// dropping the iterator stops the walk early. Objects that are present
+    /// in more than one backend are only yielded once.
+    pub fn objects(&self) -> Result<OdbObjects<'_>, Error> {
+        let mut seen = HashSet::new();
+        let mut ids = Vec::new();
+        self.foreach(|id| {
+            if seen.insert(*id) {
+                ids.push(*id);
+            }
+            true
+        })?;
+        Ok(OdbObjects {
+            odb: self,
+            ids: ids.into_iter(),
+        })
+    }
+
+    /// Call `callback` with the id, type and size of every object in the
+    /// object database, using up to `threads` threads.
+    ///
+    /// libgit2 does not expose the individual packfiles of an object
+    /// database, so the objects themselves are distributed across the
+    /// threads. The callback may be called concurrently and in any order.
+    /// Returning `false` from it stops all threads as soon as they finish
+    /// the object they are looking at; in that case `Ok(false)` is returned,
+    /// and `Ok(true)` once every object has been seen.
+    pub fn foreach_parallel<C>(&self, threads: usize, callback: C) -> Result<bool, Error>
+    where
+        C: Fn(Oid, ObjectType, usize) -> bool + Sync,
+    {
+        let ids = self.objects()?.ids.collect::<Vec<_>>();
+        let threads = threads.max(1).min(ids.len().max(1));
+        let stop = AtomicBool::new(false);
+        let results = thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|n| {
+                    let (ids, stop, callback) = (&ids, &stop, &callback);
+                    s.spawn(move || -> Result<(), Error> {
+                        for id in ids.iter().skip(n).step_by(threads) {
+                            if stop.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            let (size, kind) = self.read_header(*id)?;
+                            if !callback(*id, kind, size) {
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| match h.join() {
+                    Ok(result) => result,
+                    Err(e) => std::panic::resume_unwind(e),
+                })
+                .collect::<Vec<_>>()
+        });
+        for result in results {
+            result?;
+        }
+        Ok(!stop.load(Ordering::Relaxed))
+    }
+
     /// Read an object from the database.
     pub fn read(&self, oid: Oid) -> Result<OdbObject<'_>, Error> {
         let mut out = ptr::null_mut();
@@ -178,9 +253,32 @@ impl<'repo> Odb<'repo> {
             raw: out,
             progress: Default::default(),
             progress_payload_ptr,
+            trailer: Vec::new(),
+            name: None,
         })
     }
 
+    /// Mark an object as recently used.
+    ///
+    /// The modification time of the loose object, or of the packfile
+    /// holding it, is updated so that a concurrent `git gc` or `git prune`
+    /// considers it fresh and does not delete it. This is useful before
+    /// writing a tree or reference that points at an object which already
+    /// exists. Packfiles are touched at most once every couple of seconds.
+    ///
+    /// Returns `false` if the object is not in the object database. The
+    /// object is read in full, since libgit2 only freshens objects when an
+    /// existing object is written again.
+    pub fn freshen(&self, oid: Oid) -> Result<bool, Error> {
+        let obj = match self.read(oid) {
+            Ok(obj) => obj,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        self.write(obj.kind(), obj.data())?;
+        Ok(true)
+    }
+
     /// Checks if the object database has an object.
     pub fn exists(&self, oid: Oid) -> bool {
         unsafe { raw::git_odb_exists(self.raw, oid.raw()) != 0 }
@@ -207,6 +305,48 @@ impl<'repo> Odb<'repo> {
         }
     }
 
+    /// Resolve many abbreviated object ids at once.
+    ///
+    /// Each entry of `ids` is an abbreviated id along with the number of
+    /// hexadecimal digits that are significant. The result has an entry for
+    /// each of them, holding the full id and type of the object, or `None`
+    /// if no object or more than one object matches. Unlike `exists_prefix`,
+    /// which rescans the object database every time an id is not found,
+    /// this never refreshes it, which makes it much faster for large batches
+    /// with missing ids. Objects written by other processes since the
+    /// object database was last loaded are only found after calling
+    /// `refresh`.
+    pub fn expand_ids(
+        &self,
+        ids: &[(Oid, usize)],
+    ) -> Result<Vec<Option<(Oid, ObjectType)>>, Error> {
+        let mut raw_ids = ids
+            .iter()
+            .map(|(id, len)| raw::git_odb_expand_id {
+                id: unsafe { *id.raw() },
+                length: (*len).min(raw::GIT_OID_HEXSZ) as u16,
+                kind: raw::GIT_OBJECT_ANY,
+            })
+            .collect::<Vec<_>>();
+        unsafe {
+            try_call!(raw::git_odb_expand_ids(
+                self.raw,
+                raw_ids.as_mut_ptr(),
+                raw_ids.len()
+            ));
+        }
+        Ok(raw_ids
+            .iter()
+            .map(|raw| {
+                if raw.length == 0 {
+                    return None;
+                }
+                let id = unsafe { Oid::from_raw(&raw.id) };
+                ObjectType::from_raw(raw.kind).map(|kind| (id, kind))
+            })
+            .collect())
+    }
+
     /// Refresh the object database.
     /// This should never be needed, and is
     /// provided purely for convenience.
@@ -220,15 +360,60 @@ impl<'repo> Odb<'repo> {
         }
     }
 
+    /// Write a multi-pack-index covering all the packfiles of the object
+    /// database.
+    ///
+    /// The index is written to `objects/pack/multi-pack-index` and lets
+    /// object lookups search a single index instead of one index per
+    /// packfile, which speeds up repositories with many packs. Only the
+    /// packfiles already known to the object database are included, so
+    /// call `refresh` first if packs were added by another process.
+    pub fn write_multi_pack_index(&self) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_odb_write_multi_pack_index(self.raw));
+            Ok(())
+        }
+    }
+
     /// Adds an alternate disk backend to the object database.
-    pub fn add_disk_alternate(&self, path: &str) -> Result<(), Error> {
+    ///
+    /// The loose objects and packfiles in the `objects` directory at `path`
+    /// are searched after those of the repository, like the directories
+    /// listed in `objects/info/alternates`. This can be used after the
+    /// repository is opened, for example to see the objects of a quarantine
+    /// directory during a push or of a shared object cache. Objects are
+    /// never written to an alternate.
+    pub fn add_disk_alternate<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref().into_c_string()?;
         unsafe {
-            let path = CString::new(path)?;
             try_call!(raw::git_odb_add_disk_alternate(self.raw, path));
             Ok(())
         }
     }
 
+    /// Add a custom backend to this odb with the given priority.
+    ///
+    /// Higher values give the backend higher precedence. The default loose
+    /// and pack backends have priorities 1 and 2 respectively, and new
+    /// objects are written to the backend with the highest priority that
+    /// supports writing. The backend is dropped along with the odb.
+    pub fn add_custom_backend(
+        &self,
+        backend: Box<dyn OdbBackend>,
+        priority: i32,
+    ) -> Result<(), Error> {
+        unsafe {
+            let raw = crate::odb_backend::new_raw(backend)?;
+            let rc = raw::git_odb_add_backend(self.raw, raw, priority as c_int);
+            if rc < 0 {
+                // The odb only takes ownership of backends it added.
+                ((*raw).free.unwrap())(raw);
+                return Err(Error::last_error(rc));
+            }
+            Ok(())
+        }
+    }
+
     /// Create a new mempack backend, and add it to this odb with the given
     /// priority. Higher values give the backend higher precedence. The default
     /// loose and pack backends have priorities 1 and 2 respectively (hard-coded
@@ -271,6 +456,163 @@ impl<'repo> Odb<'repo> {
     }
 }
 
+/// An iterator over the objects of an object database, created by
+/// `Odb::objects`.
+///
+/// Each item is the id, type and size of an object.
+pub struct OdbObjects<'odb> {
+    odb: &'odb Odb<'odb>,
+    ids: std::vec::IntoIter<Oid>,
+}
+
+impl<'odb> Iterator for OdbObjects<'odb> {
+    type Item = Result<(Oid, ObjectType, usize), Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.ids.next()?;
+        Some(
+            self.odb
+                .read_header(id)
+                .map(|(size, kind)| (id, kind, size)),
+        )
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.ids.size_hint()
+    }
+}
+
+impl<'odb> ExactSizeIterator for OdbObjects<'odb> {}
+
+/// A packfile of a repository, as listed by `Repository::packfiles`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packfile {
+    name: String,
+    path: PathBuf,
+    object_count: u32,
+    size: u64,
+}
+
+impl Packfile {
+    /// The file name of the packfile, such as `pack-<hash>.pack`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The full path of the packfile.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of objects stored in the packfile, read from its index.
+    pub fn object_count(&self) -> u32 {
+        self.object_count
+    }
+
+    /// The size of the packfile in bytes, not including its index.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The size of the magic, version and fanout table of a version 2 pack
+/// index.
+const IDX_HEADER_SIZE: usize = 8 + 256 * 4;
+
+/// Implementation of `Repository::packfiles`, listing the indexed packfiles
+/// of the `objects/pack` directory `dir`.
+pub(crate) fn packfiles(dir: &Path) -> Result<Vec<Packfile>, Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut packs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.starts_with("pack-") && name.ends_with(".pack") => name,
+            _ => continue,
+        };
+        // Packs without an index are still being written.
+        let file = match fs::File::open(path.with_extension("idx")) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        // The last entry of the fanout table is the number of objects. It
+        // follows the magic and version in version 2 indexes, and starts the
+        // file in version 1 indexes, so only the header is read.
+        let mut idx = Vec::with_capacity(IDX_HEADER_SIZE);
+        file.take(IDX_HEADER_SIZE as u64).read_to_end(&mut idx)?;
+        let fanout = if idx.starts_with(b"\xfftOc") { 8 } else { 0 };
+        let count = match idx.get(fanout + 255 * 4..fanout + 256 * 4) {
+            Some(count) => u32::from_be_bytes([count[0], count[1], count[2], count[3]]),
+            None => return Err(Error::from_str("invalid pack index: too short")),
+        };
+        packs.push(Packfile {
+            name: name.to_string(),
+            size: fs::metadata(&path)?.len(),
+            object_count: count,
+            path,
+        });
+    }
+    packs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packs)
+}
+
+/// List the ids of the objects of a packfile from its index `idx`.
+pub(crate) fn pack_index_ids(idx: &Path) -> Result<Vec<Oid>, Error> {
+    let data = fs::read(idx)?;
+    let too_short = || Error::from_str("invalid pack index: too short");
+    // Version 2 indexes list the ids after the fanout table, version 1
+    // indexes interleave them with 4-byte offsets.
+    let (fanout, stride, skip) = if data.starts_with(b"\xfftOc") {
+        (8, raw::GIT_OID_RAWSZ, 0)
+    } else {
+        (0, 4 + raw::GIT_OID_RAWSZ, 4)
+    };
+    let count = data
+        .get(fanout + 255 * 4..fanout + 256 * 4)
+        .ok_or_else(too_short)?;
+    let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let table = fanout + 256 * 4;
+    (0..count)
+        .map(|i| {
+            let start = table + i * stride + skip;
+            let id = data
+                .get(start..start + raw::GIT_OID_RAWSZ)
+                .ok_or_else(too_short)?;
+            Oid::from_bytes(id)
+        })
+        .collect()
+}
+
+/// Open an object database made of the loose objects of the objects
+/// directory `objects` only, so that writing an object which is already
+/// packed creates a loose copy of it. Objects are flushed to disk as they
+/// are written.
+pub(crate) fn loose_odb(objects: &Path) -> Result<Odb<'static>, Error> {
+    let odb = Odb::new()?;
+    let objects = objects.into_c_string()?;
+    unsafe {
+        let mut backend = ptr::null_mut();
+        try_call!(raw::git_odb_backend_loose(
+            &mut backend,
+            objects,
+            -1,
+            1,
+            0,
+            0
+        ));
+        let rc = raw::git_odb_add_backend(odb.raw, backend, 1);
+        if rc < 0 {
+            // The odb only takes ownership of backends it added.
+            ((*backend).free.unwrap())(backend);
+            return Err(Error::last_error(rc));
+        }
+    }
+    Ok(odb)
+}
+
 /// An object from the Object Database.
 pub struct OdbObject<'a> {
     raw: *mut raw::git_odb_object,
@@ -441,10 +783,18 @@ pub(crate) struct OdbPackwriterCb<'repo> {
 }
 
 /// A stream to write a packfile to the ODB
+///
+/// The pack and its index are written to disk when the writer is committed.
+/// They are flushed to disk before being moved in place if
+/// `opts::enable_fsync_gitdir` is enabled. To protect the new pack from a
+/// concurrent `git gc` with a `.keep` file, index it with an `Indexer`
+/// instead.
 pub struct OdbPackwriter<'repo> {
     raw: *mut raw::git_odb_writepack,
     progress: raw::git_indexer_progress,
     progress_payload_ptr: *mut OdbPackwriterCb<'repo>,
+    trailer: Vec<u8>,
+    name: Option<String>,
 }
 
 impl<'repo> OdbPackwriter<'repo> {
@@ -460,11 +810,29 @@ impl<'repo> OdbPackwriter<'repo> {
             if res < 0 {
                 Err(Error::last_error(res))
             } else {
+                // The pack is named after its trailing checksum, unless it was
+                // a thin pack whose missing bases were appended to it.
+                if self.progress.local_objects == 0 && self.trailer.len() == 20 {
+                    self.name = Some(Oid::from_bytes(&self.trailer)?.to_string());
+                }
                 Ok(res)
             }
         }
     }
 
+    /// Get the name of the packfile written to the object database, that is
+    /// the hexadecimal checksum used in `pack-<checksum>.pack`.
+    ///
+    /// libgit2 keeps the indexer of the writer to itself, so the name cannot
+    /// be queried with `git_indexer_name`. Instead it is taken from the last
+    /// 20 bytes written, which hold the checksum that names the pack as long
+    /// as the pack is complete. It is therefore only available once the
+    /// writer has been committed, and is `None` for thin packs, since libgit2
+    /// completes them with base objects and therefore changes their checksum.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
     /// The callback through which progress is monitored. Be aware that this is
     /// called inline, so performance may be affected.
     pub fn progress<F>(&mut self, cb: F) -> &mut OdbPackwriter<'repo>
@@ -494,6 +862,10 @@ impl<'repo> io::Write for OdbPackwriter<'repo> {
             if res < 0 {
                 Err(io::Error::new(io::ErrorKind::Other, "Write error"))
             } else {
+                self.trailer
+                    .extend_from_slice(&buf[buf.len().saturating_sub(20)..]);
+                let excess = self.trailer.len().saturating_sub(20);
+                self.trailer.drain(..excess);
                 Ok(buf.len())
             }
         }
@@ -646,6 +1018,125 @@ mod tests {
         assert_eq!(found_oid, id);
     }
 
+    #[test]
+    fn add_disk_alternate() {
+        let (_td, repo) = crate::test::repo_init();
+        let (_td2, other) = crate::test::repo_init();
+        let id = t!(other.blob(b"only in the other repository"));
+        let odb = t!(repo.odb());
+        assert!(!odb.exists(id));
+        t!(odb.add_disk_alternate(other.path().join("objects")));
+        assert!(odb.exists(id));
+        assert_eq!(
+            t!(repo.find_blob(id)).content(),
+            b"only in the other repository"
+        );
+    }
+
+    #[test]
+    fn expand_ids() {
+        let (_td, repo) = crate::test::repo_init();
+        let db = repo.odb().unwrap();
+        let blob = db.write(ObjectType::Blob, b"blob").unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let short = |id: Oid, len: usize| (Oid::from_str(&id.to_string()[..len]).unwrap(), len);
+        let missing = Oid::from_str("deadbeef").unwrap();
+        let expanded = db
+            .expand_ids(&[short(blob, 7), short(head, 12), (missing, 8), (head, 40)])
+            .unwrap();
+        assert_eq!(
+            expanded,
+            [
+                Some((blob, ObjectType::Blob)),
+                Some((head, ObjectType::Commit)),
+                None,
+                Some((head, ObjectType::Commit)),
+            ]
+        );
+    }
+
+    #[test]
+    fn objects() {
+        let (_td, repo) = crate::test::repo_init();
+        let db = repo.odb().unwrap();
+        let blob = db.write(ObjectType::Blob, b"blob").unwrap();
+        let objects = db
+            .objects()
+            .unwrap()
+            .map(|o| o.unwrap())
+            .collect::<Vec<_>>();
+        // The blob plus the initial commit and its empty tree.
+        assert_eq!(objects.len(), 3);
+        assert!(objects.contains(&(blob, ObjectType::Blob, 4)));
+        assert_eq!(db.objects().unwrap().take(1).count(), 1);
+
+        let seen = std::sync::Mutex::new(Vec::new());
+        let done = db
+            .foreach_parallel(2, |id, kind, size| {
+                seen.lock().unwrap().push((id, kind, size));
+                true
+            })
+            .unwrap();
+        assert!(done);
+        let mut seen = seen.into_inner().unwrap();
+        let mut objects = objects;
+        seen.sort_by_key(|o| o.0);
+        objects.sort_by_key(|o| o.0);
+        assert_eq!(seen, objects);
+
+        let done = db.foreach_parallel(1, |_, _, _| false).unwrap();
+        assert!(!done);
+    }
+
+    #[test]
+    fn freshen() {
+        let (td, repo) = crate::test::repo_init();
+        let db = repo.odb().unwrap();
+        let id = db.write(ObjectType::Blob, b"blob").unwrap();
+        let hex = id.to_string();
+        let path = td
+            .path()
+            .join(".git/objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        // Loose objects are read-only, but their owner may still set times.
+        let file = std::fs::File::open(&path).unwrap();
+        file.set_modified(old).unwrap();
+        drop(file);
+
+        assert!(db.freshen(id).unwrap());
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+        assert!(mtime > old);
+        assert!(!db.freshen(Oid::from_str("deadbeef").unwrap()).unwrap());
+    }
+
+    #[test]
+    fn packfiles() {
+        let (_td, repo) = crate::test::repo_init();
+        assert_eq!(repo.packfiles().unwrap(), []);
+
+        let head = repo.head().unwrap().target().unwrap();
+        let mut builder = repo.packbuilder().unwrap();
+        builder.insert_commit(head).unwrap();
+        let dir = repo.path().join("objects/pack");
+        builder.write(&dir, 0).unwrap();
+        let packs = repo.packfiles().unwrap();
+        assert_eq!(packs.len(), 1);
+        // The commit and its empty tree.
+        assert_eq!(packs[0].object_count(), 2);
+        assert_eq!(packs[0].path(), dir.join(packs[0].name()));
+        assert_eq!(
+            packs[0].size(),
+            std::fs::metadata(packs[0].path()).unwrap().len()
+        );
+
+        let db = repo.odb().unwrap();
+        db.refresh().unwrap();
+        db.write_multi_pack_index().unwrap();
+        assert!(dir.join("multi-pack-index").exists());
+    }
+
     #[test]
     fn packwriter() {
         let (_td, repo_source) = crate::test::repo_init();
@@ -663,6 +1154,26 @@ mod tests {
         assert_eq!(commit_target.id(), commit_source_id);
     }
 
+    #[test]
+    fn packwriter_name() {
+        let (_td, repo_source) = crate::test::repo_init();
+        let (_td, repo_target) = crate::test::repo_init();
+        let mut builder = t!(repo_source.packbuilder());
+        let mut buf = Buf::new();
+        let (commit_source_id, _tree) = crate::test::commit(&repo_source);
+        t!(builder.insert_object(commit_source_id, None));
+        t!(builder.write_buf(&mut buf));
+        let db = repo_target.odb().unwrap();
+        let mut packwriter = db.packwriter().unwrap();
+        assert_eq!(packwriter.name(), None);
+        packwriter.write(&buf[..10]).unwrap();
+        packwriter.write(&buf[10..]).unwrap();
+        packwriter.commit().unwrap();
+        let name = packwriter.name().unwrap();
+        let packs = repo_target.packfiles().unwrap();
+        assert_eq!(packs[0].name(), format!("pack-{}.pack", name));
+    }
+
     #[test]
     fn packwriter_progress() {
         let mut progress_called = false;
@@ -727,6 +1238,25 @@ mod tests {
         assert!(foo_file.exists());
     }
 
+    #[test]
+    fn mempack_write_to_odb() {
+        let (_td, repo) = crate::test::repo_init();
+        let odb = repo.odb().unwrap();
+        let mempack = odb.add_new_mempack_backend(1000).unwrap();
+        mempack.write_to_odb(&repo, &odb).unwrap();
+        assert_eq!(repo.packfiles().unwrap(), []);
+
+        let (commit, _tree) = crate::test::commit(&repo);
+        mempack.write_to_odb(&repo, &odb).unwrap();
+        let packs = repo.packfiles().unwrap();
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].object_count(), 3);
+        let mut buf = Buf::new();
+        mempack.dump(&repo, &mut buf).unwrap();
+        assert_eq!(&buf[8..12], &[0, 0, 0, 0]);
+        repo.find_commit(commit).unwrap();
+    }
+
     #[test]
     fn stream_read() {
         // Test for read impl of OdbReader.
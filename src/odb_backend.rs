@@ -0,0 +1,761 @@
+//! Interfaces for storing objects of an object database outside of the
+//! repository.
+
+use libc::{c_int, c_void, size_t};
+use std::borrow::Cow;
+use std::path::Path;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use crate::util::{Binding, IntoCString};
+use crate::{panic, raw, Error, ErrorClass, ErrorCode, ObjectType, Oid};
+
+/// A custom object database backend, storing objects in a database, an
+/// object store, encrypted blobs or anywhere else.
+///
+/// Backends are added to an object database with `Odb::add_custom_backend`.
+/// Only `read` and `write` need to be implemented; the other methods have
+/// default implementations built on them. A backend that can answer them
+/// more cheaply, for example by looking up an index rather than reading the
+/// whole object, should override them.
+///
+/// Panics in a backend are caught at the FFI boundary and resumed in the
+/// thread that called into the object database once libgit2 returns.
+pub trait OdbBackend: Send + Sync + 'static {
+    /// Read the object with the given id, returning its type and contents,
+    /// or `None` if the backend does not have it.
+    fn read(&self, id: Oid) -> Result<Option<(ObjectType, Vec<u8>)>, Error>;
+
+    /// Store an object whose id was computed by libgit2.
+    fn write(&self, id: Oid, kind: ObjectType, data: &[u8]) -> Result<(), Error>;
+
+    /// Read the size and type of the object with the given id, or `None` if
+    /// the backend does not have it.
+    ///
+    /// The default implementation reads the whole object.
+    fn read_header(&self, id: Oid) -> Result<Option<(usize, ObjectType)>, Error> {
+        Ok(self.read(id)?.map(|(kind, data)| (data.len(), kind)))
+    }
+
+    /// Returns whether the backend has the object with the given id.
+    ///
+    /// The default implementation uses `read_header`.
+    fn exists(&self, id: Oid) -> Result<bool, Error> {
+        Ok(self.read_header(id)?.is_some())
+    }
+
+    /// Call `callback` with the id of every object in the backend, stopping
+    /// if it returns `false`.
+    ///
+    /// This is used to look up objects by an abbreviated id. The default
+    /// implementation lists no objects, so that abbreviated ids only resolve
+    /// to objects of other backends.
+    fn foreach(&self, callback: &mut dyn FnMut(Oid) -> bool) -> Result<(), Error> {
+        let _ = callback;
+        Ok(())
+    }
+
+    /// Find the object whose id starts with the first `len` hexadecimal
+    /// digits of `short_id`, or `None` if there is no such object.
+    ///
+    /// The default implementation searches the ids listed by `foreach`, and
+    /// returns an error of code `Ambiguous` if several objects match.
+    fn exists_prefix(&self, short_id: Oid, len: usize) -> Result<Option<Oid>, Error> {
+        let short_id = short_id.to_string();
+        let prefix = &short_id[..len.min(short_id.len())];
+        let mut found = None;
+        let mut ambiguous = false;
+        self.foreach(&mut |id| {
+            if !id.to_string().starts_with(prefix) {
+                return true;
+            }
+            if found.is_some() {
+                ambiguous = true;
+                return false;
+            }
+            found = Some(id);
+            true
+        })?;
+        if ambiguous {
+            return Err(Error::new(
+                ErrorCode::Ambiguous,
+                ErrorClass::Odb,
+                format!("ambiguous object prefix {}", prefix),
+            ));
+        }
+        Ok(found)
+    }
+
+    /// Refresh the backend, picking up objects added to its storage by
+    /// others since it was last read.
+    ///
+    /// The default implementation does nothing.
+    fn refresh(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Instance of a `git_odb_backend`, must use `#[repr(C)]` to ensure that the
+/// C fields come first.
+#[repr(C)]
+struct RawOdbBackend {
+    raw: raw::git_odb_backend,
+    obj: Box<dyn OdbBackend>,
+}
+
+/// Wrap a backend into a `git_odb_backend` that is freed by libgit2.
+pub(crate) fn new_raw(backend: Box<dyn OdbBackend>) -> Result<*mut raw::git_odb_backend, Error> {
+    unsafe {
+        let mut raw: raw::git_odb_backend = std::mem::zeroed();
+        try_call!(raw::git_odb_init_backend(
+            &mut raw,
+            raw::GIT_ODB_BACKEND_VERSION
+        ));
+        raw.read = Some(backend_read);
+        raw.read_prefix = Some(backend_read_prefix);
+        raw.read_header = Some(backend_read_header);
+        raw.write = Some(backend_write);
+        raw.exists = Some(backend_exists);
+        raw.exists_prefix = Some(backend_exists_prefix);
+        raw.refresh = Some(backend_refresh);
+        raw.foreach = Some(backend_foreach);
+        raw.free = Some(backend_free);
+        let backend = Box::new(RawOdbBackend { raw, obj: backend });
+        Ok(Box::into_raw(backend) as *mut raw::git_odb_backend)
+    }
+}
+
+fn not_found(id: &dyn std::fmt::Display) -> Error {
+    Error::new(
+        ErrorCode::NotFound,
+        ErrorClass::Odb,
+        format!("object not found: {}", id),
+    )
+}
+
+fn unknown_type(kind: raw::git_object_t) -> Error {
+    Error::new(
+        ErrorCode::GenericError,
+        ErrorClass::Odb,
+        format!("unknown object type {}", kind),
+    )
+}
+
+unsafe fn backend<'a>(backend: *mut raw::git_odb_backend) -> &'a dyn OdbBackend {
+    &*(*(backend as *mut RawOdbBackend)).obj
+}
+
+fn result(ret: Option<Result<(), Error>>) -> c_int {
+    match ret {
+        Some(Ok(())) => 0,
+        Some(Err(e)) => unsafe { e.raw_set_git_error() },
+        None => -1,
+    }
+}
+
+/// Copy object data into a buffer allocated by libgit2, which frees it.
+unsafe fn copy_out(
+    backend: *mut raw::git_odb_backend,
+    data: &[u8],
+    data_out: *mut *mut c_void,
+    len_out: *mut size_t,
+) -> Result<(), Error> {
+    let buf = raw::git_odb_backend_malloc(backend, data.len());
+    if buf.is_null() && !data.is_empty() {
+        return Err(Error::from_str("out of memory"));
+    }
+    if !data.is_empty() {
+        ptr::copy_nonoverlapping(data.as_ptr(), buf as *mut u8, data.len());
+    }
+    *data_out = buf;
+    *len_out = data.len();
+    Ok(())
+}
+
+extern "C" fn backend_read(
+    data_out: *mut *mut c_void,
+    len_out: *mut size_t,
+    type_out: *mut raw::git_object_t,
+    raw_backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+) -> c_int {
+    result(panic::wrap(|| unsafe {
+        let id = Binding::from_raw(oid);
+        let (kind, data) = backend(raw_backend)
+            .read(id)?
+            .ok_or_else(|| not_found(&id))?;
+        copy_out(raw_backend, &data, data_out, len_out)?;
+        *type_out = kind.raw();
+        Ok(())
+    }))
+}
+
+extern "C" fn backend_read_prefix(
+    oid_out: *mut raw::git_oid,
+    data_out: *mut *mut c_void,
+    len_out: *mut size_t,
+    type_out: *mut raw::git_object_t,
+    raw_backend: *mut raw::git_odb_backend,
+    short_oid: *const raw::git_oid,
+    len: size_t,
+) -> c_int {
+    result(panic::wrap(|| unsafe {
+        let short_id: Oid = Binding::from_raw(short_oid);
+        let backend = backend(raw_backend);
+        let id = backend
+            .exists_prefix(short_id, len)?
+            .ok_or_else(|| not_found(&short_id))?;
+        let (kind, data) = backend.read(id)?.ok_or_else(|| not_found(&id))?;
+        copy_out(raw_backend, &data, data_out, len_out)?;
+        *type_out = kind.raw();
+        *oid_out = *id.raw();
+        Ok(())
+    }))
+}
+
+extern "C" fn backend_read_header(
+    len_out: *mut size_t,
+    type_out: *mut raw::git_object_t,
+    raw_backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+) -> c_int {
+    result(panic::wrap(|| unsafe {
+        let id = Binding::from_raw(oid);
+        let (len, kind) = backend(raw_backend)
+            .read_header(id)?
+            .ok_or_else(|| not_found(&id))?;
+        *len_out = len;
+        *type_out = kind.raw();
+        Ok(())
+    }))
+}
+
+extern "C" fn backend_write(
+    raw_backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+    data: *const c_void,
+    len: size_t,
+    kind: raw::git_object_t,
+) -> c_int {
+    result(panic::wrap(|| unsafe {
+        let id = Binding::from_raw(oid);
+        let kind = ObjectType::from_raw(kind).ok_or_else(|| unknown_type(kind))?;
+        let data = if len == 0 {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(data as *const u8, len)
+        };
+        backend(raw_backend).write(id, kind, data)
+    }))
+}
+
+extern "C" fn backend_exists(
+    raw_backend: *mut raw::git_odb_backend,
+    oid: *const raw::git_oid,
+) -> c_int {
+    let ret = panic::wrap(|| unsafe { backend(raw_backend).exists(Binding::from_raw(oid)) });
+    match ret {
+        Some(Ok(exists)) => exists as c_int,
+        Some(Err(e)) => unsafe { e.raw_set_git_error() },
+        None => -1,
+    }
+}
+
+extern "C" fn backend_exists_prefix(
+    oid_out: *mut raw::git_oid,
+    raw_backend: *mut raw::git_odb_backend,
+    short_oid: *const raw::git_oid,
+    len: size_t,
+) -> c_int {
+    result(panic::wrap(|| unsafe {
+        let short_id: Oid = Binding::from_raw(short_oid);
+        let id = backend(raw_backend)
+            .exists_prefix(short_id, len)?
+            .ok_or_else(|| not_found(&short_id))?;
+        *oid_out = *id.raw();
+        Ok(())
+    }))
+}
+
+extern "C" fn backend_refresh(raw_backend: *mut raw::git_odb_backend) -> c_int {
+    result(panic::wrap(|| unsafe { backend(raw_backend).refresh() }))
+}
+
+extern "C" fn backend_foreach(
+    raw_backend: *mut raw::git_odb_backend,
+    cb: raw::git_odb_foreach_cb,
+    payload: *mut c_void,
+) -> c_int {
+    let ret = panic::wrap(|| unsafe {
+        let cb = match cb {
+            Some(cb) => cb,
+            None => return Ok::<c_int, Error>(0),
+        };
+        let mut stopped = 0;
+        backend(raw_backend).foreach(&mut |id| {
+            stopped = cb(id.raw(), payload);
+            stopped == 0
+        })?;
+        Ok(stopped)
+    });
+    match ret {
+        Some(Ok(stopped)) => stopped,
+        Some(Err(e)) => unsafe { e.raw_set_git_error() },
+        None => -1,
+    }
+}
+
+extern "C" fn backend_free(raw_backend: *mut raw::git_odb_backend) {
+    let _ = panic::wrap(|| unsafe {
+        drop(Box::from_raw(raw_backend as *mut RawOdbBackend));
+    });
+}
+
+/// One of the built-in backends of libgit2, storing objects on disk as loose
+/// objects or packfiles.
+///
+/// This is mostly useful to wrap a built-in backend with a `LayeredBackend`,
+/// and add the result to an object database with `Odb::add_custom_backend`.
+pub struct DiskBackend {
+    raw: *mut raw::git_odb_backend,
+}
+
+// libgit2 backends are shared by all the threads using an object database.
+unsafe impl Send for DiskBackend {}
+unsafe impl Sync for DiskBackend {}
+
+impl DiskBackend {
+    /// Create a backend for the loose objects of an objects directory, such
+    /// as `.git/objects`.
+    pub fn loose<P: AsRef<Path>>(objects_dir: P) -> Result<DiskBackend, Error> {
+        crate::init();
+        let objects_dir = objects_dir.as_ref().into_c_string()?;
+        let mut out = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_odb_backend_loose(
+                &mut out,
+                objects_dir,
+                -1 as c_int,
+                0 as c_int,
+                0 as libc::c_uint,
+                0 as libc::c_uint
+            ));
+        }
+        Ok(DiskBackend { raw: out })
+    }
+
+    /// Create a read-only backend for the packfiles of an objects
+    /// directory, such as `.git/objects`.
+    pub fn pack<P: AsRef<Path>>(objects_dir: P) -> Result<DiskBackend, Error> {
+        crate::init();
+        let objects_dir = objects_dir.as_ref().into_c_string()?;
+        let mut out = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_odb_backend_pack(&mut out, objects_dir));
+        }
+        Ok(DiskBackend { raw: out })
+    }
+
+    fn check(&self, rc: c_int) -> Result<bool, Error> {
+        match rc {
+            raw::GIT_ENOTFOUND => Ok(false),
+            rc if rc < 0 => Err(Error::last_error(rc)),
+            _ => Ok(true),
+        }
+    }
+}
+
+impl OdbBackend for DiskBackend {
+    fn read(&self, id: Oid) -> Result<Option<(ObjectType, Vec<u8>)>, Error> {
+        let read = match unsafe { (*self.raw).read } {
+            Some(read) => read,
+            None => return Ok(None),
+        };
+        let mut data = ptr::null_mut();
+        let mut len = 0;
+        let mut kind = raw::GIT_OBJECT_ANY;
+        let rc = read(&mut data, &mut len, &mut kind, self.raw, id.raw());
+        if !self.check(rc)? {
+            return Ok(None);
+        }
+        unsafe {
+            let bytes = if len == 0 {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts(data as *const u8, len).to_vec()
+            };
+            raw::git_odb_backend_data_free(self.raw, data);
+            let kind = ObjectType::from_raw(kind).ok_or_else(|| unknown_type(kind))?;
+            Ok(Some((kind, bytes)))
+        }
+    }
+
+    fn write(&self, id: Oid, kind: ObjectType, data: &[u8]) -> Result<(), Error> {
+        let write = unsafe { (*self.raw).write }
+            .ok_or_else(|| Error::from_str("the backend does not support writing objects"))?;
+        let rc = write(
+            self.raw,
+            id.raw(),
+            data.as_ptr() as *const c_void,
+            data.len(),
+            kind.raw(),
+        );
+        self.check(rc)?;
+        Ok(())
+    }
+
+    fn read_header(&self, id: Oid) -> Result<Option<(usize, ObjectType)>, Error> {
+        let read_header = match unsafe { (*self.raw).read_header } {
+            Some(read_header) => read_header,
+            None => return Ok(self.read(id)?.map(|(kind, data)| (data.len(), kind))),
+        };
+        let mut len = 0;
+        let mut kind = raw::GIT_OBJECT_ANY;
+        let rc = read_header(&mut len, &mut kind, self.raw, id.raw());
+        if !self.check(rc)? {
+            return Ok(None);
+        }
+        let kind = ObjectType::from_raw(kind).ok_or_else(|| unknown_type(kind))?;
+        Ok(Some((len, kind)))
+    }
+
+    fn exists(&self, id: Oid) -> Result<bool, Error> {
+        match unsafe { (*self.raw).exists } {
+            Some(exists) => {
+                let rc = exists(self.raw, id.raw());
+                self.check(rc)?;
+                Ok(rc > 0)
+            }
+            None => Ok(self.read_header(id)?.is_some()),
+        }
+    }
+
+    fn foreach(&self, callback: &mut dyn FnMut(Oid) -> bool) -> Result<(), Error> {
+        let foreach = match unsafe { (*self.raw).foreach } {
+            Some(foreach) => foreach,
+            None => return Ok(()),
+        };
+        let mut callback = callback;
+        let payload = &mut callback as *mut _ as *mut c_void;
+        let rc = foreach(self.raw, Some(disk_foreach_cb), payload);
+        panic::check();
+        if rc < 0 && rc != raw::GIT_EUSER {
+            return Err(Error::last_error(rc));
+        }
+        Ok(())
+    }
+
+    fn exists_prefix(&self, short_id: Oid, len: usize) -> Result<Option<Oid>, Error> {
+        let exists_prefix = match unsafe { (*self.raw).exists_prefix } {
+            Some(exists_prefix) => exists_prefix,
+            None => return Ok(None),
+        };
+        let mut out = raw::git_oid {
+            id: [0; raw::GIT_OID_RAWSZ],
+        };
+        let rc = exists_prefix(&mut out, self.raw, short_id.raw(), len);
+        if !self.check(rc)? {
+            return Ok(None);
+        }
+        Ok(Some(unsafe { Binding::from_raw(&out as *const _) }))
+    }
+
+    fn refresh(&self) -> Result<(), Error> {
+        if let Some(refresh) = unsafe { (*self.raw).refresh } {
+            self.check(refresh(self.raw))?;
+        }
+        Ok(())
+    }
+}
+
+extern "C" fn disk_foreach_cb(id: *const raw::git_oid, payload: *mut c_void) -> c_int {
+    let ret = panic::wrap(|| unsafe {
+        let callback = &mut *(payload as *mut &mut dyn FnMut(Oid) -> bool);
+        callback(Binding::from_raw(id))
+    });
+    if ret == Some(true) {
+        0
+    } else {
+        raw::GIT_EUSER
+    }
+}
+
+impl Drop for DiskBackend {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(free) = (*self.raw).free {
+                free(self.raw)
+            }
+        }
+    }
+}
+
+/// The operations of an object database reported to `OdbLayer::record`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OdbOperation {
+    /// The contents of an object were read.
+    Read,
+    /// The size and type of an object were read.
+    ReadHeader,
+    /// An object was written.
+    Write,
+    /// The existence of an object was checked.
+    Exists,
+}
+
+/// Hooks run by a `LayeredBackend` around the backend it wraps.
+///
+/// All the methods have default implementations doing nothing, so a layer
+/// only needs to implement the ones it is interested in, such as `encode`
+/// and `decode` to encrypt or recompress objects at rest, or `record` to
+/// collect metrics.
+pub trait OdbLayer: Send + Sync + 'static {
+    /// Transform the contents of an object before it is written to the
+    /// wrapped backend.
+    fn encode<'a>(
+        &self,
+        id: Oid,
+        kind: ObjectType,
+        data: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, Error> {
+        let _ = (id, kind);
+        Ok(Cow::Borrowed(data))
+    }
+
+    /// Transform the contents of an object read from the wrapped backend,
+    /// undoing `encode`.
+    fn decode(&self, id: Oid, kind: ObjectType, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let _ = (id, kind);
+        Ok(data)
+    }
+
+    /// Returns whether `encode` keeps the size of objects unchanged.
+    ///
+    /// If it does, the size of objects is read from the wrapped backend
+    /// without reading their contents. The default is `false`, which is
+    /// always correct but slower.
+    fn preserves_size(&self) -> bool {
+        false
+    }
+
+    /// Called after each successful operation on an object with the
+    /// decoded size of the object, `None` if it was not found, and the time
+    /// the operation took. The size is `Some(0)` for `Exists`, which does
+    /// not read the object.
+    fn record(&self, op: OdbOperation, id: Oid, size: Option<usize>, elapsed: Duration) {
+        let _ = (op, id, size, elapsed);
+    }
+}
+
+/// A backend running the hooks of an `OdbLayer` around another backend.
+///
+/// Layers can be nested, by wrapping a `LayeredBackend` in another one.
+pub struct LayeredBackend<L> {
+    inner: Box<dyn OdbBackend>,
+    layer: L,
+}
+
+impl<L: OdbLayer> LayeredBackend<L> {
+    /// Wrap `inner` with the hooks of `layer`.
+    pub fn new(inner: Box<dyn OdbBackend>, layer: L) -> LayeredBackend<L> {
+        LayeredBackend { inner, layer }
+    }
+
+    /// The layer of this backend.
+    pub fn layer(&self) -> &L {
+        &self.layer
+    }
+}
+
+impl<L: OdbLayer> OdbBackend for LayeredBackend<L> {
+    fn read(&self, id: Oid) -> Result<Option<(ObjectType, Vec<u8>)>, Error> {
+        let start = Instant::now();
+        let object = match self.inner.read(id)? {
+            Some((kind, data)) => Some((kind, self.layer.decode(id, kind, data)?)),
+            None => None,
+        };
+        let size = object.as_ref().map(|(_, data)| data.len());
+        self.layer
+            .record(OdbOperation::Read, id, size, start.elapsed());
+        Ok(object)
+    }
+
+    fn write(&self, id: Oid, kind: ObjectType, data: &[u8]) -> Result<(), Error> {
+        let start = Instant::now();
+        let encoded = self.layer.encode(id, kind, data)?;
+        self.inner.write(id, kind, &encoded)?;
+        self.layer
+            .record(OdbOperation::Write, id, Some(data.len()), start.elapsed());
+        Ok(())
+    }
+
+    fn read_header(&self, id: Oid) -> Result<Option<(usize, ObjectType)>, Error> {
+        let start = Instant::now();
+        let header = if self.layer.preserves_size() {
+            self.inner.read_header(id)?
+        } else {
+            match self.inner.read(id)? {
+                Some((kind, data)) => Some((self.layer.decode(id, kind, data)?.len(), kind)),
+                None => None,
+            }
+        };
+        let size = header.map(|(size, _)| size);
+        self.layer
+            .record(OdbOperation::ReadHeader, id, size, start.elapsed());
+        Ok(header)
+    }
+
+    fn exists(&self, id: Oid) -> Result<bool, Error> {
+        let start = Instant::now();
+        let exists = self.inner.exists(id)?;
+        let size = if exists { Some(0) } else { None };
+        self.layer
+            .record(OdbOperation::Exists, id, size, start.elapsed());
+        Ok(exists)
+    }
+
+    fn foreach(&self, callback: &mut dyn FnMut(Oid) -> bool) -> Result<(), Error> {
+        self.inner.foreach(callback)
+    }
+
+    fn exists_prefix(&self, short_id: Oid, len: usize) -> Result<Option<Oid>, Error> {
+        self.inner.exists_prefix(short_id, len)
+    }
+
+    fn refresh(&self) -> Result<(), Error> {
+        self.inner.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DiskBackend, LayeredBackend, OdbBackend, OdbLayer, OdbOperation};
+    use crate::{Error, ErrorCode, ObjectType, Odb, Oid};
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[derive(Default, Clone)]
+    struct MemoryBackend {
+        objects: Arc<Mutex<BTreeMap<Oid, (ObjectType, Vec<u8>)>>>,
+    }
+
+    impl OdbBackend for MemoryBackend {
+        fn read(&self, id: Oid) -> Result<Option<(ObjectType, Vec<u8>)>, Error> {
+            Ok(self.objects.lock().unwrap().get(&id).cloned())
+        }
+
+        fn write(&self, id: Oid, kind: ObjectType, data: &[u8]) -> Result<(), Error> {
+            if data == b"panic" {
+                panic!("refusing to store object");
+            }
+            let mut objects = self.objects.lock().unwrap();
+            objects.insert(id, (kind, data.to_vec()));
+            Ok(())
+        }
+
+        fn foreach(&self, callback: &mut dyn FnMut(Oid) -> bool) -> Result<(), Error> {
+            for id in self.objects.lock().unwrap().keys() {
+                if !callback(*id) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn smoke() {
+        let backend = MemoryBackend::default();
+        let odb = t!(Odb::new());
+        t!(odb.add_custom_backend(Box::new(backend.clone()), 1));
+
+        let id = t!(odb.write(ObjectType::Blob, b"hello"));
+        assert_eq!(backend.objects.lock().unwrap().len(), 1);
+        assert!(odb.exists(id));
+        let obj = t!(odb.read(id));
+        assert_eq!(obj.data(), b"hello");
+        assert_eq!(obj.kind(), ObjectType::Blob);
+        assert_eq!(t!(odb.read_header(id)), (5, ObjectType::Blob));
+
+        let short = t!(Oid::from_str(&id.to_string()[..8]));
+        assert_eq!(t!(odb.exists_prefix(short, 8)), id);
+
+        let missing = Oid::hash_object(ObjectType::Blob, b"missing").unwrap();
+        assert!(!odb.exists(missing));
+        assert_eq!(odb.read(missing).err().unwrap().code(), ErrorCode::NotFound);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = odb.write(ObjectType::Blob, b"panic");
+        }));
+        assert!(panicked.is_err());
+    }
+
+    #[derive(Default, Clone)]
+    struct XorLayer {
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl OdbLayer for XorLayer {
+        fn encode<'a>(
+            &self,
+            _id: Oid,
+            _kind: ObjectType,
+            data: &'a [u8],
+        ) -> Result<Cow<'a, [u8]>, Error> {
+            Ok(data.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn decode(&self, _id: Oid, _kind: ObjectType, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+            Ok(data.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn preserves_size(&self) -> bool {
+            true
+        }
+
+        fn record(&self, op: OdbOperation, _id: Oid, _size: Option<usize>, _: Duration) {
+            if op == OdbOperation::Write {
+                self.writes.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn layered_disk_backend() {
+        let td = t!(tempfile::TempDir::new());
+        let layer = XorLayer::default();
+        let loose = t!(DiskBackend::loose(td.path()));
+        let odb = t!(Odb::new());
+        t!(odb.add_custom_backend(
+            Box::new(LayeredBackend::new(Box::new(loose), layer.clone())),
+            1
+        ));
+
+        let id = t!(odb.write(ObjectType::Blob, b"secret"));
+        assert_eq!(layer.writes.load(Ordering::SeqCst), 1);
+        assert_eq!(t!(odb.read(id)).data(), b"secret");
+        assert_eq!(t!(odb.read_header(id)), (6, ObjectType::Blob));
+
+        // The object is stored encoded on disk.
+        let raw = t!(DiskBackend::loose(td.path()));
+        let (kind, data) = t!(raw.read(id)).unwrap();
+        assert_eq!(kind, ObjectType::Blob);
+        assert_ne!(data, b"secret");
+        assert!(t!(raw.exists(id)));
+        let mut ids = Vec::new();
+        t!(raw.foreach(&mut |id| {
+            ids.push(id);
+            true
+        }));
+        assert_eq!(ids, [id]);
+
+        let missing = Oid::hash_object(ObjectType::Blob, b"missing").unwrap();
+        assert!(t!(raw.read(missing)).is_none());
+        assert!(t!(DiskBackend::pack(td.path()))
+            .write(id, kind, &data)
+            .is_err());
+    }
+}
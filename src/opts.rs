@@ -1,7 +1,11 @@
 //! Bindings to libgit2's git_libgit2_opts function.
 
+use std::alloc::{self, Layout};
 use std::ffi::CString;
 use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::{c_char, c_int, c_void, size_t};
 
 use crate::string_array::StringArray;
 use crate::util::Binding;
@@ -124,6 +128,23 @@ pub fn strict_hash_verification(enabled: bool) {
     debug_assert!(error >= 0);
 }
 
+/// Controls whether or not libgit2 will flush the files it writes in the git
+/// directory to disk, such as objects, packfiles and their indexes. Disabled
+/// by default, enabling this makes writes durable across a system crash at a
+/// significant cost in performance.
+pub fn enable_fsync_gitdir(enabled: bool) {
+    crate::init();
+    let error = unsafe {
+        raw::git_libgit2_opts(
+            raw::GIT_OPT_ENABLE_FSYNC_GITDIR as libc::c_int,
+            enabled as libc::c_int,
+        )
+    };
+    // This function cannot actually fail, but the function has an error return
+    // for other options that can.
+    debug_assert!(error >= 0);
+}
+
 /// Returns the list of git extensions that are supported. This is the list of
 /// built-in extensions supported by libgit2 and custom extensions that have
 /// been added with [`set_extensions`]. Extensions that have been negated will
@@ -414,6 +435,216 @@ pub unsafe fn set_server_timeout_in_milliseconds(timeout: libc::c_int) -> Result
     Ok(())
 }
 
+/// Set the comment part of the user agent sent over HTTP, which defaults to
+/// `libgit2 <version>`.
+///
+/// # Safety
+/// This function is modifying a C global without synchronization, so it is not
+/// thread safe, and should only be called before any thread is spawned.
+pub unsafe fn set_user_agent(user_agent: &str) -> Result<(), Error> {
+    crate::init();
+    let user_agent = CString::new(user_agent)?;
+    try_call!(raw::git_libgit2_opts(
+        raw::GIT_OPT_SET_USER_AGENT as libc::c_int,
+        user_agent.as_ptr()
+    ));
+    Ok(())
+}
+
+/// Get the comment part of the user agent sent over HTTP.
+///
+/// # Safety
+/// This function is reading a C global without synchronization, so it is not
+/// thread safe, and should only be called before any thread is spawned.
+pub unsafe fn get_user_agent() -> Result<String, Error> {
+    crate::init();
+    let buf = Buf::new();
+    try_call!(raw::git_libgit2_opts(
+        raw::GIT_OPT_GET_USER_AGENT as libc::c_int,
+        buf.raw() as *const _
+    ));
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Set the product part of the user agent sent over HTTP, which defaults to
+/// `git/2.0` so that servers treat libgit2 like git. An empty product
+/// disables the `User-Agent` header.
+///
+/// # Safety
+/// This function is modifying a C global without synchronization, so it is not
+/// thread safe, and should only be called before any thread is spawned.
+pub unsafe fn set_user_agent_product(product: &str) -> Result<(), Error> {
+    crate::init();
+    let product = CString::new(product)?;
+    try_call!(raw::git_libgit2_opts(
+        raw::GIT_OPT_SET_USER_AGENT_PRODUCT as libc::c_int,
+        product.as_ptr()
+    ));
+    Ok(())
+}
+
+/// Get the product part of the user agent sent over HTTP.
+///
+/// # Safety
+/// This function is reading a C global without synchronization, so it is not
+/// thread safe, and should only be called before any thread is spawned.
+pub unsafe fn get_user_agent_product() -> Result<String, Error> {
+    crate::init();
+    let buf = Buf::new();
+    try_call!(raw::git_libgit2_opts(
+        raw::GIT_OPT_GET_USER_AGENT_PRODUCT as libc::c_int,
+        buf.raw() as *const _
+    ));
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Get the complete user agent sent over HTTP, such as
+/// `git/2.0 (libgit2 1.9.0)`, or `None` if no `User-Agent` header is sent.
+///
+/// Like libgit2, the parentheses are left out when the comment set with
+/// `set_user_agent` is empty.
+///
+/// # Safety
+/// This function is reading a C global without synchronization, so it is not
+/// thread safe, and should only be called before any thread is spawned.
+pub unsafe fn get_user_agent_header() -> Result<Option<String>, Error> {
+    let product = get_user_agent_product()?;
+    if product.is_empty() {
+        return Ok(None);
+    }
+    let comment = get_user_agent()?;
+    if comment.is_empty() {
+        return Ok(Some(product));
+    }
+    Ok(Some(format!("{} ({})", product, comment)))
+}
+
+/// Route the memory allocations of libgit2 through the global allocator of
+/// Rust, so that they show up in the statistics of allocators such as
+/// jemalloc or mimalloc and can be capped with [`set_allocation_limit`].
+///
+/// # Safety
+///
+/// This must be called before any other function of this crate or of
+/// libgit2, as memory allocated with the previous allocator would otherwise
+/// be freed with this one.
+pub unsafe fn use_rust_allocator() -> Result<(), Error> {
+    static ALLOCATOR: raw::git_allocator = raw::git_allocator {
+        gmalloc: Some(rust_malloc),
+        grealloc: Some(rust_realloc),
+        gfree: Some(rust_free),
+    };
+
+    try_call!(raw::git_libgit2_opts(
+        raw::GIT_OPT_SET_ALLOCATOR as libc::c_int,
+        &ALLOCATOR as *const raw::git_allocator
+    ));
+    Ok(())
+}
+
+/// Limit the memory which libgit2 can have allocated at once to `limit`
+/// bytes, or lift the limit with `None`.
+///
+/// Allocations over the limit fail, which libgit2 reports as out of memory
+/// errors. This only applies once [`use_rust_allocator`] has been called.
+pub fn set_allocation_limit(limit: Option<usize>) {
+    ALLOCATION_LIMIT.store(limit.unwrap_or(usize::MAX), Ordering::SeqCst);
+}
+
+/// Return the number of bytes currently allocated by libgit2 through the
+/// allocator installed by [`use_rust_allocator`].
+pub fn allocated_bytes() -> usize {
+    ALLOCATED.load(Ordering::SeqCst)
+}
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATION_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// The size of each allocation is kept in front of it, as `free` doesn't
+/// get it but Rust's allocator needs it. This also keeps the alignment of
+/// `malloc`.
+const HEADER: usize = 16;
+
+fn layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(HEADER)?, HEADER).ok()
+}
+
+/// Account for an allocation growing by `grow` bytes, failing if it would
+/// exceed the limit.
+fn reserve(grow: usize) -> bool {
+    let limit = ALLOCATION_LIMIT.load(Ordering::SeqCst);
+    ALLOCATED
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |allocated| {
+            allocated.checked_add(grow).filter(|total| *total <= limit)
+        })
+        .is_ok()
+}
+
+extern "C" fn rust_malloc(n: size_t, _file: *const c_char, _line: c_int) -> *mut c_void {
+    let layout = match layout(n) {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    if !reserve(n) {
+        return ptr::null_mut();
+    }
+    unsafe {
+        let base = alloc::alloc(layout);
+        if base.is_null() {
+            ALLOCATED.fetch_sub(n, Ordering::SeqCst);
+            return ptr::null_mut();
+        }
+        (base as *mut usize).write(n);
+        base.add(HEADER) as *mut c_void
+    }
+}
+
+extern "C" fn rust_realloc(
+    ptr: *mut c_void,
+    size: size_t,
+    file: *const c_char,
+    line: c_int,
+) -> *mut c_void {
+    if ptr.is_null() {
+        return rust_malloc(size, file, line);
+    }
+    unsafe {
+        let base = (ptr as *mut u8).sub(HEADER);
+        let old = (base as *mut usize).read();
+        let new_layout = match layout(size) {
+            Some(layout) => layout,
+            None => return ptr::null_mut(),
+        };
+        if size > old && !reserve(size - old) {
+            return ptr::null_mut();
+        }
+        let new_base = alloc::realloc(base, layout(old).unwrap(), new_layout.size());
+        if new_base.is_null() {
+            if size > old {
+                ALLOCATED.fetch_sub(size - old, Ordering::SeqCst);
+            }
+            return ptr::null_mut();
+        }
+        if size < old {
+            ALLOCATED.fetch_sub(old - size, Ordering::SeqCst);
+        }
+        (new_base as *mut usize).write(size);
+        new_base.add(HEADER) as *mut c_void
+    }
+}
+
+extern "C" fn rust_free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let base = (ptr as *mut u8).sub(HEADER);
+        let size = (base as *mut usize).read();
+        ALLOCATED.fetch_sub(size, Ordering::SeqCst);
+        alloc::dealloc(base, layout(size).unwrap());
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -425,9 +656,14 @@ mod test {
 
     #[test]
     fn mwindow_size() {
+        // The setting is global and a tiny window breaks the indexing of
+        // packs cloned by other tests running at the same time, so use a
+        // window which is still big enough and restore the previous one.
         unsafe {
-            assert!(set_mwindow_size(1024).is_ok());
-            assert!(get_mwindow_size().unwrap() == 1024);
+            let previous = get_mwindow_size().unwrap();
+            assert!(set_mwindow_size(1024 * 1024).is_ok());
+            assert!(get_mwindow_size().unwrap() == 1024 * 1024);
+            assert!(set_mwindow_size(previous).is_ok());
         }
     }
 
@@ -462,4 +698,56 @@ mod test {
             assert!(get_server_timeout_in_milliseconds().unwrap() == 10_000);
         }
     }
+
+    #[test]
+    fn rust_allocator() {
+        // libgit2 is already initialized with its own allocator here, so
+        // only exercise the callbacks.
+        let file = ptr::null();
+        let before = allocated_bytes();
+        let p = rust_malloc(10, file, 0);
+        assert!(!p.is_null());
+        unsafe { ptr::write_bytes(p as *mut u8, 1, 10) };
+        let p = rust_realloc(p, 100, file, 0);
+        assert!(!p.is_null());
+        assert_eq!(unsafe { *(p as *mut u8).add(9) }, 1);
+        assert!(allocated_bytes() >= before + 100);
+
+        set_allocation_limit(Some(allocated_bytes()));
+        assert!(rust_malloc(1, file, 0).is_null());
+        assert!(rust_realloc(p, 101, file, 0).is_null());
+        set_allocation_limit(None);
+
+        rust_free(p);
+        rust_free(ptr::null_mut());
+    }
+
+    #[test]
+    fn user_agent() {
+        unsafe {
+            let agent = get_user_agent().unwrap();
+            let product = get_user_agent_product().unwrap();
+
+            assert!(set_user_agent("custom agent").is_ok());
+            assert_eq!(get_user_agent().unwrap(), "custom agent");
+            assert!(set_user_agent_product("product/1.0").is_ok());
+            assert_eq!(get_user_agent_product().unwrap(), "product/1.0");
+            assert_eq!(
+                get_user_agent_header().unwrap().as_deref(),
+                Some("product/1.0 (custom agent)")
+            );
+            assert!(set_user_agent("").is_ok());
+            assert_eq!(
+                get_user_agent_header().unwrap().as_deref(),
+                Some("product/1.0")
+            );
+            assert!(set_user_agent_product("").is_ok());
+            assert_eq!(get_user_agent_header().unwrap(), None);
+
+            assert!(set_user_agent(&agent).is_ok());
+            assert!(set_user_agent_product(&product).is_ok());
+            assert_eq!(get_user_agent().unwrap(), agent);
+            assert_eq!(get_user_agent_product().unwrap(), product);
+        }
+    }
 }
@@ -1,14 +1,18 @@
 use libc::{c_int, c_uint, c_void, size_t};
+use std::io::Write;
 use std::marker;
+use std::mem;
 use std::path::Path;
 use std::ptr;
 use std::slice;
 use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use crate::odb::{write_pack_progress_cb, OdbPackwriterCb};
 use crate::util::Binding;
 use crate::IntoCString;
-use crate::{panic, raw, Buf, Error, Oid, Repository, Revwalk};
+use crate::{panic, raw, Buf, Error, ErrorCode, Oid, Repository, Revwalk};
 
 #[derive(PartialEq, Eq, Clone, Debug, Copy)]
 /// Stages that are reported by the `PackBuilder` progress callback.
@@ -23,68 +27,89 @@ pub type ProgressCb<'a> = dyn FnMut(PackBuilderStage, u32, u32) -> bool + 'a;
 pub type ForEachCb<'a> = dyn FnMut(&[u8]) -> bool + 'a;
 
 /// A builder for creating a packfile
+///
+/// Deltas are searched for among the objects inserted into the pack; libgit2
+/// has no option to copy the deltas already stored in the packfiles of the
+/// repository, like `git pack-objects --reuse-delta` does.
 pub struct PackBuilder<'repo> {
     raw: *mut raw::git_packbuilder,
-    _progress: Option<Box<Box<ProgressCb<'repo>>>>,
+    _progress: Option<Box<ProgressData<'repo>>>,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
+struct ProgressData<'a> {
+    cb: Option<Box<ProgressCb<'a>>>,
+    cancel: Option<Arc<AtomicBool>>,
+    cancelled: bool,
+}
+
 impl<'repo> PackBuilder<'repo> {
     /// Insert a single object. For an optimal pack it's mandatory to insert
     /// objects in recency order, commits followed by trees and blobs.
     pub fn insert_object(&mut self, id: Oid, name: Option<&str>) -> Result<(), Error> {
         let name = crate::opt_cstr(name)?;
-        unsafe {
-            try_call!(raw::git_packbuilder_insert(self.raw, id.raw(), name));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_insert(raw, id.raw(), name));
+            Ok(())
+        })
     }
 
     /// Insert a root tree object. This will add the tree as well as all
     /// referenced trees and blobs.
     pub fn insert_tree(&mut self, id: Oid) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_packbuilder_insert_tree(self.raw, id.raw()));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_insert_tree(raw, id.raw()));
+            Ok(())
+        })
     }
 
     /// Insert a commit object. This will add a commit as well as the completed
     /// referenced tree.
     pub fn insert_commit(&mut self, id: Oid) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_packbuilder_insert_commit(self.raw, id.raw()));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_insert_commit(raw, id.raw()));
+            Ok(())
+        })
     }
 
     /// Insert objects as given by the walk. Those commits and all objects they
     /// reference will be inserted into the packbuilder.
+    ///
+    /// Commits hidden from the walk are used as the boundary of the pack: the
+    /// trees and blobs they reference are left out, so pushing `B` and hiding
+    /// `A` packs just the objects needed to go from `A` to `B`, as for the
+    /// `A..B` range.
     pub fn insert_walk(&mut self, walk: &mut Revwalk<'_>) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_packbuilder_insert_walk(self.raw, walk.raw()));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_insert_walk(raw, walk.raw()));
+            Ok(())
+        })
     }
 
     /// Recursively insert an object and its referenced objects. Insert the
     /// object as well as any object it references.
     pub fn insert_recursive(&mut self, id: Oid, name: Option<&str>) -> Result<(), Error> {
         let name = crate::opt_cstr(name)?;
-        unsafe {
-            try_call!(raw::git_packbuilder_insert_recur(self.raw, id.raw(), name));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_insert_recur(raw, id.raw(), name));
+            Ok(())
+        })
     }
 
     /// Write the contents of the packfile to an in-memory buffer. The contents
     /// of the buffer will become a valid packfile, even though there will be
     /// no attached index.
     pub fn write_buf(&mut self, buf: &mut Buf) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_packbuilder_write_buf(buf.raw(), self.raw));
-        }
-        Ok(())
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_write_buf(buf.raw(), raw));
+            Ok(())
+        })
     }
 
     /// Write the new pack and corresponding index file to path.
@@ -95,16 +120,17 @@ impl<'repo> PackBuilder<'repo> {
         let progress_payload = Box::new(OdbPackwriterCb { cb: None });
         let progress_payload_ptr = Box::into_raw(progress_payload);
 
-        unsafe {
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
             try_call!(raw::git_packbuilder_write(
-                self.raw,
+                raw,
                 path,
                 mode,
                 progress_cb,
                 progress_payload_ptr as *mut _
             ));
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     /// Create the new pack and pass each object to the callback.
@@ -115,20 +141,48 @@ impl<'repo> PackBuilder<'repo> {
         let mut cb = &mut cb as &mut ForEachCb<'_>;
         let ptr = &mut cb as *mut _;
         let foreach: raw::git_packbuilder_foreach_cb = Some(foreach_c);
-        unsafe {
-            try_call!(raw::git_packbuilder_foreach(
-                self.raw,
-                foreach,
-                ptr as *mut _
-            ));
+        let raw = self.raw;
+        self.cancellable(|| unsafe {
+            try_call!(raw::git_packbuilder_foreach(raw, foreach, ptr as *mut _));
+            Ok(())
+        })
+    }
+
+    /// Create the new pack and write it to `w`.
+    ///
+    /// This writes the pack as it is being generated, so it can be sent
+    /// straight to a socket or a file. Returns the number of bytes written.
+    pub fn write_to<W: Write>(&mut self, w: &mut W) -> Result<usize, Error> {
+        let mut written = 0;
+        let mut error = None;
+        let res = self.foreach(|bytes| match w.write_all(bytes) {
+            Ok(()) => {
+                written += bytes.len();
+                true
+            }
+            Err(e) => {
+                error = Some(e);
+                false
+            }
+        });
+        if let Some(e) = error {
+            return Err(Error::from(e));
         }
-        Ok(())
+        res?;
+        Ok(written)
     }
 
     /// `progress` will be called with progress information during pack
     /// building. Be aware that this is called inline with pack building
     /// operations, so performance may be affected.
     ///
+    /// While objects are inserted the stage is
+    /// `PackBuilderStage::AddingObjects`, along with the number of objects
+    /// inserted so far and a total of `0`. While the pack is written, the
+    /// stage is `PackBuilderStage::Deltafication` along with the number of
+    /// objects searched for deltas and the total number of objects.
+    /// Returning `false` cancels the operation.
+    ///
     /// There can only be one progress callback attached, this will replace any
     /// existing one. See `unset_progress_callback` to remove the current
     /// progress callback without attaching a new one.
@@ -136,7 +190,50 @@ impl<'repo> PackBuilder<'repo> {
     where
         F: FnMut(PackBuilderStage, u32, u32) -> bool + 'repo,
     {
-        let mut progress = Box::new(Box::new(progress) as Box<ProgressCb<'_>>);
+        let cancel = self._progress.as_mut().and_then(|p| p.cancel.take());
+        self.set_progress(Some(Box::new(progress)), cancel)
+    }
+
+    /// Remove the current progress callback.  See `set_progress_callback` to
+    /// set the progress callback.
+    pub fn unset_progress_callback(&mut self) -> Result<(), Error> {
+        let cancel = self._progress.as_mut().and_then(|p| p.cancel.take());
+        self.set_progress(None, cancel)
+    }
+
+    /// Cancel pack building once `cancel` is set to `true`.
+    ///
+    /// The flag can be set from another thread. It is checked whenever
+    /// progress is reported, both while objects are inserted and while
+    /// deltas are searched, so the operation in progress stops within about
+    /// half a second and fails with an error with the code
+    /// `ErrorCode::User`.
+    pub fn set_cancel_flag(&mut self, cancel: Arc<AtomicBool>) -> Result<(), Error> {
+        let cb = self._progress.as_mut().and_then(|p| p.cb.take());
+        self.set_progress(cb, Some(cancel))
+    }
+
+    fn set_progress(
+        &mut self,
+        cb: Option<Box<ProgressCb<'repo>>>,
+        cancel: Option<Arc<AtomicBool>>,
+    ) -> Result<(), Error> {
+        if cb.is_none() && cancel.is_none() {
+            unsafe {
+                try_call!(raw::git_packbuilder_set_callbacks(
+                    self.raw,
+                    None,
+                    ptr::null_mut()
+                ));
+            }
+            self._progress = None;
+            return Ok(());
+        }
+        let mut progress = Box::new(ProgressData {
+            cb,
+            cancel,
+            cancelled: false,
+        });
         let ptr = &mut *progress as *mut _;
         let progress_c: raw::git_packbuilder_progress = Some(progress_c);
         unsafe {
@@ -150,23 +247,34 @@ impl<'repo> PackBuilder<'repo> {
         Ok(())
     }
 
-    /// Remove the current progress callback.  See `set_progress_callback` to
-    /// set the progress callback.
-    pub fn unset_progress_callback(&mut self) -> Result<(), Error> {
-        unsafe {
-            try_call!(raw::git_packbuilder_set_callbacks(
-                self.raw,
-                None,
-                ptr::null_mut()
-            ));
-            self._progress = None;
+    /// Run `f`, reporting its failure as a cancellation if the cancel flag
+    /// stopped it. libgit2 doesn't always pass on the error of the progress
+    /// callback, so the callback records that it cancelled the operation.
+    fn cancellable<T>(&mut self, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        let res = f();
+        let cancelled = self
+            ._progress
+            .as_mut()
+            .is_some_and(|p| mem::take(&mut p.cancelled));
+        match res {
+            Err(_) if cancelled => {
+                let mut err = Error::from_str("pack building was cancelled");
+                err.set_code(ErrorCode::User);
+                Err(err)
+            }
+            res => res,
         }
-        Ok(())
     }
 
     /// Set the number of threads to be used.
     ///
-    /// Returns the number of threads to be used.
+    /// Threads are used to search for deltas. By default a single thread is
+    /// used; passing `0` uses as many threads as there are CPUs, detected
+    /// when the deltas are searched for.
+    ///
+    /// Returns the value that was set, so `0` is echoed back rather than the
+    /// detected number of CPUs. It is always `1` if libgit2 was built without
+    /// thread support.
     pub fn set_threads(&mut self, threads: u32) -> u32 {
         unsafe { raw::git_packbuilder_set_threads(self.raw, threads) }
     }
@@ -279,9 +387,21 @@ extern "C" fn progress_c(
     unsafe {
         let stage = Binding::from_raw(stage);
 
+        let progress = &mut *(data as *mut ProgressData<'_>);
+        if progress
+            .cancel
+            .as_ref()
+            .is_some_and(|c| c.load(Ordering::SeqCst))
+        {
+            progress.cancelled = true;
+            return raw::GIT_EUSER;
+        }
         let r = panic::wrap(|| {
-            let data = data as *mut Box<ProgressCb<'_>>;
-            (*data)(stage, current, total)
+            let data = &mut *(data as *mut ProgressData<'_>);
+            match data.cb {
+                Some(ref mut cb) => cb(stage, current, total),
+                None => true,
+            }
         });
         if r == Some(true) {
             0
@@ -293,7 +413,9 @@ extern "C" fn progress_c(
 
 #[cfg(test)]
 mod tests {
-    use crate::{Buf, Oid};
+    use crate::{Buf, ErrorCode, Oid};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
 
     // hash of a packfile constructed without any objects in it
     const EMPTY_PACKFILE_OID: &str = "029d08823bd8a8eab510ad6ac75c823cfd3ed31e";
@@ -405,6 +527,33 @@ mod tests {
         assert_eq!(&buf[0..12], &*pack_header(3));
     }
 
+    #[test]
+    fn insert_walk_write_to() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = t!(repo.head()).target().unwrap();
+        let (commit, _tree) = crate::test::commit(&repo);
+        let mut walk = t!(repo.revwalk());
+        t!(walk.push_range(&format!("{}..{}", base, commit)));
+        let mut builder = t!(repo.packbuilder());
+        t!(builder.insert_walk(&mut walk));
+        // the new commit, its tree and the blob, but nothing from the base
+        assert_eq!(builder.object_count(), 3);
+        let mut buf = Vec::new();
+        let written = t!(builder.write_to(&mut buf));
+        assert_eq!(written, buf.len());
+        assert_eq!(&buf[0..12], &*pack_header(3));
+    }
+
+    #[test]
+    fn threads() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut builder = t!(repo.packbuilder());
+        let auto = builder.set_threads(0);
+        // 0 asks for auto-detection, or is forced to 1 without threading.
+        assert!(auto == 0 || auto == 1);
+        assert_eq!(builder.set_threads(1), 1);
+    }
+
     #[test]
     fn insert_write() {
         let (_td, repo) = crate::test::repo_init();
@@ -492,6 +641,24 @@ mod tests {
         assert_eq!(progress_called, true);
     }
 
+    #[test]
+    fn cancel_flag() {
+        let (_td, repo) = crate::test::repo_init();
+        let mut builder = t!(repo.packbuilder());
+        let (commit, _tree) = crate::test::commit(&repo);
+        let cancel = Arc::new(AtomicBool::new(true));
+        t!(builder.set_cancel_flag(cancel.clone()));
+        t!(builder.set_progress_callback(|_, _, _| true));
+        let err = builder.insert_commit(commit).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::User);
+
+        let mut builder = t!(repo.packbuilder());
+        cancel.store(false, Ordering::SeqCst);
+        t!(builder.set_cancel_flag(cancel));
+        t!(builder.insert_commit(commit));
+        t!(builder.write_buf(&mut Buf::new()));
+    }
+
     #[test]
     fn set_threads() {
         let (_td, repo) = crate::test::repo_init();
@@ -5,7 +5,9 @@ use std::ptr;
 
 use crate::diff::{print_cb, LineCb};
 use crate::util::{into_opt_c_string, Binding};
-use crate::{raw, Blob, Buf, Diff, DiffDelta, DiffHunk, DiffLine, DiffOptions, Error};
+use crate::{
+    raw, Blob, Buf, Diff, DiffDelta, DiffHunk, DiffLine, DiffLineType, DiffOptions, Error,
+};
 
 /// A structure representing the text changes in a single diff delta.
 ///
@@ -17,6 +19,35 @@ pub struct Patch<'buffers> {
 
 unsafe impl<'buffers> Send for Patch<'buffers> {}
 
+/// The kind of change shown by a row of a side-by-side diff.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SideBySideKind {
+    /// The line is unchanged and appears on both sides.
+    Context,
+    /// The line was removed and only appears on the old side.
+    Deleted,
+    /// The line was added and only appears on the new side.
+    Added,
+    /// A removed line is paired with the line that replaced it.
+    Modified,
+}
+
+/// One side of a row of a side-by-side diff.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SideBySideLine<'a> {
+    lineno: u32,
+    content: &'a [u8],
+}
+
+/// A row of a side-by-side diff, as produced by `Patch::side_by_side`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SideBySideRow<'a> {
+    hunk: usize,
+    kind: SideBySideKind,
+    old: Option<SideBySideLine<'a>>,
+    new: Option<SideBySideLine<'a>>,
+}
+
 impl<'buffers> Binding for Patch<'buffers> {
     type Raw = *mut raw::git_patch;
     unsafe fn from_raw(raw: Self::Raw) -> Self {
@@ -212,6 +243,49 @@ impl<'buffers> Patch<'buffers> {
         }
     }
 
+    /// Arrange the lines of the Patch into rows for a side-by-side view.
+    ///
+    /// Context lines appear on both sides of a row. Within each run of
+    /// changes, removed lines are paired with added lines in order and
+    /// reported as `Modified`; any left over are reported on their own as
+    /// `Deleted` or `Added`, with the other side empty.
+    pub fn side_by_side(&self) -> Result<Vec<SideBySideRow<'_>>, Error> {
+        let mut rows = Vec::new();
+        for hunk in 0..self.num_hunks() {
+            let mut deleted = Vec::new();
+            let mut added = Vec::new();
+            for i in 0..self.num_lines_in_hunk(hunk)? {
+                let line = self.line_in_hunk(hunk, i)?;
+                match line.origin_value() {
+                    DiffLineType::Deletion => deleted.push(SideBySideLine {
+                        lineno: line.old_lineno().unwrap_or(0),
+                        content: line.content(),
+                    }),
+                    DiffLineType::Addition => added.push(SideBySideLine {
+                        lineno: line.new_lineno().unwrap_or(0),
+                        content: line.content(),
+                    }),
+                    DiffLineType::Context => {
+                        flush_changes(&mut rows, hunk, &mut deleted, &mut added);
+                        let side = |lineno: Option<u32>| SideBySideLine {
+                            lineno: lineno.unwrap_or(0),
+                            content: line.content(),
+                        };
+                        rows.push(SideBySideRow {
+                            hunk,
+                            kind: SideBySideKind::Context,
+                            old: Some(side(line.old_lineno())),
+                            new: Some(side(line.new_lineno())),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            flush_changes(&mut rows, hunk, &mut deleted, &mut added);
+        }
+        Ok(rows)
+    }
+
     /// Get the Patch text as a Buf.
     pub fn to_buf(&mut self) -> Result<Buf, Error> {
         let buf = Buf::new();
@@ -222,6 +296,66 @@ impl<'buffers> Patch<'buffers> {
     }
 }
 
+fn flush_changes<'a>(
+    rows: &mut Vec<SideBySideRow<'a>>,
+    hunk: usize,
+    deleted: &mut Vec<SideBySideLine<'a>>,
+    added: &mut Vec<SideBySideLine<'a>>,
+) {
+    let len = deleted.len().max(added.len());
+    let mut deleted = deleted.drain(..);
+    let mut added = added.drain(..);
+    for _ in 0..len {
+        let old = deleted.next();
+        let new = added.next();
+        let kind = match (old, new) {
+            (Some(_), Some(_)) => SideBySideKind::Modified,
+            (Some(_), None) => SideBySideKind::Deleted,
+            _ => SideBySideKind::Added,
+        };
+        rows.push(SideBySideRow {
+            hunk,
+            kind,
+            old,
+            new,
+        });
+    }
+}
+
+impl<'a> SideBySideLine<'a> {
+    /// The line number of this line in its file, starting from 1.
+    pub fn lineno(&self) -> u32 {
+        self.lineno
+    }
+
+    /// The content of the line, including its newline if it has one.
+    pub fn content(&self) -> &'a [u8] {
+        self.content
+    }
+}
+
+impl<'a> SideBySideRow<'a> {
+    /// The index of the hunk this row belongs to.
+    pub fn hunk(&self) -> usize {
+        self.hunk
+    }
+
+    /// The kind of change shown by this row.
+    pub fn kind(&self) -> SideBySideKind {
+        self.kind
+    }
+
+    /// The line on the old side of this row, if any.
+    pub fn old_line(&self) -> Option<SideBySideLine<'a>> {
+        self.old
+    }
+
+    /// The line on the new side of this row, if any.
+    pub fn new_line(&self) -> Option<SideBySideLine<'a>> {
+        self.new
+    }
+}
+
 impl<'buffers> std::fmt::Debug for Patch<'buffers> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         let mut ds = f.debug_struct("Patch");
@@ -233,3 +367,55 @@ impl<'buffers> std::fmt::Debug for Patch<'buffers> {
         ds.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Patch, SideBySideKind};
+
+    #[test]
+    fn side_by_side() {
+        let old = b"a\nb\nc\nd\ne\n";
+        let new = b"a\nB\nc\ne\nf\ng\n";
+        let patch = t!(Patch::from_buffers(old, None, new, None, None));
+        let rows = t!(patch.side_by_side());
+        let summary = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.kind(),
+                    row.old_line().map(|l| (l.lineno(), l.content())),
+                    row.new_line().map(|l| (l.lineno(), l.content())),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (
+                    SideBySideKind::Context,
+                    Some((1, &b"a\n"[..])),
+                    Some((1, &b"a\n"[..]))
+                ),
+                (
+                    SideBySideKind::Modified,
+                    Some((2, &b"b\n"[..])),
+                    Some((2, &b"B\n"[..]))
+                ),
+                (
+                    SideBySideKind::Context,
+                    Some((3, &b"c\n"[..])),
+                    Some((3, &b"c\n"[..]))
+                ),
+                (SideBySideKind::Deleted, Some((4, &b"d\n"[..])), None),
+                (
+                    SideBySideKind::Context,
+                    Some((5, &b"e\n"[..])),
+                    Some((4, &b"e\n"[..]))
+                ),
+                (SideBySideKind::Added, None, Some((5, &b"f\n"[..]))),
+                (SideBySideKind::Added, None, Some((6, &b"g\n"[..]))),
+            ]
+        );
+        assert!(rows.iter().all(|row| row.hunk() == 0));
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::Arc;
+
+use crate::util;
+use crate::{DiffFileStats, DiffLineType, DiffOptions, Error, IntoCString, Oid, Patch};
+use crate::{Repository, Revwalk};
+
+type LineMatcher = dyn Fn(&[u8]) -> bool + Send + Sync;
+
+#[derive(Clone)]
+enum Needle {
+    String(Vec<u8>),
+    Lines(Arc<LineMatcher>),
+}
+
+/// Options for `Repository::pickaxe`, which searches history for commits
+/// whose changes add or remove some text.
+pub struct PickaxeOptions {
+    needle: Option<Needle>,
+    pathspec: Vec<CString>,
+    threads: usize,
+}
+
+/// A commit found by `Repository::pickaxe`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PickaxeMatch {
+    id: Oid,
+    deltas: Vec<DiffFileStats>,
+}
+
+impl PickaxeOptions {
+    /// Creates a new set of pickaxe options.
+    ///
+    /// Either `string` or `line_matcher` must be set before searching.
+    pub fn new() -> PickaxeOptions {
+        PickaxeOptions {
+            needle: None,
+            pathspec: Vec::new(),
+            threads: 1,
+        }
+    }
+
+    /// Look for commits that change the number of occurrences of `string`
+    /// in a file, like `git log -S`.
+    ///
+    /// This replaces any matcher set with `line_matcher`.
+    pub fn string<T: Into<Vec<u8>>>(&mut self, string: T) -> &mut PickaxeOptions {
+        self.needle = Some(Needle::String(string.into()));
+        self
+    }
+
+    /// Look for commits that add or remove a line accepted by `matcher`, like
+    /// `git log -G`.
+    ///
+    /// The matcher is given the content of each added or removed line,
+    /// without its line ending. To search with a regular expression, wrap
+    /// the `is_match` method of a regex library in a closure. This replaces
+    /// any string set with `string`.
+    pub fn line_matcher<F>(&mut self, matcher: F) -> &mut PickaxeOptions
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.needle = Some(Needle::Lines(Arc::new(matcher)));
+        self
+    }
+
+    /// Add a path pattern limiting which files are searched.
+    ///
+    /// If no patterns are given all files are searched.
+    pub fn pathspec<T: IntoCString>(&mut self, pathspec: T) -> &mut PickaxeOptions {
+        self.pathspec
+            .push(util::cstring_to_repo_path(pathspec).unwrap());
+        self
+    }
+
+    /// Diff commits on this many threads at once.
+    ///
+    /// Each thread opens its own handle to the repository, so the search runs
+    /// on the current thread instead if such a handle would not see the same
+    /// objects, for example because backends were added to the object
+    /// database at runtime. Defaults to 1.
+    pub fn threads(&mut self, threads: usize) -> &mut PickaxeOptions {
+        self.threads = threads;
+        self
+    }
+}
+
+impl Default for PickaxeOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PickaxeMatch {
+    /// Id of the matching commit.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The files changed by the commit in which the text was found.
+    pub fn deltas(&self) -> &[DiffFileStats] {
+        &self.deltas
+    }
+}
+
+/// Implementation of `Repository::pickaxe`.
+pub(crate) fn pickaxe(
+    repo: &Repository,
+    walk: &mut Revwalk<'_>,
+    opts: &PickaxeOptions,
+) -> Result<Vec<PickaxeMatch>, Error> {
+    let needle = match opts.needle {
+        Some(ref needle) => needle.clone(),
+        None => return Err(Error::from_str("no pickaxe string or line matcher set")),
+    };
+    let ids = walk.collect::<Result<Vec<_>, _>>()?;
+    if opts.threads <= 1 || ids.len() <= 1 {
+        return search(repo, &ids, &needle, &opts.pathspec);
+    }
+    let path = match repo.worker_path()? {
+        Some(path) => path,
+        None => return search(repo, &ids, &needle, &opts.pathspec),
+    };
+
+    let mut chunks = vec![Vec::new(); opts.threads.min(ids.len())];
+    let len = chunks.len();
+    for (i, id) in ids.iter().enumerate() {
+        chunks[i % len].push(*id);
+    }
+    let handles = chunks
+        .into_iter()
+        .map(|chunk| {
+            let path = path.clone();
+            let needle = needle.clone();
+            let pathspec = opts.pathspec.clone();
+            std::thread::spawn(move || -> Result<Vec<PickaxeMatch>, Error> {
+                let repo = Repository::open_worker(&path)?;
+                search(&repo, &chunk, &needle, &pathspec)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Ok(matches) => found.extend(matches?),
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+    // Put the matches back in the order of the walk.
+    let order = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (*id, i))
+        .collect::<HashMap<_, _>>();
+    found.sort_by_key(|m| order[&m.id]);
+    Ok(found)
+}
+
+fn search(
+    repo: &Repository,
+    ids: &[Oid],
+    needle: &Needle,
+    pathspec: &[CString],
+) -> Result<Vec<PickaxeMatch>, Error> {
+    let mut found = Vec::new();
+    for id in ids {
+        let commit = repo.find_commit(*id)?;
+        // Like `git log`, merges are not diffed.
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let mut diff_opts = DiffOptions::new();
+        diff_opts.context_lines(0);
+        for spec in pathspec {
+            diff_opts.pathspec(spec.clone());
+        }
+        let diff = repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut diff_opts),
+        )?;
+
+        let mut deltas = Vec::new();
+        for i in 0..diff.deltas().len() {
+            let patch = match Patch::from_diff(&diff, i)? {
+                Some(patch) => patch,
+                None => continue,
+            };
+            if patch.delta().flags().is_binary() {
+                continue;
+            }
+            if patch_matches(&patch, needle)? {
+                deltas.push(DiffFileStats::from_patch(&patch)?);
+            }
+        }
+        if !deltas.is_empty() {
+            found.push(PickaxeMatch { id: *id, deltas });
+        }
+    }
+    Ok(found)
+}
+
+fn patch_matches(patch: &Patch<'_>, needle: &Needle) -> Result<bool, Error> {
+    let mut removed = 0;
+    let mut added = 0;
+    for hunk in 0..patch.num_hunks() {
+        for i in 0..patch.num_lines_in_hunk(hunk)? {
+            let line = patch.line_in_hunk(hunk, i)?;
+            let count = match line.origin_value() {
+                DiffLineType::Deletion => &mut removed,
+                DiffLineType::Addition => &mut added,
+                _ => continue,
+            };
+            match needle {
+                Needle::String(string) => *count += occurrences(line.content(), string),
+                Needle::Lines(matcher) => {
+                    let content = line.content();
+                    let content = content.strip_suffix(b"\n").unwrap_or(content);
+                    let content = content.strip_suffix(b"\r").unwrap_or(content);
+                    if matcher(content) {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+    Ok(removed != added)
+}
+
+/// Count the non-overlapping occurrences of `needle` in `haystack`.
+fn occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut rest = haystack;
+    while let Some(pos) = rest.windows(needle.len()).position(|w| w == needle) {
+        count += 1;
+        rest = &rest[pos + needle.len()..];
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Oid, PickaxeOptions, Repository};
+    use std::path::Path;
+
+    fn commit_file(repo: &Repository, name: &str, content: &str) -> Oid {
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, content, &tree, &[&parent])
+            .unwrap()
+    }
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let add = commit_file(&repo, "a", "fn foo() {}\n");
+        let other = commit_file(&repo, "b", "fn foo() {}\n");
+        commit_file(&repo, "a", "fn foo() { bar() }\n");
+        let remove = commit_file(&repo, "a", "fn baz() { bar() }\n");
+
+        let search = |opts: &PickaxeOptions| {
+            let mut walk = repo.revwalk().unwrap();
+            walk.push_head().unwrap();
+            repo.pickaxe(&mut walk, opts)
+                .unwrap()
+                .iter()
+                .map(|m| m.id())
+                .collect::<Vec<_>>()
+        };
+
+        let mut opts = PickaxeOptions::new();
+        opts.string("foo");
+        assert_eq!(search(&opts), [remove, other, add]);
+        opts.pathspec("a");
+        assert_eq!(search(&opts), [remove, add]);
+        opts.threads(3);
+        assert_eq!(search(&opts), [remove, add]);
+
+        let mut opts = PickaxeOptions::new();
+        opts.line_matcher(|line| line.ends_with(b"bar() }"));
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        let matches = repo.pickaxe(&mut walk, &opts).unwrap();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[1].deltas().len(), 1);
+        assert_eq!(matches[1].deltas()[0].new_path(), Some(Path::new("a")));
+        assert_eq!(matches[1].deltas()[0].insertions(), 1);
+    }
+}
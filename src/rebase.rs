@@ -1,9 +1,54 @@
-use std::ffi::CString;
+use libc::{c_char, c_int, c_void};
+use std::ffi::{CStr, CString};
+use std::mem::ManuallyDrop;
 use std::{marker, mem, ptr, str};
 
 use crate::build::CheckoutBuilder;
 use crate::util::Binding;
-use crate::{raw, Error, Index, MergeOptions, Oid, Signature};
+use crate::{panic, raw, signature, AnnotatedCommit, Commit, Error, ErrorClass, ErrorCode};
+use crate::{Index, MergeOptions, Oid, Repository, Signature, Tree};
+
+/// A callback used to create the commits of a rebase.
+///
+/// See `RebaseOptions::commit_create`.
+pub type CommitCreateCb = dyn FnMut(
+    &Signature<'_>,
+    &Signature<'_>,
+    &str,
+    &Tree<'_>,
+    &[&Commit<'_>],
+) -> Result<Option<Oid>, Error>;
+
+/// The outcome of `Repository::rebase_in_memory`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebasedCommits {
+    rewritten: Vec<(Oid, Oid)>,
+    skipped: Vec<Oid>,
+    tip: Oid,
+}
+
+/// An editable list of rebase operations, like the todo list of
+/// `git rebase -i`.
+///
+/// A todo list can be built from the operations of a `Rebase`, edited by
+/// reordering, removing and changing its items, and then run with
+/// `Repository::rebase_todo`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RebaseTodo {
+    items: Vec<RebaseTodoItem>,
+}
+
+/// A single operation in a `RebaseTodo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebaseTodoItem {
+    kind: RebaseOperationType,
+    id: Oid,
+    message: Option<String>,
+}
+
+pub(crate) struct CommitCreateData {
+    callback: Box<CommitCreateCb>,
+}
 
 /// Rebase options
 ///
@@ -13,6 +58,7 @@ pub struct RebaseOptions<'cb> {
     rewrite_notes_ref: Option<CString>,
     merge_options: Option<MergeOptions>,
     checkout_options: Option<CheckoutBuilder<'cb>>,
+    commit_create: Option<Box<CommitCreateData>>,
 }
 
 impl<'cb> Default for RebaseOptions<'cb> {
@@ -29,6 +75,7 @@ impl<'cb> RebaseOptions<'cb> {
             rewrite_notes_ref: None,
             merge_options: None,
             checkout_options: None,
+            commit_create: None,
         };
         assert_eq!(unsafe { raw::git_rebase_init_options(&mut opts.raw, 1) }, 0);
         opts
@@ -81,6 +128,38 @@ impl<'cb> RebaseOptions<'cb> {
         self
     }
 
+    /// Create the commits of the rebase with a custom callback.
+    ///
+    /// Whenever `Rebase::commit` would create a commit, the callback is given
+    /// its author, committer, message, tree and parents instead. It must
+    /// write a commit to the object database, without updating any reference,
+    /// and return its id; this allows signing commits or rewriting their
+    /// authors and timestamps. Returning `Ok(None)` lets libgit2 create the
+    /// commit as usual.
+    ///
+    /// The tree and parents belong to the repository being rebased; to write
+    /// the commit through another `Repository` handle, look them up again in
+    /// that handle first.
+    ///
+    /// The callback is moved into the `Rebase` started or opened with these
+    /// options, so it has to be set again before reusing them.
+    pub fn commit_create<F>(&mut self, callback: F) -> &mut RebaseOptions<'cb>
+    where
+        F: FnMut(
+                &Signature<'_>,
+                &Signature<'_>,
+                &str,
+                &Tree<'_>,
+                &[&Commit<'_>],
+            ) -> Result<Option<Oid>, Error>
+            + 'static,
+    {
+        self.commit_create = Some(Box::new(CommitCreateData {
+            callback: Box::new(callback),
+        }));
+        self
+    }
+
     /// Acquire a pointer to the underlying raw options.
     pub fn raw(&mut self) -> *const raw::git_rebase_options {
         unsafe {
@@ -95,6 +174,16 @@ impl<'cb> RebaseOptions<'cb> {
                 .as_ref()
                 .map(|s| s.as_ptr())
                 .unwrap_or(ptr::null());
+            match self.commit_create {
+                Some(ref mut data) => {
+                    self.raw.commit_create_cb = Some(commit_create_cb);
+                    self.raw.payload = &mut **data as *mut CommitCreateData as *mut c_void;
+                }
+                None => {
+                    self.raw.commit_create_cb = None;
+                    self.raw.payload = ptr::null_mut();
+                }
+            }
         }
         &self.raw
     }
@@ -103,10 +192,29 @@ impl<'cb> RebaseOptions<'cb> {
 /// Representation of a rebase
 pub struct Rebase<'repo> {
     raw: *mut raw::git_rebase,
+    commit_create: Option<Box<CommitCreateData>>,
+    rerere: Option<&'repo Repository>,
     _marker: marker::PhantomData<&'repo raw::git_rebase>,
 }
 
 impl<'repo> Rebase<'repo> {
+    /// Wrap a rebase of `repo` created with the given options, taking over
+    /// the callbacks that libgit2 keeps pointers to. Rebases in the working
+    /// directory run rerere as they go.
+    pub(crate) unsafe fn new(
+        repo: &'repo Repository,
+        raw: *mut raw::git_rebase,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Rebase<'repo> {
+        let mut rebase = Rebase::from_raw(raw);
+        let inmemory = opts.as_ref().is_some_and(|o| o.raw.inmemory != 0);
+        if !inmemory {
+            rebase.rerere = Some(repo);
+        }
+        rebase.commit_create = opts.and_then(|o| o.commit_create.take());
+        rebase
+    }
+
     /// Gets the count of rebase operations that are to be applied.
     pub fn len(&self) -> usize {
         unsafe { raw::git_rebase_operation_entrycount(self.raw) }
@@ -124,6 +232,17 @@ impl<'repo> Rebase<'repo> {
         unsafe { Oid::from_raw_opt(raw::git_rebase_orig_head_id(self.raw)) }
     }
 
+    /// Gets the `onto` ref name for merge rebases.
+    pub fn onto_name(&self) -> Option<&str> {
+        let name_bytes = unsafe { crate::opt_bytes(self, raw::git_rebase_onto_name(self.raw)) };
+        name_bytes.and_then(|s| str::from_utf8(s).ok())
+    }
+
+    /// Gets the `onto` id for merge rebases.
+    pub fn onto_id(&self) -> Option<Oid> {
+        unsafe { Oid::from_raw_opt(raw::git_rebase_onto_id(self.raw)) }
+    }
+
     ///  Gets the rebase operation specified by the given index.
     pub fn nth(&mut self, n: usize) -> Option<RebaseOperation<'_>> {
         unsafe {
@@ -167,6 +286,9 @@ impl<'repo> Rebase<'repo> {
     /// were introduced during the patch application from the `git_rebase_next`
     /// invocation. To keep the author and message from the original commit leave
     /// them as None
+    ///
+    /// When rerere is enabled, the resolutions of the conflicts are recorded
+    /// first, as `Repository::rerere` does.
     pub fn commit(
         &mut self,
         author: Option<&Signature<'_>>,
@@ -175,6 +297,9 @@ impl<'repo> Rebase<'repo> {
     ) -> Result<Oid, Error> {
         let mut id: raw::git_oid = unsafe { mem::zeroed() };
         let message = crate::opt_cstr(message)?;
+        if let Some(repo) = self.rerere {
+            crate::rerere::rerere(repo)?;
+        }
         unsafe {
             try_call!(raw::git_rebase_commit(
                 &mut id,
@@ -194,6 +319,9 @@ impl<'repo> Rebase<'repo> {
         unsafe {
             try_call!(raw::git_rebase_abort(self.raw));
         }
+        if let Some(repo) = self.rerere {
+            crate::rerere::clear(repo)?;
+        }
 
         Ok(())
     }
@@ -216,13 +344,20 @@ impl<'rebase> Iterator for Rebase<'rebase> {
     /// If the operation is one that applies a patch (which is any operation except
     /// GitRebaseOperation::Exec) then the patch will be applied and the index and
     /// working directory will be updated with the changes.  If there are conflicts,
-    /// you will need to address those before committing the changes.
+    /// you will need to address those before committing the changes. When
+    /// rerere is enabled, the conflicts are recorded and previously recorded
+    /// resolutions are replayed, as `Repository::rerere` does.
     fn next(&mut self) -> Option<Result<RebaseOperation<'rebase>, Error>> {
         let mut out = ptr::null_mut();
         unsafe {
             try_call_iter!(raw::git_rebase_next(&mut out, self.raw));
-            Some(Ok(RebaseOperation::from_raw(out)))
         }
+        if let Some(repo) = self.rerere {
+            if let Err(e) = crate::rerere::rerere(repo) {
+                return Some(Err(e));
+            }
+        }
+        unsafe { Some(Ok(RebaseOperation::from_raw(out))) }
     }
 }
 
@@ -231,6 +366,8 @@ impl<'repo> Binding for Rebase<'repo> {
     unsafe fn from_raw(raw: *mut raw::git_rebase) -> Rebase<'repo> {
         Rebase {
             raw,
+            commit_create: None,
+            rerere: None,
             _marker: marker::PhantomData,
         }
     }
@@ -249,7 +386,7 @@ impl<'repo> Drop for Rebase<'repo> {
 ///
 /// Describes a single instruction/operation to be performed during the
 /// rebase.
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum RebaseOperationType {
     /// The given commit is to be cherry-picked. The client should commit the
     /// changes and continue if there are no conflicts.
@@ -333,9 +470,362 @@ impl<'rebase> Binding for RebaseOperation<'rebase> {
     }
 }
 
+impl RebasedCommits {
+    /// Pairs of original and rewritten commit ids, in the order the commits
+    /// were applied.
+    pub fn rewritten(&self) -> &[(Oid, Oid)] {
+        &self.rewritten
+    }
+
+    /// The rewritten id of an original commit, if it was rewritten.
+    pub fn new_id(&self, old: Oid) -> Option<Oid> {
+        self.rewritten
+            .iter()
+            .find(|(o, _)| *o == old)
+            .map(|(_, new)| *new)
+    }
+
+    /// Original commits that were dropped because their changes were
+    /// already present upstream.
+    pub fn skipped(&self) -> &[Oid] {
+        &self.skipped
+    }
+
+    /// The last commit of the rebased branch.
+    pub fn tip(&self) -> Oid {
+        self.tip
+    }
+}
+
+impl RebaseTodo {
+    /// Creates a new, empty todo list.
+    pub fn new() -> RebaseTodo {
+        RebaseTodo::default()
+    }
+
+    /// Creates a todo list from the operations of a rebase.
+    ///
+    /// The rebase itself is left untouched, and can be aborted once the todo
+    /// list has been created.
+    pub fn from_rebase(rebase: &mut Rebase<'_>) -> RebaseTodo {
+        let mut todo = RebaseTodo::new();
+        for i in 0..rebase.len() {
+            if let Some(op) = rebase.nth(i) {
+                todo.items.push(RebaseTodoItem {
+                    kind: op.kind().unwrap_or(RebaseOperationType::Pick),
+                    id: op.id(),
+                    message: None,
+                });
+            }
+        }
+        todo
+    }
+
+    /// Add an operation applying the given commit as is.
+    pub fn pick(&mut self, id: Oid) -> &mut RebaseTodo {
+        self.push(RebaseOperationType::Pick, id, None)
+    }
+
+    /// Add an operation applying the given commit with a new message.
+    pub fn reword(&mut self, id: Oid, message: &str) -> &mut RebaseTodo {
+        self.push(RebaseOperationType::Reword, id, Some(message.to_string()))
+    }
+
+    /// Add an operation melding the given commit into the previous one,
+    /// appending its message to the previous message.
+    pub fn squash(&mut self, id: Oid) -> &mut RebaseTodo {
+        self.push(RebaseOperationType::Squash, id, None)
+    }
+
+    /// Add an operation melding the given commit into the previous one,
+    /// discarding its message.
+    pub fn fixup(&mut self, id: Oid) -> &mut RebaseTodo {
+        self.push(RebaseOperationType::Fixup, id, None)
+    }
+
+    fn push(&mut self, kind: RebaseOperationType, id: Oid, message: Option<String>) -> &mut Self {
+        self.items.push(RebaseTodoItem { kind, id, message });
+        self
+    }
+
+    /// The operations of this todo list, in the order they will be applied.
+    pub fn items(&self) -> &[RebaseTodoItem] {
+        &self.items
+    }
+
+    /// Gets the count of operations in this todo list.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns whether this todo list has no operations.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Move the operation at index `from` so that it ends up at index `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn move_item(&mut self, from: usize, to: usize) -> &mut RebaseTodo {
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        self
+    }
+
+    /// Remove the operation at the given index, dropping its commit from the
+    /// rebased branch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn remove(&mut self, index: usize) -> RebaseTodoItem {
+        self.items.remove(index)
+    }
+
+    /// Change the kind of the operation at the given index.
+    ///
+    /// Only `Pick`, `Reword`, `Squash` and `Fixup` operations can be run.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn set_kind(&mut self, index: usize, kind: RebaseOperationType) -> &mut RebaseTodo {
+        self.items[index].kind = kind;
+        self
+    }
+
+    /// Set a new message for the commit of the operation at the given index,
+    /// turning it into a `Reword` operation.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn set_message(&mut self, index: usize, message: &str) -> &mut RebaseTodo {
+        let item = &mut self.items[index];
+        item.kind = RebaseOperationType::Reword;
+        item.message = Some(message.to_string());
+        self
+    }
+}
+
+impl RebaseTodoItem {
+    /// The kind of this operation.
+    pub fn kind(&self) -> RebaseOperationType {
+        self.kind
+    }
+
+    /// The commit this operation applies.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The new message of a `Reword` operation, if one was given.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+/// Implementation of `Repository::rebase_todo`.
+///
+/// libgit2 builds the operations of a rebase itself, so each operation of the
+/// todo list is run as an in-memory rebase of its single commit onto the
+/// current tip, through `Rebase::next` and `Rebase::commit`.
+pub(crate) fn rebase_todo(
+    repo: &Repository,
+    onto: &Commit<'_>,
+    todo: &RebaseTodo,
+    committer: &Signature<'_>,
+    opts: Option<&mut RebaseOptions<'_>>,
+) -> Result<RebasedCommits, Error> {
+    let mut default_opts = RebaseOptions::new();
+    let opts = opts.unwrap_or(&mut default_opts);
+    opts.inmemory(true);
+
+    let mut tip = onto.clone();
+    let mut rewritten = Vec::new();
+    let mut skipped = Vec::new();
+    // The original commits that make up the current tip, which only gets a
+    // final id once no more commits are squashed into it.
+    let mut melded = Vec::new();
+    for item in &todo.items {
+        let squash = match item.kind {
+            RebaseOperationType::Pick | RebaseOperationType::Reword => false,
+            RebaseOperationType::Squash | RebaseOperationType::Fixup => true,
+            kind => {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Rebase,
+                    format!("cannot run {:?} operation for commit {}", kind, item.id),
+                ))
+            }
+        };
+        if squash && melded.is_empty() {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Rebase,
+                format!("cannot squash commit {} without a previous commit", item.id),
+            ));
+        }
+        let commit = repo.find_commit(item.id)?;
+        if commit.parent_count() > 1 {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Rebase,
+                format!("cannot rebase merge commit {}", item.id),
+            ));
+        }
+
+        let branch = repo.find_annotated_commit(item.id)?;
+        let upstream = match commit.parent_ids().next() {
+            Some(id) => Some(repo.find_annotated_commit(id)?),
+            None => None,
+        };
+        let onto = repo.find_annotated_commit(tip.id())?;
+        let mut rebase = repo.rebase(Some(&branch), upstream.as_ref(), Some(&onto), Some(opts))?;
+        match rebase.next() {
+            Some(op) => op?,
+            None => return Err(Error::from_str("rebase has no operation to run")),
+        };
+        if rebase.inmemory_index()?.has_conflicts() {
+            return Err(Error::new(
+                ErrorCode::MergeConflict,
+                ErrorClass::Rebase,
+                format!("conflicts while rebasing commit {}", item.id),
+            ));
+        }
+
+        let id = if squash {
+            let tree = rebase.inmemory_index()?.write_tree_to(repo)?;
+            let tree = repo.find_tree(tree)?;
+            let mut message = message_of(&tip)?.to_string();
+            if item.kind == RebaseOperationType::Squash {
+                // Like git, leave a blank line between the melded messages.
+                message.truncate(message.trim_end().len());
+                message.push_str("\n\n");
+                message.push_str(message_of(&commit)?);
+            }
+            let parents = tip.parents().collect::<Vec<_>>();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &tip.author(), committer, &message, &tree, &parents)?
+        } else {
+            match rebase.commit(None, committer, item.message.as_deref()) {
+                Ok(id) => {
+                    rewritten.extend(melded.drain(..).map(|old| (old, tip.id())));
+                    id
+                }
+                Err(ref e) if e.code() == ErrorCode::Applied => {
+                    opts.commit_create = rebase.commit_create.take();
+                    skipped.push(item.id);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        rebase.finish(None)?;
+        // Hand the commit callback back for the next operation.
+        opts.commit_create = rebase.commit_create.take();
+        melded.push(item.id);
+        tip = repo.find_commit(id)?;
+    }
+    rewritten.extend(melded.drain(..).map(|old| (old, tip.id())));
+    Ok(RebasedCommits {
+        rewritten,
+        skipped,
+        tip: tip.id(),
+    })
+}
+
+fn message_of<'a>(commit: &'a Commit<'_>) -> Result<&'a str, Error> {
+    commit
+        .message_raw()
+        .ok_or_else(|| Error::from_str("commit message is not valid utf-8"))
+}
+
+/// Implementation of `Repository::rebase_in_memory`.
+pub(crate) fn rebase_in_memory(
+    repo: &Repository,
+    branch: &AnnotatedCommit<'_>,
+    upstream: Option<&AnnotatedCommit<'_>>,
+    onto: Option<&AnnotatedCommit<'_>>,
+    committer: &Signature<'_>,
+    opts: Option<&mut RebaseOptions<'_>>,
+) -> Result<RebasedCommits, Error> {
+    let mut default_opts = RebaseOptions::new();
+    let opts = opts.unwrap_or(&mut default_opts);
+    opts.inmemory(true);
+    let mut rebase = repo.rebase(Some(branch), upstream, onto, Some(opts))?;
+
+    let mut tip = match rebase.onto_id() {
+        Some(id) => id,
+        None => return Err(Error::from_str("rebase has no onto commit")),
+    };
+    let mut rewritten = Vec::new();
+    let mut skipped = Vec::new();
+    while let Some(op) = rebase.next() {
+        let old = op?.id();
+        if rebase.inmemory_index()?.has_conflicts() {
+            return Err(Error::new(
+                ErrorCode::MergeConflict,
+                ErrorClass::Rebase,
+                format!("conflicts while rebasing commit {}", old),
+            ));
+        }
+        match rebase.commit(None, committer, None) {
+            Ok(new) => {
+                rewritten.push((old, new));
+                tip = new;
+            }
+            Err(ref e) if e.code() == ErrorCode::Applied => skipped.push(old),
+            Err(e) => return Err(e),
+        }
+    }
+    rebase.finish(Some(committer))?;
+    Ok(RebasedCommits {
+        rewritten,
+        skipped,
+        tip,
+    })
+}
+
+extern "C" fn commit_create_cb(
+    out: *mut raw::git_oid,
+    author: *const raw::git_signature,
+    committer: *const raw::git_signature,
+    _message_encoding: *const c_char,
+    message: *const c_char,
+    tree: *const raw::git_tree,
+    parent_count: usize,
+    parents: *mut *const raw::git_commit,
+    payload: *mut c_void,
+) -> c_int {
+    let ret = panic::wrap(|| unsafe {
+        let data = &mut *(payload as *mut CommitCreateData);
+        let author = signature::from_raw_const(&(), author);
+        let committer = signature::from_raw_const(&(), committer);
+        let message = String::from_utf8_lossy(CStr::from_ptr(message).to_bytes());
+        // These objects are owned by libgit2, so they must not be freed.
+        let tree = ManuallyDrop::new(Tree::from_raw(tree as *mut raw::git_tree));
+        let parents = (0..parent_count)
+            .map(|i| ManuallyDrop::new(Commit::from_raw(*parents.add(i) as *mut raw::git_commit)))
+            .collect::<Vec<_>>();
+        let parents = parents.iter().map(|p| &**p).collect::<Vec<_>>();
+        match (data.callback)(&author, &committer, &message, &tree, &parents) {
+            Ok(Some(id)) => {
+                ptr::copy_nonoverlapping(id.raw(), out, 1);
+                0
+            }
+            Ok(None) => raw::GIT_PASSTHROUGH as c_int,
+            Err(e) => e.raw_set_git_error(),
+        }
+    });
+    ret.unwrap_or(-1)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{RebaseOperationType, RebaseOptions, Signature};
+    use crate::{RebaseOperationType, RebaseOptions, RebaseTodo, Signature};
     use std::{fs, path};
 
     #[test]
@@ -438,4 +928,159 @@ mod tests {
         }
         rebase.finish(None).unwrap();
     }
+
+    #[test]
+    fn commit_create() {
+        let (td, repo) = crate::test::repo_init();
+        let tip = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = Signature::now("testname", "testemail").unwrap();
+        let mut index = repo.index().unwrap();
+        fs::File::create(td.path().join("file_a")).unwrap();
+        index.add_path(path::Path::new("file_a")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let c1 = repo.commit(None, &sig, &sig, "A", &tree, &[&tip]).unwrap();
+
+        let path = repo.path().to_path_buf();
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true)
+            .commit_create(move |author, _committer, message, tree, parents| {
+                let repo = crate::Repository::open(&path)?;
+                let committer = Signature::now("rewriter", "rewriter@example.com")?;
+                let message = format!("{}\nRewritten-by: rewriter\n", message);
+                let tree = repo.find_tree(tree.id())?;
+                let parents = parents
+                    .iter()
+                    .map(|p| repo.find_commit(p.id()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let parents = parents.iter().collect::<Vec<_>>();
+                let id = repo.commit(None, author, &committer, &message, &tree, &parents)?;
+                Ok(Some(id))
+            });
+        let branch = repo.find_annotated_commit(c1).unwrap();
+        let upstream = repo.find_annotated_commit(tip.id()).unwrap();
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        rebase.next().unwrap().unwrap();
+        let id = rebase.commit(None, &sig, None).unwrap();
+
+        let commit = repo.find_commit(id).unwrap();
+        assert_eq!(commit.author().name(), Some("testname"));
+        assert_eq!(commit.committer().name(), Some("rewriter"));
+        assert_eq!(commit.message(), Some("A\nRewritten-by: rewriter\n"));
+        assert_eq!(commit.parent_id(0).unwrap(), tip.id());
+    }
+
+    #[test]
+    fn rebase_in_memory() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = Signature::now("testname", "testemail").unwrap();
+        let base = repo.head().unwrap().peel_to_commit().unwrap();
+        let head = base.id();
+        let commit = |parent: &crate::Commit<'_>, name: &str| {
+            let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            let blob = repo.blob(name.as_bytes()).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let id = repo
+                .commit(None, &sig, &sig, name, &tree, &[parent])
+                .unwrap();
+            repo.find_commit(id).unwrap()
+        };
+        let a = commit(&base, "a");
+        let b = commit(&a, "b");
+        let c = commit(&base, "c");
+        let upstream = commit(&c, "a");
+
+        let branch = repo.find_annotated_commit(b.id()).unwrap();
+        let onto = repo.find_annotated_commit(upstream.id()).unwrap();
+        let base = repo.find_annotated_commit(base.id()).unwrap();
+        let rebased = repo
+            .rebase_in_memory(&branch, Some(&base), Some(&onto), &sig, None)
+            .unwrap();
+
+        assert_eq!(rebased.skipped(), [a.id()]);
+        assert_eq!(rebased.rewritten().len(), 1);
+        let new_b = rebased.new_id(b.id()).unwrap();
+        assert_eq!(rebased.tip(), new_b);
+        let new_b = repo.find_commit(new_b).unwrap();
+        assert_eq!(new_b.message(), Some("b"));
+        assert_eq!(new_b.parent_id(0).unwrap(), upstream.id());
+        assert_eq!(repo.head().unwrap().target(), Some(head));
+    }
+
+    #[test]
+    fn rebase_todo() {
+        let (_td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().peel_to_commit().unwrap();
+        let committer = Signature::now("committer", "committer@example.com").unwrap();
+        let mut tip = base.clone();
+        for (file, author) in [
+            ("a", "alice"),
+            ("b", "bob"),
+            ("c", "carol"),
+            ("d", "dave"),
+            ("e", "eve"),
+        ]
+        .iter()
+        {
+            let author = Signature::now(author, "author@example.com").unwrap();
+            let mut builder = repo.treebuilder(Some(&tip.tree().unwrap())).unwrap();
+            builder
+                .insert(file, repo.blob(file.as_bytes()).unwrap(), 0o100644)
+                .unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let message = format!("{}\n", file);
+            let id = repo
+                .commit(None, &author, &author, &message, &tree, &[&tip])
+                .unwrap();
+            tip = repo.find_commit(id).unwrap();
+        }
+
+        let branch = repo.find_annotated_commit(tip.id()).unwrap();
+        let upstream = repo.find_annotated_commit(base.id()).unwrap();
+        let mut opts = RebaseOptions::new();
+        opts.inmemory(true);
+        let mut rebase = repo
+            .rebase(Some(&branch), Some(&upstream), None, Some(&mut opts))
+            .unwrap();
+        let mut todo = RebaseTodo::from_rebase(&mut rebase);
+        rebase.abort().unwrap();
+        let ids = todo.items().iter().map(|i| i.id()).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 5);
+
+        // d, reword a, fixup b, squash c, drop e.
+        todo.set_message(0, "A\n")
+            .set_kind(1, RebaseOperationType::Fixup)
+            .set_kind(2, RebaseOperationType::Squash)
+            .move_item(3, 0);
+        assert_eq!(todo.remove(4).id(), ids[4]);
+        let rebased = repo
+            .rebase_todo(&base, &todo, &committer, Some(&mut opts))
+            .unwrap();
+
+        let tip = repo.find_commit(rebased.tip()).unwrap();
+        let d = tip.parent(0).unwrap();
+        assert_eq!(d.parent_id(0).unwrap(), base.id());
+        assert_eq!(d.message(), Some("d\n"));
+        assert_eq!(d.author().name(), Some("dave"));
+        assert_eq!(d.committer().name(), Some("committer"));
+        assert_eq!(
+            rebased.rewritten(),
+            [
+                (ids[3], d.id()),
+                (ids[0], tip.id()),
+                (ids[1], tip.id()),
+                (ids[2], tip.id())
+            ]
+        );
+        assert_eq!(tip.message(), Some("A\n\nc\n"));
+        assert_eq!(tip.author().name(), Some("alice"));
+        let tree = tip.tree().unwrap();
+        for name in ["a", "b", "c", "d"].iter() {
+            assert!(tree.get_name(name).is_some());
+        }
+        assert!(tree.get_name("e").is_none());
+        assert!(rebased.skipped().is_empty());
+    }
 }
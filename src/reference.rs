@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::ffi::CString;
 use std::marker;
 use std::mem;
@@ -501,6 +502,38 @@ impl<'repo, 'references> Iterator for ReferenceNames<'repo, 'references> {
     }
 }
 
+/// Implementation of `Repository::reference_names_page`.
+pub(crate) fn names_page(
+    repo: &Repository,
+    glob: Option<&str>,
+    start_after: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, Error> {
+    let mut refs = match glob {
+        Some(glob) => repo.references_glob(glob)?,
+        None => repo.references()?,
+    };
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    // Keep the `limit` smallest names seen so far, the largest on top.
+    let mut page = BinaryHeap::<String>::with_capacity(limit + 1);
+    for name in refs.names() {
+        let name = name?;
+        if matches!(start_after, Some(after) if name <= after) {
+            continue;
+        }
+        if page.len() == limit {
+            if matches!(page.peek(), Some(last) if name >= last.as_str()) {
+                continue;
+            }
+            page.pop();
+        }
+        page.push(name.to_string());
+    }
+    Ok(page.into_sorted_vec())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{ObjectType, Reference, ReferenceType};
@@ -589,4 +622,27 @@ mod tests {
         let mut head = head.rename("refs/foo", true, "test").unwrap();
         head.delete().unwrap();
     }
+
+    #[test]
+    fn names_page() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.refname_to_id("HEAD").unwrap();
+        for name in ["d", "b", "e", "a", "c"].iter() {
+            repo.reference(&format!("refs/tags/{}", name), head, false, "")
+                .unwrap();
+        }
+        let page = |after, limit| {
+            repo.reference_names_page(Some("refs/tags/*"), after, limit)
+                .unwrap()
+        };
+        assert_eq!(page(None, 2), ["refs/tags/a", "refs/tags/b"]);
+        assert_eq!(page(Some("refs/tags/b"), 2), ["refs/tags/c", "refs/tags/d"]);
+        assert_eq!(page(Some("refs/tags/d"), 2), ["refs/tags/e"]);
+        assert!(page(Some("refs/tags/e"), 2).is_empty());
+        assert!(page(None, 0).is_empty());
+
+        let all = repo.reference_names_page(None, None, 100).unwrap();
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[5], "refs/tags/e");
+    }
 }
@@ -1,17 +1,32 @@
 use libc::size_t;
+use std::collections::HashSet;
 use std::iter::FusedIterator;
 use std::marker;
 use std::ops::Range;
 use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::util::Binding;
-use crate::{raw, signature, Error, Oid, Signature};
+use crate::{raw, signature, Error, ErrorClass, ErrorCode, Oid, Repository, Signature, Time};
 
 /// A reference log of a git repository.
 pub struct Reflog {
     raw: *mut raw::git_reflog,
 }
 
+/// Options for `Repository::reflog_expire`.
+///
+/// Cutoffs which are not set are read from the `gc.reflogExpire` and
+/// `gc.reflogExpireUnreachable` configuration, which default to 90 and 30
+/// days ago, like `git reflog expire`. Per-reference `gc.<pattern>.*`
+/// settings are not supported.
+#[derive(Clone, Debug, Default)]
+pub struct ReflogExpireOptions {
+    expire: Option<Option<Time>>,
+    expire_unreachable: Option<Option<Time>>,
+    dry_run: bool,
+}
+
 /// An entry inside the reflog of a repository
 pub struct ReflogEntry<'reflog> {
     raw: *const raw::git_reflog_entry,
@@ -100,6 +115,156 @@ impl Reflog {
     }
 }
 
+impl ReflogExpireOptions {
+    /// Creates a new set of options, reading the cutoffs from the
+    /// configuration.
+    pub fn new() -> ReflogExpireOptions {
+        ReflogExpireOptions::default()
+    }
+
+    /// Entries older than this are removed. `None` keeps all entries.
+    pub fn expire(&mut self, expire: Option<Time>) -> &mut ReflogExpireOptions {
+        self.expire = Some(expire);
+        self
+    }
+
+    /// Entries older than this are removed if the commit they record is
+    /// not reachable from the current target of the reference, such as
+    /// commits discarded by a reset or a rebase. `None` keeps them until
+    /// they reach the `expire` cutoff.
+    pub fn expire_unreachable(&mut self, expire: Option<Time>) -> &mut ReflogExpireOptions {
+        self.expire_unreachable = Some(expire);
+        self
+    }
+
+    /// Only count the entries which would be removed, without writing the
+    /// reflog.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut ReflogExpireOptions {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+/// Parse a reflog expiry setting, such as `90.days.ago`, `2 weeks`, `now`
+/// or `never`, into a cutoff time.
+fn parse_expiry(value: &str, now: i64) -> Result<Option<Time>, Error> {
+    let invalid = || {
+        Error::new(
+            ErrorCode::Invalid,
+            ErrorClass::Config,
+            format!("invalid reflog expiry '{}'", value),
+        )
+    };
+    let value = value.trim().to_ascii_lowercase();
+    match &value[..] {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => return Ok(Some(Time::new(i64::MAX, 0))),
+        _ => {}
+    }
+    let words = value
+        .split(|c: char| c == '.' || c == '_' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>();
+    let (count, unit) = match words[..] {
+        [count, unit] | [count, unit, "ago"] => (count, unit),
+        _ => return Err(invalid()),
+    };
+    let count = count.parse::<i64>().map_err(|_| invalid())?;
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let seconds = match unit {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        "month" => 30 * 24 * 60 * 60,
+        "year" => 365 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    Ok(Some(Time::new(
+        now.saturating_sub(count.saturating_mul(seconds)),
+        0,
+    )))
+}
+
+fn config_expiry(
+    repo: &Repository,
+    name: &str,
+    default: &str,
+    now: i64,
+) -> Result<Option<Time>, Error> {
+    match repo.config()?.get_string(name) {
+        Ok(value) => parse_expiry(&value, now),
+        Err(e) if e.code() == ErrorCode::NotFound => parse_expiry(default, now),
+        Err(e) => Err(e),
+    }
+}
+
+/// Implementation of `Repository::reflog_expire`.
+pub(crate) fn expire(
+    repo: &Repository,
+    refname: &str,
+    opts: &ReflogExpireOptions,
+) -> Result<usize, Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let expire = match opts.expire {
+        Some(expire) => expire,
+        None => config_expiry(repo, "gc.reflogExpire", "90.days.ago", now)?,
+    };
+    let expire_unreachable = match opts.expire_unreachable {
+        Some(expire) => expire,
+        None => config_expiry(repo, "gc.reflogExpireUnreachable", "30.days.ago", now)?,
+    };
+    // Lock the reference first, so that no entry is added to the reflog
+    // between reading and rewriting it.
+    let mut tx = repo.transaction()?;
+    if !opts.dry_run {
+        tx.lock_ref(refname)?;
+    }
+    let mut reflog = repo.reflog(refname)?;
+
+    // The commits reachable from the tip are only needed for the entries
+    // between the two cutoffs, and are walked at most once.
+    let tip = repo.refname_to_id(refname).ok();
+    let mut reachable: Option<HashSet<Oid>> = None;
+    let mut expired = Vec::new();
+    for (i, entry) in reflog.iter().enumerate() {
+        let when = entry.committer().when().seconds();
+        let before = |cutoff: Option<Time>| matches!(cutoff, Some(t) if when < t.seconds());
+        if before(expire) {
+            expired.push(i);
+        } else if before(expire_unreachable) {
+            if reachable.is_none() {
+                let mut set = tip.into_iter().collect::<HashSet<_>>();
+                let mut walk = repo.revwalk()?;
+                // Tips which are not commits reach nothing but themselves.
+                if tip.is_some_and(|tip| walk.push(tip).is_ok()) {
+                    for id in walk {
+                        set.insert(id?);
+                    }
+                }
+                reachable = Some(set);
+            }
+            if !reachable.as_ref().unwrap().contains(&entry.id_new()) {
+                expired.push(i);
+            }
+        }
+    }
+    if opts.dry_run || expired.is_empty() {
+        return Ok(expired.len());
+    }
+    // Remove from the oldest entry, so the indexes stay valid.
+    for &i in expired.iter().rev() {
+        reflog.remove(i, true)?;
+    }
+    tx.set_reflog(refname, reflog)?;
+    tx.commit()?;
+    Ok(expired.len())
+}
+
 impl Binding for Reflog {
     type Raw = *mut raw::git_reflog;
 
@@ -180,6 +345,9 @@ impl<'reflog> ExactSizeIterator for ReflogIter<'reflog> {}
 
 #[cfg(test)]
 mod tests {
+    use super::parse_expiry;
+    use crate::{Oid, ReflogExpireOptions, Signature, Time};
+
     #[test]
     fn smoke() {
         let (_td, repo) = crate::test::repo_init();
@@ -193,4 +361,58 @@ mod tests {
         repo.reflog_rename("HEAD", "refs/heads/foo").unwrap();
         repo.reflog_delete("refs/heads/foo").unwrap();
     }
+
+    #[test]
+    fn parse() {
+        let day = 24 * 60 * 60;
+        let at = |t: Option<Time>| t.map(|t| t.seconds());
+        assert_eq!(
+            at(t!(parse_expiry("90.days.ago", 100 * day))),
+            Some(10 * day)
+        );
+        assert_eq!(at(t!(parse_expiry("2 weeks", 100 * day))), Some(86 * day));
+        assert_eq!(at(t!(parse_expiry("1.hour", 3600))), Some(0));
+        assert_eq!(at(t!(parse_expiry("never", 0))), None);
+        assert_eq!(at(t!(parse_expiry("now", 0))), Some(i64::MAX));
+        assert!(parse_expiry("soon", 0).is_err());
+    }
+
+    #[test]
+    fn expire() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = t!(repo.refname_to_id("refs/heads/main"));
+        let (tip, _) = crate::test::commit(&repo);
+        let old = t!(Signature::new(
+            "old",
+            "old@example.com",
+            &Time::new(1000, 0)
+        ));
+        let mut reflog = t!(repo.reflog("refs/heads/main"));
+        t!(reflog.append(head, &old, Some("old reachable")));
+        t!(reflog.append(Oid::zero(), &old, Some("old unreachable")));
+        t!(reflog.write());
+        let len = reflog.len();
+
+        // Only the unreachable entry is older than the unreachable cutoff.
+        let mut opts = ReflogExpireOptions::new();
+        opts.expire(None)
+            .expire_unreachable(Some(Time::new(2000, 0)))
+            .dry_run(true);
+        assert_eq!(t!(repo.reflog_expire("refs/heads/main", Some(&opts))), 1);
+        assert_eq!(t!(repo.reflog("refs/heads/main")).len(), len);
+        opts.dry_run(false);
+        assert_eq!(t!(repo.reflog_expire("refs/heads/main", Some(&opts))), 1);
+        assert_eq!(t!(repo.reflog("refs/heads/main")).len(), len - 1);
+
+        // The configured defaults expire the old reachable entry too.
+        assert_eq!(t!(repo.reflog_expire("refs/heads/main", None)), 1);
+        let reflog = t!(repo.reflog("refs/heads/main"));
+        assert_eq!(reflog.len(), len - 2);
+        assert!(reflog.iter().all(|e| e.committer().when().seconds() > 2000));
+        assert_eq!(t!(repo.refname_to_id("refs/heads/main")), tip);
+
+        t!(t!(repo.config()).set_str("gc.reflogExpire", "now"));
+        assert_eq!(t!(repo.reflog_expire("refs/heads/main", None)), len - 2);
+        assert!(t!(repo.reflog("refs/heads/main")).is_empty());
+    }
 }
@@ -10,6 +10,7 @@ use std::str;
 use std::{ffi::CString, os::raw::c_char};
 
 use crate::string_array::StringArray;
+use crate::tracing::CallSpan;
 use crate::util::Binding;
 use crate::{call, raw, Buf, Direction, Error, FetchPrune, Oid, ProxyOptions, Refspec};
 use crate::{AutotagOption, Progress, RemoteCallbacks, RemoteUpdateFlags, Repository};
@@ -39,6 +40,13 @@ pub struct RemoteHead<'remote> {
 }
 
 /// Options which can be specified to various fetch operations.
+///
+/// libgit2 negotiates fetches with version 0 and 1 of the git protocol, so
+/// capabilities which only exist in protocol version 2, such as
+/// `packfile-uris`, are never requested and servers send the whole pack
+/// in-band. Packs offloaded to a CDN can still be downloaded separately and
+/// indexed with `Indexer::append_from` before fetching, in which case the
+/// fetch only transfers the objects missing from them.
 pub struct FetchOptions<'cb> {
     callbacks: Option<RemoteCallbacks<'cb>>,
     depth: i32,
@@ -91,6 +99,16 @@ pub fn remote_into_raw(remote: Remote<'_>) -> *mut raw::git_remote {
     ret
 }
 
+/// Attach the operation and refspecs of a failed fetch or push to `e`.
+fn context<S: AsRef<str>>(e: Error, operation: &str, refspecs: &[S]) -> Error {
+    let e = e.with_operation(operation);
+    if refspecs.is_empty() {
+        return e;
+    }
+    let refspecs = refspecs.iter().map(|s| s.as_ref()).collect::<Vec<_>>();
+    e.with_refspec(&refspecs.join(" "))
+}
+
 impl<'repo> Remote<'repo> {
     /// Ensure the remote name is well-formed.
     pub fn is_valid_name(remote_name: &str) -> bool {
@@ -311,9 +329,18 @@ impl<'repo> Remote<'repo> {
         let (_a, _b, arr) = crate::util::iter2cstrs(refspecs.iter())?;
         let msg = crate::opt_cstr(reflog_msg)?;
         let raw = opts.map(|o| o.raw());
-        unsafe {
-            try_call!(raw::git_remote_fetch(self.raw, &arr, raw.as_ref(), msg));
+        let span = CallSpan::fetch(self.url());
+        let rc = span.in_scope(|| unsafe {
+            call!(raw::git_remote_fetch(self.raw, &arr, raw.as_ref(), msg))
+        });
+        if let Err(e) = crate::call::c_try(rc) {
+            crate::panic::check();
+            return Err(context(e, "fetch", refspecs));
         }
+        let stats = self.stats();
+        span.record("total_objects", stats.total_objects() as u64);
+        span.record("received_objects", stats.received_objects() as u64);
+        span.record("received_bytes", stats.received_bytes() as u64);
         Ok(())
     }
 
@@ -355,7 +382,11 @@ impl<'repo> Remote<'repo> {
         let (_a, _b, arr) = crate::util::iter2cstrs(refspecs.iter())?;
         let raw = opts.map(|o| o.raw());
         unsafe {
-            try_call!(raw::git_remote_push(self.raw, &arr, raw.as_ref()));
+            let rc = call!(raw::git_remote_push(self.raw, &arr, raw.as_ref()));
+            if let Err(e) = crate::call::c_try(rc) {
+                crate::panic::check();
+                return Err(context(e, "push", refspecs));
+            }
         }
         Ok(())
     }
@@ -1,4 +1,5 @@
 use libc::{c_char, c_int, c_uint, c_void, size_t};
+use std::collections::HashMap;
 use std::env;
 use std::ffi::{CStr, CString, OsStr};
 use std::mem;
@@ -14,28 +15,52 @@ use crate::oid_array::OidArray;
 use crate::stash::{stash_cb, StashApplyOptions, StashCbData, StashSaveOptions};
 use crate::string_array::StringArray;
 use crate::tagforeach::{tag_foreach_cb, TagForeachCB, TagForeachData};
+use crate::tracing::CallSpan;
 use crate::util::{self, path_to_repo_path, Binding};
-use crate::worktree::{Worktree, WorktreeAddOptions};
+use crate::worktree::{Worktree, WorktreeAddOptions, WorktreeInfo};
 use crate::CherrypickOptions;
+use crate::CleanOptions;
+use crate::CommitGraphWriter;
+use crate::MergeBaseTree;
 use crate::RevertOptions;
 use crate::{mailmap::Mailmap, panic};
 use crate::{
-    raw, AttrCheckFlags, Buf, Error, Object, Remote, RepositoryOpenFlags, RepositoryState, Revspec,
-    StashFlags,
+    raw, AttrCheckFlags, AttrOptions, Buf, Error, ErrorCode, Object, Remote, RepositoryOpenFlags,
+    RepositoryState, Revspec, StashFlags,
 };
 use crate::{
-    AnnotatedCommit, MergeAnalysis, MergeOptions, MergePreference, SubmoduleIgnore,
-    SubmoduleStatus, SubmoduleUpdate,
+    AnnotatedCommit, MergeAnalysis, MergeOptions, MergePreference, MergeRenamePrediction,
+    SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdate,
+};
+use crate::{
+    ApplyLocation, ApplyOptions, Rebase, RebaseOptions, RebaseTodo, RebasedCommits, RerereStatus,
+    Sequencer,
+};
+use crate::{
+    Blame, BlameOptions, BlameProgress, Reference, References, ResetType, Signature, Submodule,
+};
+use crate::{
+    Blob, BlobWriter, Branch, BranchType, Branches, Bundle, Commit, Config, Index, Oid, Tree,
+};
+use crate::{
+    CommitRewrite, FetchHeadEntry, FileHistory, GcOptions, HistoryRewrite, NotesMergeOutcome,
+    NotesMergeStrategy, PickaxeMatch, PickaxeOptions, RefStats, SignatureVerification,
+    SignatureVerifier, SubmoduleAddOptions, SubmoduleStatusEntry, SubmoduleUpdateAllOptions,
+    SubmoduleUpdateResults, TagInfo, Time,
+};
+use crate::{
+    Describe, Direction, IntoCString, Reflog, ReflogExpireOptions, RepositoryInitMode, RevparseMode,
+};
+use crate::{DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, Packfile, TreeBuilder};
+use crate::{DiffLineType, IndexConflict, IndexEntry, MergeFileOptions, MergeFileResult, Patch};
+use crate::{
+    Note, Notes, ObjectType, RefUpdates, Revwalk, Sort, Status, StatusOptions, Statuses, Tag,
+    Transaction,
 };
-use crate::{ApplyLocation, ApplyOptions, Rebase, RebaseOptions};
-use crate::{Blame, BlameOptions, Reference, References, ResetType, Signature, Submodule};
-use crate::{Blob, BlobWriter, Branch, BranchType, Branches, Commit, Config, Index, Oid, Tree};
-use crate::{Describe, IntoCString, Reflog, RepositoryInitMode, RevparseMode};
-use crate::{DescribeOptions, Diff, DiffOptions, Odb, PackBuilder, TreeBuilder};
-use crate::{Note, Notes, ObjectType, Revwalk, Status, StatusOptions, Statuses, Tag, Transaction};
 
 type MergeheadForeachCb<'a> = dyn FnMut(&Oid) -> bool + 'a;
 type FetchheadForeachCb<'a> = dyn FnMut(&str, &[u8], &Oid, bool) -> bool + 'a;
+type AttrForeachCb<'a> = dyn FnMut(&str, Option<&[u8]>) -> bool + 'a;
 
 struct FetchheadForeachCbData<'a> {
     callback: &'a mut FetchheadForeachCb<'a>,
@@ -45,6 +70,10 @@ struct MergeheadForeachCbData<'a> {
     callback: &'a mut MergeheadForeachCb<'a>,
 }
 
+struct AttrForeachCbData<'a> {
+    callback: &'a mut AttrForeachCb<'a>,
+}
+
 extern "C" fn mergehead_foreach_cb(oid: *const raw::git_oid, payload: *mut c_void) -> c_int {
     panic::wrap(|| unsafe {
         let data = &mut *(payload as *mut MergeheadForeachCbData<'_>);
@@ -95,6 +124,37 @@ extern "C" fn fetchhead_foreach_cb(
     .unwrap_or(1)
 }
 
+extern "C" fn attr_foreach_cb(
+    name: *const c_char,
+    value: *const c_char,
+    payload: *mut c_void,
+) -> c_int {
+    panic::wrap(|| unsafe {
+        let data = &mut *(payload as *mut AttrForeachCbData<'_>);
+        let res = {
+            let callback = &mut data.callback;
+
+            assert!(!name.is_null());
+
+            let name = str::from_utf8(CStr::from_ptr(name).to_bytes()).unwrap();
+            let value = if value.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(value).to_bytes())
+            };
+
+            callback(name, value)
+        };
+
+        if res {
+            0
+        } else {
+            1
+        }
+    })
+    .unwrap_or(1)
+}
+
 /// An owned git repository, representing all state associated with the
 /// underlying filesystem.
 ///
@@ -130,10 +190,12 @@ impl Repository {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Repository, Error> {
         crate::init();
         // Normal file path OK (does not need Windows conversion).
-        let path = path.as_ref().into_c_string()?;
+        let path = path.as_ref();
+        let cpath = path.into_c_string()?;
         let mut ret = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_repository_open(&mut ret, path));
+            crate::call::c_try(raw::git_repository_open(&mut ret, cpath.as_ptr()))
+                .map_err(|e| e.with_operation("open repository").with_path(path))?;
             Ok(Binding::from_raw(ret))
         }
     }
@@ -392,10 +454,11 @@ impl Repository {
 
     /// Find a single object, as specified by a revision string.
     pub fn revparse_single(&self, spec: &str) -> Result<Object<'_>, Error> {
-        let spec = CString::new(spec)?;
+        let cspec = CString::new(spec)?;
         let mut obj = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_revparse_single(&mut obj, self.raw, spec));
+            crate::call::c_try(raw::git_revparse_single(&mut obj, self.raw, cspec.as_ptr()))
+                .map_err(|e| e.with_operation("parse revision").with_refspec(spec))?;
             assert!(!obj.is_null());
             Ok(Binding::from_raw(obj))
         }
@@ -528,6 +591,73 @@ impl Repository {
         Ok(())
     }
 
+    /// Add a submodule section to the `.gitmodules` file of the working
+    /// directory, with the given path and URL.
+    ///
+    /// The name and path must be relative paths which don't escape the
+    /// working directory, and the section must not exist yet. The file is
+    /// not staged. To set up the submodule itself, use `submodule`.
+    pub fn gitmodules_add(&self, name: &str, path: &str, url: &str) -> Result<(), Error> {
+        crate::submodule::gitmodules_add(self, name, path, url)
+    }
+
+    /// Remove the section of a submodule from the `.gitmodules` file of the
+    /// working directory, returning whether it existed.
+    pub fn gitmodules_remove(&self, name: &str) -> Result<bool, Error> {
+        crate::submodule::gitmodules_remove(self, name)
+    }
+
+    /// Enable per-worktree configuration, the `extensions.worktreeConfig`
+    /// setting, like `git worktree` does when it is first needed.
+    ///
+    /// This also sets `core.repositoryFormatVersion` to 1 as the extension
+    /// requires, and moves `core.worktree` and a true `core.bare`, which only
+    /// apply to the main worktree, to its `config.worktree` file. Repositories
+    /// have to be opened again to read the worktree configuration.
+    pub fn enable_worktree_config(&self) -> Result<(), Error> {
+        let mut common = Config::open(&self.commondir().join("config"))?;
+        let mut main = Config::open(&self.commondir().join("config.worktree"))?;
+        // The keys are moved while the common configuration is locked, so
+        // that no other process changes them in the meantime.
+        common.transaction(|common| {
+            for key in ["core.bare", "core.worktree"].iter() {
+                let value = match common.get_string(key) {
+                    Ok(value) => value,
+                    Err(e) if e.code() == ErrorCode::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                // `core.bare = false` applies to all the worktrees alike.
+                if *key == "core.bare" && !Config::parse_bool(&value[..])? {
+                    continue;
+                }
+                main.set_str(key, &value)?;
+                common.remove(key)?;
+            }
+            common.set_i32("core.repositoryformatversion", 1)?;
+            common.set_bool("extensions.worktreeconfig", true)
+        })
+    }
+
+    /// Open the `config.worktree` file of the worktree this repository was
+    /// opened from, holding its own configuration.
+    ///
+    /// Fails unless per-worktree configuration is enabled, see
+    /// `enable_worktree_config`.
+    pub fn worktree_config(&self) -> Result<Config, Error> {
+        let common = Config::open(&self.commondir().join("config"))?.snapshot()?;
+        if !common
+            .get_bool("extensions.worktreeconfig")
+            .unwrap_or(false)
+        {
+            return Err(Error::new(
+                crate::ErrorCode::Invalid,
+                crate::ErrorClass::Config,
+                "per-worktree configuration is not enabled",
+            ));
+        }
+        Config::open(&self.path().join("config.worktree"))
+    }
+
     /// Get the currently active namespace for this repository.
     ///
     /// If there is no namespace, or the namespace is not a valid utf8 string,
@@ -565,6 +695,84 @@ impl Repository {
         }
     }
 
+    /// The prefix of the references of the active namespace, such as
+    /// `refs/namespaces/foo/refs/namespaces/bar/` for the `foo/bar`
+    /// namespace, or `None` if there is no namespace.
+    fn namespace_prefix(&self) -> Option<String> {
+        let namespace = self.namespace()?;
+        let mut prefix = String::new();
+        for part in namespace.split('/').filter(|p| !p.is_empty()) {
+            prefix.push_str("refs/namespaces/");
+            prefix.push_str(part);
+            prefix.push('/');
+        }
+        Some(prefix)
+    }
+
+    /// Return the full name of a reference of the active namespace, such as
+    /// `refs/namespaces/foo/refs/heads/main` for `refs/heads/main` in the
+    /// `foo` namespace, like git does for `GIT_NAMESPACE`.
+    ///
+    /// Without a namespace, the name is returned unchanged.
+    pub fn namespaced_ref_name(&self, name: &str) -> String {
+        match self.namespace_prefix() {
+            Some(prefix) => format!("{}{}", prefix, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Return the name of a reference relative to the active namespace,
+    /// undoing `namespaced_ref_name`.
+    ///
+    /// Returns `None` if the reference is outside of the namespace. Without
+    /// a namespace, the name is returned unchanged.
+    pub fn strip_namespace<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match self.namespace_prefix() {
+            Some(prefix) => name.strip_prefix(prefix.as_str()),
+            None => Some(name),
+        }
+    }
+
+    /// Rewrite a refspec so that the references of this repository it
+    /// designates are in the active namespace.
+    ///
+    /// Those are the destination of a fetch refspec and the source of a push
+    /// refspec; the side naming the references of the other repository is
+    /// left alone. Force (`+`) and negative (`^`) refspecs are supported.
+    pub fn namespaced_refspec(&self, refspec: &str, direction: Direction) -> String {
+        if self.namespace_prefix().is_none() {
+            return refspec.to_string();
+        }
+        let (force, spec) = match refspec.strip_prefix('+') {
+            Some(spec) => ("+", spec),
+            None => ("", refspec),
+        };
+        if let Some(negative) = spec.strip_prefix('^') {
+            return match direction {
+                Direction::Fetch => refspec.to_string(),
+                Direction::Push => format!("{}^{}", force, self.namespaced_ref_name(negative)),
+            };
+        }
+        let (src, dst) = match spec.find(':') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+        let rewrite = |name: &str| {
+            if name.is_empty() {
+                String::new()
+            } else {
+                self.namespaced_ref_name(name)
+            }
+        };
+        match (direction, dst) {
+            (Direction::Fetch, Some(dst)) => format!("{}{}:{}", force, src, rewrite(dst)),
+            (Direction::Fetch, None) => refspec.to_string(),
+            (Direction::Push, Some(dst)) => format!("{}{}:{}", force, rewrite(src), dst),
+            // `git push <name>` pushes the reference to the same name.
+            (Direction::Push, None) => format!("{}{}:{}", force, rewrite(src), src),
+        }
+    }
+
     /// Retrieves the Git merge message.
     /// Remember to remove the message when finished.
     pub fn message(&self) -> Result<String, Error> {
@@ -929,6 +1137,23 @@ impl Repository {
         }
     }
 
+    /// List a page of reference names, in sorted order.
+    ///
+    /// Only the names matching `glob`, if any, and sorting after
+    /// `start_after` are considered, and at most `limit` of them are
+    /// returned. Passing the last name of a page as `start_after` returns
+    /// the next page. The glob is applied by the reference database, which
+    /// only reads the loose references under its leading directory, and
+    /// the page is selected without keeping every name in memory.
+    pub fn reference_names_page(
+        &self,
+        glob: Option<&str>,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, Error> {
+        crate::reference::names_page(self, glob, start_after, limit)
+    }
+
     /// Load all submodules for this repository and return them.
     pub fn submodules(&self) -> Result<Vec<Submodule<'_>>, Error> {
         struct Data<'a, 'b> {
@@ -974,18 +1199,200 @@ impl Repository {
     /// status, then the results from rename detection (if you enable it) may
     /// not be accurate. To do rename detection properly, this must be called
     /// with no pathspec so that all files can be considered.
+    ///
+    /// libgit2 does not implement git's untracked cache (the `UNTR` index
+    /// extension), so untracked directories are scanned on every call and the
+    /// extension is dropped whenever the index is written. When polling status
+    /// frequently, leave `recurse_untracked_dirs` disabled, use
+    /// `include_untracked(false)` where untracked files are not needed, or
+    /// restrict the scan with pathspecs.
     pub fn statuses(&self, options: Option<&mut StatusOptions>) -> Result<Statuses<'_>, Error> {
+        let baseline = match options.as_ref().and_then(|s| s.baseline_id()) {
+            Some(id) => Some(self.find_tree(id)?),
+            None => None,
+        };
+
+        // With a file system monitor only the reported paths are checked, so
+        // add every path staged relative to the baseline to still report them.
+        let mut staged = Vec::new();
+        if options.as_ref().map(|s| s.uses_fsmonitor()) == Some(true) {
+            let head = match baseline {
+                Some(_) => None,
+                None => match self.head() {
+                    Ok(head) => Some(head.peel_to_tree()?),
+                    Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+                    Err(e) => return Err(e),
+                },
+            };
+            let tree = baseline.as_ref().or(head.as_ref());
+            let diff = self.diff_tree_to_index(tree, None, None)?;
+            for delta in diff.deltas() {
+                for file in [delta.old_file(), delta.new_file()].iter() {
+                    if let Some(path) = file.path_bytes() {
+                        staged.push(CString::new(path)?);
+                    }
+                }
+            }
+        }
+
         let mut ret = ptr::null_mut();
         unsafe {
             try_call!(raw::git_status_list_new(
                 &mut ret,
                 self.raw,
-                options.map(|s| s.raw()).unwrap_or(ptr::null())
+                options
+                    .map(|s| s.raw_with(baseline.as_ref(), &staged))
+                    .unwrap_or(ptr::null())
             ));
             Ok(Binding::from_raw(ret))
         }
     }
 
+    /// Gather file status information like `statuses`, splitting the work
+    /// across `threads` worker threads.
+    ///
+    /// The top-level entries of the working directory and of the index are
+    /// divided among the workers, each of which opens its own handle to the
+    /// repository from its working directory and computes the status of its
+    /// share. The results are merged and returned sorted by path, which can be
+    /// much faster than `statuses` for large working trees.
+    ///
+    /// Since each worker only sees part of the tree, renames between different
+    /// top-level entries are not detected. The workers' handles only see what
+    /// is on disk: configuration set in memory on this handle is not used, and
+    /// if the handle opened from the working directory is not the same
+    /// repository or worktree as this one, or its object database has a
+    /// different set of backends, the status is computed on the current thread
+    /// instead. The same happens if `options` contains pathspecs or file
+    /// system monitor paths, `threads` is less than 2, or the repository is
+    /// bare.
+    pub fn statuses_parallel(
+        &self,
+        options: Option<&mut StatusOptions>,
+        threads: usize,
+    ) -> Result<Vec<(PathBuf, Status)>, Error> {
+        fn collect(statuses: &Statuses<'_>) -> Vec<(Vec<u8>, Status)> {
+            statuses
+                .iter()
+                .map(|e| (e.path_bytes().to_vec(), e.status()))
+                .collect()
+        }
+        fn into_paths(statuses: Vec<(Vec<u8>, Status)>) -> Vec<(PathBuf, Status)> {
+            statuses
+                .into_iter()
+                .map(|(path, status)| (util::bytes2path(&path).to_path_buf(), status))
+                .collect()
+        }
+
+        let settings = match options {
+            Some(ref o) if o.has_pathspecs() || o.uses_fsmonitor() => None,
+            Some(ref o) => Some(o.settings()),
+            None => Some(StatusOptions::new().settings()),
+        };
+        let (workdir, settings) = match (self.workdir(), settings) {
+            (Some(workdir), Some(settings)) if threads > 1 => (workdir, settings),
+            _ => return Ok(into_paths(collect(&self.statuses(options)?))),
+        };
+
+        let path = match self.worker_path()? {
+            Some(path) => path,
+            None => return Ok(into_paths(collect(&self.statuses(options)?))),
+        };
+
+        // Split the tree on its top-level entries, taking them from both the
+        // working directory and the index so deleted files are covered too.
+        let mut names = Vec::new();
+        let entries = std::fs::read_dir(workdir).map_err(Error::from)?;
+        for entry in entries {
+            let name = entry.map_err(Error::from)?;
+            let name = name.file_name().into_c_string()?.into_bytes();
+            if name != b".git" {
+                names.push(name);
+            }
+        }
+        for entry in self.index()?.iter() {
+            let end = entry.path.iter().position(|b| *b == b'/');
+            names.push(entry.path[..end.unwrap_or(entry.path.len())].to_vec());
+        }
+        names.sort();
+        names.dedup();
+
+        let mut chunks = vec![Vec::new(); threads.min(names.len()).max(1)];
+        let len = chunks.len();
+        for (i, name) in names.into_iter().enumerate() {
+            chunks[i % len].push(name);
+        }
+
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| {
+                let path = path.clone();
+                std::thread::spawn(move || -> Result<Vec<(Vec<u8>, Status)>, Error> {
+                    if chunk.is_empty() {
+                        return Ok(Vec::new());
+                    }
+                    let repo = Repository::open_worker(&path)?;
+                    let mut opts = StatusOptions::from_settings(settings);
+                    opts.disable_pathspec_match(true);
+                    for name in chunk {
+                        opts.pathspec(name);
+                    }
+                    let statuses = repo.statuses(Some(&mut opts))?;
+                    Ok(collect(&statuses))
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut ret = Vec::new();
+        for handle in handles {
+            match handle.join() {
+                Ok(statuses) => ret.extend(statuses?),
+                Err(e) => std::panic::resume_unwind(e),
+            }
+        }
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(into_paths(ret))
+    }
+
+    /// The path from which worker threads can open their own handle to this
+    /// repository with `open_worker`, or `None` if such a handle would not
+    /// match this one.
+    ///
+    /// Handles are opened from the working directory, or from the repository
+    /// path if it is bare. They do not match if that opens another repository
+    /// or worktree, or if backends were added to the object database of this
+    /// handle at runtime.
+    pub(crate) fn worker_path(&self) -> Result<Option<PathBuf>, Error> {
+        let path = self.workdir().unwrap_or_else(|| self.path());
+        let probe = match Repository::open_worker(path) {
+            Ok(probe) => probe,
+            Err(_) => return Ok(None),
+        };
+        let num_backends = |repo: &Repository| -> Result<size_t, Error> {
+            Ok(unsafe { raw::git_odb_num_backends(repo.odb()?.raw()) })
+        };
+        if probe.path() != self.path() || num_backends(&probe)? != num_backends(self)? {
+            return Ok(None);
+        }
+        Ok(Some(path.to_path_buf()))
+    }
+
+    /// Open a handle for a worker thread from a path given by `worker_path`.
+    pub(crate) fn open_worker(path: &Path) -> Result<Repository, Error> {
+        Repository::open_ext(path, RepositoryOpenFlags::NO_SEARCH, &[] as &[&OsStr])
+    }
+
+    /// Remove untracked files from the working directory, like `git clean`.
+    ///
+    /// Returns the paths, relative to the working directory, that were removed
+    /// or, in dry-run mode, would be removed. Untracked directories that are
+    /// removed as a whole are reported instead of their contents. Nested
+    /// repositories are left alone unless `CleanOptions::nested_repositories`
+    /// is set.
+    pub fn clean(&self, opts: &CleanOptions) -> Result<Vec<PathBuf>, Error> {
+        crate::clean::clean(self, opts)
+    }
+
     /// Test if the ignore rules apply to a given file.
     ///
     /// This function checks the ignore rules to see if they would apply to the
@@ -1125,6 +1532,174 @@ impl Repository {
         }
     }
 
+    /// Get the values of several git attributes for a path at once.
+    ///
+    /// The returned vector holds one value per name, in the same order, with
+    /// `None` for attributes that are not specified for the path. As with
+    /// `get_attr_bytes`, use
+    /// [`AttrValue::from_bytes`](crate::AttrValue::from_bytes) to interpret
+    /// the values.
+    pub fn get_attr_many(
+        &self,
+        path: &Path,
+        names: &[&str],
+        flags: AttrCheckFlags,
+    ) -> Result<Vec<Option<&[u8]>>, Error> {
+        let path = util::cstring_to_repo_path(path)?;
+        let names = names
+            .iter()
+            .map(|name| CString::new(*name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut name_ptrs = names.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+        let mut values = vec![ptr::null(); names.len()];
+        unsafe {
+            try_call!(raw::git_attr_get_many(
+                values.as_mut_ptr(),
+                self.raw(),
+                flags.bits(),
+                path,
+                name_ptrs.len(),
+                name_ptrs.as_mut_ptr()
+            ));
+            Ok(values
+                .into_iter()
+                .map(|value| crate::opt_bytes(self, value))
+                .collect())
+        }
+    }
+
+    /// Invoke `callback` for each git attribute defined on a path.
+    ///
+    /// The callback receives the name of the attribute and its value, if any.
+    /// Return `true` to continue iterating or `false` to stop.
+    pub fn attr_foreach<C>(
+        &self,
+        path: &Path,
+        flags: AttrCheckFlags,
+        mut callback: C,
+    ) -> Result<(), Error>
+    where
+        C: FnMut(&str, Option<&[u8]>) -> bool,
+    {
+        let path = util::cstring_to_repo_path(path)?;
+        unsafe {
+            let mut data = AttrForeachCbData {
+                callback: &mut callback,
+            };
+            let cb: raw::git_attr_foreach_cb = Some(attr_foreach_cb);
+            try_call!(raw::git_attr_foreach(
+                self.raw(),
+                flags.bits(),
+                path,
+                cb,
+                &mut data as *mut _ as *mut _
+            ));
+            Ok(())
+        }
+    }
+
+    /// Get the value of a git attribute for a path as a byte slice, looking
+    /// up attributes according to `opts`.
+    ///
+    /// This is like `get_attr_bytes`, but also allows attributes to be read
+    /// from the tree of a given commit with `AttrOptions::commit`, or from a
+    /// given tree with `AttrOptions::tree`.
+    pub fn get_attr_ext(
+        &self,
+        path: &Path,
+        name: &str,
+        opts: &mut AttrOptions,
+    ) -> Result<Option<&[u8]>, Error> {
+        let mut ret = ptr::null();
+        let path = util::cstring_to_repo_path(path)?;
+        let name = CString::new(name)?;
+        unsafe {
+            try_call!(raw::git_attr_get_ext(
+                &mut ret,
+                self.raw(),
+                opts.raw(self)?,
+                path,
+                name
+            ));
+            Ok(crate::opt_bytes(self, ret))
+        }
+    }
+
+    /// Get the values of several git attributes for a path at once, looking
+    /// up attributes according to `opts`.
+    ///
+    /// See `get_attr_many` and `get_attr_ext`.
+    pub fn get_attr_many_ext(
+        &self,
+        path: &Path,
+        names: &[&str],
+        opts: &mut AttrOptions,
+    ) -> Result<Vec<Option<&[u8]>>, Error> {
+        let path = util::cstring_to_repo_path(path)?;
+        let names = names
+            .iter()
+            .map(|name| CString::new(*name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut name_ptrs = names.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+        let mut values = vec![ptr::null(); names.len()];
+        unsafe {
+            try_call!(raw::git_attr_get_many_ext(
+                values.as_mut_ptr(),
+                self.raw(),
+                opts.raw(self)?,
+                path,
+                name_ptrs.len(),
+                name_ptrs.as_mut_ptr()
+            ));
+            Ok(values
+                .into_iter()
+                .map(|value| crate::opt_bytes(self, value))
+                .collect())
+        }
+    }
+
+    /// Invoke `callback` for each git attribute defined on a path, looking up
+    /// attributes according to `opts`.
+    ///
+    /// See `attr_foreach` and `get_attr_ext`.
+    pub fn attr_foreach_ext<C>(
+        &self,
+        path: &Path,
+        opts: &mut AttrOptions,
+        mut callback: C,
+    ) -> Result<(), Error>
+    where
+        C: FnMut(&str, Option<&[u8]>) -> bool,
+    {
+        let path = util::cstring_to_repo_path(path)?;
+        unsafe {
+            let mut data = AttrForeachCbData {
+                callback: &mut callback,
+            };
+            let cb: raw::git_attr_foreach_cb = Some(attr_foreach_cb);
+            try_call!(raw::git_attr_foreach_ext(
+                self.raw(),
+                opts.raw(self)?,
+                path,
+                cb,
+                &mut data as *mut _ as *mut _
+            ));
+            Ok(())
+        }
+    }
+
+    /// Flush the cache of ignore and attribute files.
+    ///
+    /// `.gitignore` and `.gitattributes` files are cached after they are
+    /// first read, so edits made while the repository is open may not be
+    /// seen until the cache is flushed.
+    pub fn flush_attr_cache(&self) -> Result<(), Error> {
+        unsafe {
+            try_call!(raw::git_attr_cache_flush(self.raw));
+        }
+        Ok(())
+    }
+
     /// Write an in-memory buffer to the ODB as a blob.
     ///
     /// The Oid returned can in turn be passed to `find_blob` to get a handle to
@@ -1194,7 +1769,8 @@ impl Repository {
     pub fn find_blob(&self, oid: Oid) -> Result<Blob<'_>, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_blob_lookup(&mut raw, self.raw(), oid.raw()));
+            crate::call::c_try(raw::git_blob_lookup(&mut raw, self.raw(), oid.raw()))
+                .map_err(|e| e.with_operation("find blob").with_oid(oid))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -1208,6 +1784,96 @@ impl Repository {
         }
     }
 
+    /// List the packfiles in the `objects/pack` directory of this
+    /// repository, sorted by name.
+    ///
+    /// Packfiles whose index has not been written yet, such as those of an
+    /// ongoing fetch, are skipped. Together with `Odb::write_multi_pack_index`
+    /// this can be used to decide when to repack or write a multi-pack-index.
+    pub fn packfiles(&self) -> Result<Vec<Packfile>, Error> {
+        crate::odb::packfiles(&self.commondir().join("objects").join("pack"))
+    }
+
+    /// Write the commit-graph of this repository, covering all the commits
+    /// reachable from its references and `HEAD`.
+    ///
+    /// Once written, revision walks, merge-base computations and
+    /// ahead/behind counts read commits from the graph and use its
+    /// generation numbers, which is much faster on large histories. Commits
+    /// created afterwards are parsed as usual until the graph is written
+    /// again; libgit2 always rewrites the whole graph.
+    pub fn write_commit_graph(&self) -> Result<(), Error> {
+        let mut walk = self.revwalk()?;
+        walk.push_glob("*")?;
+        match walk.push_head() {
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {}
+            res => res?,
+        }
+        let info = self.commondir().join("objects").join("info");
+        let mut writer = CommitGraphWriter::new(info)?;
+        writer.add_revwalk(&mut walk)?;
+        writer.commit()
+    }
+
+    /// Delete the loose objects that are unreachable and older than `expire`.
+    ///
+    /// Like `git prune`, objects are kept when they are reachable from a
+    /// reference, a reflog entry, `HEAD` or the index, including those of
+    /// the worktrees of the repository. Objects in packfiles are never
+    /// deleted. Loose objects newer than `expire` are kept as well, since
+    /// another process may be about to reference them; `git gc` uses an
+    /// expiry of two weeks ago by default.
+    ///
+    /// Returns the ids of the pruned objects, sorted. If `dry_run` is true
+    /// the objects are only listed and not deleted.
+    pub fn prune(&self, expire: Time, dry_run: bool) -> Result<Vec<Oid>, Error> {
+        crate::gc::prune(self, expire, dry_run)
+    }
+
+    /// Count the loose and packed references of the repository.
+    pub fn ref_stats(&self) -> Result<RefStats, Error> {
+        crate::gc::ref_stats(self)
+    }
+
+    /// Clean up and optimize the repository, like `git gc`.
+    ///
+    /// Depending on `opts`, old reflog entries are expired, loose references
+    /// are packed, all reachable objects are packed into a single packfile,
+    /// unreachable loose objects are pruned and the metadata of deleted
+    /// worktrees is removed. See `GcOptions` for the defaults.
+    ///
+    /// No other process should write to the repository during the
+    /// collection, as objects it writes may be deleted. Handles to the
+    /// repository that are already open, including this one, may keep
+    /// seeing pruned objects through their caches and mapped packfiles;
+    /// reopen the repository to get a fresh view.
+    pub fn gc(&self, opts: Option<&mut GcOptions<'_>>) -> Result<(), Error> {
+        match opts {
+            Some(opts) => crate::gc::gc(self, opts),
+            None => crate::gc::gc(self, &mut GcOptions::new()),
+        }
+    }
+
+    /// Fetch the objects of a bundle into the repository, like
+    /// `git bundle unbundle`.
+    ///
+    /// The prerequisites of the bundle must be present in the repository.
+    /// No references are created, the returned `Bundle` lists the references
+    /// of the bundle and `Bundle::update_refs` can be used to store them.
+    /// Bundles are written with `BundleBuilder`.
+    pub fn unbundle<R: std::io::Read>(&self, reader: R) -> Result<Bundle, Error> {
+        crate::bundle::unbundle(self, reader)
+    }
+
+    /// Move all loose references into the `packed-refs` file, like
+    /// `git pack-refs --all`.
+    ///
+    /// `ref_stats` tells how many loose references there are, to decide
+    /// when packing is worth it.
+    pub fn pack_refs(&self) -> Result<(), Error> {
+        crate::gc::pack_refs(self)
+    }
+
     /// Override the object database for this repository
     pub fn set_odb(&self, odb: &Odb<'_>) -> Result<(), Error> {
         unsafe {
@@ -1396,35 +2062,144 @@ impl Repository {
         }
     }
 
-    /// Extract the signature from a commit
+    /// Create a signed commit and update a reference to point to it, in one
+    /// call.
     ///
-    /// Returns a tuple containing the signature in the first value and the
-    /// signed data in the second.
-    pub fn extract_signature(
+    /// The commit is built as with `commit_create_buffer`, and `sign` is
+    /// called with its contents to produce the signature, which is stored
+    /// in the `gpgsig` header. If `update_ref` is given, the reference is
+    /// then updated, following symbolic references such as `HEAD`, with a
+    /// `commit: <summary>` reflog entry. As with `commit`, the reference
+    /// must currently point to the first of `parents`, or not exist for a
+    /// root commit.
+    ///
+    /// Returns the id of the signed commit.
+    #[allow(clippy::too_many_arguments)]
+    pub fn commit_signed_create<F>(
         &self,
-        commit_id: &Oid,
-        signature_field: Option<&str>,
-    ) -> Result<(Buf, Buf), Error> {
-        let signature_field = crate::opt_cstr(signature_field)?;
-        let signature = Buf::new();
-        let content = Buf::new();
-        unsafe {
-            try_call!(raw::git_commit_extract_signature(
-                signature.raw(),
-                content.raw(),
-                self.raw(),
-                commit_id.raw() as *mut _,
-                signature_field
-            ));
-            Ok((signature, content))
-        }
-    }
-
-    /// Lookup a reference to one of the commits in a repository.
-    pub fn find_commit(&self, oid: Oid) -> Result<Commit<'_>, Error> {
-        let mut raw = ptr::null_mut();
+        update_ref: Option<&str>,
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        message: &str,
+        tree: &Tree<'_>,
+        parents: &[&Commit<'_>],
+        sign: F,
+    ) -> Result<Oid, Error>
+    where
+        F: FnOnce(&str) -> Result<String, Error>,
+    {
+        let buf = self.commit_create_buffer(author, committer, message, tree, parents)?;
+        let content = buf
+            .as_str()
+            .ok_or_else(|| Error::from_str("commit contents are not valid utf-8"))?;
+        let signature = sign(content)?;
+        let id = self.commit_signed(content, &signature, None)?;
+        let update_ref = match update_ref {
+            Some(update_ref) => update_ref,
+            None => return Ok(id),
+        };
+
+        let mut name = update_ref.to_string();
+        for _ in 0..5 {
+            match self.find_reference(&name) {
+                Ok(reference) => match reference.symbolic_target() {
+                    Some(target) => name = target.to_string(),
+                    None => break,
+                },
+                Err(e) if e.code() == ErrorCode::NotFound => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let summary = message.lines().next().unwrap_or("");
+        match parents.first() {
+            Some(parent) => {
+                let log_message = format!("commit: {}", summary);
+                self.reference_matching(&name, id, true, parent.id(), &log_message)?;
+            }
+            None => {
+                let log_message = format!("commit (initial): {}", summary);
+                self.reference(&name, id, false, &log_message)?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Rewrite the commits of `refname` since `base`, or all of them if
+    /// `base` is `None`, as described by `rewrite`, and update the
+    /// reference, following symbolic references such as `HEAD`.
+    ///
+    /// The commits in the range must form a linear history. Returns the
+    /// original and new id of each commit of the range, oldest first.
+    /// Commits which didn't need rewriting map to themselves.
+    pub fn rewrite_commits(
+        &self,
+        refname: &str,
+        base: Option<Oid>,
+        rewrite: &CommitRewrite<'_>,
+    ) -> Result<Vec<(Oid, Oid)>, Error> {
+        crate::rewrite::rewrite_commits(self, refname, base, rewrite)
+    }
+
+    /// Rewrite the whole history of the repository as described by
+    /// `rewrite`, and update the references to the new commits and tags.
+    ///
+    /// Returns the new id of every rewritten commit, keyed by its original
+    /// id. A pruned commit maps to the commit replacing it, and is missing
+    /// if there is none, in which case the references to it are deleted.
+    pub fn rewrite_history(
+        &self,
+        rewrite: &mut HistoryRewrite<'_>,
+    ) -> Result<HashMap<Oid, Oid>, Error> {
+        crate::rewrite::rewrite_history(self, rewrite)
+    }
+
+    /// Extract the signature from a commit
+    ///
+    /// Returns a tuple containing the signature in the first value and the
+    /// signed data in the second.
+    pub fn extract_signature(
+        &self,
+        commit_id: &Oid,
+        signature_field: Option<&str>,
+    ) -> Result<(Buf, Buf), Error> {
+        let signature_field = crate::opt_cstr(signature_field)?;
+        let signature = Buf::new();
+        let content = Buf::new();
         unsafe {
-            try_call!(raw::git_commit_lookup(&mut raw, self.raw(), oid.raw()));
+            try_call!(raw::git_commit_extract_signature(
+                signature.raw(),
+                content.raw(),
+                self.raw(),
+                commit_id.raw() as *mut _,
+                signature_field
+            ));
+            Ok((signature, content))
+        }
+    }
+
+    /// Verify the signature of a commit with `verifier`, like
+    /// `git verify-commit`.
+    ///
+    /// The signature and the signed data are extracted from the commit and
+    /// passed to `verifier`. An unsigned commit is reported with
+    /// `SignatureVerdict::Unsigned`.
+    ///
+    /// Verifiers running `gpg` and `ssh-keygen` are available with the
+    /// `signature-commands` feature.
+    pub fn verify_commit_signature(
+        &self,
+        id: Oid,
+        verifier: &dyn SignatureVerifier,
+    ) -> Result<SignatureVerification, Error> {
+        crate::verify::verify_commit(self, id, verifier)
+    }
+
+    /// Lookup a reference to one of the commits in a repository.
+    pub fn find_commit(&self, oid: Oid) -> Result<Commit<'_>, Error> {
+        let mut raw = ptr::null_mut();
+        unsafe {
+            crate::call::c_try(raw::git_commit_lookup(&mut raw, self.raw(), oid.raw()))
+                .map_err(|e| e.with_operation("find commit").with_oid(oid))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -1460,12 +2235,13 @@ impl Repository {
     pub fn find_object(&self, oid: Oid, kind: Option<ObjectType>) -> Result<Object<'_>, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_object_lookup(
+            crate::call::c_try(call!(raw::git_object_lookup(
                 &mut raw,
                 self.raw(),
                 oid.raw(),
                 kind
-            ));
+            )))
+            .map_err(|e| e.with_operation("find object").with_oid(oid))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -1652,10 +2428,15 @@ impl Repository {
 
     /// Lookup a reference to one of the objects in a repository.
     pub fn find_reference(&self, name: &str) -> Result<Reference<'_>, Error> {
-        let name = CString::new(name)?;
+        let cname = CString::new(name)?;
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_reference_lookup(&mut raw, self.raw(), name));
+            crate::call::c_try(raw::git_reference_lookup(
+                &mut raw,
+                self.raw(),
+                cname.as_ptr(),
+            ))
+            .map_err(|e| e.with_operation("find reference").with_refspec(name))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -1775,6 +2556,53 @@ impl Repository {
         }
     }
 
+    /// Add a submodule, like `git submodule add`.
+    ///
+    /// This sets up the submodule with `submodule`, clones it, checks out
+    /// the configured branch, and adds it along with `.gitmodules` to the
+    /// index, ready to be committed. If any step fails, the `.gitmodules`
+    /// entry and the directories created for the submodule are removed.
+    pub fn submodule_add(
+        &self,
+        url: &str,
+        path: &Path,
+        opts: Option<&mut SubmoduleAddOptions<'_>>,
+    ) -> Result<Submodule<'_>, Error> {
+        match opts {
+            Some(opts) => crate::submodule::add(self, url, path, opts),
+            None => crate::submodule::add(self, url, path, &mut SubmoduleAddOptions::new()),
+        }
+    }
+
+    /// Move the git directories of submodules into `.git/modules` of this
+    /// repository, replacing them with gitlinks, like
+    /// `git submodule absorbgitdirs`.
+    ///
+    /// Only the submodule `name` is absorbed if given, and all of them
+    /// otherwise, along with their own submodules. Submodules which are not
+    /// checked out or already use a gitlink are left alone. Returns the
+    /// number of git directories moved.
+    pub fn absorb_submodule_git_dirs(&self, name: Option<&str>) -> Result<usize, Error> {
+        crate::submodule::absorb_git_dirs(self, name)
+    }
+
+    /// Initialize and update all the submodules, like
+    /// `git submodule update --init --recursive --jobs <n>`.
+    ///
+    /// Independent submodules are updated in parallel according to
+    /// `opts`, and nested submodules once their parent has been updated.
+    /// A failure to update a submodule doesn't stop the others: the result
+    /// of each submodule is returned along with its path, sorted by path.
+    pub fn update_submodules_recursive(
+        &self,
+        opts: Option<&mut SubmoduleUpdateAllOptions<'_>>,
+    ) -> Result<SubmoduleUpdateResults, Error> {
+        match opts {
+            Some(opts) => crate::submodule::update_all(self, opts),
+            None => crate::submodule::update_all(self, &mut SubmoduleUpdateAllOptions::new()),
+        }
+    }
+
     /// Lookup submodule information by name or path.
     ///
     /// Given either the submodule name or path (they are usually the same),
@@ -1805,6 +2633,21 @@ impl Repository {
         Ok(SubmoduleStatus::from_bits_truncate(ret as u32))
     }
 
+    /// Get the status of all the submodules at once, along with the ids they
+    /// record in `HEAD`, the index and their working directory, and their
+    /// configured branch.
+    ///
+    /// The index, `HEAD` and `.gitmodules` of the repository are only read
+    /// once for all the submodules. Submodule repositories are still opened
+    /// to check their working directory, unless `ignore` is
+    /// `SubmoduleIgnore::Dirty` or `SubmoduleIgnore::All`.
+    pub fn submodule_statuses(
+        &self,
+        ignore: SubmoduleIgnore,
+    ) -> Result<Vec<SubmoduleStatusEntry>, Error> {
+        crate::submodule::statuses(self, ignore)
+    }
+
     /// Set the ignore rule for the submodule in the configuration
     ///
     /// This does not affect any currently-loaded instances.
@@ -1865,7 +2708,8 @@ impl Repository {
     pub fn find_tree(&self, oid: Oid) -> Result<Tree<'_>, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_tree_lookup(&mut raw, self.raw(), oid.raw()));
+            crate::call::c_try(raw::git_tree_lookup(&mut raw, self.raw(), oid.raw()))
+                .map_err(|e| e.with_operation("find tree").with_oid(oid))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -1988,7 +2832,8 @@ impl Repository {
     pub fn find_tag(&self, id: Oid) -> Result<Tag<'_>, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
-            try_call!(raw::git_tag_lookup(&mut raw, self.raw, id.raw()));
+            crate::call::c_try(raw::git_tag_lookup(&mut raw, self.raw, id.raw()))
+                .map_err(|e| e.with_operation("find tag").with_oid(id))?;
             Ok(Binding::from_raw(raw))
         }
     }
@@ -2041,6 +2886,20 @@ impl Repository {
         }
     }
 
+    /// List the tags of the repository matching `pattern`, along with the
+    /// object each of them finally points to, sorted by name. The tagger of
+    /// an annotated tag is read with `TagInfo::tagger`.
+    ///
+    /// `pattern` is matched against the names of the tags as with
+    /// `tag_names`. This takes a single pass over the references. Tags in
+    /// `packed-refs` are listed from the peeled values it records, and
+    /// otherwise only the headers of the objects pointed to by the tags and
+    /// the tag objects of annotated tags are read. Tags of tags are peeled
+    /// all the way down in both cases.
+    pub fn tags_detailed(&self, pattern: Option<&str>) -> Result<Vec<TagInfo>, Error> {
+        crate::tag::list_detailed(self, pattern)
+    }
+
     /// iterate over all tags calling `cb` on each.
     /// the callback is provided the tag id and name
     pub fn tag_foreach<T>(&self, cb: T) -> Result<(), Error>
@@ -2063,20 +2922,24 @@ impl Repository {
 
     /// Updates files in the index and the working tree to match the content of
     /// the commit pointed at by HEAD.
-    pub fn checkout_head(&self, opts: Option<&mut CheckoutBuilder<'_>>) -> Result<(), Error> {
-        unsafe {
+    pub fn checkout_head(&self, mut opts: Option<&mut CheckoutBuilder<'_>>) -> Result<(), Error> {
+        CallSpan::checkout(self, "head").in_scope(|| unsafe {
             let mut raw_opts = mem::zeroed();
             try_call!(raw::git_checkout_init_options(
                 &mut raw_opts,
                 raw::GIT_CHECKOUT_OPTIONS_VERSION
             ));
-            if let Some(c) = opts {
+            if let Some(c) = opts.as_deref_mut() {
                 c.configure(&mut raw_opts);
             }
 
             try_call!(raw::git_checkout_head(self.raw, &raw_opts));
-        }
-        Ok(())
+            if let Some(c) = opts.filter(|c| c.previewing()) {
+                let target = crate::build::tree_paths(&self.head()?.peel_to_tree()?)?;
+                c.preview_removals(self, &target)?;
+            }
+            Ok(())
+        })
     }
 
     /// Updates files in the working tree to match the content of the index.
@@ -2085,25 +2948,32 @@ impl Repository {
     pub fn checkout_index(
         &self,
         index: Option<&mut Index>,
-        opts: Option<&mut CheckoutBuilder<'_>>,
+        mut opts: Option<&mut CheckoutBuilder<'_>>,
     ) -> Result<(), Error> {
-        unsafe {
+        CallSpan::checkout(self, "index").in_scope(|| unsafe {
             let mut raw_opts = mem::zeroed();
             try_call!(raw::git_checkout_init_options(
                 &mut raw_opts,
                 raw::GIT_CHECKOUT_OPTIONS_VERSION
             ));
-            if let Some(c) = opts {
+            if let Some(c) = opts.as_deref_mut() {
                 c.configure(&mut raw_opts);
             }
 
             try_call!(raw::git_checkout_index(
                 self.raw,
-                index.map(|i| &mut *i.raw()),
+                index.as_deref().map(|i| &mut *i.raw()),
                 &raw_opts
             ));
-        }
-        Ok(())
+            if let Some(c) = opts.filter(|c| c.previewing()) {
+                let target = match index {
+                    Some(index) => crate::build::index_paths(index),
+                    None => crate::build::index_paths(&self.index()?),
+                };
+                c.preview_removals(self, &target)?;
+            }
+            Ok(())
+        })
     }
 
     /// Updates files in the index and working tree to match the content of the
@@ -2111,21 +2981,25 @@ impl Repository {
     pub fn checkout_tree(
         &self,
         treeish: &Object<'_>,
-        opts: Option<&mut CheckoutBuilder<'_>>,
+        mut opts: Option<&mut CheckoutBuilder<'_>>,
     ) -> Result<(), Error> {
-        unsafe {
+        CallSpan::checkout(self, "tree").in_scope(|| unsafe {
             let mut raw_opts = mem::zeroed();
             try_call!(raw::git_checkout_init_options(
                 &mut raw_opts,
                 raw::GIT_CHECKOUT_OPTIONS_VERSION
             ));
-            if let Some(c) = opts {
+            if let Some(c) = opts.as_deref_mut() {
                 c.configure(&mut raw_opts);
             }
 
             try_call!(raw::git_checkout_tree(self.raw, &*treeish.raw(), &raw_opts));
-        }
-        Ok(())
+            if let Some(c) = opts.filter(|c| c.previewing()) {
+                let target = crate::build::tree_paths(&treeish.peel_to_tree()?)?;
+                c.preview_removals(self, &target)?;
+            }
+            Ok(())
+        })
     }
 
     /// Merges the given commit(s) into HEAD, writing the results into the
@@ -2136,6 +3010,10 @@ impl Repository {
     /// For compatibility with git, the repository is put into a merging state.
     /// Once the commit is done (or if the user wishes to abort), you should
     /// clear this state by calling cleanup_state().
+    ///
+    /// When rerere is enabled, which `rerere` describes, the conflicts are
+    /// recorded and previously recorded resolutions are replayed. Errors of rerere are ignored, since the merge itself succeeded;
+    /// call `rerere` again to see them.
     pub fn merge(
         &self,
         annotated_commits: &[&AnnotatedCommit<'_>],
@@ -2165,6 +3043,11 @@ impl Repository {
                 &raw_checkout_opts
             ));
         }
+        // The merge is done at this point, so a failure of rerere must not
+        // make it look like the merge failed.
+        if crate::rerere::enabled(self).unwrap_or(false) {
+            let _ = crate::rerere::rerere(self);
+        }
         Ok(())
     }
 
@@ -2216,6 +3099,155 @@ impl Repository {
         }
     }
 
+    /// Predict how rename detection behaves when merging the given trees
+    /// with `merge_trees` and the given options.
+    ///
+    /// libgit2 silently skips inexact rename detection when a merge changes
+    /// more paths than the target limit and does not report it afterwards.
+    /// This diffs both sides against the ancestor and applies the same
+    /// limits, without merging, so `MergeRenamePrediction::limit_exceeded`
+    /// tells beforehand whether the limit will be hit. Depending on the result, a
+    /// caller may raise `MergeOptions::target_limit` to get renames detected
+    /// at the cost of a slower merge, or disable `MergeOptions::find_renames`
+    /// to skip rename detection entirely.
+    pub fn predict_merge_renames(
+        &self,
+        ancestor_tree: &Tree<'_>,
+        our_tree: &Tree<'_>,
+        their_tree: &Tree<'_>,
+        opts: Option<&MergeOptions>,
+    ) -> Result<MergeRenamePrediction, Error> {
+        crate::merge::predict_merge_renames(self, ancestor_tree, our_tree, their_tree, opts)
+    }
+
+    /// Find the tree that a merge of two commits uses as its base.
+    ///
+    /// If the commits have several merge bases, as happens after criss-cross
+    /// merges, they are merged into a virtual base the same way `merge` and
+    /// `merge_commits` do, honoring the `no_recursive` and `recursion_limit`
+    /// settings of `opts`. This can be used to explain surprising conflicts
+    /// by diffing each side of the merge against the base that was used.
+    pub fn merge_base_tree(
+        &self,
+        one: Oid,
+        two: Oid,
+        opts: Option<&MergeOptions>,
+    ) -> Result<MergeBaseTree, Error> {
+        crate::merge::merge_base_tree(self, one, two, opts)
+    }
+
+    /// Merge two files as they exist in the index, using the given common
+    /// ancestor as the baseline, producing a `MergeFileResult` that reflects
+    /// the merge result.
+    ///
+    /// The ancestor may be `None` when both sides added the file.
+    pub fn merge_file_from_index(
+        &self,
+        ancestor: Option<&IndexEntry>,
+        ours: &IndexEntry,
+        theirs: &IndexEntry,
+        opts: Option<&mut MergeFileOptions>,
+    ) -> Result<MergeFileResult, Error> {
+        let ancestor_path = match ancestor {
+            Some(entry) => Some(CString::new(&entry.path[..])?),
+            None => None,
+        };
+        let ours_path = CString::new(&ours.path[..])?;
+        let theirs_path = CString::new(&theirs.path[..])?;
+        unsafe {
+            let ancestor = match (ancestor, &ancestor_path) {
+                (Some(entry), Some(path)) => Some(entry.to_raw(path)),
+                _ => None,
+            };
+            let ours = ours.to_raw(&ours_path);
+            let theirs = theirs.to_raw(&theirs_path);
+
+            let mut ret = mem::zeroed();
+            try_call!(raw::git_merge_file_from_index(
+                &mut ret,
+                self.raw(),
+                ancestor.as_ref().map_or(ptr::null(), |a| a as *const _),
+                &ours,
+                &theirs,
+                opts.map_or(ptr::null(), |o| o.raw())
+            ));
+            Ok(Binding::from_raw(ret))
+        }
+    }
+
+    /// Merge the sides of a conflict found in an index.
+    ///
+    /// This merges the contents of the "ours" and "theirs" entries of the
+    /// conflict against its ancestor, honoring the favor and conflict style
+    /// set in `opts`. The result holds the merged contents, along with the
+    /// path and mode to use for the resolved entry. Conflicts where one side
+    /// deleted the file have no contents to merge and return an error of
+    /// class `Merge`.
+    pub fn merge_file_from_conflict(
+        &self,
+        conflict: &IndexConflict,
+        opts: Option<&mut MergeFileOptions>,
+    ) -> Result<MergeFileResult, Error> {
+        match (&conflict.our, &conflict.their) {
+            (Some(ours), Some(theirs)) => {
+                self.merge_file_from_index(conflict.ancestor.as_ref(), ours, theirs, opts)
+            }
+            _ => Err(Error::new(
+                ErrorCode::Unmerged,
+                crate::ErrorClass::Merge,
+                "cannot merge the contents of a conflict where one side is deleted",
+            )),
+        }
+    }
+
+    /// Reuse recorded resolutions of conflicts, like `git rerere`.
+    ///
+    /// For each conflicted file in the index whose conflict was resolved
+    /// before, the recorded resolution is applied to the working directory,
+    /// and also to the index if `rerere.autoupdate` is set. The conflicts of
+    /// other files are recorded, and files whose recorded conflicts have
+    /// since been resolved in the working directory have their resolution
+    /// recorded. Run this after a merge stops with conflicts and again once
+    /// they have been resolved, before committing.
+    ///
+    /// This does nothing unless rerere is enabled, with `rerere.enabled` or
+    /// by the existence of the `rr-cache` directory. Conflicts and their
+    /// resolutions are stored in `rr-cache` in the same format as git, but
+    /// conflicts are identified by the id of a blob rather than by a bare
+    /// SHA-1 digest, so resolutions recorded by git are not reused.
+    ///
+    /// `merge`, rebases in the working directory and sequencers run this on
+    /// their own.
+    pub fn rerere(&self) -> Result<RerereStatus, Error> {
+        crate::rerere::rerere(self)
+    }
+
+    /// Forget the conflicts recorded by `rerere` which have not been
+    /// resolved yet, like `git rerere clear`.
+    ///
+    /// Use this when abandoning a merge.
+    pub fn rerere_clear(&self) -> Result<(), Error> {
+        crate::rerere::clear(self)
+    }
+
+    /// Render the conflict recorded in `index` for a single path, with
+    /// conflict markers, without touching the working directory.
+    ///
+    /// This produces the same contents a checkout writes for the conflicted
+    /// file, using the conflict style and labels set in `opts` rather than
+    /// the `merge.conflictStyle` configuration. Labels default to the path
+    /// of the file. Returns an error of code `NotFound` if the path is not
+    /// conflicted.
+    pub fn render_conflict(
+        &self,
+        index: &Index,
+        path: &Path,
+        opts: Option<&mut MergeFileOptions>,
+    ) -> Result<MergeFileResult, Error> {
+        let conflict = index.conflict_get(path)?;
+        self.merge_file_from_conflict(&conflict, opts)
+    }
+
     /// Remove all the metadata associated with an ongoing command like merge,
     /// revert, cherry-pick, etc. For example: MERGE_HEAD, MERGE_MSG, etc.
     pub fn cleanup_state(&self) -> Result<(), Error> {
@@ -2289,7 +3321,7 @@ impl Repository {
         branch: Option<&AnnotatedCommit<'_>>,
         upstream: Option<&AnnotatedCommit<'_>>,
         onto: Option<&AnnotatedCommit<'_>>,
-        opts: Option<&mut RebaseOptions<'_>>,
+        mut opts: Option<&mut RebaseOptions<'_>>,
     ) -> Result<Rebase<'_>, Error> {
         let mut rebase: *mut raw::git_rebase = ptr::null_mut();
         unsafe {
@@ -2299,24 +3331,68 @@ impl Repository {
                 branch.map(|c| c.raw()),
                 upstream.map(|c| c.raw()),
                 onto.map(|c| c.raw()),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
+                opts.as_mut().map(|o| o.raw()).unwrap_or(ptr::null())
             ));
 
-            Ok(Rebase::from_raw(rebase))
+            Ok(Rebase::new(self, rebase, opts))
         }
     }
 
+    /// Rebase a branch entirely in memory, returning how its commits were
+    /// rewritten.
+    ///
+    /// This runs a rebase with `RebaseOptions::inmemory` set, committing each
+    /// operation as `committer` while keeping the original authors and
+    /// messages. Neither the working directory, the index nor any reference
+    /// is touched, so the caller decides what to do with the new tip, for
+    /// example updating a branch on a server. Commits whose changes are
+    /// already upstream are skipped. If an operation conflicts the rebase
+    /// stops with an error of code `MergeConflict`.
+    pub fn rebase_in_memory(
+        &self,
+        branch: &AnnotatedCommit<'_>,
+        upstream: Option<&AnnotatedCommit<'_>>,
+        onto: Option<&AnnotatedCommit<'_>>,
+        committer: &Signature<'_>,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<RebasedCommits, Error> {
+        crate::rebase::rebase_in_memory(self, branch, upstream, onto, committer, opts)
+    }
+
+    /// Rebase commits onto `onto` following an edited todo list, like the
+    /// plan of `git rebase -i`.
+    ///
+    /// Each operation of the todo list is run as an in-memory rebase of its
+    /// commit on top of the previous one and committed as `committer`, so the
+    /// working directory, the index and references are left untouched; `opts`
+    /// is used for each of these rebases. Squashed commits keep the author of
+    /// the commit they are melded into, and their messages are separated by
+    /// a blank line. Commits that are already applied are skipped, and a
+    /// conflict stops with an error of code `MergeConflict`.
+    pub fn rebase_todo(
+        &self,
+        onto: &Commit<'_>,
+        todo: &RebaseTodo,
+        committer: &Signature<'_>,
+        opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<RebasedCommits, Error> {
+        crate::rebase::rebase_todo(self, onto, todo, committer, opts)
+    }
+
     /// Opens an existing rebase that was previously started by either an
     /// invocation of `rebase()` or by another client.
-    pub fn open_rebase(&self, opts: Option<&mut RebaseOptions<'_>>) -> Result<Rebase<'_>, Error> {
+    pub fn open_rebase(
+        &self,
+        mut opts: Option<&mut RebaseOptions<'_>>,
+    ) -> Result<Rebase<'_>, Error> {
         let mut rebase: *mut raw::git_rebase = ptr::null_mut();
         unsafe {
             try_call!(raw::git_rebase_open(
                 &mut rebase,
                 self.raw(),
-                opts.map(|o| o.raw()).unwrap_or(ptr::null())
+                opts.as_mut().map(|o| o.raw()).unwrap_or(ptr::null())
             ));
-            Ok(Rebase::from_raw(rebase))
+            Ok(Rebase::new(self, rebase, opts))
         }
     }
 
@@ -2421,12 +3497,137 @@ impl Repository {
         }
     }
 
+    /// Add a note for an object to a notes commit, without updating any
+    /// reference.
+    ///
+    /// A new notes commit is created with `parent` as its parent, or as a
+    /// root commit if `parent` is `None`, holding the notes of `parent`
+    /// along with the new note. If `force` is specified then a previous
+    /// note for the object is overwritten.
+    ///
+    /// Returns the id of the new notes commit and of the note blob. The
+    /// commit can then be made the tip of any notes reference, for instance
+    /// with `reference_matching` to detect concurrent updates.
+    pub fn note_commit_create(
+        &self,
+        parent: Option<&Commit<'_>>,
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        oid: Oid,
+        note: &str,
+        force: bool,
+    ) -> Result<(Oid, Oid), Error> {
+        let note = CString::new(note)?;
+        let mut commit = raw::git_oid {
+            id: [0; raw::GIT_OID_RAWSZ],
+        };
+        let mut blob = raw::git_oid {
+            id: [0; raw::GIT_OID_RAWSZ],
+        };
+        unsafe {
+            try_call!(raw::git_note_commit_create(
+                &mut commit,
+                &mut blob,
+                self.raw,
+                parent.map_or(ptr::null_mut(), |p| p.raw()),
+                author.raw(),
+                committer.raw(),
+                oid.raw(),
+                note,
+                force
+            ));
+            Ok((
+                Binding::from_raw(&commit as *const _),
+                Binding::from_raw(&blob as *const _),
+            ))
+        }
+    }
+
+    /// Read the note for an object from a notes commit.
+    pub fn find_note_in_commit(
+        &self,
+        notes_commit: &Commit<'_>,
+        id: Oid,
+    ) -> Result<Note<'_>, Error> {
+        let mut ret = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_note_commit_read(
+                &mut ret,
+                self.raw,
+                notes_commit.raw(),
+                id.raw()
+            ));
+            Ok(Binding::from_raw(ret))
+        }
+    }
+
+    /// Remove the note for an object from a notes commit, without updating
+    /// any reference.
+    ///
+    /// Returns the id of the new notes commit, whose parent is
+    /// `notes_commit`.
+    pub fn note_commit_remove(
+        &self,
+        notes_commit: &Commit<'_>,
+        author: &Signature<'_>,
+        committer: &Signature<'_>,
+        id: Oid,
+    ) -> Result<Oid, Error> {
+        let mut ret = raw::git_oid {
+            id: [0; raw::GIT_OID_RAWSZ],
+        };
+        unsafe {
+            try_call!(raw::git_note_commit_remove(
+                &mut ret,
+                self.raw,
+                notes_commit.raw(),
+                author.raw(),
+                committer.raw(),
+                id.raw()
+            ));
+            Ok(Binding::from_raw(&ret as *const _))
+        }
+    }
+
+    /// Creates a new iterator for the notes of a notes commit.
+    ///
+    /// The iterator yields pairs of (Oid, Oid) as with `notes`.
+    pub fn notes_in_commit(&self, notes_commit: &Commit<'_>) -> Result<Notes<'_>, Error> {
+        let mut ret = ptr::null_mut();
+        unsafe {
+            try_call!(raw::git_note_commit_iterator_new(
+                &mut ret,
+                notes_commit.raw()
+            ));
+            Ok(Binding::from_raw(ret))
+        }
+    }
+
+    /// Merge the notes of `other` into the notes reference `notes_ref`, like
+    /// `git notes merge`.
+    ///
+    /// The `notes_ref` argument is the canonical name of the reference to
+    /// merge into, defaulting to "refs/notes/commits", and `other` is the
+    /// canonical name of the notes reference to merge in. The notes of an
+    /// object which were changed on both sides are resolved according to
+    /// `strategy`. If a merge commit is needed, it is made by `signature`
+    /// and `notes_ref` is updated to point to it.
+    pub fn notes_merge(
+        &self,
+        notes_ref: Option<&str>,
+        other: &str,
+        signature: &Signature<'_>,
+        strategy: NotesMergeStrategy,
+    ) -> Result<NotesMergeOutcome, Error> {
+        crate::note::merge(self, notes_ref, other, signature, strategy)
+    }
+
     /// Create a revwalk that can be used to traverse the commit graph.
     pub fn revwalk(&self) -> Result<Revwalk<'_>, Error> {
         let mut raw = ptr::null_mut();
         unsafe {
             try_call!(raw::git_revwalk_new(&mut raw, self.raw()));
-            Ok(Binding::from_raw(raw))
+            Ok(Revwalk::new(raw, CallSpan::revwalk(self)))
         }
     }
 
@@ -2434,20 +3635,124 @@ impl Repository {
     pub fn blame_file(
         &self,
         path: &Path,
-        opts: Option<&mut BlameOptions>,
+        mut opts: Option<&mut BlameOptions>,
     ) -> Result<Blame<'_>, Error> {
+        if let Some(opts) = opts.as_mut() {
+            if !opts.line_ranges().is_empty() {
+                return crate::blame::blame_ranges(self, path, opts);
+            }
+        }
         let path = path_to_repo_path(path)?;
         let mut raw = ptr::null_mut();
 
-        unsafe {
+        let mut blame: Blame<'_> = unsafe {
             try_call!(raw::git_blame_file(
                 &mut raw,
                 self.raw(),
                 path,
-                opts.map(|s| s.raw())
+                opts.as_ref().map(|s| s.raw())
             ));
-            Ok(Binding::from_raw(raw))
+            Binding::from_raw(raw)
+        };
+        if let Some(opts) = opts {
+            if opts.has_ignore_revs() {
+                crate::blame::apply_ignore_revs(self, &mut blame, opts)?;
+            }
         }
+        Ok(blame)
+    }
+
+    /// Get the blame for a single file, reporting results as they become
+    /// available.
+    ///
+    /// The lines to blame (all of them, or those selected with
+    /// `BlameOptions::min_line` and `max_line` or `add_line_range`) are
+    /// blamed in chunks of `chunk_lines` lines, from the top of the file
+    /// down. After each chunk the callback is given the blame for just those
+    /// lines along with the overall progress, so that large files can be
+    /// displayed progressively. Returning `false` from the callback stops the
+    /// blame early.
+    ///
+    /// Each chunk walks the history on its own, so blaming a whole file this
+    /// way costs more in total than a single `blame_file`. A `chunk_lines` of
+    /// zero blames everything in one chunk.
+    pub fn blame_file_incremental<F>(
+        &self,
+        path: &Path,
+        opts: Option<&mut BlameOptions>,
+        chunk_lines: usize,
+        mut callback: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&Blame<'_>, BlameProgress) -> bool,
+    {
+        let mut default_opts = BlameOptions::new();
+        let opts = opts.unwrap_or(&mut default_opts);
+        let (min_line, max_line) = opts.line_range();
+
+        let commit = match opts.newest_commit_id() {
+            Some(id) => self.find_commit(id)?,
+            None => self.head()?.peel_to_commit()?,
+        };
+        let blob = commit
+            .tree()?
+            .get_path(path)?
+            .to_object(self)?
+            .peel_to_blob()?;
+        let content = blob.content();
+        let mut total = content.iter().filter(|b| **b == b'\n').count();
+        if content.last().map(|b| *b != b'\n') == Some(true) {
+            total += 1;
+        }
+
+        let mut ranges = opts.line_ranges();
+        if ranges.is_empty() {
+            ranges.push((min_line, max_line));
+        }
+        let ranges = ranges
+            .into_iter()
+            .map(|(min, max)| {
+                let last = if max == 0 { total } else { max.min(total) };
+                (min.max(1), last)
+            })
+            .filter(|&(first, last)| first <= last)
+            .collect::<Vec<_>>();
+        let total_lines = ranges
+            .iter()
+            .map(|&(first, last)| last + 1 - first)
+            .sum::<usize>();
+        let chunk_lines = if chunk_lines == 0 {
+            total_lines.max(1)
+        } else {
+            chunk_lines
+        };
+
+        let saved_ranges = opts.replace_line_ranges(Vec::new());
+        let mut result = Ok(());
+        let mut attributed = 0;
+        'ranges: for (first, last) in ranges {
+            let mut start = first;
+            while start <= last {
+                let end = (start + chunk_lines - 1).min(last);
+                opts.min_line(start).max_line(end);
+                let blame = match self.blame_file(path, Some(&mut *opts)) {
+                    Ok(blame) => blame,
+                    Err(e) => {
+                        result = Err(e);
+                        break 'ranges;
+                    }
+                };
+                attributed += end + 1 - start;
+                let progress = BlameProgress::new(attributed, total_lines);
+                if !callback(&blame, progress) {
+                    break 'ranges;
+                }
+                start = end + 1;
+            }
+        }
+        opts.min_line(min_line).max_line(max_line);
+        opts.replace_line_ranges(saved_ranges);
+        result
     }
 
     /// Find a merge base between two commits
@@ -2566,6 +3871,87 @@ impl Repository {
         }
     }
 
+    /// Iterate over the commits that changed a file, starting from `start`
+    /// and following the file across renames and copies, like
+    /// `git log --follow`.
+    pub fn file_history(&self, start: Oid, path: &Path) -> Result<FileHistory<'_>, Error> {
+        FileHistory::new(self, start, path)
+    }
+
+    /// Search the commits produced by a revwalk for changes adding or
+    /// removing some text, like `git log -S` or `git log -G`.
+    ///
+    /// Each non-merge commit is diffed against its parent and the matching
+    /// commits are returned in the order of the walk, along with the files
+    /// in which the text was found.
+    pub fn pickaxe(
+        &self,
+        walk: &mut Revwalk<'_>,
+        opts: &PickaxeOptions,
+    ) -> Result<Vec<PickaxeMatch>, Error> {
+        crate::pickaxe::pickaxe(self, walk, opts)
+    }
+
+    /// Compute the patch ID of the changes introduced by a commit.
+    ///
+    /// The commit is diffed against its first parent (or the empty tree for a
+    /// root commit) and the patch ID of that diff is returned, so that
+    /// commits making the same change on different bases get the same ID.
+    /// Merge commits have no patch ID and yield `None`, like in
+    /// `git patch-id`.
+    pub fn commit_patch_id(&self, commit: Oid) -> Result<Option<Oid>, Error> {
+        let commit = self.find_commit(commit)?;
+        if commit.parent_count() > 1 {
+            return Ok(None);
+        }
+        let parent_tree = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        let diff = self.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        Ok(Some(diff.patchid(None)?))
+    }
+
+    /// Find which commits of a branch have equivalent changes upstream.
+    ///
+    /// This is the equivalent of `git cherry upstream head limit`. The
+    /// commits reachable from `head` but not from `upstream` (nor from
+    /// `limit`, if given) are listed oldest first, each paired with whether
+    /// a commit with the same patch ID exists in the commits reachable from
+    /// `upstream` but not from `head`. Merge commits are skipped.
+    pub fn cherry(
+        &self,
+        upstream: Oid,
+        head: Oid,
+        limit: Option<Oid>,
+    ) -> Result<Vec<(Oid, bool)>, Error> {
+        let mut upstream_ids = std::collections::HashSet::new();
+        let mut walk = self.revwalk()?;
+        walk.push(upstream)?;
+        walk.hide(head)?;
+        for id in walk {
+            if let Some(patch_id) = self.commit_patch_id(id?)? {
+                upstream_ids.insert(patch_id);
+            }
+        }
+
+        let mut walk = self.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(head)?;
+        walk.hide(upstream)?;
+        if let Some(limit) = limit {
+            walk.hide(limit)?;
+        }
+        let mut commits = Vec::new();
+        for id in walk {
+            let id = id?;
+            if let Some(patch_id) = self.commit_patch_id(id)? {
+                commits.push((id, upstream_ids.contains(&patch_id)));
+            }
+        }
+        Ok(commits)
+    }
+
     /// Count the number of unique commits between two commit objects
     ///
     /// There is no need for branches containing the commits to have any
@@ -2587,6 +3973,21 @@ impl Repository {
         }
     }
 
+    /// Count the commits each local branch with an upstream is ahead and
+    /// behind of it, like `graph_ahead_behind` for every branch.
+    ///
+    /// The histories of all the branches are walked at once, which is much
+    /// cheaper than calling `graph_ahead_behind` for each branch when there
+    /// are many of them. Returns the short name of each branch along with
+    /// its ahead and behind counts. Branches whose upstream is not
+    /// configured or does not exist are skipped.
+    ///
+    /// The counts stop being updated as soon as the remaining commits are
+    /// reachable from both sides of every branch or from neither.
+    pub fn branches_ahead_behind(&self) -> Result<Vec<(String, usize, usize)>, Error> {
+        crate::branch::ahead_behind(self)
+    }
+
     /// Determine if a commit is the descendant of another commit
     ///
     /// Note that a commit is not considered a descendant of itself, in contrast
@@ -2615,6 +4016,22 @@ impl Repository {
         }
     }
 
+    /// Remove old entries from the reflog of a reference, like
+    /// `git reflog expire`.
+    ///
+    /// The reflog is rewritten under the lock of the reference. Returns the
+    /// number of entries removed, or that would be removed for a dry run.
+    pub fn reflog_expire(
+        &self,
+        name: &str,
+        opts: Option<&ReflogExpireOptions>,
+    ) -> Result<usize, Error> {
+        match opts {
+            Some(opts) => crate::reflog::expire(self, name, opts),
+            None => crate::reflog::expire(self, name, &ReflogExpireOptions::new()),
+        }
+    }
+
     /// Delete the reflog for the given reference
     pub fn reflog_delete(&self, name: &str) -> Result<(), Error> {
         let name = CString::new(name)?;
@@ -2752,7 +4169,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "tree_to_tree");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_tree_to_tree(
                 &mut ret,
                 self.raw(),
@@ -2761,7 +4179,9 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a diff between a tree and repository index.
@@ -2784,7 +4204,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "tree_to_index");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_tree_to_index(
                 &mut ret,
                 self.raw(),
@@ -2793,7 +4214,9 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a diff between two index objects.
@@ -2807,7 +4230,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "index_to_index");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_index_to_index(
                 &mut ret,
                 self.raw(),
@@ -2816,7 +4240,9 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a diff between the repository index and the workdir directory.
@@ -2838,7 +4264,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "index_to_workdir");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_index_to_workdir(
                 &mut ret,
                 self.raw(),
@@ -2846,7 +4273,9 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a diff between a tree and the working directory.
@@ -2873,7 +4302,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "tree_to_workdir");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_tree_to_workdir(
                 &mut ret,
                 self.raw(),
@@ -2881,7 +4311,9 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a diff between a tree and the working directory using index data
@@ -2896,7 +4328,8 @@ impl Repository {
         opts: Option<&mut DiffOptions>,
     ) -> Result<Diff<'_>, Error> {
         let mut ret = ptr::null_mut();
-        unsafe {
+        let span = CallSpan::diff(self, "tree_to_workdir_with_index");
+        let diff: Diff<'_> = span.in_scope(|| unsafe {
             try_call!(raw::git_diff_tree_to_workdir_with_index(
                 &mut ret,
                 self.raw(),
@@ -2904,10 +4337,26 @@ impl Repository {
                 opts.map(|s| s.raw())
             ));
             Ok(Binding::from_raw(ret))
-        }
+        })?;
+        span.record("deltas", diff.deltas().len() as u64);
+        Ok(diff)
     }
 
     /// Create a PackBuilder
+    ///
+    /// The delta compression of the builder is tuned by the configuration of
+    /// the repository at the time the builder is created:
+    ///
+    /// * `pack.windowMemory` limits the memory used by the delta window,
+    ///   `0` meaning unlimited.
+    /// * `pack.bigFileThreshold` is the size above which files are stored
+    ///   without looking for deltas, trading pack size for speed.
+    /// * `pack.deltaCacheSize` and `pack.deltaCacheLimit` bound the cache of
+    ///   deltas computed while searching for the best ones.
+    ///
+    /// libgit2 always uses a delta window of 10 objects and a maximum delta
+    /// depth of 50. The number of threads is set with
+    /// `PackBuilder::set_threads`.
     pub fn packbuilder(&self) -> Result<PackBuilder<'_>, Error> {
         let mut ret = ptr::null_mut();
         unsafe {
@@ -3058,6 +4507,23 @@ impl Repository {
         Ok(ignored == 1)
     }
 
+    /// Test if the ignore rules apply to each of the given paths.
+    ///
+    /// The returned vector holds one entry per path, in the same order.
+    ///
+    /// This is a convenience loop over `is_path_ignored`, not a single
+    /// traversal of the ignore rules: libgit2 has no batch query, so every
+    /// path is matched against the ignore stack on its own. The ignore files
+    /// themselves are only read once, into the repository's attribute cache,
+    /// and shared by all of the checks. Use `flush_attr_cache` to pick up
+    /// changes to ignore files made afterwards.
+    pub fn are_paths_ignored<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<bool>, Error> {
+        paths
+            .iter()
+            .map(|path| self.is_path_ignored(path))
+            .collect()
+    }
+
     /// Perform a cherrypick
     pub fn cherrypick(
         &self,
@@ -3136,12 +4602,53 @@ impl Repository {
         }
     }
 
+    /// Retrieve the upstream merge of a local branch, that is the name of
+    /// the branch on the upstream remote it is configured to merge, such as
+    /// `refs/heads/main`.
+    ///
+    /// `refname` must be in the form `refs/heads/{branch_name}`
+    pub fn branch_upstream_merge(&self, refname: &str) -> Result<Buf, Error> {
+        let refname = CString::new(refname)?;
+        unsafe {
+            let buf = Buf::new();
+            try_call!(raw::git_branch_upstream_merge(buf.raw(), self.raw, refname));
+            Ok(buf)
+        }
+    }
+
     /// Apply a Diff to the given repo, making changes directly in the working directory, the index, or both.
+    ///
+    /// If `ApplyOptions::three_way` is set and the diff does not apply
+    /// cleanly, the changes are merged instead, possibly leaving conflicts.
     pub fn apply(
         &self,
         diff: &Diff<'_>,
         location: ApplyLocation,
         options: Option<&mut ApplyOptions<'_>>,
+    ) -> Result<(), Error> {
+        let three_way = options.as_ref().map(|o| o.is_three_way()) == Some(true);
+        let check = options.as_ref().map(|o| o.is_check()) == Some(true);
+        match self.apply_raw(diff, location, options) {
+            // Added and deleted files fail with `NotFound` or `Exists`
+            // rather than `ApplyFail` when their preimage doesn't match.
+            Err(e)
+                if three_way
+                    && matches!(
+                        e.code(),
+                        ErrorCode::ApplyFail | ErrorCode::NotFound | ErrorCode::Exists
+                    ) =>
+            {
+                crate::apply::apply_three_way(self, diff, location, check)
+            }
+            result => result,
+        }
+    }
+
+    fn apply_raw(
+        &self,
+        diff: &Diff<'_>,
+        location: ApplyLocation,
+        options: Option<&mut ApplyOptions<'_>>,
     ) -> Result<(), Error> {
         unsafe {
             try_call!(raw::git_apply(
@@ -3155,7 +4662,120 @@ impl Repository {
         }
     }
 
+    /// Stage only the selected hunks of a patch into the index, like
+    /// `git add -p`.
+    ///
+    /// The patch is expected to describe a change from the index to the
+    /// working directory for a single file, for example one produced by
+    /// `Patch::from_diff` on the result of `diff_index_to_workdir`. `hunks`
+    /// holds the indices of the hunks to stage; all other hunks are left
+    /// unstaged. Hunks are located by their context, so they still apply if
+    /// the index entry has shifted since the patch was generated.
+    ///
+    /// The index is written to disk and the updated entry for the file is
+    /// returned.
+    pub fn stage_hunks(&self, patch: &mut Patch<'_>, hunks: &[usize]) -> Result<IndexEntry, Error> {
+        let path = match patch.delta().new_file().path() {
+            Some(path) => path.to_path_buf(),
+            None => return Err(Error::from_str("patch has no path")),
+        };
+        let diff = Diff::from_buffer(&patch.to_buf()?)?;
+        let mut hunk_idx = 0;
+        let mut opts = ApplyOptions::new();
+        opts.hunk_callback(|_hunk| {
+            let selected = hunks.contains(&hunk_idx);
+            hunk_idx += 1;
+            selected
+        });
+        self.apply(&diff, ApplyLocation::Index, Some(&mut opts))?;
+
+        let index = self.index()?;
+        match index.get_path(&path, 0) {
+            Some(entry) => Ok(entry),
+            None => Err(Error::from_str("path is not in the index")),
+        }
+    }
+
+    /// Discard the selected hunks of a patch from the working directory.
+    ///
+    /// This is the inverse of `stage_hunks`: the reverse of each selected hunk
+    /// is applied to the file in the working directory, leaving the index
+    /// untouched. The patch is expected to describe a change to the working
+    /// directory for a single file, for example one produced by
+    /// `Patch::from_diff` on the result of `diff_index_to_workdir`.
+    ///
+    /// If the file no longer matches the patch where a selected hunk applies,
+    /// an error with the code `ErrorCode::Modified` is returned and the file is
+    /// left alone. When `dry_run` is true the file is never written.
+    ///
+    /// Returns the resulting contents of the file.
+    pub fn discard_hunks(
+        &self,
+        patch: &Patch<'_>,
+        hunks: &[usize],
+        dry_run: bool,
+    ) -> Result<Vec<u8>, Error> {
+        let workdir = match self.workdir() {
+            Some(workdir) => workdir,
+            None => return Err(Error::from_str("cannot discard hunks in a bare repository")),
+        };
+        let path = match patch.delta().new_file().path() {
+            Some(path) => workdir.join(path),
+            None => return Err(Error::from_str("patch has no path")),
+        };
+        let content = std::fs::read(&path).map_err(Error::from)?;
+        let mut lines = content.split_inclusive(|b| *b == b'\n').collect::<Vec<_>>();
+
+        let mut selected = hunks.to_vec();
+        selected.sort();
+        selected.dedup();
+        // Work from the bottom of the file up so that the line numbers of the
+        // hunks which are still to be discarded stay valid.
+        for &hunk_idx in selected.iter().rev() {
+            let (hunk, num_lines) = patch.hunk(hunk_idx)?;
+            let mut old = Vec::new();
+            let mut new = Vec::new();
+            for i in 0..num_lines {
+                let line = patch.line_in_hunk(hunk_idx, i)?;
+                match line.origin_value() {
+                    DiffLineType::Context => {
+                        old.push(line.content());
+                        new.push(line.content());
+                    }
+                    DiffLineType::Deletion => old.push(line.content()),
+                    DiffLineType::Addition => new.push(line.content()),
+                    _ => {}
+                }
+            }
+
+            let start = if hunk.new_lines() == 0 {
+                hunk.new_start() as usize
+            } else {
+                hunk.new_start() as usize - 1
+            };
+            let end = start + new.len();
+            if end > lines.len() || lines[start..end] != new[..] {
+                return Err(Error::new(
+                    ErrorCode::Modified,
+                    crate::ErrorClass::None,
+                    "file has been modified since the patch was generated",
+                ));
+            }
+            lines.splice(start..end, old);
+        }
+
+        let discarded = lines.concat();
+        if !dry_run {
+            std::fs::write(&path, &discarded).map_err(Error::from)?;
+        }
+        Ok(discarded)
+    }
+
     /// Apply a Diff to the provided tree, and return the resulting Index.
+    ///
+    /// Neither the working directory nor the repository's index is touched,
+    /// so this also works in bare repositories. The returned in-memory index
+    /// can be turned into a tree with `Index::write_tree_to`.
     pub fn apply_to_tree(
         &self,
         tree: &Tree<'_>,
@@ -3175,6 +4795,39 @@ impl Repository {
         }
     }
 
+    /// Cherry-pick the commits in `from..to` onto `HEAD`, oldest first,
+    /// like `git cherry-pick from..to`.
+    ///
+    /// Each commit that applies cleanly is committed with its original
+    /// author and message, and the ids of the new commits are returned. If a
+    /// commit conflicts, an error of code `MergeConflict` is returned and
+    /// the progress is saved in the repository; resolve the conflicts in the
+    /// index and working directory, then resume with
+    /// `open_sequencer()?.continue_()` or give up with
+    /// `open_sequencer()?.abort()`.
+    pub fn cherry_pick_range(&self, from: Oid, to: Oid) -> Result<Vec<Oid>, Error> {
+        Sequencer::cherry_pick(self, from, to)?.continue_()
+    }
+
+    /// Revert the commits in `from..to`, newest first, like
+    /// `git revert from..to`.
+    ///
+    /// Each commit that reverts cleanly is committed, and the ids of the new
+    /// commits are returned. If a commit conflicts, an error of code
+    /// `MergeConflict` is returned and the progress is saved in the
+    /// repository; resolve the conflicts in the index and working directory,
+    /// then resume with `open_sequencer()?.continue_()` or give up with
+    /// `open_sequencer()?.abort()`.
+    pub fn revert_range(&self, from: Oid, to: Oid) -> Result<Vec<Oid>, Error> {
+        Sequencer::revert(self, from, to)?.continue_()
+    }
+
+    /// Open the sequence of cherry-picks or reverts that is in progress,
+    /// after one was stopped by a conflict.
+    pub fn open_sequencer(&self) -> Result<Sequencer<'_>, Error> {
+        Sequencer::open(self)
+    }
+
     /// Reverts the given commit, producing changes in the index and working directory.
     pub fn revert(
         &self,
@@ -3227,6 +4880,28 @@ impl Repository {
         }
     }
 
+    /// Lists all the worktrees of the repository along with their path,
+    /// `HEAD`, lock status and whether they are prunable, like
+    /// `git worktree list`.
+    ///
+    /// The main worktree comes first. The state of each worktree is read
+    /// from the administrative files of the repository, without opening the
+    /// worktree itself.
+    pub fn worktrees_detailed(&self) -> Result<Vec<WorktreeInfo>, Error> {
+        crate::worktree::list(self)
+    }
+
+    /// Repair the links between the repository and its worktrees, like
+    /// `git worktree repair`.
+    ///
+    /// Each of `paths` is a worktree which has been moved: the repository
+    /// is updated to find it there. The worktrees which haven't moved are
+    /// then updated to find the repository, in case it has moved itself.
+    /// Returns the names of the worktrees which needed repairing.
+    pub fn worktree_repair(&self, paths: &[&Path]) -> Result<Vec<String>, Error> {
+        crate::worktree::repair(self, paths)
+    }
+
     /// Opens a worktree by name for the given repository
     ///
     /// This can open any worktree that the worktrees method returns.
@@ -3246,20 +4921,7 @@ impl Repository {
         path: &Path,
         opts: Option<&WorktreeAddOptions<'a>>,
     ) -> Result<Worktree, Error> {
-        let mut raw = ptr::null_mut();
-        let raw_name = CString::new(name)?;
-        let raw_path = path.into_c_string()?;
-
-        unsafe {
-            try_call!(raw::git_worktree_add(
-                &mut raw,
-                self.raw,
-                raw_name,
-                raw_path,
-                opts.map(|o| o.raw())
-            ));
-            Ok(Binding::from_raw(raw))
-        }
+        crate::worktree::add(self, name, path, opts)
     }
 
     /// Create a new transaction
@@ -3271,13 +4933,23 @@ impl Repository {
         }
     }
 
+    /// Apply a set of reference updates under a single transaction.
+    ///
+    /// `f` describes the updates on a `RefUpdates`. All the references are
+    /// then locked and their expected values checked before any of them is
+    /// written, so a stale value or an error returned by `f` leaves every
+    /// reference untouched. If writing fails partway, the references already
+    /// written are put back on a best-effort basis.
+    pub fn update_refs<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut RefUpdates) -> Result<T, Error>,
+    {
+        crate::transaction::update_refs(self, f)
+    }
+
     /// Gets this repository's mailmap.
     pub fn mailmap(&self) -> Result<Mailmap, Error> {
-        let mut ret = ptr::null_mut();
-        unsafe {
-            try_call!(raw::git_mailmap_from_repository(&mut ret, self.raw));
-            Ok(Binding::from_raw(ret))
-        }
+        crate::mailmap::from_repository(self)
     }
 
     ///  If a merge is in progress, invoke 'callback' for each commit ID in the
@@ -3300,6 +4972,90 @@ impl Repository {
         }
     }
 
+    /// Read the entries of the `FETCH_HEAD` file, written by the last fetch.
+    ///
+    /// Returns an empty list if nothing was fetched yet.
+    pub fn fetch_head(&self) -> Result<Vec<FetchHeadEntry>, Error> {
+        let mut entries = Vec::new();
+        let ret = self.fetchhead_foreach(|ref_name, remote_url, id, is_merge| {
+            entries.push(FetchHeadEntry {
+                ref_name: ref_name.to_string(),
+                remote_url: remote_url.to_vec(),
+                id: *id,
+                is_merge,
+            });
+            true
+        });
+        match ret {
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+            Ok(()) => Ok(entries),
+        }
+    }
+
+    /// Read the commits of the `MERGE_HEAD` file, being merged by a merge in
+    /// progress.
+    ///
+    /// Returns an empty list if no merge is in progress.
+    pub fn merge_heads(&self) -> Result<Vec<Oid>, Error> {
+        let mut ids = Vec::new();
+        let mut callback = |id: &Oid| {
+            ids.push(*id);
+            true
+        };
+        let mut data = MergeheadForeachCbData {
+            callback: &mut callback,
+        };
+        let rc = unsafe {
+            raw::git_repository_mergehead_foreach(
+                self.raw(),
+                Some(mergehead_foreach_cb),
+                &mut data as *mut _ as *mut _,
+            )
+        };
+        match rc {
+            raw::GIT_ENOTFOUND => Ok(Vec::new()),
+            rc if rc < 0 => {
+                panic::check();
+                Err(Error::last_error(rc))
+            }
+            _ => Ok(ids),
+        }
+    }
+
+    /// Read the `ORIG_HEAD` pseudo-reference, the previous position of
+    /// `HEAD` saved by commands moving it drastically, such as a reset,
+    /// merge or rebase.
+    pub fn orig_head(&self) -> Result<Option<Oid>, Error> {
+        self.pseudo_ref("ORIG_HEAD")
+    }
+
+    /// Read the `CHERRY_PICK_HEAD` pseudo-reference, the commit being
+    /// cherry-picked by a cherry-pick in progress.
+    pub fn cherry_pick_head(&self) -> Result<Option<Oid>, Error> {
+        self.pseudo_ref("CHERRY_PICK_HEAD")
+    }
+
+    /// Read the `REVERT_HEAD` pseudo-reference, the commit being reverted by
+    /// a revert in progress.
+    pub fn revert_head(&self) -> Result<Option<Oid>, Error> {
+        self.pseudo_ref("REVERT_HEAD")
+    }
+
+    fn pseudo_ref(&self, name: &str) -> Result<Option<Oid>, Error> {
+        match self.refname_to_id(name) {
+            Ok(id) => Ok(Some(id)),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Create an iterator over the references under `refs/worktree/`, which
+    /// are private to the worktree this repository was opened from.
+    pub fn worktree_references(&self) -> Result<References<'_>, Error> {
+        self.references_glob("refs/worktree/*")
+    }
+
     /// Invoke 'callback' for each entry in the given FETCH_HEAD file.
     ///
     /// `callback` will be called with with following arguments:
@@ -3508,8 +5264,10 @@ mod tests {
     use crate::build::CheckoutBuilder;
     use crate::CherrypickOptions;
     use crate::{
-        ObjectType, Oid, Repository, ResetType, Signature, SubmoduleIgnore, SubmoduleUpdate,
+        Direction, ObjectType, Oid, Repository, ResetType, Signature, SubmoduleIgnore,
+        SubmoduleUpdate,
     };
+    use crate::{MergeFileOptions, MergeOptions};
     use std::ffi::OsStr;
     use std::fs;
     use std::path::Path;
@@ -3708,6 +5466,41 @@ mod tests {
         assert_eq!(behind, 1);
     }
 
+    #[test]
+    fn smoke_cherry() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let commit_file = |parent: Oid, name: &str, message: &str| {
+            let parent = repo.find_commit(parent).unwrap();
+            let blob = repo.blob(name.as_bytes()).unwrap();
+            let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            builder.insert(name, blob, 0o100644).unwrap();
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            repo.commit(None, &sig, &sig, message, &tree, &[&parent])
+                .unwrap()
+        };
+        let base = repo.head().unwrap().target().unwrap();
+        let upstream_a = commit_file(base, "a", "add a");
+        let upstream = commit_file(upstream_a, "b", "add b");
+        let topic_a = commit_file(base, "a", "cherry-pick a");
+        let topic = commit_file(topic_a, "c", "add c");
+
+        assert_ne!(upstream_a, topic_a);
+        assert_eq!(
+            repo.commit_patch_id(upstream_a).unwrap(),
+            repo.commit_patch_id(topic_a).unwrap()
+        );
+        assert_ne!(
+            repo.commit_patch_id(upstream).unwrap(),
+            repo.commit_patch_id(topic).unwrap()
+        );
+
+        let cherry = repo.cherry(upstream, topic, None).unwrap();
+        assert_eq!(cherry, [(topic_a, true), (topic, false)]);
+        let cherry = repo.cherry(upstream, topic, Some(topic_a)).unwrap();
+        assert_eq!(cherry, [(topic, false)]);
+    }
+
     #[test]
     fn smoke_graph_descendant_of() {
         let (_td, repo) = graph_repo_init();
@@ -3784,6 +5577,341 @@ mod tests {
         assert_eq!(obj.id(), head_id);
     }
 
+    #[test]
+    fn smoke_predict_merge_renames() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree = |files: &[(&str, &str)]| {
+            let mut builder = repo.treebuilder(None).unwrap();
+            for (name, content) in files {
+                let blob = repo.blob(content.as_bytes()).unwrap();
+                builder.insert(name, blob, 0o100644).unwrap();
+            }
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let ancestor = tree(&[("a", "a"), ("b", "b"), ("c", "c")]);
+        let ours = tree(&[("a", "a2"), ("renamed", "b"), ("c", "c")]);
+        let theirs = tree(&[("a", "a"), ("b", "b"), ("c", "c2")]);
+
+        let prediction = repo
+            .predict_merge_renames(&ancestor, &ours, &theirs, None)
+            .unwrap();
+        assert!(prediction.find_renames());
+        assert_eq!(prediction.threshold(), 50);
+        assert_eq!(prediction.target_limit(), 1000);
+        assert_eq!(prediction.changed_paths(), 4);
+        assert!(!prediction.limit_exceeded());
+
+        let mut config = repo.config().unwrap();
+        config.set_i32("merge.renameLimit", 3).unwrap();
+        let prediction = repo
+            .predict_merge_renames(&ancestor, &ours, &theirs, None)
+            .unwrap();
+        assert_eq!(prediction.target_limit(), 3);
+        assert!(prediction.limit_exceeded());
+
+        let mut opts = MergeOptions::new();
+        opts.target_limit(4);
+        let prediction = repo
+            .predict_merge_renames(&ancestor, &ours, &theirs, Some(&opts))
+            .unwrap();
+        assert!(!prediction.limit_exceeded());
+        opts.target_limit(1).find_renames(false);
+        let prediction = repo
+            .predict_merge_renames(&ancestor, &ours, &theirs, Some(&opts))
+            .unwrap();
+        assert!(!prediction.limit_exceeded());
+    }
+
+    #[test]
+    fn smoke_merge_base_tree() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let base = repo.head().unwrap().peel_to_commit().unwrap();
+        let commit = |parents: &[Oid], files: &[&str], message: &str| {
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let mut builder = repo.treebuilder(None).unwrap();
+            for name in files {
+                let blob = repo.blob(name.as_bytes()).unwrap();
+                builder.insert(name, blob, 0o100644).unwrap();
+            }
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &sig, &sig, message, &tree, &parents)
+                .unwrap()
+        };
+        let x = commit(&[base.id()], &["x"], "x");
+        let y = commit(&[base.id()], &["y"], "y");
+        let xy = commit(&[x, y], &["x", "y"], "merge y");
+        let yx = commit(&[y, x], &["x", "y"], "merge x");
+
+        let result = repo.merge_base_tree(x, xy, None).unwrap();
+        assert!(!result.is_virtual());
+        assert_eq!(result.bases(), [x]);
+        assert_eq!(
+            result.tree_id(),
+            Some(repo.find_commit(x).unwrap().tree_id())
+        );
+
+        let result = repo.merge_base_tree(xy, yx, None).unwrap();
+        assert!(result.is_virtual());
+        let mut bases = result.bases().to_vec();
+        bases.sort();
+        let mut expected = vec![x, y];
+        expected.sort();
+        assert_eq!(bases, expected);
+        let tree = repo.find_tree(result.tree_id().unwrap()).unwrap();
+        assert!(tree.get_name("x").is_some());
+        assert!(tree.get_name("y").is_some());
+
+        let mut opts = MergeOptions::new();
+        opts.no_recursive(true);
+        let result = repo.merge_base_tree(xy, yx, Some(&opts)).unwrap();
+        assert!(!result.is_virtual());
+        assert_eq!(result.bases().len(), 2);
+        assert_eq!(
+            result.tree_id(),
+            Some(repo.find_commit(result.bases()[0]).unwrap().tree_id())
+        );
+    }
+
+    #[test]
+    fn smoke_merge_base_tree_conflicts() {
+        let (_td, repo) = crate::test::repo_init();
+        let sig = repo.signature().unwrap();
+        let commit = |parents: &[Oid], files: &[(&str, &str)]| {
+            let parents = parents
+                .iter()
+                .map(|id| repo.find_commit(*id).unwrap())
+                .collect::<Vec<_>>();
+            let mut builder = repo.treebuilder(None).unwrap();
+            for (name, content) in files {
+                let blob = repo.blob(content.as_bytes()).unwrap();
+                builder.insert(name, blob, 0o100644).unwrap();
+            }
+            let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+            let parents = parents.iter().collect::<Vec<_>>();
+            repo.commit(None, &sig, &sig, "commit", &tree, &parents)
+                .unwrap()
+        };
+        let base = commit(&[], &[("f", "one\n"), ("g", "g\n")]);
+        let x = commit(&[base], &[("g", "g\n")]);
+        let y = commit(&[base], &[("f", "two\n"), ("g", "g\n")]);
+        let xy = commit(&[x, y], &[("g", "xy\n")]);
+        let yx = commit(&[y, x], &[("g", "yx\n")]);
+
+        let result = repo.merge_base_tree(xy, yx, None).unwrap();
+        assert!(result.is_virtual());
+        assert_eq!(result.conflicts(), [Path::new("f")]);
+        let tree = repo.find_tree(result.tree_id().unwrap()).unwrap();
+        assert!(tree.get_name("f").is_none());
+        assert!(tree.get_name("g").is_some());
+    }
+
+    #[test]
+    fn smoke_merge_file_from_conflict() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree_with = |content: &str| {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("file", blob, 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let ancestor = tree_with("one\ntwo\nthree\n");
+        let ours = tree_with("one\n2\nthree\n");
+        let theirs = tree_with("one\nII\nthree\n");
+        let index = repo.merge_trees(&ancestor, &ours, &theirs, None).unwrap();
+        let conflict = index.conflicts().unwrap().next().unwrap().unwrap();
+
+        let result = repo.merge_file_from_conflict(&conflict, None).unwrap();
+        assert!(!result.is_automergeable());
+        assert_eq!(result.path(), Some("file"));
+        assert_eq!(result.mode(), 0o100644);
+        let content = String::from_utf8(result.content().to_vec()).unwrap();
+        assert!(content.contains("<<<<<<< file\n2\n=======\nII\n>>>>>>> file\n"));
+
+        let mut opts = MergeFileOptions::new();
+        opts.favor(crate::FileFavor::Theirs);
+        let result = repo
+            .merge_file_from_conflict(&conflict, Some(&mut opts))
+            .unwrap();
+        assert!(result.is_automergeable());
+        assert_eq!(result.content(), b"one\nII\nthree\n");
+
+        let mut opts = MergeFileOptions::new();
+        opts.our_label("mine").their_label("yours");
+        let result = repo
+            .merge_file_from_index(
+                None,
+                conflict.our.as_ref().unwrap(),
+                conflict.their.as_ref().unwrap(),
+                Some(&mut opts),
+            )
+            .unwrap();
+        let content = String::from_utf8(result.content().to_vec()).unwrap();
+        assert!(content.contains("<<<<<<< mine"));
+        assert!(content.contains(">>>>>>> yours"));
+    }
+
+    #[test]
+    fn smoke_render_conflict() {
+        let (_td, repo) = crate::test::repo_init();
+        let tree_with = |content: &str| {
+            let blob = repo.blob(content.as_bytes()).unwrap();
+            let mut builder = repo.treebuilder(None).unwrap();
+            builder.insert("file", blob, 0o100644).unwrap();
+            repo.find_tree(builder.write().unwrap()).unwrap()
+        };
+        let ancestor = tree_with("one\ntwo\nthree\n");
+        let ours = tree_with("one\nsame\n2\nthree\n");
+        let theirs = tree_with("one\nsame\nII\nthree\n");
+        let index = repo.merge_trees(&ancestor, &ours, &theirs, None).unwrap();
+
+        let mut opts = MergeFileOptions::new();
+        opts.style_zdiff3(true)
+            .ancestor_label("base")
+            .our_label("mine")
+            .their_label("yours");
+        let result = repo
+            .render_conflict(&index, Path::new("file"), Some(&mut opts))
+            .unwrap();
+        assert_eq!(
+            result.content(),
+            &b"one\nsame\n<<<<<<< mine\n2\n||||||| base\ntwo\n=======\nII\n>>>>>>> yours\nthree\n"
+                [..]
+        );
+
+        let err = repo
+            .render_conflict(&index, Path::new("missing"), None)
+            .unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn smoke_namespaced_names() {
+        let (_td, repo) = crate::test::repo_init();
+        assert_eq!(
+            repo.namespaced_ref_name("refs/heads/main"),
+            "refs/heads/main"
+        );
+        assert_eq!(
+            repo.namespaced_refspec("refs/heads/*:refs/heads/*", Direction::Fetch),
+            "refs/heads/*:refs/heads/*"
+        );
+
+        repo.set_namespace("foo/bar").unwrap();
+        let full = "refs/namespaces/foo/refs/namespaces/bar/refs/heads/main";
+        assert_eq!(repo.namespaced_ref_name("refs/heads/main"), full);
+        assert_eq!(repo.strip_namespace(full), Some("refs/heads/main"));
+        assert_eq!(repo.strip_namespace("refs/heads/main"), None);
+
+        repo.set_namespace("ns").unwrap();
+        assert_eq!(
+            repo.namespaced_refspec("+refs/heads/*:refs/remotes/origin/*", Direction::Fetch),
+            "+refs/heads/*:refs/namespaces/ns/refs/remotes/origin/*"
+        );
+        assert_eq!(
+            repo.namespaced_refspec("refs/heads/main", Direction::Push),
+            "refs/namespaces/ns/refs/heads/main:refs/heads/main"
+        );
+        assert_eq!(
+            repo.namespaced_refspec(":refs/heads/gone", Direction::Push),
+            ":refs/heads/gone"
+        );
+        assert_eq!(
+            repo.namespaced_refspec("^refs/heads/wip", Direction::Fetch),
+            "^refs/heads/wip"
+        );
+    }
+
+    #[test]
+    fn smoke_gitmodules_and_worktree_config() {
+        let (td, repo) = crate::test::repo_init();
+        t!(repo.gitmodules_add("lib", "vendor/lib", "https://example.com/lib"));
+        assert!(repo
+            .gitmodules_add("lib", "other", "https://example.com/lib")
+            .is_err());
+        assert!(repo
+            .gitmodules_add("../evil", "evil", "https://example.com")
+            .is_err());
+        assert!(repo
+            .gitmodules_add("evil", "a/../../b", "https://example.com")
+            .is_err());
+        assert!(repo
+            .gitmodules_add("evil", "evil", "--upload-pack=x")
+            .is_err());
+        let mut gitmodules = t!(crate::Config::open(&td.path().join(".gitmodules")));
+        let gitmodules = t!(gitmodules.snapshot());
+        assert_eq!(t!(gitmodules.get_str("submodule.lib.path")), "vendor/lib");
+        assert!(t!(repo.gitmodules_remove("lib")));
+        assert!(!t!(repo.gitmodules_remove("lib")));
+
+        assert!(repo.worktree_config().is_err());
+        t!(repo.enable_worktree_config());
+        let mut config = t!(repo.worktree_config());
+        t!(config.set_str("user.name", "worktree"));
+        let repo = t!(Repository::open(td.path()));
+        let config = t!(t!(repo.config()).snapshot());
+        assert_eq!(t!(config.get_i32("core.repositoryformatversion")), 1);
+        assert_eq!(t!(config.get_str("user.name")), "worktree");
+    }
+
+    #[test]
+    fn smoke_pseudo_refs() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.refname_to_id("HEAD").unwrap();
+        assert!(repo.fetch_head().unwrap().is_empty());
+        assert!(repo.merge_heads().unwrap().is_empty());
+        assert_eq!(repo.orig_head().unwrap(), None);
+        assert_eq!(repo.cherry_pick_head().unwrap(), None);
+
+        let fetch_head = format!(
+            "{0}\t\tbranch 'main' of https://example.com/repo\n\
+             {0}\tnot-for-merge\tbranch 'next' of https://example.com/repo\n",
+            head
+        );
+        fs::write(repo.path().join("FETCH_HEAD"), fetch_head).unwrap();
+        let entries = repo.fetch_head().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].ref_name(), "refs/heads/main");
+        assert_eq!(entries[0].remote_url(), Some("https://example.com/repo"));
+        assert_eq!(entries[0].id(), head);
+        assert!(entries[0].is_merge());
+        assert!(!entries[1].is_merge());
+
+        fs::write(repo.path().join("MERGE_HEAD"), format!("{}\n", head)).unwrap();
+        assert_eq!(repo.merge_heads().unwrap(), [head]);
+        fs::write(repo.path().join("ORIG_HEAD"), format!("{}\n", head)).unwrap();
+        assert_eq!(repo.orig_head().unwrap(), Some(head));
+
+        repo.reference("refs/worktree/a", head, false, "").unwrap();
+        let names = repo
+            .worktree_references()
+            .unwrap()
+            .names()
+            .map(|n| n.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["refs/worktree/a"]);
+    }
+
+    #[test]
+    fn smoke_write_commit_graph() {
+        let (_td, repo) = crate::test::repo_init();
+        let (first, _) = crate::test::commit(&repo);
+        repo.write_commit_graph().unwrap();
+        crate::CommitGraph::open(repo.path().join("objects")).unwrap();
+
+        let base = repo.head().unwrap().target().unwrap();
+        let (second, _) = crate::test::commit(&repo);
+        assert_eq!(repo.graph_ahead_behind(second, base).unwrap(), (1, 0));
+        assert_eq!(repo.merge_base(second, first).unwrap(), first);
+        let mut walk = repo.revwalk().unwrap();
+        walk.push(second).unwrap();
+        assert_eq!(walk.count(), 3);
+    }
+
     /// create the following:
     ///    /---o4
     ///   /---o3
@@ -4034,6 +6162,106 @@ mod tests {
         }
     }
 
+    #[test]
+    fn smoke_are_paths_ignored() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join(".gitignore"), "*.log\ntarget/\n").unwrap();
+
+        let ignored = repo
+            .are_paths_ignored(&["a.log", "a.txt", "target/debug", "src/b.log"])
+            .unwrap();
+        assert_eq!(ignored, [true, false, true, true]);
+
+        fs::write(td.path().join(".gitignore"), "*.txt\n").unwrap();
+        repo.flush_attr_cache().unwrap();
+        let ignored = repo.are_paths_ignored(&["a.log", "a.txt"]).unwrap();
+        assert_eq!(ignored, [false, true]);
+    }
+
+    #[test]
+    fn smoke_attr_many_and_foreach() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(
+            td.path().join(".gitattributes"),
+            "*.bin binary\n*.txt text eol=lf -diff\n",
+        )
+        .unwrap();
+        let flags = crate::AttrCheckFlags::FILE_THEN_INDEX;
+        let path = Path::new("a.txt");
+
+        let values = repo
+            .get_attr_many(path, &["text", "eol", "diff", "merge"], flags)
+            .unwrap();
+        assert_eq!(values.len(), 4);
+        assert_eq!(
+            crate::AttrValue::from_bytes(values[0]),
+            crate::AttrValue::True
+        );
+        assert_eq!(values[1], Some(&b"lf"[..]));
+        assert_eq!(
+            crate::AttrValue::from_bytes(values[2]),
+            crate::AttrValue::False
+        );
+        assert_eq!(values[3], None);
+
+        let mut attrs = Vec::new();
+        repo.attr_foreach(path, flags, |name, value| {
+            attrs.push((name.to_string(), value.map(|v| v.to_vec())));
+            true
+        })
+        .unwrap();
+        attrs.sort();
+        assert_eq!(attrs.len(), 3);
+        assert_eq!(attrs[1], ("eol".to_string(), Some(b"lf".to_vec())));
+    }
+
+    #[test]
+    fn smoke_attr_from_commit() {
+        let (td, repo) = crate::test::repo_init();
+        fs::write(td.path().join(".gitattributes"), "*.txt eol=crlf\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(".gitattributes")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let commit = repo
+            .commit(None, &sig, &sig, "attrs", &tree, &[&parent])
+            .unwrap();
+        index.remove_path(Path::new(".gitattributes")).unwrap();
+        fs::remove_file(td.path().join(".gitattributes")).unwrap();
+
+        let path = Path::new("a.txt");
+        let mut opts = crate::AttrOptions::new();
+        opts.flags(crate::AttrCheckFlags::NO_SYSTEM);
+        assert_eq!(repo.get_attr_ext(path, "eol", &mut opts).unwrap(), None);
+
+        opts.commit(commit);
+        assert_eq!(
+            repo.get_attr_ext(path, "eol", &mut opts).unwrap(),
+            Some(&b"crlf"[..])
+        );
+        let values = repo
+            .get_attr_many_ext(path, &["eol", "text"], &mut opts)
+            .unwrap();
+        assert_eq!(values, [Some(&b"crlf"[..]), None]);
+        let mut names = Vec::new();
+        repo.attr_foreach_ext(path, &mut opts, |name, _| {
+            names.push(name.to_string());
+            true
+        })
+        .unwrap();
+        assert_eq!(names, ["eol"]);
+
+        let mut opts = crate::AttrOptions::new();
+        opts.flags(crate::AttrCheckFlags::NO_SYSTEM).tree(tree.id());
+        assert_eq!(
+            repo.get_attr_ext(path, "eol", &mut opts).unwrap(),
+            Some(&b"crlf"[..])
+        );
+        opts.commit(parent.id());
+        assert_eq!(repo.get_attr_ext(path, "eol", &mut opts).unwrap(), None);
+    }
+
     #[test]
     fn smoke_is_path_ignored() {
         let (_td, repo) = graph_repo_init();
@@ -4387,4 +6615,67 @@ Committer Name <committer.proper@email> <committer@email>"#,
             crate::test::realpath(worktree_repo.commondir()).unwrap()
         );
     }
+
+    #[test]
+    fn smoke_stage_hunks() {
+        let (td, repo) = crate::test::repo_init();
+        let path = Path::new("file");
+        let old = (1..=20)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        fs::write(td.path().join(path), &old).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(path).unwrap();
+        index.write().unwrap();
+
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 19\n", "line nineteen\n");
+        fs::write(td.path().join(path), &new).unwrap();
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        let mut patch = crate::Patch::from_diff(&diff, 0).unwrap().unwrap();
+        assert_eq!(patch.num_hunks(), 2);
+
+        let entry = repo.stage_hunks(&mut patch, &[1]).unwrap();
+        let blob = repo.find_blob(entry.id).unwrap();
+        assert_eq!(
+            blob.content(),
+            old.replace("line 19\n", "line nineteen\n").as_bytes()
+        );
+        assert_eq!(fs::read_to_string(td.path().join(path)).unwrap(), new);
+    }
+
+    #[test]
+    fn smoke_discard_hunks() {
+        let (td, repo) = crate::test::repo_init();
+        let path = Path::new("file");
+        let old = (1..=20)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        fs::write(td.path().join(path), &old).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(path).unwrap();
+        index.write().unwrap();
+
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 19\n", "");
+        fs::write(td.path().join(path), &new).unwrap();
+        let diff = repo.diff_index_to_workdir(None, None).unwrap();
+        let patch = crate::Patch::from_diff(&diff, 0).unwrap().unwrap();
+        assert_eq!(patch.num_hunks(), 2);
+
+        let expected = old.replace("line 2\n", "line two\n");
+        let content = repo.discard_hunks(&patch, &[1], true).unwrap();
+        assert_eq!(content, expected.as_bytes());
+        assert_eq!(fs::read_to_string(td.path().join(path)).unwrap(), new);
+
+        let content = repo.discard_hunks(&patch, &[0, 1], false).unwrap();
+        assert_eq!(content, old.as_bytes());
+        assert_eq!(fs::read_to_string(td.path().join(path)).unwrap(), old);
+
+        let err = repo.discard_hunks(&patch, &[0], false).unwrap_err();
+        assert_eq!(err.code(), crate::ErrorCode::Modified);
+        assert_eq!(fs::read_to_string(td.path().join(path)).unwrap(), old);
+    }
 }
@@ -0,0 +1,354 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::util;
+use crate::{Error, ErrorCode, ObjectType, Oid, Repository};
+
+/// The outcome of `Repository::rerere`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RerereStatus {
+    replayed: Vec<PathBuf>,
+    recorded: Vec<PathBuf>,
+    resolved: Vec<PathBuf>,
+}
+
+impl RerereStatus {
+    /// Conflicted files which were resolved using a recorded resolution.
+    pub fn replayed(&self) -> &[PathBuf] {
+        &self.replayed
+    }
+
+    /// Conflicted files whose conflicts were recorded, so that their
+    /// resolution can be recorded once they are resolved.
+    pub fn recorded(&self) -> &[PathBuf] {
+        &self.recorded
+    }
+
+    /// Previously recorded conflicts for which a resolution was recorded.
+    pub fn resolved(&self) -> &[PathBuf] {
+        &self.resolved
+    }
+}
+
+/// Returns whether rerere is enabled, either explicitly with
+/// `rerere.enabled` or by the existence of the `rr-cache` directory.
+pub(crate) fn enabled(repo: &Repository) -> Result<bool, Error> {
+    match repo.config()?.get_bool("rerere.enabled") {
+        Ok(enabled) => Ok(enabled),
+        Err(ref e) if e.code() == ErrorCode::NotFound => Ok(cache_dir(repo).is_dir()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Implementation of `Repository::rerere`.
+pub(crate) fn rerere(repo: &Repository) -> Result<RerereStatus, Error> {
+    let mut status = RerereStatus::default();
+    if !enabled(repo)? {
+        return Ok(status);
+    }
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Err(Error::from_str("cannot use rerere in a bare repository")),
+    };
+    let autoupdate = match repo.config()?.get_bool("rerere.autoupdate") {
+        Ok(autoupdate) => autoupdate,
+        Err(ref e) if e.code() == ErrorCode::NotFound => false,
+        Err(e) => return Err(e),
+    };
+    let mut index = repo.index()?;
+    let mut pending = read_merge_rr(repo)?;
+
+    // Record or replay the conflicts which are new since the last run.
+    let mut paths = Vec::new();
+    for conflict in index.conflicts()? {
+        let conflict = conflict?;
+        if let (Some(ours), Some(_)) = (conflict.our, conflict.their) {
+            paths.push(util::bytes2path(&ours.path).to_path_buf());
+        }
+    }
+    for path in paths {
+        if pending.iter().any(|(_, p)| *p == path) {
+            continue;
+        }
+        let content = fs::read(workdir.join(&path))?;
+        let (preimage, id) = match normalize(&content)? {
+            Some(normalized) => normalized,
+            None => continue,
+        };
+        let dir = cache_dir(repo).join(&id);
+        match fs::read(dir.join("postimage")) {
+            Ok(postimage) => {
+                let recorded = fs::read(dir.join("preimage"))?;
+                let merged = crate::merge::merge_buffers(&recorded, &preimage, &postimage)?;
+                if merged.is_automergeable() {
+                    fs::write(workdir.join(&path), merged.content())?;
+                    if autoupdate {
+                        index.add_path(&path)?;
+                    }
+                    status.replayed.push(path);
+                    continue;
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        fs::create_dir_all(&dir).and_then(|()| fs::write(dir.join("preimage"), &preimage))?;
+        pending.push((id, path.clone()));
+        status.recorded.push(path);
+    }
+
+    // Record the resolutions of the conflicts that have been resolved.
+    let mut remaining = Vec::new();
+    for (id, path) in pending {
+        let content = match fs::read(workdir.join(&path)) {
+            Ok(content) => content,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if normalize(&content)?.is_some() {
+            remaining.push((id, path));
+            continue;
+        }
+        let dir = cache_dir(repo).join(&id);
+        fs::create_dir_all(&dir).and_then(|()| fs::write(dir.join("postimage"), &content))?;
+        status.resolved.push(path);
+    }
+    if autoupdate && !status.replayed.is_empty() {
+        index.write()?;
+    }
+    write_merge_rr(repo, &remaining)?;
+    Ok(status)
+}
+
+/// Implementation of `Repository::rerere_clear`.
+pub(crate) fn clear(repo: &Repository) -> Result<(), Error> {
+    for (id, _) in read_merge_rr(repo)? {
+        let dir = cache_dir(repo).join(&id);
+        if !dir.join("postimage").exists() {
+            match fs::remove_dir_all(&dir) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => result?,
+            }
+        }
+    }
+    write_merge_rr(repo, &[])
+}
+
+fn cache_dir(repo: &Repository) -> PathBuf {
+    repo.path().join("rr-cache")
+}
+
+/// Read the conflicts waiting for a resolution from `MERGE_RR`, which holds
+/// a conflict id, a tab and a path, terminated by a NUL byte, for each.
+fn read_merge_rr(repo: &Repository) -> Result<Vec<(String, PathBuf)>, Error> {
+    let data = match fs::read(repo.path().join("MERGE_RR")) {
+        Ok(data) => data,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for entry in data.split(|b| *b == 0).filter(|e| !e.is_empty()) {
+        let tab = match entry.iter().position(|b| *b == b'\t') {
+            Some(tab) => tab,
+            None => return Err(Error::from_str("corrupt MERGE_RR")),
+        };
+        let id = String::from_utf8_lossy(&entry[..tab]).into_owned();
+        entries.push((id, util::bytes2path(&entry[tab + 1..]).to_path_buf()));
+    }
+    Ok(entries)
+}
+
+fn write_merge_rr(repo: &Repository, entries: &[(String, PathBuf)]) -> Result<(), Error> {
+    let path = repo.path().join("MERGE_RR");
+    if entries.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        };
+    }
+    let mut data = Vec::new();
+    for (id, entry) in entries {
+        data.extend_from_slice(id.as_bytes());
+        data.push(b'\t');
+        data.extend_from_slice(util::path_to_repo_path(entry)?.as_bytes());
+        data.push(0);
+    }
+    Ok(fs::write(path, data)?)
+}
+
+/// Normalize the conflicts in `content` the way git does, returning the
+/// preimage and the conflict id, or `None` if there are no conflicts.
+///
+/// The conflict id hashes the sides of the conflicts like git does, but as
+/// the id of a blob holding them rather than as their bare SHA-1 digest, so
+/// the resolutions recorded by git and by this crate are not shared even
+/// though the format of the `rr-cache` directory is the same.
+///
+/// In the preimage the conflict markers lose their labels, the common
+/// ancestor of diff3 style conflicts is dropped and the two sides of each
+/// conflict are sorted, so the same conflict gets the same preimage whatever
+/// side it was merged from.
+fn normalize(content: &[u8]) -> Result<Option<(Vec<u8>, String)>, Error> {
+    enum State {
+        Outside,
+        One,
+        Base,
+        Two,
+    }
+
+    let mut state = State::Outside;
+    let mut preimage = Vec::new();
+    let mut hashed = Vec::new();
+    let mut hunks = 0;
+    let (mut one, mut two) = (Vec::new(), Vec::new());
+    for line in content.split_inclusive(|b| *b == b'\n') {
+        match state {
+            State::Outside if is_marker(line, b'<') => state = State::One,
+            State::Outside => preimage.extend_from_slice(line),
+            State::One if is_marker(line, b'|') => state = State::Base,
+            State::One | State::Base if is_marker(line, b'=') => state = State::Two,
+            State::One => one.extend_from_slice(line),
+            State::Base => {}
+            State::Two if is_marker(line, b'>') => {
+                if one > two {
+                    std::mem::swap(&mut one, &mut two);
+                }
+                preimage.extend_from_slice(b"<<<<<<<\n");
+                preimage.extend_from_slice(&one);
+                preimage.extend_from_slice(b"=======\n");
+                preimage.extend_from_slice(&two);
+                preimage.extend_from_slice(b">>>>>>>\n");
+                for side in [&mut one, &mut two].iter_mut() {
+                    hashed.append(side);
+                    hashed.push(0);
+                }
+                hunks += 1;
+                state = State::Outside;
+            }
+            State::Two => two.extend_from_slice(line),
+        }
+    }
+    match state {
+        State::Outside if hunks > 0 => {
+            let id = Oid::hash_object(ObjectType::Blob, &hashed)?;
+            Ok(Some((preimage, id.to_string())))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns whether `line` is a conflict marker of the default size made of
+/// `marker`. Markers starting or ending a conflict must be followed by a
+/// label.
+fn is_marker(line: &[u8], marker: u8) -> bool {
+    const SIZE: usize = 7;
+    if line.len() <= SIZE || line[..SIZE].iter().any(|b| *b != marker) {
+        return false;
+    }
+    match line[SIZE] {
+        b' ' => true,
+        _ if marker == b'<' || marker == b'>' => false,
+        b => b.is_ascii_whitespace(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn normalize() {
+        let content =
+            b"a\n<<<<<<< HEAD\ntheirs\n||||||| base\nbase\n=======\nours\n>>>>>>> other\nb\n";
+        let (preimage, id) = t!(super::normalize(content)).unwrap();
+        assert_eq!(
+            preimage,
+            b"a\n<<<<<<<\nours\n=======\ntheirs\n>>>>>>>\nb\n".to_vec()
+        );
+        assert_eq!(id, "1f36f52e95a03b9654d2d816d424dfa8aad2c01f");
+        assert!(t!(super::normalize(b"a\n=======\nb\n")).is_none());
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(repo.config()).set_bool("rerere.enabled", true));
+        let sig = t!(repo.signature());
+        let head = t!(t!(repo.head()).peel_to_commit());
+        let commit = |content: &str| {
+            let blob = t!(repo.blob(content.as_bytes()));
+            let mut builder = t!(repo.treebuilder(Some(&head.tree().unwrap())));
+            t!(builder.insert("file", blob, 0o100644));
+            let tree = t!(repo.find_tree(t!(builder.write())));
+            t!(repo.commit(None, &sig, &sig, content, &tree, &[&head]))
+        };
+        let ours = t!(repo.find_object(commit("ours\n"), None));
+        let theirs = commit("theirs\n");
+
+        t!(repo.reset(&ours, crate::ResetType::Hard, None));
+        assert!(repo.cherry_pick_range(head.id(), theirs).is_err());
+        let cache = repo.path().join("rr-cache");
+        let entries = t!(fs::read_dir(&cache)).count();
+        assert_eq!(entries, 1);
+        let status = t!(repo.rerere());
+        assert!(status.recorded().is_empty());
+
+        t!(fs::write(td.path().join("file"), "resolved\n"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("file")));
+        t!(index.write());
+        t!(t!(repo.open_sequencer()).continue_());
+        let postimage = cache
+            .join("1f36f52e95a03b9654d2d816d424dfa8aad2c01f")
+            .join("postimage");
+        assert_eq!(t!(fs::read_to_string(postimage)), "resolved\n");
+        assert!(!repo.path().join("MERGE_RR").exists());
+
+        t!(repo.reset(&ours, crate::ResetType::Hard, None));
+        assert!(repo.cherry_pick_range(head.id(), theirs).is_err());
+        assert_eq!(t!(fs::read_to_string(td.path().join("file"))), "resolved\n");
+        t!(t!(repo.open_sequencer()).abort());
+
+        t!(t!(repo.config()).set_bool("rerere.enabled", false));
+        assert!(repo.cherry_pick_range(head.id(), theirs).is_err());
+        assert!(t!(repo.rerere()).recorded().is_empty());
+        assert_ne!(t!(fs::read_to_string(td.path().join("file"))), "resolved\n");
+    }
+
+    #[test]
+    fn merge_and_rebase() {
+        let (td, repo) = crate::test::repo_init();
+        t!(t!(repo.config()).set_bool("rerere.enabled", true));
+        let sig = t!(repo.signature());
+        let head = t!(t!(repo.head()).peel_to_commit());
+        let commit = |content: &str| {
+            let blob = t!(repo.blob(content.as_bytes()));
+            let mut builder = t!(repo.treebuilder(Some(&head.tree().unwrap())));
+            t!(builder.insert("file", blob, 0o100644));
+            let tree = t!(repo.find_tree(t!(builder.write())));
+            t!(repo.commit(None, &sig, &sig, content, &tree, &[&head]))
+        };
+        let ours = t!(repo.find_object(commit("ours\n"), None));
+        let theirs = commit("theirs\n");
+
+        t!(repo.reset(&ours, crate::ResetType::Hard, None));
+        let annotated = t!(repo.find_annotated_commit(theirs));
+        t!(repo.merge(&[&annotated], None, None));
+        assert!(t!(repo.index()).has_conflicts());
+        assert!(repo.path().join("MERGE_RR").exists());
+        t!(fs::write(td.path().join("file"), "resolved\n"));
+        assert_eq!(t!(repo.rerere()).resolved(), [Path::new("file")]);
+        t!(repo.cleanup_state());
+
+        t!(repo.reset(&ours, crate::ResetType::Hard, None));
+        let branch = t!(repo.find_annotated_commit(ours.id()));
+        let upstream = t!(repo.find_annotated_commit(head.id()));
+        let mut rebase = t!(repo.rebase(Some(&branch), Some(&upstream), Some(&annotated), None));
+        t!(rebase.next().unwrap());
+        assert_eq!(t!(fs::read_to_string(td.path().join("file"))), "resolved\n");
+        t!(rebase.abort());
+        assert!(!repo.path().join("MERGE_RR").exists());
+    }
+}
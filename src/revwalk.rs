@@ -2,6 +2,7 @@ use libc::{c_int, c_uint, c_void};
 use std::ffi::CString;
 use std::marker;
 
+use crate::tracing::CallSpan;
 use crate::util::Binding;
 use crate::{panic, raw, Error, Oid, Repository, Sort};
 
@@ -9,6 +10,8 @@ use crate::{panic, raw, Error, Oid, Repository, Sort};
 /// more leaves and excluding one or more roots.
 pub struct Revwalk<'repo> {
     raw: *mut raw::git_revwalk,
+    span: CallSpan,
+    walked: u64,
     _marker: marker::PhantomData<&'repo Repository>,
 }
 
@@ -47,6 +50,15 @@ impl<'repo, 'cb, C: FnMut(Oid) -> bool> RevwalkWithHideCb<'repo, 'cb, C> {
 }
 
 impl<'repo> Revwalk<'repo> {
+    pub(crate) fn new(raw: *mut raw::git_revwalk, span: CallSpan) -> Revwalk<'repo> {
+        Revwalk {
+            raw,
+            span,
+            walked: 0,
+            _marker: marker::PhantomData,
+        }
+    }
+
     /// Reset a revwalk to allow re-configuring it.
     ///
     /// The revwalk is automatically reset when iteration of its commits
@@ -219,10 +231,7 @@ impl<'repo> Revwalk<'repo> {
 impl<'repo> Binding for Revwalk<'repo> {
     type Raw = *mut raw::git_revwalk;
     unsafe fn from_raw(raw: *mut raw::git_revwalk) -> Revwalk<'repo> {
-        Revwalk {
-            raw,
-            _marker: marker::PhantomData,
-        }
+        Revwalk::new(raw, CallSpan::none())
     }
     fn raw(&self) -> *mut raw::git_revwalk {
         self.raw
@@ -241,10 +250,16 @@ impl<'repo> Iterator for Revwalk<'repo> {
         let mut out: raw::git_oid = raw::git_oid {
             id: [0; raw::GIT_OID_RAWSZ],
         };
-        unsafe {
-            try_call_iter!(raw::git_revwalk_next(&mut out, self.raw()));
+        let raw = self.raw;
+        let next = self.span.in_scope(|| unsafe {
+            try_call_iter!(raw::git_revwalk_next(&mut out, raw));
             Some(Ok(Binding::from_raw(&out as *const _)))
+        });
+        if let Some(Ok(_)) = next {
+            self.walked += 1;
+            self.span.record("commits", self.walked);
         }
+        next
     }
 }
 
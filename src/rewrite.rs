@@ -0,0 +1,719 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::util::bytes2path;
+use crate::{Commit, Error, ErrorClass, ErrorCode, ObjectType, Oid, Repository, Signature};
+use crate::{Sort, Tree};
+
+/// Edits to apply to a linear range of commits with
+/// `Repository::rewrite_commits`, the way `git rebase -i` amends a commit
+/// deep in a stack.
+///
+/// Every commit of the range after an edited one is rewritten on top of
+/// the new commits. When the tree of a commit is changed, or the range is
+/// moved onto other parents, the changes are carried over to the following
+/// commits, which fails on conflicts.
+///
+/// Rewritten commits lose their signatures and extra headers.
+pub struct CommitRewrite<'a> {
+    parents: Option<Vec<Oid>>,
+    messages: HashMap<Oid, String>,
+    authors: HashMap<Oid, Signature<'static>>,
+    trees: HashMap<Oid, Oid>,
+    committer: Option<&'a Signature<'a>>,
+    keep_committer_dates: bool,
+}
+
+impl<'a> CommitRewrite<'a> {
+    /// Creates a rewrite which leaves the commits unchanged.
+    pub fn new() -> CommitRewrite<'a> {
+        CommitRewrite {
+            parents: None,
+            messages: HashMap::new(),
+            authors: HashMap::new(),
+            trees: HashMap::new(),
+            committer: None,
+            keep_committer_dates: false,
+        }
+    }
+
+    /// Move the range onto `parents`, which become the parents of its first
+    /// commit.
+    pub fn reparent(&mut self, parents: &[Oid]) -> &mut CommitRewrite<'a> {
+        self.parents = Some(parents.to_vec());
+        self
+    }
+
+    /// Replace the message of `commit`.
+    pub fn message(&mut self, commit: Oid, message: &str) -> &mut CommitRewrite<'a> {
+        self.messages.insert(commit, message.to_string());
+        self
+    }
+
+    /// Replace the author of `commit`.
+    pub fn author(&mut self, commit: Oid, author: &Signature<'_>) -> &mut CommitRewrite<'a> {
+        self.authors.insert(commit, author.to_owned());
+        self
+    }
+
+    /// Replace the tree of `commit`.
+    pub fn tree(&mut self, commit: Oid, tree: Oid) -> &mut CommitRewrite<'a> {
+        self.trees.insert(commit, tree);
+        self
+    }
+
+    /// The committer of the rewritten commits, defaulting to
+    /// `Repository::signature`.
+    pub fn committer(&mut self, committer: &'a Signature<'a>) -> &mut CommitRewrite<'a> {
+        self.committer = Some(committer);
+        self
+    }
+
+    /// Keep the committer dates of the original commits, instead of the
+    /// time of the committer signature.
+    ///
+    /// Defaults to `false`.
+    pub fn keep_committer_dates(&mut self, keep: bool) -> &mut CommitRewrite<'a> {
+        self.keep_committer_dates = keep;
+        self
+    }
+
+    fn edits(&self, id: Oid) -> bool {
+        self.messages.contains_key(&id)
+            || self.authors.contains_key(&id)
+            || self.trees.contains_key(&id)
+    }
+}
+
+impl Default for CommitRewrite<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn rewrite_commits(
+    repo: &Repository,
+    refname: &str,
+    base: Option<Oid>,
+    rewrite: &CommitRewrite<'_>,
+) -> Result<Vec<(Oid, Oid)>, Error> {
+    let reference = repo.find_reference(refname)?.resolve()?;
+    let name = reference
+        .name()
+        .ok_or_else(|| Error::from_str("reference name is not valid utf-8"))?
+        .to_string();
+    let head = reference
+        .target()
+        .ok_or_else(|| Error::from_str("reference has no target"))?;
+
+    // Collect the range, oldest first.
+    let mut chain = Vec::new();
+    let mut id = head;
+    while Some(id) != base {
+        let commit = repo.find_commit(id)?;
+        match commit.parent_count() {
+            0 if base.is_some() => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Object,
+                    "the base is not an ancestor of the reference",
+                ));
+            }
+            0 => {
+                chain.push(commit);
+                break;
+            }
+            1 => {
+                id = commit.parent_id(0)?;
+                chain.push(commit);
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Object,
+                    format!("cannot rewrite merge commit {}", commit.id()),
+                ));
+            }
+        }
+    }
+    chain.reverse();
+
+    let committer = match rewrite.committer {
+        Some(committer) => committer.to_owned(),
+        None => repo.signature()?,
+    };
+    let mut parents = match (&rewrite.parents, chain.first()) {
+        (Some(parents), _) => parents.clone(),
+        (None, Some(first)) => first.parent_ids().collect(),
+        (None, None) => Vec::new(),
+    };
+    let mut map = Vec::with_capacity(chain.len());
+    for commit in &chain {
+        let id = commit.id();
+        let old_parents = commit.parent_ids().collect::<Vec<_>>();
+        if parents == old_parents && !rewrite.edits(id) {
+            map.push((id, id));
+            parents = vec![id];
+            continue;
+        }
+
+        let tree = match rewrite.trees.get(&id) {
+            Some(tree) => repo.find_tree(*tree)?,
+            None => carry_over(repo, commit, &old_parents, &parents)?,
+        };
+        let author = match rewrite.authors.get(&id) {
+            Some(author) => author.to_owned(),
+            None => commit.author().to_owned(),
+        };
+        let committer = if rewrite.keep_committer_dates {
+            let name = committer
+                .name()
+                .ok_or_else(|| Error::from_str("committer name is not valid utf-8"))?;
+            let email = committer
+                .email()
+                .ok_or_else(|| Error::from_str("committer email is not valid utf-8"))?;
+            Signature::new(name, email, &commit.committer().when())?
+        } else {
+            committer.to_owned()
+        };
+        let message = match rewrite.messages.get(&id) {
+            Some(message) => &message[..],
+            None => commit
+                .message_raw()
+                .ok_or_else(|| Error::from_str("commit message is not valid utf-8"))?,
+        };
+        let parent_commits = parents
+            .iter()
+            .map(|id| repo.find_commit(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parent_refs = parent_commits.iter().collect::<Vec<_>>();
+        let new_id = repo.commit(None, &author, &committer, message, &tree, &parent_refs)?;
+        map.push((id, new_id));
+        parents = vec![new_id];
+    }
+
+    if let Some(&(_, new_head)) = map.last() {
+        if new_head != head {
+            let msg = format!("rewrite: {} commits", map.len());
+            repo.reference_matching(&name, new_head, true, head, &msg)?;
+        }
+    }
+    Ok(map)
+}
+
+/// The tree of `commit` with the changes made by the new parents, or the
+/// original tree if the parents have the same tree.
+fn carry_over<'repo>(
+    repo: &'repo Repository,
+    commit: &Commit<'repo>,
+    old_parents: &[Oid],
+    new_parents: &[Oid],
+) -> Result<Tree<'repo>, Error> {
+    let tree_of = |parents: &[Oid]| -> Result<Tree<'repo>, Error> {
+        match parents.first() {
+            Some(id) => repo.find_commit(*id)?.tree(),
+            None => repo.find_tree(repo.treebuilder(None)?.write()?),
+        }
+    };
+    let old_base = tree_of(old_parents)?;
+    let new_base = tree_of(new_parents)?;
+    if old_base.id() == new_base.id() {
+        return commit.tree();
+    }
+    let mut index = repo.merge_trees(&old_base, &new_base, &commit.tree()?, None)?;
+    if index.has_conflicts() {
+        return Err(Error::new(
+            ErrorCode::MergeConflict,
+            ErrorClass::Merge,
+            format!("conflicts while rewriting commit {}", commit.id()),
+        ));
+    }
+    repo.find_tree(index.write_tree_to(repo)?)
+}
+
+type PathFilter<'cb> = dyn FnMut(&Path) -> bool + 'cb;
+type MessageFilter<'cb> = dyn FnMut(Oid, &str) -> Option<String> + 'cb;
+type IdentityFilter<'cb> = dyn FnMut(&Signature<'_>) -> Option<Signature<'static>> + 'cb;
+
+/// A rewrite of the whole history of a repository with
+/// `Repository::rewrite_history`, like `git filter-repo` does, to remove
+/// files or secrets from every commit or fix up identities and messages.
+///
+/// Every commit reachable from the selected references is rewritten, along
+/// with the annotated tags pointing to them, and the references are updated
+/// to the new history. The new commits are built in memory and written as a
+/// single pack before any reference is updated, so an error leaves the
+/// references untouched.
+///
+/// Rewritten commits and tags lose their signatures and extra headers. The
+/// index and working directory are not updated.
+pub struct HistoryRewrite<'cb> {
+    refs: Vec<String>,
+    paths: Option<Box<PathFilter<'cb>>>,
+    messages: Option<Box<MessageFilter<'cb>>>,
+    identities: Option<Box<IdentityFilter<'cb>>>,
+    prune_empty: bool,
+}
+
+impl<'cb> HistoryRewrite<'cb> {
+    /// Creates a rewrite of the branches and tags which leaves the history
+    /// unchanged.
+    pub fn new() -> HistoryRewrite<'cb> {
+        HistoryRewrite {
+            refs: Vec::new(),
+            paths: None,
+            messages: None,
+            identities: None,
+            prune_empty: true,
+        }
+    }
+
+    /// Rewrite the references matching `glob`, such as `refs/heads/*`,
+    /// instead of `refs/heads/*` and `refs/tags/*`.
+    ///
+    /// May be called several times to add more references. A detached
+    /// `HEAD` is always rewritten.
+    pub fn refs(&mut self, glob: &str) -> &mut HistoryRewrite<'cb> {
+        self.refs.push(glob.to_string());
+        self
+    }
+
+    /// Keep only the files for which `filter` returns `true`, given their
+    /// path from the root of the tree.
+    ///
+    /// Directories left empty are removed.
+    pub fn path_filter<F>(&mut self, filter: F) -> &mut HistoryRewrite<'cb>
+    where
+        F: FnMut(&Path) -> bool + 'cb,
+    {
+        self.paths = Some(Box::new(filter));
+        self
+    }
+
+    /// Rewrite commit messages. `filter` is given the id and message of each
+    /// original commit and returns the new message, or `None` to keep it.
+    pub fn message_filter<F>(&mut self, filter: F) -> &mut HistoryRewrite<'cb>
+    where
+        F: FnMut(Oid, &str) -> Option<String> + 'cb,
+    {
+        self.messages = Some(Box::new(filter));
+        self
+    }
+
+    /// Rewrite the authors, committers and taggers. `filter` returns the new
+    /// signature, or `None` to keep it.
+    pub fn identity_filter<F>(&mut self, filter: F) -> &mut HistoryRewrite<'cb>
+    where
+        F: FnMut(&Signature<'_>) -> Option<Signature<'static>> + 'cb,
+    {
+        self.identities = Some(Box::new(filter));
+        self
+    }
+
+    /// Drop the commits left without changes by the path filter, apart from
+    /// merges and the commits which were already empty.
+    ///
+    /// Defaults to `true`.
+    pub fn prune_empty(&mut self, prune: bool) -> &mut HistoryRewrite<'cb> {
+        self.prune_empty = prune;
+        self
+    }
+
+    fn identity(&mut self, signature: &Signature<'_>) -> Signature<'static> {
+        self.identities
+            .as_mut()
+            .and_then(|filter| filter(signature))
+            .unwrap_or_else(|| signature.to_owned())
+    }
+}
+
+impl Default for HistoryRewrite<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(crate) fn rewrite_history(
+    repo: &Repository,
+    rewrite: &mut HistoryRewrite<'_>,
+) -> Result<HashMap<Oid, Oid>, Error> {
+    // Build the new objects through a second handle on the repository, so
+    // that the mempack doesn't outlive the rewrite.
+    let scratch = Repository::open(repo.path())?;
+    let odb = scratch.odb()?;
+    let mempack = odb.add_new_mempack_backend(1000)?;
+
+    let mut refs = Vec::new();
+    let globs = if rewrite.refs.is_empty() {
+        vec!["refs/heads/*".to_string(), "refs/tags/*".to_string()]
+    } else {
+        rewrite.refs.clone()
+    };
+    for glob in &globs {
+        for reference in scratch.references_glob(glob)? {
+            let reference = reference?;
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                refs.push((name.to_string(), target));
+            }
+        }
+    }
+    if scratch.head_detached()? {
+        if let Some(target) = scratch.head()?.target() {
+            refs.push(("HEAD".to_string(), target));
+        }
+    }
+
+    let mut walk = scratch.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    for (_, target) in &refs {
+        if let Ok(commit) = scratch.find_object(*target, None)?.peel_to_commit() {
+            walk.push(commit.id())?;
+        }
+    }
+
+    let mut trees = HashMap::new();
+    let mut map = HashMap::new();
+    for id in walk {
+        let commit = scratch.find_commit(id?)?;
+        if let Some(new_id) = rewrite_commit(&scratch, rewrite, &commit, &mut map, &mut trees)? {
+            map.insert(commit.id(), new_id);
+        }
+    }
+
+    // The mempack only dumps commits and what they reference, so the new
+    // tags are created in the repository once the commits are there.
+    mempack.write_to_odb(&scratch, &repo.odb()?)?;
+    repo.odb()?.refresh()?;
+
+    let mut updates = Vec::new();
+    for (name, target) in refs {
+        let new_target = match map.get(&target) {
+            Some(id) => Some(*id),
+            None => match repo.find_tag(target) {
+                Ok(tag) => rewrite_tag(repo, rewrite, &tag, &map)?,
+                Err(_) if repo.find_commit(target).is_ok() => None,
+                Err(_) => Some(target),
+            },
+        };
+        if new_target != Some(target) {
+            updates.push((name, target, new_target));
+        }
+    }
+
+    repo.update_refs(|refs| {
+        for (name, old, new) in &updates {
+            match new {
+                Some(new) => refs.update(name, *new, Some(*old), "rewrite history"),
+                None => refs.delete(name, Some(*old)),
+            };
+        }
+        Ok(())
+    })?;
+    Ok(map)
+}
+
+/// Rewrite `commit` on top of the already rewritten parents, returning
+/// `None` if it's pruned without any parent to replace it.
+fn rewrite_commit(
+    repo: &Repository,
+    rewrite: &mut HistoryRewrite<'_>,
+    commit: &Commit<'_>,
+    map: &mut HashMap<Oid, Oid>,
+    trees: &mut HashMap<(Oid, PathBuf), Option<Oid>>,
+) -> Result<Option<Oid>, Error> {
+    let mut parents = Vec::new();
+    for id in commit.parent_ids() {
+        if let Some(new_id) = map.get(&id) {
+            if !parents.contains(new_id) {
+                parents.push(*new_id);
+            }
+        }
+    }
+    let tree = match rewrite.paths.as_mut() {
+        Some(filter) => {
+            match filter_tree(repo, commit.tree_id(), Path::new(""), &mut **filter, trees)? {
+                Some(id) => id,
+                None => repo.treebuilder(None)?.write()?,
+            }
+        }
+        None => commit.tree_id(),
+    };
+
+    if rewrite.prune_empty && commit.parent_count() <= 1 && parents.len() <= 1 {
+        let tree_of = |id: Option<Oid>| -> Result<Oid, Error> {
+            match id {
+                Some(id) => Ok(repo.find_commit(id)?.tree_id()),
+                None => repo.treebuilder(None)?.write(),
+            }
+        };
+        let was_empty = commit.tree_id() == tree_of(commit.parent_ids().next())?;
+        if !was_empty && tree == tree_of(parents.first().copied())? {
+            return Ok(parents.first().copied());
+        }
+    }
+
+    let author = rewrite.identity(&commit.author());
+    let committer = rewrite.identity(&commit.committer());
+    let original = commit
+        .message_raw()
+        .ok_or_else(|| Error::from_str("commit message is not valid utf-8"))?;
+    let filtered = match rewrite.messages.as_mut() {
+        Some(filter) => filter(commit.id(), original),
+        None => None,
+    };
+    let message = filtered.as_deref().unwrap_or(original);
+
+    let unchanged = tree == commit.tree_id()
+        && parents == commit.parent_ids().collect::<Vec<_>>()
+        && author == commit.author()
+        && committer == commit.committer()
+        && message == original;
+    if unchanged {
+        return Ok(Some(commit.id()));
+    }
+    let parent_commits = parents
+        .iter()
+        .map(|id| repo.find_commit(*id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let parent_refs = parent_commits.iter().collect::<Vec<_>>();
+    let tree = repo.find_tree(tree)?;
+    repo.commit(None, &author, &committer, message, &tree, &parent_refs)
+        .map(Some)
+}
+
+/// Keep the files of the tree `id` at `prefix` matching `filter`, returning
+/// `None` if none is left.
+fn filter_tree(
+    repo: &Repository,
+    id: Oid,
+    prefix: &Path,
+    filter: &mut PathFilter<'_>,
+    trees: &mut HashMap<(Oid, PathBuf), Option<Oid>>,
+) -> Result<Option<Oid>, Error> {
+    let key = (id, prefix.to_path_buf());
+    if let Some(filtered) = trees.get(&key) {
+        return Ok(*filtered);
+    }
+    let tree = repo.find_tree(id)?;
+    let mut builder = repo.treebuilder(None)?;
+    let mut changed = false;
+    for entry in tree.iter() {
+        let path = prefix.join(bytes2path(entry.name_bytes()));
+        let kept = if entry.kind() == Some(ObjectType::Tree) {
+            filter_tree(repo, entry.id(), &path, filter, trees)?
+        } else if filter(&path) {
+            Some(entry.id())
+        } else {
+            None
+        };
+        match kept {
+            Some(kept) => {
+                changed |= kept != entry.id();
+                builder.insert(entry.name_bytes(), kept, entry.filemode())?;
+            }
+            None => changed = true,
+        }
+    }
+    let filtered = if builder.is_empty() {
+        None
+    } else if changed {
+        Some(builder.write()?)
+    } else {
+        Some(id)
+    };
+    trees.insert(key, filtered);
+    Ok(filtered)
+}
+
+/// Rewrite an annotated tag of a commit, or of a tag of one, returning
+/// `None` if the commit was pruned.
+fn rewrite_tag(
+    repo: &Repository,
+    rewrite: &mut HistoryRewrite<'_>,
+    tag: &crate::Tag<'_>,
+    map: &HashMap<Oid, Oid>,
+) -> Result<Option<Oid>, Error> {
+    let target = match tag.target_type() {
+        Some(ObjectType::Commit) => map.get(&tag.target_id()).copied(),
+        Some(ObjectType::Tag) => {
+            let nested = repo.find_tag(tag.target_id())?;
+            rewrite_tag(repo, rewrite, &nested, map)?
+        }
+        _ => return Ok(Some(tag.id())),
+    };
+    let target = match target {
+        Some(target) => target,
+        None => return Ok(None),
+    };
+    let tagger = tag.tagger().map(|tagger| rewrite.identity(&tagger));
+    if target == tag.target_id() && tagger == tag.tagger() {
+        return Ok(Some(tag.id()));
+    }
+    let name = String::from_utf8_lossy(tag.name_bytes());
+    let message = String::from_utf8_lossy(tag.message_bytes().unwrap_or(b""));
+    if let Some(tagger) = &tagger {
+        let target = repo.find_object(target, None)?;
+        return repo
+            .tag_annotation_create(&name, &target, tagger, &message)
+            .map(Some);
+    }
+    // libgit2 can't create a tag without a tagger, as old tags can be.
+    let mut buf = format!(
+        "object {}\ntype {}\ntag {}\n",
+        target,
+        tag.target_type().unwrap(),
+        name
+    )
+    .into_bytes();
+    buf.push(b'\n');
+    buf.extend_from_slice(tag.message_bytes().unwrap_or(b""));
+    repo.odb()?.write(ObjectType::Tag, &buf).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::path::Path;
+
+    use crate::{CommitRewrite, HistoryRewrite, Signature};
+
+    #[test]
+    fn rewrite_commits() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = t!(Signature::now("foo", "bar"));
+        let mut index = t!(repo.index());
+        let mut ids = vec![t!(repo.head()).target().unwrap()];
+        for name in &["a", "b", "c"] {
+            t!(fs::write(td.path().join(name), name));
+            t!(index.add_path(name.as_ref()));
+            let tree = t!(repo.find_tree(t!(index.write_tree())));
+            let parent = t!(repo.find_commit(*ids.last().unwrap()));
+            ids.push(t!(repo.commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                name,
+                &tree,
+                &[&parent]
+            )));
+        }
+
+        // Nothing to do.
+        let map = t!(repo.rewrite_commits("HEAD", Some(ids[0]), &CommitRewrite::new()));
+        assert_eq!(
+            map,
+            vec![(ids[1], ids[1]), (ids[2], ids[2]), (ids[3], ids[3])]
+        );
+
+        // Amend the tree and message of `a`, which carries over to `b` and
+        // `c`.
+        let mut builder = t!(repo.treebuilder(Some(&t!(t!(repo.find_commit(ids[1])).tree()))));
+        let blob = t!(repo.blob(b"amended"));
+        t!(builder.insert("a", blob, 0o100644));
+        let tree = t!(builder.write());
+        let author = t!(Signature::now("author", "author@example.com"));
+        let mut rewrite = CommitRewrite::new();
+        rewrite
+            .tree(ids[1], tree)
+            .message(ids[2], "b, reworded")
+            .author(ids[2], &author)
+            .committer(&sig)
+            .keep_committer_dates(true);
+        let map = t!(repo.rewrite_commits("HEAD", Some(ids[0]), &rewrite));
+        assert_eq!(map.len(), 3);
+        assert!(map.iter().all(|(old, new)| old != new));
+
+        let head = t!(repo.head()).target().unwrap();
+        assert_eq!(head, map[2].1);
+        let c = t!(repo.find_commit(head));
+        let b = t!(c.parent(0));
+        let a = t!(b.parent(0));
+        assert_eq!(a.id(), map[0].1);
+        assert_eq!(a.tree_id(), tree);
+        assert_eq!(b.message(), Some("b, reworded"));
+        assert_eq!(b.author().name(), Some("author"));
+        assert_eq!(
+            c.committer().when(),
+            t!(repo.find_commit(ids[3])).committer().when()
+        );
+        let blob = t!(t!(c.tree()).get_path("a".as_ref()));
+        assert_eq!(t!(repo.find_blob(blob.id())).content(), b"amended");
+        assert!(t!(c.tree()).get_path("c".as_ref()).is_ok());
+
+        // Merge commits can't be rewritten.
+        let mut rewrite = CommitRewrite::new();
+        rewrite.reparent(&[ids[0], ids[1]]);
+        t!(repo.rewrite_commits("HEAD", Some(ids[0]), &rewrite));
+        assert!(repo.rewrite_commits("HEAD", None, &rewrite).is_err());
+    }
+
+    #[test]
+    fn rewrite_history() {
+        let (td, repo) = crate::test::repo_init();
+        let sig = t!(repo.signature());
+        let mut index = t!(repo.index());
+        let mut ids = vec![t!(repo.head()).target().unwrap()];
+        let files: &[&[&str]] = &[&["secret", "src/a"], &["src/b"], &["secret"]];
+        for names in files {
+            for name in *names {
+                let path = td.path().join(name);
+                t!(fs::create_dir_all(path.parent().unwrap()));
+                t!(fs::write(&path, format!("{} {}", name, ids.len())));
+                t!(index.add_path(Path::new(name)));
+            }
+            let tree = t!(repo.find_tree(t!(index.write_tree())));
+            let parent = t!(repo.find_commit(*ids.last().unwrap()));
+            let message = format!("commit {}", ids.len());
+            ids.push(t!(repo.commit(
+                Some("HEAD"),
+                &sig,
+                &sig,
+                &message,
+                &tree,
+                &[&parent]
+            )));
+        }
+        let head = t!(repo.find_object(ids[3], None));
+        let v1 = t!(repo.tag("v1", &head, &sig, "v1", false));
+        t!(repo.tag("nested", &t!(repo.find_object(v1, None)), &sig, "", false));
+        t!(repo.tag_lightweight("pruned", &head, false));
+
+        let new_sig = t!(Signature::now("new", "new@example.com"));
+        let mut rewrite = HistoryRewrite::new();
+        rewrite
+            .path_filter(|path| path != Path::new("secret"))
+            .message_filter(|_, message| Some(message.replace("commit", "change")))
+            .identity_filter(|sig| {
+                if sig.name() == Some("name") {
+                    Some(new_sig.clone())
+                } else {
+                    None
+                }
+            });
+        let map = t!(repo.rewrite_history(&mut rewrite));
+        assert_eq!(map.len(), 4);
+        assert_eq!(map[&ids[3]], map[&ids[2]]);
+
+        let head = t!(t!(repo.head()).peel_to_commit());
+        assert_eq!(head.id(), map[&ids[2]]);
+        assert_eq!(head.message(), Some("change 2"));
+        assert_eq!(head.author().email(), Some("new@example.com"));
+        let tree = t!(head.tree());
+        assert!(tree.get_path(Path::new("secret")).is_err());
+        assert!(tree.get_path(Path::new("src/b")).is_ok());
+        let parent = t!(head.parent(0));
+        assert_eq!(parent.id(), map[&ids[1]]);
+        assert_eq!(t!(parent.parent(0)).id(), map[&ids[0]]);
+
+        let tag = t!(t!(repo.revparse_single("refs/tags/v1")).peel_to_tag());
+        assert_eq!(tag.target_id(), head.id());
+        assert_eq!(t!(tag.peel()).id(), head.id());
+        assert_eq!(tag.tagger().unwrap().name(), Some("new"));
+        let nested = t!(t!(repo.revparse_single("refs/tags/nested")).peel_to_tag());
+        assert_eq!(nested.target_id(), tag.id());
+        assert_eq!(t!(nested.peel()).id(), head.id());
+        assert_eq!(nested.tagger().unwrap().name(), Some("new"));
+        assert_eq!(t!(repo.refname_to_id("refs/tags/pruned")), head.id());
+    }
+}
@@ -0,0 +1,422 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{Commit, Error, ErrorClass, ErrorCode, Oid, Repository, RepositoryState};
+use crate::{ResetType, Sort};
+
+/// A sequence of commits being cherry-picked or reverted one by one, like
+/// `git cherry-pick` and `git revert` do when given a range of commits.
+///
+/// The progress of a sequence is kept in the `sequencer` directory of the
+/// repository in the same format git uses, so a sequence which stopped
+/// because of a conflict can be resumed with `continue_` or abandoned with
+/// `abort`, either with this crate or with the `--continue` and `--abort`
+/// options of git.
+pub struct Sequencer<'repo> {
+    repo: &'repo Repository,
+    action: Action,
+    head: Oid,
+    todo: Vec<Oid>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Action {
+    Pick,
+    Revert,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    fn class(&self) -> ErrorClass {
+        match self {
+            Action::Pick => ErrorClass::CherryPick,
+            Action::Revert => ErrorClass::Revert,
+        }
+    }
+
+    fn state(&self) -> RepositoryState {
+        match self {
+            Action::Pick => RepositoryState::CherryPickSequence,
+            Action::Revert => RepositoryState::RevertSequence,
+        }
+    }
+}
+
+impl<'repo> Sequencer<'repo> {
+    /// Start cherry-picking the commits in `from..to`, oldest first.
+    pub(crate) fn cherry_pick(
+        repo: &'repo Repository,
+        from: Oid,
+        to: Oid,
+    ) -> Result<Sequencer<'repo>, Error> {
+        Sequencer::start(repo, Action::Pick, from, to)
+    }
+
+    /// Start reverting the commits in `from..to`, newest first.
+    pub(crate) fn revert(
+        repo: &'repo Repository,
+        from: Oid,
+        to: Oid,
+    ) -> Result<Sequencer<'repo>, Error> {
+        Sequencer::start(repo, Action::Revert, from, to)
+    }
+
+    fn start(
+        repo: &'repo Repository,
+        action: Action,
+        from: Oid,
+        to: Oid,
+    ) -> Result<Sequencer<'repo>, Error> {
+        if repo.state() != RepositoryState::Clean {
+            return Err(Error::new(
+                ErrorCode::Locked,
+                action.class(),
+                "a merge, rebase or sequence is already in progress",
+            ));
+        }
+        let head = repo.head()?.peel_to_commit()?.id();
+        let mut walk = repo.revwalk()?;
+        match action {
+            Action::Pick => walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?,
+            Action::Revert => walk.set_sorting(Sort::TOPOLOGICAL)?,
+        }
+        walk.push(to)?;
+        walk.hide(from)?;
+        let todo = walk.collect::<Result<Vec<_>, _>>()?;
+        Ok(Sequencer {
+            repo,
+            action,
+            head,
+            todo,
+        })
+    }
+
+    /// Open the sequence in progress in the repository.
+    pub(crate) fn open(repo: &'repo Repository) -> Result<Sequencer<'repo>, Error> {
+        let dir = sequencer_dir(repo);
+        let read = |name: &str| match fs::read_to_string(dir.join(name)) {
+            Ok(s) => Ok(s),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Repository,
+                "no cherry-pick or revert sequence in progress",
+            )),
+            Err(e) => Err(Error::from(e)),
+        };
+        let head = read("head")?.trim().parse()?;
+        let mut action = None;
+        let mut todo = Vec::new();
+        for line in read("todo")?.lines() {
+            let mut words = line.split_whitespace();
+            let (this, id) = match (words.next(), words.next()) {
+                (None, _) | (Some("#"), _) => continue,
+                (Some("pick"), Some(id)) | (Some("p"), Some(id)) => (Action::Pick, id),
+                (Some("revert"), Some(id)) => (Action::Revert, id),
+                _ => {
+                    return Err(Error::new(
+                        ErrorCode::Invalid,
+                        ErrorClass::Repository,
+                        format!("invalid sequencer instruction '{}'", line),
+                    ))
+                }
+            };
+            if action.is_some() && action != Some(this) {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::Repository,
+                    "cannot mix cherry-picks and reverts in one sequence",
+                ));
+            }
+            action = Some(this);
+            todo.push(id.parse()?);
+        }
+        Ok(Sequencer {
+            repo,
+            action: action.unwrap_or(Action::Pick),
+            head,
+            todo,
+        })
+    }
+
+    /// The kind of sequence, either `RepositoryState::CherryPickSequence` or
+    /// `RepositoryState::RevertSequence`.
+    pub fn kind(&self) -> RepositoryState {
+        self.action.state()
+    }
+
+    /// The commit `HEAD` pointed to when the sequence was started.
+    pub fn head(&self) -> Oid {
+        self.head
+    }
+
+    /// The commits that are still to be applied, including the one that
+    /// stopped the sequence.
+    pub fn todo(&self) -> &[Oid] {
+        &self.todo
+    }
+
+    /// Apply the remaining commits, returning the ids of the new commits.
+    ///
+    /// Each commit that applies cleanly is committed on top of `HEAD`.
+    /// Cherry-picks keep the original author and message, while reverts are
+    /// authored by the default signature of the repository with a message
+    /// like the one of `git revert`. Commits which turn out to be empty are
+    /// skipped. If a commit conflicts, the sequence stops with an error of
+    /// code `MergeConflict` and its state is saved, leaving the conflicts in
+    /// the index and working directory to be resolved. When rerere is
+    /// enabled, the conflicts are recorded and previously recorded
+    /// resolutions are replayed into the working directory, as
+    /// `Repository::rerere` does.
+    ///
+    /// When the sequence was stopped by a conflict, the resolved index is
+    /// committed first, so all conflicts must have been resolved.
+    pub fn continue_(&mut self) -> Result<Vec<Oid>, Error> {
+        let mut created = Vec::new();
+        if self.repo.state() == self.action.state() {
+            let mut index = self.repo.index()?;
+            if index.has_conflicts() {
+                return Err(Error::new(
+                    ErrorCode::Unmerged,
+                    self.action.class(),
+                    "resolve the conflicts before continuing",
+                ));
+            }
+            crate::rerere::rerere(self.repo)?;
+            let tree = index.write_tree()?;
+            let commit = self.repo.find_commit(self.todo[0])?;
+            created.extend(self.commit(&commit, tree)?);
+            self.todo.remove(0);
+        }
+
+        while !self.todo.is_empty() {
+            let commit = self.repo.find_commit(self.todo[0])?;
+            match self.action {
+                Action::Pick => self.repo.cherrypick(&commit, None)?,
+                Action::Revert => self.repo.revert(&commit, None)?,
+            }
+            let mut index = self.repo.index()?;
+            if index.has_conflicts() {
+                crate::rerere::rerere(self.repo)?;
+                self.save()?;
+                return Err(Error::new(
+                    ErrorCode::MergeConflict,
+                    self.action.class(),
+                    format!("conflicts while applying commit {}", commit.id()),
+                ));
+            }
+            let tree = index.write_tree()?;
+            created.extend(self.commit(&commit, tree)?);
+            self.todo.remove(0);
+        }
+        Ok(created)
+    }
+
+    /// Abandon the sequence, resetting `HEAD`, the index and the working
+    /// directory to where they were when it was started.
+    pub fn abort(&mut self) -> Result<(), Error> {
+        let head = self.repo.find_object(self.head, None)?;
+        self.repo.reset(&head, ResetType::Hard, None)?;
+        self.repo.cleanup_state()?;
+        crate::rerere::clear(self.repo)?;
+        self.todo.clear();
+        Ok(())
+    }
+
+    /// Commit `tree` for the applied `commit`, unless it would be empty, and
+    /// clear the state left by applying it.
+    fn commit(&self, commit: &Commit<'_>, tree: Oid) -> Result<Option<Oid>, Error> {
+        let parent = self.repo.head()?.peel_to_commit()?;
+        let id = if parent.tree_id() == tree {
+            None
+        } else {
+            let message = match self.repo.message() {
+                Ok(message) => crate::message_prettify(message, Some(b'#'))?,
+                Err(ref e) if e.code() == ErrorCode::NotFound => self.message(commit)?,
+                Err(e) => return Err(e),
+            };
+            let tree = self.repo.find_tree(tree)?;
+            let committer = self.repo.signature()?;
+            let author = match self.action {
+                Action::Pick => commit.author().to_owned(),
+                Action::Revert => committer.clone(),
+            };
+            Some(self.repo.commit(
+                Some("HEAD"),
+                &author,
+                &committer,
+                &message,
+                &tree,
+                &[&parent],
+            )?)
+        };
+        // This also removes the sequencer directory, which is written again
+        // if the sequence stops.
+        self.repo.cleanup_state()?;
+        Ok(id)
+    }
+
+    /// The message used for `commit` when the repository has no prepared
+    /// message.
+    fn message(&self, commit: &Commit<'_>) -> Result<String, Error> {
+        match self.action {
+            Action::Pick => commit
+                .message_raw()
+                .map(|m| m.to_string())
+                .ok_or_else(|| Error::from_str("commit message is not valid utf-8")),
+            Action::Revert => Ok(format!(
+                "Revert \"{}\"\n\nThis reverts commit {}.\n",
+                commit.summary().unwrap_or(""),
+                commit.id()
+            )),
+        }
+    }
+
+    /// Write the state of the sequence, in the same format as git.
+    fn save(&self) -> Result<(), Error> {
+        let dir = sequencer_dir(self.repo);
+        let mut todo = String::new();
+        for id in &self.todo {
+            let commit = self.repo.find_commit(*id)?;
+            todo.push_str(&format!(
+                "{} {} {}\n",
+                self.action.name(),
+                id,
+                commit.summary().unwrap_or("")
+            ));
+        }
+        let current = self.repo.head()?.peel_to_commit()?.id();
+        fs::create_dir_all(&dir)
+            .and_then(|()| fs::write(dir.join("head"), format!("{}\n", self.head)))
+            .and_then(|()| fs::write(dir.join("abort-safety"), format!("{}\n", current)))
+            .and_then(|()| fs::write(dir.join("todo"), todo))
+            .map_err(Error::from)
+    }
+}
+
+fn sequencer_dir(repo: &Repository) -> PathBuf {
+    repo.path().join("sequencer")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ErrorCode, Oid, Repository, RepositoryState};
+    use std::fs;
+    use std::path::Path;
+
+    fn commit_file(repo: &Repository, name: &str, content: &str) -> Oid {
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        let mut builder = repo.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, content, &tree, &[&parent])
+            .unwrap()
+    }
+
+    #[test]
+    fn cherry_pick_range() {
+        let (td, repo) = crate::test::repo_init();
+        let base = repo.head().unwrap().target().unwrap();
+        let a = commit_file(&repo, "a", "a\n");
+        commit_file(&repo, "b", "b\n");
+        let c = commit_file(&repo, "conflict", "theirs\n");
+        let head = repo.find_object(base, None).unwrap();
+        t!(repo.reset(&head, crate::ResetType::Hard, None));
+        let ours = commit_file(&repo, "conflict", "ours\n");
+        t!(repo.reset(
+            &repo.find_object(ours, None).unwrap(),
+            crate::ResetType::Hard,
+            None
+        ));
+
+        let err = repo.cherry_pick_range(base, c).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::MergeConflict);
+        assert_eq!(repo.state(), RepositoryState::CherryPickSequence);
+        let mut sequencer = t!(repo.open_sequencer());
+        assert_eq!(sequencer.head(), ours);
+        assert_eq!(sequencer.todo(), [c]);
+        assert_eq!(t!(fs::read_to_string(td.path().join("a"))), "a\n");
+
+        t!(fs::write(td.path().join("conflict"), "resolved\n"));
+        let mut index = t!(repo.index());
+        t!(index.add_path(Path::new("conflict")));
+        t!(index.write());
+        let created = t!(sequencer.continue_());
+        assert_eq!(created.len(), 1);
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        let tip = t!(repo.head().unwrap().peel_to_commit());
+        assert_eq!(tip.id(), created[0]);
+        assert_eq!(tip.message(), Some("theirs\n"));
+        let b = t!(tip.parent(0));
+        assert_eq!(b.message(), Some("b\n"));
+        assert_eq!(t!(b.parent(0)).message(), Some("a\n"));
+        assert!(repo.open_sequencer().is_err());
+
+        t!(repo.reset(
+            &repo.find_object(ours, None).unwrap(),
+            crate::ResetType::Hard,
+            None
+        ));
+        assert!(repo.cherry_pick_range(a, c).is_err());
+        t!(t!(repo.open_sequencer()).abort());
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        assert_eq!(repo.head().unwrap().target(), Some(ours));
+        assert!(!td.path().join("b").exists());
+    }
+
+    #[test]
+    fn revert_range() {
+        let (td, repo) = crate::test::repo_init();
+        let a = commit_file(&repo, "a", "a\n");
+        let b = commit_file(&repo, "conflict", "b\n");
+        let c = commit_file(&repo, "c", "c\n");
+        let d = commit_file(&repo, "conflict", "d\n");
+        let reset_to_d = || {
+            let d = repo.find_object(d, None).unwrap();
+            t!(repo.reset(&d, crate::ResetType::Hard, None));
+        };
+        reset_to_d();
+
+        let created = t!(repo.revert_range(b, d));
+        assert_eq!(created.len(), 2);
+        let tip = t!(repo.head().unwrap().peel_to_commit());
+        assert_eq!(tip.id(), created[1]);
+        let message = format!("Revert \"c\"\n\nThis reverts commit {}.\n", c);
+        assert_eq!(tip.message(), Some(&*message));
+        assert!(!td.path().join("c").exists());
+        assert_eq!(t!(fs::read_to_string(td.path().join("conflict"))), "b\n");
+
+        reset_to_d();
+        let err = repo.revert_range(a, b).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::MergeConflict);
+        assert_eq!(repo.state(), RepositoryState::RevertSequence);
+        let err = repo.revert_range(a, b).unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Locked);
+        t!(t!(repo.open_sequencer()).abort());
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        assert_eq!(repo.head().unwrap().target(), Some(d));
+
+        assert!(repo.revert_range(a, b).is_err());
+        let mut sequencer = t!(repo.open_sequencer());
+        assert_eq!(sequencer.kind(), RepositoryState::RevertSequence);
+        assert_eq!(sequencer.todo(), [b]);
+        t!(fs::remove_file(td.path().join("conflict")));
+        let mut index = t!(repo.index());
+        t!(index.remove_path(Path::new("conflict")));
+        t!(index.write());
+        let created = t!(sequencer.continue_());
+        assert_eq!(repo.state(), RepositoryState::Clean);
+        let tip = t!(repo.head().unwrap().peel_to_commit());
+        assert_eq!(created, [tip.id()]);
+        let message = format!("Revert \"b\"\n\nThis reverts commit {}.\n", b);
+        assert_eq!(tip.message(), Some(&*message));
+        assert_eq!(t!(tip.parent(0)).id(), d);
+    }
+}
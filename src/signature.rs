@@ -61,6 +61,29 @@ impl<'a> Signature<'a> {
         }
     }
 
+    /// Parse a signature in the format used by commit and tag headers, such
+    /// as `Name <email> 1234567890 +0200`.
+    ///
+    /// The time zone offset is kept as written, including the sign of a
+    /// `-0000` offset.
+    pub fn from_buffer(buf: &str) -> Result<Signature<'static>, Error> {
+        crate::init();
+        let mut ret = ptr::null_mut();
+        let buf = CString::new(buf)?;
+        unsafe {
+            try_call!(raw::git_signature_from_buffer(&mut ret, buf));
+            Ok(Binding::from_raw(ret))
+        }
+    }
+
+    /// Format this signature as it is written in commit and tag headers,
+    /// such as `Name <email> 1234567890 +0200`.
+    ///
+    /// This is the inverse of `from_buffer`.
+    pub fn to_raw_string(&self) -> String {
+        format!("{} {}", self, self.when().to_raw_string())
+    }
+
     /// Gets the name on the signature.
     ///
     /// Returns `None` if the name is not valid utf-8
@@ -185,4 +208,23 @@ mod tests {
         drop(s.clone());
         drop(s.to_owned());
     }
+
+    #[test]
+    fn from_buffer() {
+        let s = Signature::from_buffer("foo bar <foo@example.com> 1234567890 +0200").unwrap();
+        assert_eq!(s.name(), Some("foo bar"));
+        assert_eq!(s.email(), Some("foo@example.com"));
+        assert_eq!(s.when(), Time::new(1234567890, 120));
+        assert_eq!(
+            s.to_raw_string(),
+            "foo bar <foo@example.com> 1234567890 +0200"
+        );
+        assert_eq!(s.when().to_rfc2822(), "Sat, 14 Feb 2009 01:31:30 +0200");
+
+        let s = Signature::from_buffer("foo <bar> 1234567890 -0130").unwrap();
+        assert_eq!(s.when().offset_minutes(), -90);
+        assert_eq!(s.to_raw_string(), "foo <bar> 1234567890 -0130");
+
+        assert!(Signature::from_buffer("foo 1234567890 +0000").is_err());
+    }
 }
@@ -1,13 +1,16 @@
 use libc::{c_char, c_uint, size_t};
 use std::ffi::CString;
+use std::fmt::Write;
 use std::iter::FusedIterator;
 use std::marker;
 use std::mem;
 use std::ops::Range;
+use std::ptr;
 use std::str;
 
 use crate::util::{self, Binding};
-use crate::{raw, DiffDelta, IntoCString, Repository, Status};
+use crate::{raw, DiffDelta, DiffFile, Error, ErrorCode, FileMode, IntoCString, Oid};
+use crate::{Repository, Status, SubmoduleIgnore, SubmoduleStatus, Tree};
 
 /// Options that can be provided to `repo.statuses()` to control how the status
 /// information is gathered.
@@ -15,6 +18,22 @@ pub struct StatusOptions {
     raw: raw::git_status_options,
     pathspec: Vec<CString>,
     ptrs: Vec<*const c_char>,
+    baseline: Option<Oid>,
+    fsmonitor: Option<Vec<CString>>,
+    fsmonitor_ptrs: Vec<*const c_char>,
+    effective: raw::git_status_options,
+}
+
+/// The settings of a `StatusOptions` other than its pathspecs.
+///
+/// Unlike `StatusOptions` this holds no pointers, so it can be sent to other
+/// threads and turned back into options there.
+#[derive(Clone, Copy)]
+pub(crate) struct StatusSettings {
+    show: raw::git_status_show_t,
+    flags: c_uint,
+    rename_threshold: u16,
+    baseline: Option<Oid>,
 }
 
 /// Enumeration of possible methods of what can be shown through a status
@@ -76,6 +95,10 @@ impl StatusOptions {
                 raw,
                 pathspec: Vec::new(),
                 ptrs: Vec::new(),
+                baseline: None,
+                fsmonitor: None,
+                fsmonitor_ptrs: Vec::new(),
+                effective: mem::zeroed(),
             }
         }
     }
@@ -225,14 +248,112 @@ impl StatusOptions {
         self
     }
 
+    /// Set the tree to compare the index against instead of the tree of HEAD.
+    ///
+    /// This allows computing status relative to an arbitrary tree, for example
+    /// the tree of a merge base to show the changes since a branch point. The
+    /// tree must belong to the repository the status is computed for.
+    pub fn baseline(&mut self, tree: &Tree<'_>) -> &mut StatusOptions {
+        self.baseline = Some(tree.id());
+        self
+    }
+
+    /// Limit checks of the working directory to the given paths, as reported
+    /// by a file system monitor such as watchman.
+    ///
+    /// This mirrors git's `core.fsmonitor`: the caller asks its monitor which
+    /// paths changed since the token it saved after the previous status call
+    /// and passes them here. Only those paths (and everything below any
+    /// directory among them) are examined in the working directory, while
+    /// changes staged in the index are still reported for every path. New
+    /// untracked files are only found if the monitor reports them.
+    ///
+    /// When set, these paths replace any pathspecs configured with `pathspec`.
+    /// The same effect for `Repository::diff_index_to_workdir` can be had by
+    /// passing the paths to `DiffOptions::pathspec` along with
+    /// `DiffOptions::disable_pathspec_match`.
+    pub fn fsmonitor_paths<T, I>(&mut self, paths: I) -> &mut StatusOptions
+    where
+        T: IntoCString,
+        I: IntoIterator<Item = T>,
+    {
+        let paths = paths
+            .into_iter()
+            .map(|p| util::cstring_to_repo_path(p).unwrap())
+            .collect();
+        self.fsmonitor = Some(paths);
+        self
+    }
+
+    pub(crate) fn baseline_id(&self) -> Option<Oid> {
+        self.baseline
+    }
+
+    pub(crate) fn uses_fsmonitor(&self) -> bool {
+        self.fsmonitor.is_some()
+    }
+
+    pub(crate) fn has_pathspecs(&self) -> bool {
+        !self.pathspec.is_empty()
+    }
+
+    pub(crate) fn settings(&self) -> StatusSettings {
+        StatusSettings {
+            show: self.raw.show,
+            flags: self.raw.flags,
+            rename_threshold: self.raw.rename_threshold,
+            baseline: self.baseline,
+        }
+    }
+
+    pub(crate) fn from_settings(settings: StatusSettings) -> StatusOptions {
+        let mut opts = StatusOptions::new();
+        opts.raw.show = settings.show;
+        opts.raw.flags = settings.flags;
+        opts.raw.rename_threshold = settings.rename_threshold;
+        opts.baseline = settings.baseline;
+        opts
+    }
+
     /// Get a pointer to the inner list of status options.
     ///
     /// This function is unsafe as the returned structure has interior pointers
     /// and may no longer be valid if these options continue to be mutated.
+    ///
+    /// The tree configured with `baseline` is not included in the returned
+    /// structure. If paths were configured with `fsmonitor_paths`, they are
+    /// used as the pathspec in place of the ones added with `pathspec`, and
+    /// pathspec matching is disabled.
     pub unsafe fn raw(&mut self) -> *const raw::git_status_options {
-        self.raw.pathspec.strings = self.ptrs.as_ptr() as *mut _;
-        self.raw.pathspec.count = self.ptrs.len() as size_t;
-        &self.raw
+        self.raw_with(None, &[])
+    }
+
+    /// Like `raw`, but also fills in the baseline tree and, if file system
+    /// monitor paths are configured, uses them plus `staged` as the pathspec.
+    ///
+    /// The options are filled into a copy, so the settings of `self` are left
+    /// untouched. The strings in `staged` must outlive the use of the returned
+    /// options.
+    pub(crate) unsafe fn raw_with(
+        &mut self,
+        baseline: Option<&Tree<'_>>,
+        staged: &[CString],
+    ) -> *const raw::git_status_options {
+        self.effective = ptr::read(&self.raw);
+        match self.fsmonitor {
+            Some(ref paths) => {
+                self.fsmonitor_ptrs = paths.iter().chain(staged).map(|p| p.as_ptr()).collect();
+                self.effective.pathspec.strings = self.fsmonitor_ptrs.as_ptr() as *mut _;
+                self.effective.pathspec.count = self.fsmonitor_ptrs.len() as size_t;
+                self.effective.flags |= raw::GIT_STATUS_OPT_DISABLE_PATHSPEC_MATCH as c_uint;
+            }
+            None => {
+                self.effective.pathspec.strings = self.ptrs.as_ptr() as *mut _;
+                self.effective.pathspec.count = self.ptrs.len() as size_t;
+            }
+        }
+        self.effective.baseline = baseline.map(|t| t.raw()).unwrap_or(ptr::null_mut());
+        &self.effective
     }
 }
 
@@ -267,6 +388,284 @@ impl<'repo> Statuses<'repo> {
             range: 0..self.len(),
         }
     }
+
+    /// Render this list in the format of `git status --porcelain=v2`.
+    ///
+    /// `repo` must be the repository these statuses were gathered from; it is
+    /// used to look up conflicting index stages and submodule states. If
+    /// `branch` is `true` the `# branch.*` header lines are emitted first, as
+    /// with `git status --porcelain=v2 --branch`.
+    ///
+    /// Changed entries are listed first, followed by untracked and then
+    /// ignored entries. Paths are quoted the way git quotes them with the
+    /// default `core.quotePath` setting.
+    pub fn to_porcelain_v2(&self, repo: &Repository, branch: bool) -> Result<String, Error> {
+        let mut out = String::new();
+        let mut untracked = String::new();
+        let mut ignored = String::new();
+        if branch {
+            porcelain_v2_branch(repo, &mut out)?;
+        }
+
+        let mut index = None;
+        for entry in self.iter() {
+            let status = entry.status();
+            let head_to_index = entry.head_to_index();
+            let index_to_workdir = entry.index_to_workdir();
+
+            if status.is_conflicted() {
+                if index.is_none() {
+                    index = Some(repo.index()?);
+                }
+                let path = util::bytes2path(entry.path_bytes());
+                let conflict = index.as_ref().unwrap().conflict_get(path)?;
+                let xy = match (
+                    conflict.ancestor.is_some(),
+                    conflict.our.is_some(),
+                    conflict.their.is_some(),
+                ) {
+                    (true, false, false) => "DD",
+                    (false, true, false) => "AU",
+                    (true, true, false) => "UD",
+                    (false, false, true) => "UA",
+                    (true, false, true) => "DU",
+                    (false, true, true) => "AA",
+                    _ => "UU",
+                };
+                let stages = [&conflict.ancestor, &conflict.our, &conflict.their];
+                let modes = stages.iter().map(|e| e.as_ref().map_or(0, |e| e.mode));
+                let ids = stages
+                    .iter()
+                    .map(|e| e.as_ref().map_or(Oid::zero(), |e| e.id));
+                let workdir_mode = index_to_workdir
+                    .as_ref()
+                    .map_or(0, |d| u32::from(d.new_file().mode()));
+                let all_modes = modes.chain(Some(workdir_mode)).collect::<Vec<_>>();
+                let sub = porcelain_v2_submodule(repo, &entry, &all_modes)?;
+                write!(out, "u {} {}", xy, sub).unwrap();
+                for mode in all_modes {
+                    write!(out, " {:06o}", mode).unwrap();
+                }
+                for id in ids {
+                    write!(out, " {}", id).unwrap();
+                }
+                out.push(' ');
+                quote_path(entry.path_bytes(), &mut out);
+                out.push('\n');
+                continue;
+            }
+
+            if status.is_ignored() {
+                ignored.push_str("! ");
+                quote_path(entry.path_bytes(), &mut ignored);
+                ignored.push('\n');
+                continue;
+            }
+            if status == Status::WT_NEW {
+                untracked.push_str("? ");
+                quote_path(entry.path_bytes(), &mut untracked);
+                untracked.push('\n');
+                continue;
+            }
+
+            let x = if status.is_index_new() {
+                'A'
+            } else if status.is_index_modified() {
+                'M'
+            } else if status.is_index_deleted() {
+                'D'
+            } else if status.is_index_renamed() {
+                'R'
+            } else if status.is_index_typechange() {
+                'T'
+            } else {
+                '.'
+            };
+            let y = if status.is_wt_new() {
+                'A'
+            } else if status.is_wt_modified() {
+                'M'
+            } else if status.is_wt_deleted() {
+                'D'
+            } else if status.is_wt_renamed() {
+                'R'
+            } else if status.is_wt_typechange() {
+                'T'
+            } else {
+                '.'
+            };
+
+            // Without a delta for one side, that side is unchanged from the
+            // adjacent one.
+            let (head, idx) = match (&head_to_index, &index_to_workdir) {
+                (Some(d), _) => (d.old_file(), d.new_file()),
+                (None, Some(d)) => (d.old_file(), d.old_file()),
+                (None, None) => continue,
+            };
+            let mode = |f: &DiffFile<'_>| u32::from(f.mode());
+            let workdir_mode = match index_to_workdir {
+                Some(ref d) => mode(&d.new_file()),
+                None => mode(&idx),
+            };
+            let modes = [mode(&head), mode(&idx), workdir_mode];
+            let sub = porcelain_v2_submodule(repo, &entry, &modes)?;
+
+            let rename = if status.is_index_renamed() {
+                head_to_index.as_ref()
+            } else if status.is_wt_renamed() {
+                index_to_workdir.as_ref()
+            } else {
+                None
+            };
+            let kind = if rename.is_some() { '2' } else { '1' };
+            write!(out, "{} {}{} {}", kind, x, y, sub).unwrap();
+            write!(
+                out,
+                " {:06o} {:06o} {:06o} {} {} ",
+                modes[0],
+                modes[1],
+                modes[2],
+                head.id(),
+                idx.id()
+            )
+            .unwrap();
+            match rename {
+                Some(rename) => {
+                    write!(out, "R{} ", rename.similarity()).unwrap();
+                    quote_path(rename.new_file().path_bytes().unwrap(), &mut out);
+                    out.push('\t');
+                    quote_path(rename.old_file().path_bytes().unwrap(), &mut out);
+                }
+                None => quote_path(entry.path_bytes(), &mut out),
+            }
+            out.push('\n');
+        }
+
+        out.push_str(&untracked);
+        out.push_str(&ignored);
+        Ok(out)
+    }
+}
+
+/// Writes the `# branch.*` header lines of the porcelain v2 format.
+fn porcelain_v2_branch(repo: &Repository, out: &mut String) -> Result<(), Error> {
+    let head = match repo.head() {
+        Ok(head) => Some(head),
+        Err(ref e) if e.code() == ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(e),
+    };
+    match head.as_ref().and_then(|h| h.target()) {
+        Some(id) => writeln!(out, "# branch.oid {}", id).unwrap(),
+        None => out.push_str("# branch.oid (initial)\n"),
+    }
+    if repo.head_detached()? {
+        out.push_str("# branch.head (detached)\n");
+        return Ok(());
+    }
+    let refname = match head {
+        Some(ref head) => head.name().map(|s| s.to_string()),
+        None => repo
+            .find_reference("HEAD")?
+            .symbolic_target()
+            .map(|s| s.to_string()),
+    };
+    let refname = match refname {
+        Some(refname) => refname,
+        None => return Ok(()),
+    };
+    writeln!(
+        out,
+        "# branch.head {}",
+        refname.trim_start_matches("refs/heads/")
+    )
+    .unwrap();
+
+    let upstream = match repo.branch_upstream_name(&refname) {
+        Ok(upstream) => upstream,
+        Err(ref e) if e.code() == ErrorCode::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let upstream = match upstream.as_str() {
+        Some(upstream) => upstream,
+        None => return Ok(()),
+    };
+    let short = upstream
+        .strip_prefix("refs/remotes/")
+        .or_else(|| upstream.strip_prefix("refs/heads/"))
+        .unwrap_or(upstream);
+    writeln!(out, "# branch.upstream {}", short).unwrap();
+
+    // A configured upstream whose ref is missing is reported as "gone" by
+    // omitting the ahead/behind line.
+    let local = head.as_ref().and_then(|h| h.target());
+    let remote = repo
+        .find_reference(upstream)
+        .ok()
+        .and_then(|r| r.resolve().ok())
+        .and_then(|r| r.target());
+    if let (Some(local), Some(remote)) = (local, remote) {
+        let (ahead, behind) = repo.graph_ahead_behind(local, remote)?;
+        writeln!(out, "# branch.ab +{} -{}", ahead, behind).unwrap();
+    }
+    Ok(())
+}
+
+/// Returns the `<sub>` field of the porcelain v2 format for an entry with the
+/// given modes.
+fn porcelain_v2_submodule(
+    repo: &Repository,
+    entry: &StatusEntry<'_>,
+    modes: &[u32],
+) -> Result<String, Error> {
+    if !modes.contains(&u32::from(FileMode::Commit)) {
+        return Ok("N...".to_string());
+    }
+    let path = match entry.path() {
+        Some(path) => path.trim_end_matches('/'),
+        None => return Ok("S...".to_string()),
+    };
+    let name = repo
+        .find_submodule(path)?
+        .name()
+        .unwrap_or(path)
+        .to_string();
+    let status = repo.submodule_status(&name, SubmoduleIgnore::Unspecified)?;
+    let flag = |on: bool, c: char| if on { c } else { '.' };
+    Ok(format!(
+        "S{}{}{}",
+        flag(status.contains(SubmoduleStatus::WD_MODIFIED), 'C'),
+        flag(
+            status.intersects(SubmoduleStatus::WD_INDEX_MODIFIED | SubmoduleStatus::WD_WD_MODIFIED),
+            'M'
+        ),
+        flag(status.contains(SubmoduleStatus::WD_UNTRACKED), 'U'),
+    ))
+}
+
+/// Appends `path` to `out`, quoted the way git quotes paths in its output.
+fn quote_path(path: &[u8], out: &mut String) {
+    let needs_quote = |b: u8| !(0x20..0x7f).contains(&b) || b == b'"' || b == b'\\';
+    if !path.iter().any(|b| needs_quote(*b)) {
+        out.push_str(str::from_utf8(path).unwrap());
+        return;
+    }
+    out.push('"');
+    for &b in path {
+        match b {
+            0x07 => out.push_str("\\a"),
+            0x08 => out.push_str("\\b"),
+            b'\t' => out.push_str("\\t"),
+            b'\n' => out.push_str("\\n"),
+            0x0b => out.push_str("\\v"),
+            0x0c => out.push_str("\\f"),
+            b'\r' => out.push_str("\\r"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b if needs_quote(b) => write!(out, "\\{:03o}", b).unwrap(),
+            b => out.push(b as char),
+        }
+    }
+    out.push('"');
 }
 
 impl<'repo> Binding for Statuses<'repo> {
@@ -370,6 +769,8 @@ impl<'statuses> Binding for StatusEntry<'statuses> {
 #[cfg(test)]
 mod tests {
     use super::StatusOptions;
+    use crate::raw;
+    use libc::c_uint;
     use std::fs::File;
     use std::io::prelude::*;
     use std::path::Path;
@@ -405,6 +806,113 @@ mod tests {
         assert_eq!(status.path(), Some("foo"));
     }
 
+    #[test]
+    fn baseline() {
+        let (td, repo) = crate::test::repo_init();
+        let initial = repo.head().unwrap().peel_to_tree().unwrap();
+        crate::test::commit(&repo);
+        t!(t!(repo.index()).write());
+        t!(File::create(&td.path().join("bar")));
+
+        let mut opts = StatusOptions::new();
+        opts.show(crate::StatusShow::Index);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        assert_eq!(statuses.len(), 0);
+
+        opts.baseline(&initial);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        assert_eq!(statuses.len(), 1);
+        let status = statuses.get(0).unwrap();
+        assert_eq!(status.path(), Some("foo"));
+        assert!(status.status().contains(crate::Status::INDEX_NEW));
+    }
+
+    #[test]
+    fn parallel() {
+        let (td, repo) = crate::test::repo_init();
+        crate::test::commit(&repo);
+        t!(t!(repo.index()).write());
+        for dir in ["a", "b", "c"].iter() {
+            t!(::std::fs::create_dir(td.path().join(dir)));
+            t!(File::create(td.path().join(dir).join("file")));
+        }
+        t!(::std::fs::remove_file(td.path().join("foo")));
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).recurse_untracked_dirs(true);
+        let serial: Vec<_> = t!(repo.statuses(Some(&mut opts)))
+            .iter()
+            .map(|e| (Path::new(e.path().unwrap()).to_path_buf(), e.status()))
+            .collect();
+        let parallel = t!(repo.statuses_parallel(Some(&mut opts), 3));
+        assert_eq!(serial.len(), 4);
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn porcelain_v2() {
+        let (td, repo) = crate::test::repo_init();
+        let (commit, _) = crate::test::commit(&repo);
+        let mut index = t!(repo.index());
+        t!(index.write());
+        t!(t!(File::create(td.path().join("foo"))).write_all(b"changed"));
+        t!(File::create(td.path().join("new file")));
+        t!(File::create(td.path().join("staged")));
+        t!(index.add_path(Path::new("staged")));
+        t!(index.write());
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true);
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        let output = t!(statuses.to_porcelain_v2(&repo, true));
+
+        let empty = index.get_path(Path::new("staged"), 0).unwrap().id;
+        let expected = format!(
+            "# branch.oid {commit}\n\
+             # branch.head main\n\
+             1 .M N... 100644 100644 100644 {empty} {empty} foo\n\
+             1 A. N... 000000 100644 100644 {zero} {empty} staged\n\
+             ? new file\n",
+            commit = commit,
+            empty = empty,
+            zero = crate::Oid::zero(),
+        );
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn fsmonitor() {
+        let (td, repo) = crate::test::repo_init();
+        crate::test::commit(&repo);
+        let mut index = t!(repo.index());
+        t!(index.write());
+        t!(t!(File::create(td.path().join("foo"))).write_all(b"changed"));
+        t!(File::create(td.path().join("bar")));
+        t!(File::create(td.path().join("staged")));
+        t!(index.add_path(Path::new("staged")));
+        t!(index.write());
+
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).fsmonitor_paths(["bar"].iter());
+        let statuses = t!(repo.statuses(Some(&mut opts)));
+        let paths: Vec<_> = statuses
+            .iter()
+            .map(|e| e.path().unwrap().to_string())
+            .collect();
+        assert_eq!(paths, ["bar", "staged"]);
+        assert_eq!(
+            opts.settings().flags & raw::GIT_STATUS_OPT_DISABLE_PATHSPEC_MATCH as c_uint,
+            0
+        );
+
+        let parallel = t!(repo.statuses_parallel(Some(&mut opts), 3));
+        let paths: Vec<_> = parallel
+            .iter()
+            .map(|(path, _)| path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["bar", "staged"]);
+    }
+
     #[test]
     fn gitignore() {
         let (td, repo) = crate::test::repo_init();
@@ -1,13 +1,19 @@
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs;
+use std::io;
 use std::marker;
 use std::mem;
 use std::os::raw::c_int;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
+use std::sync::{Condvar, Mutex};
+use std::thread;
 
 use crate::util::{self, Binding};
-use crate::{build::CheckoutBuilder, SubmoduleIgnore, SubmoduleUpdate};
-use crate::{raw, Error, FetchOptions, Oid, Repository};
+use crate::{build::CheckoutBuilder, SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdate};
+use crate::{raw, BranchType, Config, Error, ErrorClass, ErrorCode, FetchOptions, Oid, Repository};
 
 /// A structure to represent a git [submodule][1]
 ///
@@ -325,6 +331,632 @@ impl<'cb> Default for SubmoduleUpdateOptions<'cb> {
     }
 }
 
+/// Options for `Repository::submodule_add`.
+pub struct SubmoduleAddOptions<'cb> {
+    use_gitlink: bool,
+    fetch: Option<FetchOptions<'cb>>,
+    depth: i32,
+    branch: Option<String>,
+}
+
+/// The status of a submodule along with the ids it records, as returned by
+/// `Repository::submodule_statuses`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubmoduleStatusEntry {
+    name: Vec<u8>,
+    path: PathBuf,
+    status: SubmoduleStatus,
+    head_id: Option<Oid>,
+    index_id: Option<Oid>,
+    workdir_id: Option<Oid>,
+    branch: Option<Vec<u8>>,
+}
+
+/// Callback for `SubmoduleUpdateAllOptions::progress`, called with the path
+/// of each submodule once it has been updated, or has failed to.
+pub type SubmoduleUpdateProgress<'a> = dyn FnMut(&Path, Result<(), &Error>) + Send + 'a;
+
+/// The result of updating each submodule, along with its path, as returned
+/// by `Repository::update_submodules_recursive`.
+pub type SubmoduleUpdateResults = Vec<(PathBuf, Result<(), Error>)>;
+
+/// Options for `Repository::update_submodules_recursive`.
+pub struct SubmoduleUpdateAllOptions<'a> {
+    init: bool,
+    recursive: bool,
+    jobs: usize,
+    fetch: Option<Box<FetchFactory<'a>>>,
+    progress: Option<Box<SubmoduleUpdateProgress<'a>>>,
+}
+
+impl<'a> SubmoduleUpdateAllOptions<'a> {
+    /// Return default options: initialize submodules, recurse into nested
+    /// submodules, and update one submodule at a time.
+    pub fn new() -> SubmoduleUpdateAllOptions<'a> {
+        SubmoduleUpdateAllOptions {
+            init: true,
+            recursive: true,
+            jobs: 1,
+            fetch: None,
+            progress: None,
+        }
+    }
+
+    /// Whether to initialize submodules which are not initialized yet, like
+    /// `--init`. Otherwise they are skipped.
+    pub fn init(&mut self, init: bool) -> &mut SubmoduleUpdateAllOptions<'a> {
+        self.init = init;
+        self
+    }
+
+    /// Whether to also update the submodules of the submodules, like
+    /// `--recursive`.
+    pub fn recursive(&mut self, recursive: bool) -> &mut SubmoduleUpdateAllOptions<'a> {
+        self.recursive = recursive;
+        self
+    }
+
+    /// The number of submodules updated in parallel, like `--jobs`. Each one
+    /// is updated by a thread of its own, with a repository opened by that
+    /// thread. Values below 1 are treated as 1.
+    pub fn jobs(&mut self, jobs: usize) -> &mut SubmoduleUpdateAllOptions<'a> {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Set a function creating the fetch options, such as credential
+    /// callbacks, used to clone and fetch each submodule.
+    pub fn fetch_options<F>(&mut self, f: F) -> &mut SubmoduleUpdateAllOptions<'a>
+    where
+        F: Fn() -> FetchOptions<'static> + Send + Sync + 'a,
+    {
+        self.fetch = Some(Box::new(f));
+        self
+    }
+
+    /// Set a callback called each time a submodule has been updated, or
+    /// failed to, with its path relative to the working directory of the
+    /// top-level repository.
+    pub fn progress<F>(&mut self, cb: F) -> &mut SubmoduleUpdateAllOptions<'a>
+    where
+        F: FnMut(&Path, Result<(), &Error>) + Send + 'a,
+    {
+        self.progress = Some(Box::new(cb));
+        self
+    }
+}
+
+impl<'a> Default for SubmoduleUpdateAllOptions<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Creates the fetch options used to clone and fetch each submodule.
+type FetchFactory<'a> = dyn Fn() -> FetchOptions<'static> + Send + Sync + 'a;
+
+/// A submodule to update: the git directory of the repository containing
+/// it, its name, and the path of that repository in the top-level one.
+struct UpdateJob {
+    repo: PathBuf,
+    name: String,
+    prefix: PathBuf,
+}
+
+struct UpdateQueue {
+    jobs: VecDeque<UpdateJob>,
+    active: usize,
+}
+
+/// Marks a job as done when dropped, even if the worker panics, so that the
+/// other workers don't wait for it forever.
+struct Active<'a>(&'a Mutex<UpdateQueue>, &'a Condvar);
+
+impl Drop for Active<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        queue.active -= 1;
+        self.1.notify_all();
+    }
+}
+
+/// List the submodules of `repo` to update. With `init`, they are
+/// initialized here rather than by the workers, which would otherwise race
+/// to write the configuration of `repo`.
+fn submodule_jobs(repo: &Repository, prefix: &Path, init: bool) -> Result<Vec<UpdateJob>, Error> {
+    let mut jobs = Vec::new();
+    for mut submodule in repo.submodules()? {
+        if init {
+            submodule.init(false)?;
+        }
+        if let Some(name) = submodule.name() {
+            jobs.push(UpdateJob {
+                repo: repo.path().to_path_buf(),
+                name: name.to_string(),
+                prefix: prefix.to_path_buf(),
+            });
+        }
+    }
+    Ok(jobs)
+}
+
+/// Update a single submodule, returning its path and the jobs for its own
+/// submodules.
+fn update_one(
+    job: &UpdateJob,
+    init: bool,
+    recursive: bool,
+    fetch: Option<&FetchFactory<'_>>,
+) -> (PathBuf, Result<Vec<UpdateJob>, Error>) {
+    let repo = match Repository::open(&job.repo) {
+        Ok(repo) => repo,
+        Err(e) => return (job.prefix.join(&job.name), Err(e)),
+    };
+    let mut submodule = match repo.find_submodule(&job.name) {
+        Ok(submodule) => submodule,
+        Err(e) => return (job.prefix.join(&job.name), Err(e)),
+    };
+    let path = job.prefix.join(submodule.path());
+    let result = (|| {
+        // Without `init`, submodules which are not initialized are skipped.
+        let key = format!("submodule.{}.url", job.name);
+        if repo.config()?.get_entry(&key).is_err() {
+            return Ok(Vec::new());
+        }
+        let mut update = SubmoduleUpdateOptions::new();
+        if let Some(fetch) = fetch {
+            update.fetch(fetch());
+        }
+        submodule.update(false, Some(&mut update))?;
+        if !recursive {
+            return Ok(Vec::new());
+        }
+        let child = submodule.open()?;
+        submodule_jobs(&child, &path, init)
+    })();
+    (path, result)
+}
+
+/// Implementation of `Repository::update_submodules_recursive`.
+pub(crate) fn update_all(
+    repo: &Repository,
+    opts: &mut SubmoduleUpdateAllOptions<'_>,
+) -> Result<SubmoduleUpdateResults, Error> {
+    let queue = Mutex::new(UpdateQueue {
+        jobs: submodule_jobs(repo, Path::new(""), opts.init)?.into(),
+        active: 0,
+    });
+    let ready = Condvar::new();
+    let results = Mutex::new(Vec::new());
+    let SubmoduleUpdateAllOptions {
+        init,
+        recursive,
+        jobs,
+        fetch,
+        progress,
+    } = opts;
+    let (init, recursive, fetch) = (*init, *recursive, fetch.as_deref());
+    let progress = Mutex::new(progress.as_mut());
+
+    thread::scope(|scope| {
+        for _ in 0..*jobs {
+            scope.spawn(|| loop {
+                let job = {
+                    let mut queue = queue.lock().unwrap();
+                    loop {
+                        if let Some(job) = queue.jobs.pop_front() {
+                            queue.active += 1;
+                            break job;
+                        }
+                        if queue.active == 0 {
+                            return;
+                        }
+                        queue = ready.wait(queue).unwrap();
+                    }
+                };
+                let _active = Active(&queue, &ready);
+                let (path, result) = update_one(&job, init, recursive, fetch);
+                let result = result.map(|children| {
+                    let mut queue = queue.lock().unwrap();
+                    queue.jobs.extend(children);
+                });
+                if let Some(progress) = progress.lock().unwrap().as_mut() {
+                    progress(&path, result.as_ref().map(|_| ()));
+                }
+                results.lock().unwrap().push((path, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(results)
+}
+
+impl<'cb> SubmoduleAddOptions<'cb> {
+    /// Creates a default set of options for adding a submodule.
+    pub fn new() -> SubmoduleAddOptions<'cb> {
+        SubmoduleAddOptions {
+            use_gitlink: true,
+            fetch: None,
+            depth: 0,
+            branch: None,
+        }
+    }
+
+    /// Whether to store the repository of the submodule in `.git/modules`
+    /// of the superproject, with a gitlink from the working directory, as
+    /// git does, rather than in the working directory directly.
+    ///
+    /// Defaults to `true`.
+    pub fn use_gitlink(&mut self, use_gitlink: bool) -> &mut SubmoduleAddOptions<'cb> {
+        self.use_gitlink = use_gitlink;
+        self
+    }
+
+    /// Options for fetching the submodule, such as credentials callbacks.
+    pub fn fetch(&mut self, opts: FetchOptions<'cb>) -> &mut SubmoduleAddOptions<'cb> {
+        self.fetch = Some(opts);
+        self
+    }
+
+    /// Clone the submodule with a history truncated to `depth` commits, and
+    /// record it as shallow in `.gitmodules`.
+    ///
+    /// Defaults to `0`, cloning the full history.
+    pub fn depth(&mut self, depth: i32) -> &mut SubmoduleAddOptions<'cb> {
+        self.depth = depth.max(0);
+        self
+    }
+
+    /// Check out `branch` of the submodule instead of the default branch of
+    /// its remote, and record it in `.gitmodules`.
+    pub fn branch(&mut self, branch: &str) -> &mut SubmoduleAddOptions<'cb> {
+        self.branch = Some(branch.to_string());
+        self
+    }
+}
+
+impl<'cb> Default for SubmoduleAddOptions<'cb> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Implementation of `Repository::submodule_add`.
+pub(crate) fn add<'repo>(
+    repo: &'repo Repository,
+    url: &str,
+    path: &Path,
+    opts: &mut SubmoduleAddOptions<'_>,
+) -> Result<Submodule<'repo>, Error> {
+    let workdir_path = repo.workdir().map(|workdir| workdir.join(path));
+    let existed = matches!(&workdir_path, Some(p) if p.exists());
+    let mut submodule = repo.submodule(url, path, opts.use_gitlink)?;
+    if let Err(e) = finish_add(repo, &mut submodule, opts) {
+        // Undo what was set up, so that the submodule can be added again.
+        let name = String::from_utf8_lossy(submodule.name_bytes()).into_owned();
+        let _ = gitmodules_remove(repo, &name);
+        if let Some(workdir_path) = workdir_path.filter(|_| !existed) {
+            let _ = fs::remove_dir_all(workdir_path);
+        }
+        if opts.use_gitlink {
+            let _ = fs::remove_dir_all(repo.path().join("modules").join(&name));
+        }
+        return Err(e);
+    }
+    Ok(submodule)
+}
+
+fn finish_add(
+    repo: &Repository,
+    submodule: &mut Submodule<'_>,
+    opts: &mut SubmoduleAddOptions<'_>,
+) -> Result<(), Error> {
+    if opts.branch.is_some() || opts.depth > 0 {
+        let section = format!(
+            "submodule.{}",
+            String::from_utf8_lossy(submodule.name_bytes())
+        );
+        let mut config = open_gitmodules(repo)?;
+        config.transaction(|config| {
+            if let Some(branch) = &opts.branch {
+                config.set_str(&format!("{}.branch", section), branch)?;
+            }
+            if opts.depth > 0 {
+                config.set_bool(&format!("{}.shallow", section), true)?;
+            }
+            Ok(())
+        })?;
+        submodule.reload(true)?;
+    }
+
+    let mut fetch = opts.fetch.take().unwrap_or_default();
+    if opts.depth > 0 {
+        fetch.depth(opts.depth);
+    }
+    let mut update = SubmoduleUpdateOptions::new();
+    update.fetch(fetch);
+    let sub_repo = submodule.clone(Some(&mut update))?;
+
+    if let Some(branch) = &opts.branch {
+        let id = sub_repo.refname_to_id(&format!("refs/remotes/origin/{}", branch))?;
+        // The clone may already have checked out the branch, which
+        // `Repository::branch` refuses to move, so update the reference
+        // directly.
+        let refname = format!("refs/heads/{}", branch);
+        sub_repo.reference(&refname, id, true, "submodule: add")?;
+        let mut local = sub_repo.find_branch(branch, BranchType::Local)?;
+        local.set_upstream(Some(&format!("origin/{}", branch)))?;
+        sub_repo.set_head(&refname)?;
+        sub_repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+    }
+    submodule.add_finalize()
+}
+
+impl SubmoduleStatusEntry {
+    /// The name of the submodule.
+    ///
+    /// Returns `None` if the name is not valid utf-8.
+    pub fn name(&self) -> Option<&str> {
+        str::from_utf8(&self.name).ok()
+    }
+
+    /// The name of the submodule, as a byte array.
+    pub fn name_bytes(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// The path of the submodule, relative to the working directory of the
+    /// superproject.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The status of the submodule.
+    pub fn status(&self) -> SubmoduleStatus {
+        self.status
+    }
+
+    /// The id of the submodule in the `HEAD` commit of the superproject.
+    pub fn head_id(&self) -> Option<Oid> {
+        self.head_id
+    }
+
+    /// The id of the submodule in the index of the superproject.
+    pub fn index_id(&self) -> Option<Oid> {
+        self.index_id
+    }
+
+    /// The id of the `HEAD` of the checked out submodule, if any.
+    pub fn workdir_id(&self) -> Option<Oid> {
+        self.workdir_id
+    }
+
+    /// The branch configured for the submodule.
+    ///
+    /// Returns `None` if there is no branch or if it is not valid utf-8.
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_ref().and_then(|b| str::from_utf8(b).ok())
+    }
+
+    /// The branch configured for the submodule, as a byte array.
+    pub fn branch_bytes(&self) -> Option<&[u8]> {
+        self.branch.as_deref()
+    }
+}
+
+/// Implementation of `Repository::submodule_statuses`.
+pub(crate) fn statuses(
+    repo: &Repository,
+    ignore: SubmoduleIgnore,
+) -> Result<Vec<SubmoduleStatusEntry>, Error> {
+    // Caching the submodules makes `git_submodule_status` reuse those loaded
+    // by `git_submodule_foreach`, instead of reading the index, `HEAD` and
+    // `.gitmodules` again for each of them.
+    struct Cache<'a>(&'a Repository);
+
+    impl Drop for Cache<'_> {
+        fn drop(&mut self) {
+            unsafe {
+                raw::git_repository_submodule_cache_clear(self.0.raw());
+            }
+        }
+    }
+
+    unsafe {
+        try_call!(raw::git_repository_submodule_cache_all(repo.raw()));
+    }
+    let _cache = Cache(repo);
+    let mut entries = Vec::new();
+    for submodule in repo.submodules()? {
+        let name = CString::new(submodule.name_bytes())?;
+        let mut status = 0;
+        unsafe {
+            try_call!(raw::git_submodule_status(
+                &mut status,
+                repo.raw(),
+                name,
+                ignore
+            ));
+        }
+        entries.push(SubmoduleStatusEntry {
+            name: submodule.name_bytes().to_vec(),
+            path: submodule.path().to_path_buf(),
+            status: SubmoduleStatus::from_bits_truncate(status as u32),
+            head_id: submodule.head_id(),
+            index_id: submodule.index_id(),
+            workdir_id: submodule.workdir_id(),
+            branch: submodule.branch_bytes().map(|b| b.to_vec()),
+        });
+    }
+    Ok(entries)
+}
+
+fn invalid_submodule(msg: String) -> Error {
+    Error::new(ErrorCode::Invalid, ErrorClass::Submodule, msg)
+}
+
+/// Check that a submodule name or path is a relative path which doesn't
+/// escape the working directory, as git requires.
+fn validate_relative(what: &str, value: &str) -> Result<(), Error> {
+    let escapes = value.split(['/', '\\']).any(|part| part == "..");
+    if value.is_empty()
+        || value.starts_with('/')
+        || value.starts_with('\\')
+        || value.contains(':')
+        || escapes
+        || value.contains('\n')
+    {
+        return Err(invalid_submodule(format!(
+            "invalid submodule {} '{}'",
+            what, value
+        )));
+    }
+    Ok(())
+}
+
+fn open_gitmodules(repo: &Repository) -> Result<Config, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("cannot edit .gitmodules of a bare repository"))?;
+    Config::open(&workdir.join(".gitmodules"))
+}
+
+/// Implementation of `Repository::gitmodules_add`.
+pub(crate) fn gitmodules_add(
+    repo: &Repository,
+    name: &str,
+    path: &str,
+    url: &str,
+) -> Result<(), Error> {
+    validate_relative("name", name)?;
+    validate_relative("path", path)?;
+    if url.is_empty() || url.starts_with('-') || url.contains('\n') {
+        return Err(invalid_submodule(format!(
+            "invalid submodule url '{}'",
+            url
+        )));
+    }
+    let mut config = open_gitmodules(repo)?;
+    let section = format!("submodule.{}", name);
+    if config.get_entry(&format!("{}.path", section)).is_ok()
+        || config.get_entry(&format!("{}.url", section)).is_ok()
+    {
+        return Err(Error::new(
+            ErrorCode::Exists,
+            ErrorClass::Submodule,
+            format!("submodule '{}' already exists in .gitmodules", name),
+        ));
+    }
+    config.transaction(|config| {
+        config.set_str(&format!("{}.path", section), path)?;
+        config.set_str(&format!("{}.url", section), url)
+    })
+}
+
+/// Implementation of `Repository::absorb_submodule_git_dirs`.
+pub(crate) fn absorb_git_dirs(repo: &Repository, name: Option<&str>) -> Result<usize, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("cannot absorb submodules of a bare repository"))?;
+    let submodules = match name {
+        Some(name) => vec![repo.find_submodule(name)?],
+        None => repo.submodules()?,
+    };
+    let modules = repo.commondir().join("modules");
+    let mut absorbed = 0;
+    for submodule in &submodules {
+        absorbed += absorb(workdir, &modules, submodule, false)?;
+    }
+    Ok(absorbed)
+}
+
+/// Move the git directory of `submodule` into `modules`, and then those of
+/// its own submodules. `relocated` is true if the git directory of the
+/// superproject was just moved, in which case existing gitlinks are
+/// rewritten to follow it.
+fn absorb(
+    workdir: &Path,
+    modules: &Path,
+    submodule: &Submodule<'_>,
+    relocated: bool,
+) -> Result<usize, Error> {
+    let name = submodule
+        .name()
+        .ok_or_else(|| invalid_submodule("submodule name is not valid utf-8".to_string()))?;
+    validate_relative("name", name)?;
+    let sub_workdir = workdir.join(submodule.path());
+    let dotgit = sub_workdir.join(".git");
+    let gitdir = modules.join(name);
+    let moved = match fs::symlink_metadata(&dotgit) {
+        Ok(meta) if meta.is_dir() => {
+            if gitdir.exists() {
+                return Err(Error::new(
+                    ErrorCode::Exists,
+                    ErrorClass::Submodule,
+                    format!(
+                        "cannot absorb submodule '{}': '{}' already exists",
+                        name,
+                        gitdir.display()
+                    ),
+                ));
+            }
+            fs::create_dir_all(gitdir.parent().unwrap())?;
+            fs::rename(&dotgit, &gitdir)?;
+            true
+        }
+        Ok(_) => false,
+        // The submodule is not checked out.
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let relocated = moved || (relocated && gitdir.is_dir());
+    if relocated {
+        if let Err(e) = connect_gitdir(&sub_workdir, &gitdir) {
+            // Put the git directory back rather than leave the submodule
+            // without one.
+            if moved {
+                let _ = fs::remove_file(&dotgit);
+                let _ = fs::rename(&gitdir, &dotgit);
+            }
+            return Err(e);
+        }
+    }
+
+    let repo = Repository::open(&sub_workdir)?;
+    let mut absorbed = moved as usize;
+    for nested in repo.submodules()? {
+        absorbed += absorb(&sub_workdir, &gitdir.join("modules"), &nested, relocated)?;
+    }
+    Ok(absorbed)
+}
+
+/// Point the working directory `workdir` to the git directory `gitdir`
+/// with a gitlink, and back with `core.worktree`, using relative paths as
+/// git does.
+fn connect_gitdir(workdir: &Path, gitdir: &Path) -> Result<(), Error> {
+    let link = format!(
+        "gitdir: {}\n",
+        util::slashes(&util::relative_path(workdir, gitdir))?
+    );
+    fs::write(workdir.join(".git"), link)?;
+    let mut config = Config::open(&gitdir.join("config"))?;
+    config.set_str(
+        "core.worktree",
+        &util::slashes(&util::relative_path(gitdir, workdir))?,
+    )
+}
+
+/// Implementation of `Repository::gitmodules_remove`.
+pub(crate) fn gitmodules_remove(repo: &Repository, name: &str) -> Result<bool, Error> {
+    validate_relative("name", name)?;
+    let mut config = open_gitmodules(repo)?;
+    Ok(!config
+        .remove_section(&format!("submodule.{}", name))?
+        .is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -333,7 +965,10 @@ mod tests {
     use url::Url;
 
     use crate::Repository;
-    use crate::SubmoduleUpdateOptions;
+    use crate::{
+        SubmoduleAddOptions, SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdateAllOptions,
+        SubmoduleUpdateOptions,
+    };
 
     #[test]
     fn smoke() {
@@ -468,4 +1103,115 @@ mod tests {
         t!(child.repo_init(true));
         assert!(child.open().is_ok());
     }
+
+    #[test]
+    fn update_all_submodules() {
+        let (_td, grandchild) = crate::test::repo_init();
+        let (_td, child) = crate::test::repo_init();
+        let (_td, parent) = crate::test::repo_init();
+
+        let url = |repo: &Repository| Url::from_file_path(repo.workdir().unwrap()).unwrap();
+        let add = |repo: &Repository, sub: &Repository, path: &str| {
+            let mut sub = t!(repo.submodule(url(sub).as_str(), Path::new(path), true));
+            t!(sub.clone(None));
+            t!(sub.add_to_index(true));
+            t!(sub.add_finalize());
+            crate::test::commit(repo);
+        };
+        add(&child, &grandchild, "nested");
+        add(&parent, &child, "a");
+        add(&parent, &grandchild, "b");
+
+        let td = TempDir::new().unwrap();
+        let clone = t!(Repository::clone(url(&parent).as_str(), td.path()));
+        let mut seen = Vec::new();
+        let mut opts = SubmoduleUpdateAllOptions::new();
+        opts.jobs(2).progress(|path, result| {
+            assert!(result.is_ok(), "{}: {:?}", path.display(), result);
+            seen.push(path.to_path_buf());
+        });
+        let results = t!(clone.update_submodules_recursive(Some(&mut opts)));
+        drop(opts);
+        let paths = results.iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [Path::new("a"), Path::new("a/nested"), Path::new("b")]
+        );
+        assert!(results.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(seen.len(), 3);
+        assert!(td.path().join("a/nested/.git").exists());
+    }
+
+    #[test]
+    fn submodule_statuses() {
+        let (_td, child) = crate::test::repo_init();
+        let (_td, parent) = crate::test::repo_init();
+        let url = Url::from_file_path(child.workdir().unwrap()).unwrap();
+        let mut sub = t!(parent.submodule(url.as_str(), Path::new("bar"), true));
+        t!(sub.clone(None));
+        t!(sub.add_to_index(true));
+        t!(sub.add_finalize());
+        crate::test::commit(&parent);
+        let id = t!(child.refname_to_id("HEAD"));
+
+        let statuses = t!(parent.submodule_statuses(SubmoduleIgnore::None));
+        assert_eq!(statuses.len(), 1);
+        let entry = &statuses[0];
+        assert_eq!(entry.name(), Some("bar"));
+        assert_eq!(entry.path(), Path::new("bar"));
+        assert!(entry
+            .status()
+            .contains(SubmoduleStatus::IN_HEAD | SubmoduleStatus::IN_INDEX));
+        assert_eq!(entry.head_id(), Some(id));
+        assert_eq!(entry.index_id(), Some(id));
+        assert_eq!(entry.workdir_id(), Some(id));
+        assert_eq!(entry.branch(), None);
+    }
+
+    #[test]
+    fn submodule_add() {
+        let (_td, child) = crate::test::repo_init();
+        let (td, parent) = crate::test::repo_init();
+        let url = Url::from_file_path(child.workdir().unwrap()).unwrap();
+
+        let mut opts = SubmoduleAddOptions::new();
+        opts.branch("main");
+        let sub = t!(parent.submodule_add(url.as_str(), Path::new("bar"), Some(&mut opts)));
+        assert_eq!(sub.branch(), Some("main"));
+        let sub_repo = t!(sub.open());
+        assert_eq!(t!(sub_repo.head()).name(), Some("refs/heads/main"));
+        let index = t!(parent.index());
+        assert!(index.get_path(Path::new("bar"), 0).is_some());
+        assert!(index.get_path(Path::new(".gitmodules"), 0).is_some());
+
+        let bad = td.path().join("missing");
+        let bad = Url::from_file_path(&bad).unwrap();
+        assert!(parent
+            .submodule_add(bad.as_str(), Path::new("baz"), None)
+            .is_err());
+        assert!(!td.path().join("baz").exists());
+        assert!(parent.find_submodule("baz").is_err());
+    }
+
+    #[test]
+    fn absorb_git_dirs() {
+        let (_td, child) = crate::test::repo_init();
+        let (td, parent) = crate::test::repo_init();
+        let url = Url::from_file_path(child.workdir().unwrap()).unwrap();
+        let mut sub = t!(parent.submodule(url.as_str(), Path::new("bar"), false));
+        t!(sub.clone(None));
+        t!(sub.add_finalize());
+        assert!(td.path().join("bar/.git").is_dir());
+
+        assert_eq!(t!(parent.absorb_submodule_git_dirs(None)), 1);
+        assert!(td.path().join("bar/.git").is_file());
+        assert!(parent.path().join("modules/bar").is_dir());
+        let sub = t!(parent.find_submodule("bar"));
+        let sub_repo = t!(sub.open());
+        assert_eq!(
+            t!(sub_repo.head()).target(),
+            Some(t!(child.refname_to_id("HEAD")))
+        );
+        assert_eq!(t!(parent.absorb_submodule_git_dirs(Some("bar"))), 0);
+    }
 }
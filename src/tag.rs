@@ -5,7 +5,7 @@ use std::ptr;
 use std::str;
 
 use crate::util::Binding;
-use crate::{call, raw, signature, Error, Object, ObjectType, Oid, Signature};
+use crate::{call, raw, signature, Error, Object, ObjectType, Oid, Repository, Signature};
 
 /// A structure to represent a git [tag][1]
 ///
@@ -15,6 +15,16 @@ pub struct Tag<'repo> {
     _marker: marker::PhantomData<Object<'repo>>,
 }
 
+/// A tag along with the object it finally points to, as listed by
+/// `Repository::tags_detailed`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TagInfo {
+    name: String,
+    id: Oid,
+    target_id: Oid,
+    annotated: bool,
+}
+
 impl<'repo> Tag<'repo> {
     /// Determine whether a tag name is valid, meaning that (when prefixed with refs/tags/) that
     /// it is a valid reference name, and that any additional tag name restrictions are imposed
@@ -117,6 +127,80 @@ impl<'repo> Tag<'repo> {
     }
 }
 
+impl TagInfo {
+    /// The name of the tag, without the `refs/tags/` prefix.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The id the reference of the tag points to: the tag object for an
+    /// annotated tag, and the tagged object itself for a lightweight tag.
+    pub fn id(&self) -> Oid {
+        self.id
+    }
+
+    /// The id of the object the tag finally points to, once peeled through
+    /// all the tag objects.
+    pub fn target_id(&self) -> Oid {
+        self.target_id
+    }
+
+    /// Whether the tag is an annotated tag, as opposed to a lightweight tag.
+    pub fn is_annotated(&self) -> bool {
+        self.annotated
+    }
+
+    /// Read the tagger of an annotated tag, if it has one.
+    ///
+    /// The tag object is only read here, since listing the tags doesn't need
+    /// it when `packed-refs` records what the tag points to.
+    pub fn tagger(&self, repo: &Repository) -> Result<Option<Signature<'static>>, Error> {
+        if !self.annotated {
+            return Ok(None);
+        }
+        let tag = repo.find_tag(self.id)?;
+        Ok(tag.tagger().map(|tagger| tagger.to_owned()))
+    }
+}
+
+/// Implementation of `Repository::tags_detailed`.
+pub(crate) fn list_detailed(
+    repo: &Repository,
+    pattern: Option<&str>,
+) -> Result<Vec<TagInfo>, Error> {
+    let odb = repo.odb()?;
+    let glob = format!("refs/tags/{}", pattern.unwrap_or("*"));
+    let mut tags = Vec::new();
+    for reference in repo.references_glob(&glob)? {
+        let reference = reference?;
+        let (name, id) = match (reference.name(), reference.target()) {
+            (Some(name), Some(id)) => (name["refs/tags/".len()..].to_string(), id),
+            _ => continue,
+        };
+        // Packed annotated tags come with the object they peel to, so that
+        // their tag objects don't need to be read. That object is only one
+        // level down for a tag of a tag though, so it is peeled further like
+        // the objects of loose tags, whose types are read without inflating
+        // them.
+        let (mut target_id, mut annotated) = match reference.target_peel() {
+            Some(peeled) => (peeled, true),
+            None => (id, false),
+        };
+        while odb.read_header(target_id)?.1 == ObjectType::Tag {
+            target_id = repo.find_tag(target_id)?.target_id();
+            annotated = true;
+        }
+        tags.push(TagInfo {
+            name,
+            id,
+            target_id,
+            annotated,
+        });
+    }
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tags)
+}
+
 impl<'repo> std::fmt::Debug for Tag<'repo> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         let mut ds = f.debug_struct("Tag");
@@ -231,4 +315,42 @@ mod tests {
         let tags = t!(repo.tag_names(Some("b*")));
         assert_eq!(tags.len(), 0);
     }
+
+    #[test]
+    fn tags_detailed() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+        let obj = repo.find_object(head, None).unwrap();
+        let sig = repo.signature().unwrap();
+        let annotated = repo.tag("v1", &obj, &sig, "msg", false).unwrap();
+        let nested = repo
+            .tag(
+                "v1-signed",
+                &repo.find_object(annotated, None).unwrap(),
+                &sig,
+                "msg",
+                false,
+            )
+            .unwrap();
+        repo.tag_lightweight("v2", &obj, false).unwrap();
+        repo.tag_lightweight("other", &obj, false).unwrap();
+
+        let tags = repo.tags_detailed(Some("v*")).unwrap();
+        let names = tags.iter().map(|t| t.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["v1", "v1-signed", "v2"]);
+        assert!(tags.iter().all(|t| t.target_id() == head));
+        assert_eq!(tags[0].id(), annotated);
+        assert!(tags[0].is_annotated());
+        let tagger = tags[0].tagger(&repo).unwrap().unwrap();
+        assert_eq!(tagger.name(), sig.name());
+        assert_eq!(tags[1].id(), nested);
+        assert_eq!(tags[2].id(), head);
+        assert!(!tags[2].is_annotated());
+        assert!(tags[2].tagger(&repo).unwrap().is_none());
+        assert_eq!(repo.tags_detailed(None).unwrap().len(), 4);
+
+        // Packed tags are listed from their peeled values.
+        repo.pack_refs().unwrap();
+        assert_eq!(repo.tags_detailed(Some("v*")).unwrap(), tags);
+    }
 }
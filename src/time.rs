@@ -44,6 +44,93 @@ impl Time {
     pub fn sign(&self) -> char {
         self.raw.sign as u8 as char
     }
+
+    /// Return the same point in time in another time zone, `offset` being
+    /// the time zone offset in minutes.
+    pub fn with_offset(&self, offset: i32) -> Time {
+        Time::new(self.seconds(), offset)
+    }
+
+    /// Format this time as it is written in commit and tag headers, such as
+    /// `1234567890 +0200`.
+    pub fn to_raw_string(&self) -> String {
+        format!("{} {}", self.seconds(), self.format_offset(""))
+    }
+
+    /// Format this time in its own time zone following ISO 8601, such as
+    /// `2009-02-14T01:31:30+02:00`, or `2009-02-13T23:31:30Z` for UTC.
+    pub fn to_iso8601(&self) -> String {
+        let (year, month, day, hour, minute, second, _) = self.civil();
+        let offset = if self.offset_minutes() == 0 && self.sign() != '-' {
+            "Z".to_string()
+        } else {
+            self.format_offset(":")
+        };
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+            year, month, day, hour, minute, second, offset
+        )
+    }
+
+    /// Format this time in its own time zone following RFC 2822, such as
+    /// `Sat, 14 Feb 2009 01:31:30 +0200`.
+    pub fn to_rfc2822(&self) -> String {
+        const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let (year, month, day, hour, minute, second, weekday) = self.civil();
+        format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            DAYS[weekday as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            hour,
+            minute,
+            second,
+            self.format_offset("")
+        )
+    }
+
+    /// Format the offset as `+hhmm`, with `separator` between the hours and
+    /// the minutes.
+    fn format_offset(&self, separator: &str) -> String {
+        let offset = self.offset_minutes();
+        let sign = if offset < 0 { '-' } else { self.sign() };
+        let sign = if sign == '-' { '-' } else { '+' };
+        let offset = offset.abs();
+        format!("{}{:02}{}{:02}", sign, offset / 60, separator, offset % 60)
+    }
+
+    /// Split the local time into year, month, day, hours, minutes, seconds
+    /// and day of the week (0 being Sunday).
+    fn civil(&self) -> (i64, u32, u32, u32, u32, u32, u32) {
+        let local = self.seconds() + i64::from(self.offset_minutes()) * 60;
+        let days = local.div_euclid(86400);
+        let secs = local.rem_euclid(86400) as u32;
+        let weekday = (days + 4).rem_euclid(7) as u32;
+
+        // Days to civil date, from Howard Hinnant's `civil_from_days`.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            weekday,
+        )
+    }
 }
 
 impl PartialOrd for Time {
@@ -5,7 +5,7 @@ use std::{
 
 use libc::{c_char, c_int};
 
-use crate::{panic, raw, util::Binding, Error};
+use crate::{panic, raw, util::Binding, Error, Repository};
 
 /// Available tracing levels.  When tracing is set to a particular level,
 /// callers will be provided tracing at the given level and all lower levels.
@@ -125,6 +125,122 @@ extern "C" fn tracing_cb_c(level: raw::git_trace_level_t, msg: *const c_char) {
     });
 }
 
+/// Forward the trace messages of libgit2 up to `level` to the `tracing`
+/// crate, as events with the `git2::libgit2` target.
+///
+/// This replaces any callback registered with `trace_set`.
+#[cfg(feature = "tracing")]
+pub fn trace_set_tracing(level: TraceLevel) -> Result<(), Error> {
+    trace_set(level, forward_trace)
+}
+
+#[cfg(feature = "tracing")]
+fn forward_trace(level: TraceLevel, msg: &[u8]) {
+    let msg = String::from_utf8_lossy(msg);
+    match level {
+        TraceLevel::None => {}
+        TraceLevel::Fatal | TraceLevel::Error => {
+            ::tracing::error!(target: "git2::libgit2", "{}", msg)
+        }
+        TraceLevel::Warn => ::tracing::warn!(target: "git2::libgit2", "{}", msg),
+        TraceLevel::Info => ::tracing::info!(target: "git2::libgit2", "{}", msg),
+        TraceLevel::Debug => ::tracing::debug!(target: "git2::libgit2", "{}", msg),
+        TraceLevel::Trace => ::tracing::trace!(target: "git2::libgit2", "{}", msg),
+    }
+}
+
+/// A span around a high-level call such as a fetch or a checkout, reported
+/// to the `tracing` crate when the `tracing` feature is enabled and doing
+/// nothing otherwise.
+pub(crate) struct CallSpan {
+    #[cfg(feature = "tracing")]
+    span: ::tracing::Span,
+}
+
+impl CallSpan {
+    /// A span which reports nothing.
+    pub(crate) fn none() -> CallSpan {
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: ::tracing::Span::none(),
+        }
+    }
+
+    /// A span around a fetch from the remote at `url`.
+    pub(crate) fn fetch(url: Option<&str>) -> CallSpan {
+        let _ = url;
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: ::tracing::info_span!(
+                "git2::fetch",
+                remote.url = url.unwrap_or(""),
+                total_objects = ::tracing::field::Empty,
+                received_objects = ::tracing::field::Empty,
+                received_bytes = ::tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// A span around a checkout of `what`, such as `"head"`, in `repo`.
+    pub(crate) fn checkout(repo: &Repository, what: &str) -> CallSpan {
+        let _ = (repo, what);
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: ::tracing::info_span!(
+                "git2::checkout",
+                repo.path = %repo.path().display(),
+                source = what,
+            ),
+        }
+    }
+
+    /// A span around a diff of `what`, such as `"tree_to_tree"`, in `repo`.
+    pub(crate) fn diff(repo: &Repository, what: &str) -> CallSpan {
+        let _ = (repo, what);
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: ::tracing::debug_span!(
+                "git2::diff",
+                repo.path = %repo.path().display(),
+                kind = what,
+                deltas = ::tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// A span around the iterations of a revwalk in `repo`.
+    pub(crate) fn revwalk(repo: &Repository) -> CallSpan {
+        let _ = repo;
+        CallSpan {
+            #[cfg(feature = "tracing")]
+            span: ::tracing::debug_span!(
+                "git2::revwalk",
+                repo.path = %repo.path().display(),
+                commits = ::tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Run `f` within this span.
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.in_scope(f)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            f()
+        }
+    }
+
+    /// Set the value of the count `field` of this span.
+    pub(crate) fn record(&self, field: &str, value: u64) {
+        let _ = (field, value);
+        #[cfg(feature = "tracing")]
+        self.span.record(field, value);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::TraceLevel;
@@ -137,4 +253,112 @@ mod tests {
         })
         .expect("libgit2 can set global trace callback");
     }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn forward_to_tracing() {
+        super::trace_set_tracing(TraceLevel::Trace).expect("libgit2 can set global trace callback");
+
+        let (_td, repo) = crate::test::repo_init();
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        assert_eq!(walk.count(), 1);
+    }
+
+    /// A subscriber which keeps the spans and events it is given as text.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl Recorder {
+        fn push(&self, kind: &str, name: &str, fields: impl FnOnce(&mut FieldsVisitor)) {
+            let mut visitor = FieldsVisitor(format!("{} {}", kind, name));
+            fields(&mut visitor);
+            self.0.lock().unwrap().push(visitor.0);
+        }
+
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.0.lock().unwrap())
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    struct FieldsVisitor(String);
+
+    #[cfg(feature = "tracing")]
+    impl ::tracing::field::Visit for FieldsVisitor {
+        fn record_debug(&mut self, field: &::tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl ::tracing::Subscriber for Recorder {
+        fn enabled(&self, _: &::tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &::tracing::span::Attributes<'_>) -> ::tracing::span::Id {
+            self.push("span", span.metadata().name(), |v| span.record(v));
+            ::tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &::tracing::span::Id, values: &::tracing::span::Record<'_>) {
+            self.push("record", "", |v| values.record(v));
+        }
+
+        fn record_follows_from(&self, _: &::tracing::span::Id, _: &::tracing::span::Id) {}
+
+        fn event(&self, event: &::tracing::Event<'_>) {
+            self.push("event", event.metadata().target(), |v| event.record(v));
+        }
+
+        fn enter(&self, _: &::tracing::span::Id) {}
+
+        fn exit(&self, _: &::tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn spans_and_events() {
+        let recorder = Recorder::default();
+        let (_td, repo) = crate::test::repo_init();
+        let path = repo.path().display().to_string();
+        ::tracing::subscriber::with_default(recorder.clone(), || {
+            let mut walk = repo.revwalk().unwrap();
+            walk.push_head().unwrap();
+            assert_eq!(walk.count(), 1);
+            assert_eq!(
+                recorder.take(),
+                [
+                    format!("span git2::revwalk repo.path={}", path),
+                    "record  commits=1".to_string(),
+                ]
+            );
+
+            let tree = repo.head().unwrap().peel_to_tree().unwrap();
+            repo.diff_tree_to_tree(Some(&tree), Some(&tree), None)
+                .unwrap();
+            assert_eq!(
+                recorder.take(),
+                [
+                    format!("span git2::diff repo.path={} kind=\"tree_to_tree\"", path),
+                    "record  deltas=0".to_string(),
+                ]
+            );
+
+            repo.checkout_head(None).unwrap();
+            assert_eq!(
+                recorder.take(),
+                [format!(
+                    "span git2::checkout repo.path={} source=\"head\"",
+                    path
+                )]
+            );
+
+            super::forward_trace(TraceLevel::Warn, b"odd");
+            assert_eq!(recorder.take(), ["event git2::libgit2 message=odd"]);
+        });
+    }
 }
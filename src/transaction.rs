@@ -1,7 +1,10 @@
 use std::ffi::CString;
 use std::marker;
 
-use crate::{raw, util::Binding, Error, Oid, Reflog, Repository, Signature};
+use crate::{
+    raw, util::Binding, Error, ErrorClass, ErrorCode, Oid, ReferenceType, Reflog, Repository,
+    Signature,
+};
 
 /// A structure representing a transactional update of a repository's references.
 ///
@@ -149,6 +152,160 @@ impl<'repo> Transaction<'repo> {
     }
 }
 
+/// A set of reference updates applied together by `Repository::update_refs`.
+///
+/// Updates can check the value a reference had before: `None` skips the
+/// check, `Some(Oid::zero())` requires the reference not to exist and any
+/// other id requires the reference to point to it.
+pub struct RefUpdates {
+    updates: Vec<RefUpdate>,
+}
+
+struct RefUpdate {
+    name: String,
+    expected: Option<Oid>,
+    target: RefTarget,
+    message: String,
+}
+
+#[derive(Clone, PartialEq)]
+enum RefTarget {
+    Direct(Oid),
+    Symbolic(String),
+    Missing,
+}
+
+impl RefUpdates {
+    /// Point `refname` to `target`, creating it if needed.
+    pub fn update(
+        &mut self,
+        refname: &str,
+        target: Oid,
+        expected: Option<Oid>,
+        message: &str,
+    ) -> &mut RefUpdates {
+        self.push(refname, RefTarget::Direct(target), expected, message)
+    }
+
+    /// Make `refname` a symbolic reference to `target`, creating it if
+    /// needed.
+    pub fn update_symbolic(
+        &mut self,
+        refname: &str,
+        target: &str,
+        expected: Option<Oid>,
+        message: &str,
+    ) -> &mut RefUpdates {
+        let target = RefTarget::Symbolic(target.to_string());
+        self.push(refname, target, expected, message)
+    }
+
+    /// Delete `refname`.
+    pub fn delete(&mut self, refname: &str, expected: Option<Oid>) -> &mut RefUpdates {
+        self.push(refname, RefTarget::Missing, expected, "")
+    }
+
+    fn push(
+        &mut self,
+        refname: &str,
+        target: RefTarget,
+        expected: Option<Oid>,
+        message: &str,
+    ) -> &mut RefUpdates {
+        self.updates.push(RefUpdate {
+            name: refname.to_string(),
+            expected,
+            target,
+            message: message.to_string(),
+        });
+        self
+    }
+}
+
+fn current_target(repo: &Repository, refname: &str) -> Result<RefTarget, Error> {
+    match repo.find_reference(refname) {
+        Ok(r) => match r.kind() {
+            Some(ReferenceType::Symbolic) => Ok(RefTarget::Symbolic(
+                String::from_utf8_lossy(r.symbolic_target_bytes().unwrap_or(b"")).into_owned(),
+            )),
+            _ => Ok(RefTarget::Direct(r.target().unwrap_or_else(Oid::zero))),
+        },
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(RefTarget::Missing),
+        Err(e) => Err(e),
+    }
+}
+
+fn resolved_id(repo: &Repository, target: &RefTarget) -> Oid {
+    match target {
+        RefTarget::Direct(id) => *id,
+        RefTarget::Symbolic(name) => repo.refname_to_id(name).unwrap_or_else(|_| Oid::zero()),
+        RefTarget::Missing => Oid::zero(),
+    }
+}
+
+fn apply(
+    tx: &mut Transaction<'_>,
+    name: &str,
+    target: &RefTarget,
+    message: &str,
+) -> Result<(), Error> {
+    match target {
+        RefTarget::Direct(id) => tx.set_target(name, *id, None, message),
+        RefTarget::Symbolic(to) => tx.set_symbolic_target(name, to, None, message),
+        RefTarget::Missing => tx.remove(name),
+    }
+}
+
+/// Implementation of `Repository::update_refs`.
+pub(crate) fn update_refs<T, F>(repo: &Repository, f: F) -> Result<T, Error>
+where
+    F: FnOnce(&mut RefUpdates) -> Result<T, Error>,
+{
+    let mut updates = RefUpdates {
+        updates: Vec::new(),
+    };
+    let ret = f(&mut updates)?;
+    let updates = updates.updates;
+
+    let mut tx = repo.transaction()?;
+    let mut previous = Vec::with_capacity(updates.len());
+    for update in &updates {
+        tx.lock_ref(&update.name)?;
+    }
+    for update in &updates {
+        let current = current_target(repo, &update.name)?;
+        if let Some(expected) = update.expected {
+            if resolved_id(repo, &current) != expected {
+                return Err(Error::new(
+                    ErrorCode::Modified,
+                    ErrorClass::Reference,
+                    format!("reference '{}' is not at the expected value", update.name),
+                ));
+            }
+        }
+        previous.push(current);
+    }
+    for update in &updates {
+        apply(&mut tx, &update.name, &update.target, &update.message)?;
+    }
+    if let Err(e) = tx.commit() {
+        // Put back the references which were already written.
+        if let Ok(mut tx) = repo.transaction() {
+            for (update, previous) in updates.iter().zip(&previous) {
+                if tx.lock_ref(&update.name).is_err() {
+                    continue;
+                }
+                if current_target(repo, &update.name).ok().as_ref() == Some(&update.target) {
+                    let _ = apply(&mut tx, &update.name, previous, "update_refs: rollback");
+                }
+            }
+            let _ = tx.commit();
+        }
+        return Err(e);
+    }
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{Error, ErrorClass, ErrorCode, Oid, Repository};
@@ -282,4 +439,50 @@ mod tests {
             Err(e) if is_not_locked_err(&e)
         ))
     }
+
+    #[test]
+    fn update_refs() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = t!(repo.refname_to_id("HEAD"));
+        let (next, _) = crate::test::commit(&repo);
+
+        t!(repo.update_refs(|refs| {
+            refs.update("refs/heads/a", head, Some(Oid::zero()), "create a")
+                .update("refs/heads/main", head, Some(next), "rewind main")
+                .update_symbolic("refs/heads/b", "refs/heads/a", None, "link b");
+            Ok(())
+        }));
+        assert_eq!(t!(repo.refname_to_id("refs/heads/a")), head);
+        assert_eq!(t!(repo.refname_to_id("refs/heads/main")), head);
+        assert_eq!(t!(repo.refname_to_id("refs/heads/b")), head);
+        let reflog = t!(repo.reflog("refs/heads/main"));
+        assert_eq!(reflog.get(0).unwrap().message(), Some("rewind main"));
+
+        // A stale expected value leaves every reference untouched.
+        let err = repo
+            .update_refs(|refs| {
+                refs.update("refs/heads/a", next, Some(head), "move a")
+                    .delete("refs/heads/main", Some(next));
+                Ok(())
+            })
+            .unwrap_err();
+        assert_eq!(err.code(), ErrorCode::Modified);
+        assert_eq!(t!(repo.refname_to_id("refs/heads/a")), head);
+
+        // So does an error returned by the closure.
+        let err = repo
+            .update_refs(|refs| {
+                refs.delete("refs/heads/a", None);
+                Err::<(), _>(Error::from_str("nope"))
+            })
+            .unwrap_err();
+        assert_eq!(err.message(), "nope");
+        t!(repo.find_reference("refs/heads/a"));
+
+        t!(repo.update_refs(|refs| {
+            refs.delete("refs/heads/a", Some(head));
+            Ok(())
+        }));
+        assert!(repo.find_reference("refs/heads/a").is_err());
+    }
 }
@@ -324,9 +324,12 @@ extern "C" fn stream_read(
         }
     });
     match ret {
-        Some(Ok(_)) => 0,
+        Some(Ok(_)) => {
+            crate::error::clear_pending_source();
+            0
+        }
         Some(Err(e)) => unsafe {
-            set_err_io(&e);
+            set_err_io(e);
             -2
         },
         None => -1,
@@ -346,18 +349,23 @@ extern "C" fn stream_write(
         transport.obj.write_all(buf)
     });
     match ret {
-        Some(Ok(())) => 0,
+        Some(Ok(())) => {
+            crate::error::clear_pending_source();
+            0
+        }
         Some(Err(e)) => unsafe {
-            set_err_io(&e);
+            set_err_io(e);
             -2
         },
         None => -1,
     }
 }
 
-unsafe fn set_err_io(e: &io::Error) {
-    let s = CString::new(e.to_string()).unwrap();
+unsafe fn set_err_io(e: io::Error) {
+    let message = e.to_string();
+    let s = CString::new(&message[..]).unwrap();
     raw::git_error_set_str(raw::GIT_ERROR_NET as c_int, s.as_ptr());
+    crate::error::set_pending_source(&message, Box::new(e));
 }
 
 // callback used by smart transports to free a `SmartSubtransportStream`
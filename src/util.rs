@@ -234,6 +234,29 @@ pub fn path_to_repo_path(path: &Path) -> Result<CString, Error> {
     }
 }
 
+/// The path of `to` relative to the directory `from`, both being absolute.
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    path
+}
+
+/// Render `path` with forward slashes, as git writes paths in gitlinks and
+/// configuration files.
+pub fn slashes(path: &Path) -> Result<String, Error> {
+    path.to_str()
+        .map(|s| s.replace('\\', "/"))
+        .ok_or_else(|| Error::from_str("path is not valid utf-8"))
+}
+
 pub fn cstring_to_repo_path<T: IntoCString>(path: T) -> Result<CString, Error> {
     fixup_windows_path(path.into_c_string()?)
 }
@@ -0,0 +1,421 @@
+use crate::{Error, ErrorCode, Oid, Repository, Time};
+
+#[cfg(feature = "signature-commands")]
+pub use self::commands::{GpgVerifier, SshVerifier};
+
+/// The outcome of verifying the signature of a commit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SignatureVerdict {
+    /// The signature is valid and was made by a known key.
+    Good,
+    /// The signature doesn't match the signed data, or was made by an
+    /// expired or revoked key.
+    Bad,
+    /// The key which made the signature is not known, so the signature
+    /// can't be checked.
+    UnknownKey,
+    /// The commit is not signed.
+    Unsigned,
+}
+
+/// The result of verifying the signature of a commit, as returned by
+/// `Repository::verify_commit_signature`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureVerification {
+    verdict: SignatureVerdict,
+    signer: Option<String>,
+    key: Option<String>,
+    time: Option<Time>,
+}
+
+/// A backend checking signatures, such as gpg or ssh-keygen.
+///
+/// This is implemented for closures taking the signature and the signed
+/// data, so that any signing scheme can be plugged in.
+pub trait SignatureVerifier {
+    /// Verify `signature` against the data it signs.
+    ///
+    /// An error is only returned if the verification couldn't be carried
+    /// out; an invalid signature is reported with `SignatureVerdict::Bad`.
+    fn verify(&self, signature: &[u8], signed_data: &[u8]) -> Result<SignatureVerification, Error>;
+}
+
+impl<F> SignatureVerifier for F
+where
+    F: Fn(&[u8], &[u8]) -> Result<SignatureVerification, Error>,
+{
+    fn verify(&self, signature: &[u8], signed_data: &[u8]) -> Result<SignatureVerification, Error> {
+        self(signature, signed_data)
+    }
+}
+
+impl SignatureVerification {
+    /// Create a verification result, for use by a `SignatureVerifier`.
+    pub fn new(
+        verdict: SignatureVerdict,
+        signer: Option<&str>,
+        key: Option<&str>,
+        time: Option<Time>,
+    ) -> SignatureVerification {
+        SignatureVerification {
+            verdict,
+            signer: signer.map(|s| s.to_string()),
+            key: key.map(|s| s.to_string()),
+            time,
+        }
+    }
+
+    /// The outcome of the verification.
+    pub fn verdict(&self) -> SignatureVerdict {
+        self.verdict
+    }
+
+    /// Whether the signature is good.
+    pub fn is_good(&self) -> bool {
+        self.verdict == SignatureVerdict::Good
+    }
+
+    /// The identity of the signer, such as `Name <email>` for gpg or the
+    /// principal for ssh, if known.
+    pub fn signer(&self) -> Option<&str> {
+        self.signer.as_deref()
+    }
+
+    /// The key which made the signature, such as its id or fingerprint, if
+    /// known.
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+
+    /// When the signature was made, if known.
+    pub fn time(&self) -> Option<Time> {
+        self.time
+    }
+}
+
+/// Implementation of `Repository::verify_commit_signature`.
+pub(crate) fn verify_commit(
+    repo: &Repository,
+    id: Oid,
+    verifier: &dyn SignatureVerifier,
+) -> Result<SignatureVerification, Error> {
+    let (signature, signed_data) = match repo.extract_signature(&id, None) {
+        Ok(extracted) => extracted,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            return Ok(SignatureVerification::new(
+                SignatureVerdict::Unsigned,
+                None,
+                None,
+                None,
+            ))
+        }
+        Err(e) => return Err(e),
+    };
+    verifier.verify(&signature, &signed_data)
+}
+
+#[cfg(feature = "signature-commands")]
+mod commands {
+    use log::debug;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process::{self, Command, Output, Stdio};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::{SignatureVerdict, SignatureVerification, SignatureVerifier};
+    use crate::{Error, Time};
+
+    /// Verifies OpenPGP signatures with `gpg --verify`, as
+    /// `git verify-commit` does.
+    pub struct GpgVerifier {
+        program: PathBuf,
+    }
+
+    /// Verifies SSH signatures with `ssh-keygen -Y verify` against an
+    /// allowed signers file, as `git verify-commit` does with
+    /// `gpg.format = ssh`.
+    pub struct SshVerifier {
+        program: PathBuf,
+        allowed_signers: PathBuf,
+    }
+
+    /// A temporary file holding a signature, removed when dropped.
+    struct SignatureFile(PathBuf);
+
+    impl SignatureFile {
+        fn new(signature: &[u8]) -> Result<SignatureFile, Error> {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "git2-signature-{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            let ret = SignatureFile(path);
+            file.write_all(signature)?;
+            Ok(ret)
+        }
+    }
+
+    impl Drop for SignatureFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Run `cmd` with `input` on its standard input.
+    fn run(cmd: &mut Command, input: &[u8]) -> Result<Output, Error> {
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            const CREATE_NO_WINDOW: u32 = 0x08000000;
+            cmd.creation_flags(CREATE_NO_WINDOW);
+        }
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        debug!("executing signature verifier {:?}", cmd);
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| Error::from_str(&format!("failed to run {:?}: {}", cmd, e)))?;
+        let mut stdin = child.stdin.take().unwrap();
+        // Write from another thread so that a full stdout pipe can't block
+        // the child while we are still writing.
+        thread::scope(|scope| {
+            // The verifier may exit without reading all of its input.
+            scope.spawn(move || {
+                let _ = stdin.write_all(input);
+            });
+            Ok(child.wait_with_output()?)
+        })
+    }
+
+    impl GpgVerifier {
+        /// Creates a verifier running `gpg` from the `PATH`.
+        pub fn new() -> GpgVerifier {
+            GpgVerifier {
+                program: PathBuf::from("gpg"),
+            }
+        }
+
+        /// The gpg program to run, as configured by `gpg.program`.
+        pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut GpgVerifier {
+            self.program = program.as_ref().to_path_buf();
+            self
+        }
+    }
+
+    impl Default for GpgVerifier {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl SignatureVerifier for GpgVerifier {
+        fn verify(
+            &self,
+            signature: &[u8],
+            signed_data: &[u8],
+        ) -> Result<SignatureVerification, Error> {
+            let file = SignatureFile::new(signature)?;
+            let output = run(
+                Command::new(&self.program)
+                    .arg("--status-fd=1")
+                    .arg("--verify")
+                    .arg(&file.0)
+                    .arg("-"),
+                signed_data,
+            )?;
+            Ok(parse_gpg_status(&String::from_utf8_lossy(&output.stdout)))
+        }
+    }
+
+    /// Parse the machine readable output of `gpg --status-fd`.
+    pub(super) fn parse_gpg_status(status: &str) -> SignatureVerification {
+        let mut verdict = None;
+        let mut signer = None;
+        let mut key = None;
+        let mut time = None;
+        for line in status.lines() {
+            let line = match line.strip_prefix("[GNUPG:] ") {
+                Some(line) => line,
+                None => continue,
+            };
+            let mut words = line.splitn(3, ' ');
+            let keyword = words.next().unwrap_or("");
+            let first = words.next();
+            let rest = words.next();
+            match keyword {
+                "GOODSIG" | "BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG" => {
+                    verdict = Some(if keyword == "GOODSIG" {
+                        SignatureVerdict::Good
+                    } else {
+                        SignatureVerdict::Bad
+                    });
+                    key = key.or(first);
+                    signer = rest;
+                }
+                "ERRSIG" | "NO_PUBKEY" => {
+                    verdict = verdict.or(Some(SignatureVerdict::UnknownKey));
+                    key = key.or(first);
+                }
+                "VALIDSIG" => {
+                    // The fingerprint, which is more precise than the key id.
+                    key = first;
+                    time = rest
+                        .and_then(|rest| rest.split(' ').nth(1))
+                        .and_then(|t| t.parse().ok())
+                        .map(|t| Time::new(t, 0));
+                }
+                _ => {}
+            }
+        }
+        SignatureVerification::new(verdict.unwrap_or(SignatureVerdict::Bad), signer, key, time)
+    }
+
+    impl SshVerifier {
+        /// Creates a verifier checking signatures against the keys of
+        /// `allowed_signers`, as configured by `gpg.ssh.allowedSignersFile`,
+        /// by running `ssh-keygen` from the `PATH`.
+        pub fn new<P: AsRef<Path>>(allowed_signers: P) -> SshVerifier {
+            SshVerifier {
+                program: PathBuf::from("ssh-keygen"),
+                allowed_signers: allowed_signers.as_ref().to_path_buf(),
+            }
+        }
+
+        /// The ssh-keygen program to run, as configured by
+        /// `gpg.ssh.program`.
+        pub fn program<P: AsRef<Path>>(&mut self, program: P) -> &mut SshVerifier {
+            self.program = program.as_ref().to_path_buf();
+            self
+        }
+    }
+
+    impl SignatureVerifier for SshVerifier {
+        fn verify(
+            &self,
+            signature: &[u8],
+            signed_data: &[u8],
+        ) -> Result<SignatureVerification, Error> {
+            let file = SignatureFile::new(signature)?;
+            let output = run(
+                Command::new(&self.program)
+                    .args(["-Y", "find-principals", "-f"])
+                    .arg(&self.allowed_signers)
+                    .arg("-s")
+                    .arg(&file.0),
+                &[],
+            )?;
+            let principals = String::from_utf8_lossy(&output.stdout);
+            let principal = match principals.lines().next() {
+                Some(principal) if output.status.success() => principal.trim(),
+                _ => {
+                    return Ok(SignatureVerification::new(
+                        SignatureVerdict::UnknownKey,
+                        None,
+                        None,
+                        None,
+                    ))
+                }
+            };
+
+            let output = run(
+                Command::new(&self.program)
+                    .args(["-Y", "verify", "-n", "git", "-f"])
+                    .arg(&self.allowed_signers)
+                    .arg("-I")
+                    .arg(principal)
+                    .arg("-s")
+                    .arg(&file.0),
+                signed_data,
+            )?;
+            // On success, ssh-keygen prints something like
+            // `Good "git" signature for alice with ED25519 key SHA256:...`.
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let key = stdout
+                .lines()
+                .find(|line| line.starts_with("Good"))
+                .and_then(|line| line.split_whitespace().last());
+            let verdict = if output.status.success() {
+                SignatureVerdict::Good
+            } else {
+                SignatureVerdict::Bad
+            };
+            Ok(SignatureVerification::new(
+                verdict,
+                Some(principal),
+                key,
+                None,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SignatureVerdict, SignatureVerification};
+    use crate::Error;
+
+    #[test]
+    fn smoke() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        let never = |_: &[u8], _: &[u8]| -> Result<SignatureVerification, Error> {
+            panic!("an unsigned commit has nothing to verify")
+        };
+        let result = repo.verify_commit_signature(head.id(), &never).unwrap();
+        assert_eq!(result.verdict(), SignatureVerdict::Unsigned);
+
+        let sig = repo.signature().unwrap();
+        let tree = head.tree().unwrap();
+        let buf = repo
+            .commit_create_buffer(&sig, &sig, "signed", &tree, &[&head])
+            .unwrap();
+        let content = buf.as_str().unwrap();
+        let id = repo.commit_signed(content, "sig", None).unwrap();
+        let verifier = |signature: &[u8], data: &[u8]| {
+            let good = signature == b"sig" && data == content.as_bytes();
+            Ok(SignatureVerification::new(
+                if good {
+                    SignatureVerdict::Good
+                } else {
+                    SignatureVerdict::Bad
+                },
+                Some("name <email>"),
+                None,
+                None,
+            ))
+        };
+        let result = repo.verify_commit_signature(id, &verifier).unwrap();
+        assert!(result.is_good());
+        assert_eq!(result.signer(), Some("name <email>"));
+    }
+
+    #[test]
+    #[cfg(feature = "signature-commands")]
+    fn gpg_status() {
+        let status = "[GNUPG:] NEWSIG\n\
+                      [GNUPG:] GOODSIG 0123456789ABCDEF Name <email>\n\
+                      [GNUPG:] VALIDSIG 0123456789ABCDEF0123456789ABCDEF01234567 2024-01-02 1704153600 0 4 0 1 10 00\n";
+        let result = super::commands::parse_gpg_status(status);
+        assert!(result.is_good());
+        assert_eq!(result.signer(), Some("Name <email>"));
+        assert_eq!(
+            result.key(),
+            Some("0123456789ABCDEF0123456789ABCDEF01234567")
+        );
+        assert_eq!(result.time().map(|t| t.seconds()), Some(1704153600));
+
+        let status = "[GNUPG:] ERRSIG 0123456789ABCDEF 1 10 00 1704153600 9\n\
+                      [GNUPG:] NO_PUBKEY 0123456789ABCDEF\n";
+        let result = super::commands::parse_gpg_status(status);
+        assert_eq!(result.verdict(), SignatureVerdict::UnknownKey);
+        assert_eq!(result.key(), Some("0123456789ABCDEF"));
+    }
+}
@@ -2,9 +2,11 @@ use crate::buf::Buf;
 use crate::reference::Reference;
 use crate::repo::Repository;
 use crate::util::{self, Binding};
-use crate::{raw, Error};
+use crate::{raw, Error, ErrorClass, ErrorCode, IntoCString, Oid, StatusOptions};
+use std::ffi::CString;
+use std::fs;
 use std::os::raw::c_int;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::str;
 use std::{marker, mem};
@@ -20,7 +22,9 @@ pub struct Worktree {
 /// Options which can be used to configure how a worktree is initialized
 pub struct WorktreeAddOptions<'a> {
     raw: raw::git_worktree_add_options,
-    _marker: marker::PhantomData<Reference<'a>>,
+    new_branch: Option<(String, Oid)>,
+    lock_reason: Option<String>,
+    _marker: marker::PhantomData<&'a Reference<'a>>,
 }
 
 /// Options to configure how worktree pruning is performed
@@ -37,6 +41,27 @@ pub enum WorktreeLockStatus {
     Locked(Option<String>),
 }
 
+/// A worktree of a repository, as listed by
+/// `Repository::worktrees_detailed`.
+#[derive(PartialEq, Debug)]
+pub struct WorktreeInfo {
+    name: Option<String>,
+    path: PathBuf,
+    head: Option<WorktreeHead>,
+    lock_status: WorktreeLockStatus,
+    prunable: bool,
+}
+
+/// What the `HEAD` of a worktree points to.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WorktreeHead {
+    /// A branch, by its full name such as `refs/heads/main`, which may not
+    /// have any commit yet.
+    Branch(String),
+    /// A detached commit.
+    Detached(Oid),
+}
+
 impl Worktree {
     /// Open a worktree of a the repository
     ///
@@ -129,6 +154,62 @@ impl Worktree {
         Ok(())
     }
 
+    /// Moves the worktree to `new_path`, like `git worktree move`.
+    ///
+    /// The worktree must not be locked, nor have uncommitted changes or
+    /// untracked files, and `new_path` must not exist yet. The directory is
+    /// renamed, so it can't be moved to another filesystem.
+    pub fn rename_path(&mut self, new_path: &Path) -> Result<(), Error> {
+        if let WorktreeLockStatus::Locked(reason) = self.is_locked()? {
+            return Err(Error::new(
+                ErrorCode::Locked,
+                ErrorClass::Worktree,
+                match reason {
+                    Some(reason) => {
+                        format!("cannot move a locked worktree, lock reason: {}", reason)
+                    }
+                    None => "cannot move a locked worktree".to_string(),
+                },
+            ));
+        }
+        let repo = Repository::open_from_worktree(self)?;
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true).exclude_submodules(true);
+        if !repo.statuses(Some(&mut opts))?.is_empty() {
+            return Err(Error::new(
+                ErrorCode::Uncommitted,
+                ErrorClass::Worktree,
+                "cannot move a worktree with uncommitted changes or untracked files",
+            ));
+        }
+        let new_path = if new_path.is_absolute() {
+            new_path.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(new_path)
+        };
+        if new_path.exists() {
+            return Err(Error::new(
+                ErrorCode::Exists,
+                ErrorClass::Worktree,
+                format!("'{}' already exists", new_path.display()),
+            ));
+        }
+
+        let name = self
+            .name()
+            .ok_or_else(|| Error::from_str("worktree name is not valid utf-8"))?
+            .to_string();
+        let gitdir = repo.path().to_path_buf();
+        let commondir = repo.commondir().to_path_buf();
+        drop(repo);
+        fs::rename(self.path(), &new_path)?;
+        repair_link(&gitdir.join("gitdir"), "", &new_path.join(".git"))?;
+
+        // The worktree caches its path, so look it up again.
+        *self = Repository::open(&commondir)?.find_worktree(&name)?;
+        Ok(())
+    }
+
     /// Checks if the worktree is prunable
     pub fn is_prunable(&self, opts: Option<&mut WorktreePruneOptions>) -> Result<bool, Error> {
         unsafe {
@@ -141,6 +222,191 @@ impl Worktree {
     }
 }
 
+impl WorktreeInfo {
+    /// The name of the worktree, or `None` for the main worktree.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The path of the top-level of the worktree, or of the repository
+    /// itself for a bare main worktree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// What the `HEAD` of the worktree points to, or `None` if it can't be
+    /// read.
+    pub fn head(&self) -> Option<&WorktreeHead> {
+        self.head.as_ref()
+    }
+
+    /// Whether the worktree is locked, and why.
+    pub fn lock_status(&self) -> &WorktreeLockStatus {
+        &self.lock_status
+    }
+
+    /// Whether the worktree is missing from the filesystem, and not locked,
+    /// so that `git worktree prune` would remove it.
+    pub fn is_prunable(&self) -> bool {
+        self.prunable
+    }
+}
+
+fn read_head(gitdir: &Path) -> Option<WorktreeHead> {
+    let head = fs::read_to_string(gitdir.join("HEAD")).ok()?;
+    let head = head.trim_end();
+    match head.strip_prefix("ref:") {
+        Some(name) => Some(WorktreeHead::Branch(name.trim().to_string())),
+        None => Oid::from_str(head).ok().map(WorktreeHead::Detached),
+    }
+}
+
+/// Implementation of `Repository::worktrees_detailed`.
+pub(crate) fn list(repo: &Repository) -> Result<Vec<WorktreeInfo>, Error> {
+    let commondir = repo.commondir();
+    let bare = repo.config()?.get_bool("core.bare").unwrap_or(false);
+    let main_path = if bare {
+        commondir.to_path_buf()
+    } else if !repo.is_worktree() {
+        repo.workdir().unwrap_or(commondir).to_path_buf()
+    } else {
+        commondir.parent().unwrap_or(commondir).to_path_buf()
+    };
+    let mut list = vec![WorktreeInfo {
+        name: None,
+        path: main_path,
+        head: read_head(commondir),
+        lock_status: WorktreeLockStatus::Unlocked,
+        prunable: false,
+    }];
+
+    for name in repo.worktrees()?.iter().flatten() {
+        let gitdir = commondir.join("worktrees").join(name);
+        let lock_status = match fs::read_to_string(gitdir.join("locked")) {
+            Ok(reason) => {
+                let reason = reason.trim_end_matches(['\r', '\n']);
+                WorktreeLockStatus::Locked(Some(reason.to_string()).filter(|r| !r.is_empty()))
+            }
+            Err(_) => WorktreeLockStatus::Unlocked,
+        };
+        // The `gitdir` file holds the path of the `.git` file of the worktree.
+        let dotgit = fs::read_to_string(gitdir.join("gitdir"))
+            .ok()
+            .map(|dotgit| PathBuf::from(dotgit.trim_end()));
+        let exists = matches!(&dotgit, Some(dotgit) if dotgit.exists());
+        let path = match dotgit.as_deref().and_then(Path::parent) {
+            Some(path) => path.to_path_buf(),
+            None => gitdir.clone(),
+        };
+        list.push(WorktreeInfo {
+            name: Some(name.to_string()),
+            path,
+            head: read_head(&gitdir),
+            prunable: !exists && lock_status == WorktreeLockStatus::Unlocked,
+            lock_status,
+        });
+    }
+    Ok(list)
+}
+
+/// Implementation of `Repository::worktree_repair`.
+pub(crate) fn repair(repo: &Repository, paths: &[&Path]) -> Result<Vec<String>, Error> {
+    let admin = repo.commondir().join("worktrees");
+    let mut repaired = Vec::new();
+
+    // Worktrees which have moved are found from their `.git` file, which
+    // still names their administrative directory.
+    for path in paths {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        let dotgit = path.join(".git");
+        let content = fs::read_to_string(&dotgit)?;
+        let name = content
+            .trim_end()
+            .strip_prefix("gitdir:")
+            .and_then(|gitdir| Path::new(gitdir.trim()).file_name())
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| Error::from_str("not a linked worktree"))?;
+        let gitdir = admin.join(name);
+        if !gitdir.is_dir() {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Worktree,
+                format!("no worktree named '{}' in the repository", name),
+            ));
+        }
+        let mut changed = repair_link(&gitdir.join("gitdir"), "", &dotgit)?;
+        changed |= repair_link(&dotgit, "gitdir: ", &gitdir)?;
+        if changed {
+            repaired.push(name.to_string());
+        }
+    }
+
+    // Worktrees which haven't moved are pointed back to the repository, in
+    // case the repository itself has moved.
+    for name in repo.worktrees()?.iter().flatten() {
+        let gitdir = admin.join(name);
+        let dotgit = match fs::read_to_string(gitdir.join("gitdir")) {
+            // Relative links are relative to the administrative directory.
+            Ok(dotgit) => gitdir.join(dotgit.trim_end()),
+            Err(_) => continue,
+        };
+        if dotgit.is_file()
+            && repair_link(&dotgit, "gitdir: ", &gitdir)?
+            && !repaired.iter().any(|n| n == name)
+        {
+            repaired.push(name.to_string());
+        }
+    }
+    Ok(repaired)
+}
+
+/// Make `file` hold `prefix` followed by `target`, returning whether it had
+/// to be rewritten.
+///
+/// A link which already leads to `target` is left alone. Otherwise it is
+/// rewritten in the same form as before, relative to the directory of
+/// `file` if it was relative and absolute if not.
+fn repair_link(file: &Path, prefix: &str, target: &Path) -> Result<bool, Error> {
+    let current = fs::read_to_string(file).ok();
+    let current = current
+        .as_deref()
+        .and_then(|c| c.trim_end().strip_prefix(prefix))
+        .map(|c| PathBuf::from(c.trim()));
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    let relative = match current {
+        Some(ref current) => {
+            if same_path(&dir.join(current), target) {
+                return Ok(false);
+            }
+            current.is_relative()
+        }
+        None => false,
+    };
+    let target = if relative {
+        util::slashes(&util::relative_path(dir, target))?
+    } else {
+        target
+            .to_str()
+            .ok_or_else(|| Error::from_str("worktree path is not valid utf-8"))?
+            .to_string()
+    };
+    fs::write(file, format!("{}{}\n", prefix, target))?;
+    Ok(true)
+}
+
+/// Whether `a` and `b` are the same file, comparing them as they are when
+/// they don't both exist.
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 impl<'a> WorktreeAddOptions<'a> {
     /// Creates a default set of add options.
     ///
@@ -154,6 +420,8 @@ impl<'a> WorktreeAddOptions<'a> {
             );
             WorktreeAddOptions {
                 raw,
+                new_branch: None,
+                lock_reason: None,
                 _marker: marker::PhantomData,
             }
         }
@@ -184,12 +452,89 @@ impl<'a> WorktreeAddOptions<'a> {
         self
     }
 
+    /// Create the branch `name` at the commit `target` and check it out in
+    /// the new worktree, like `git worktree add -b <name>`.
+    ///
+    /// Adding the worktree fails if the branch already exists, and the
+    /// branch is deleted again if the worktree can't be added. This takes
+    /// precedence over `reference`.
+    pub fn new_branch(&mut self, name: &str, target: Oid) -> &mut WorktreeAddOptions<'a> {
+        self.new_branch = Some((name.to_string(), target));
+        self
+    }
+
+    /// Lock the newly added worktree with `reason`, like
+    /// `git worktree add --lock --reason <reason>`.
+    ///
+    /// The worktree is locked from the moment it is created, so it can't be
+    /// pruned before the reason is recorded.
+    pub fn lock_reason(&mut self, reason: &str) -> &mut WorktreeAddOptions<'a> {
+        self.raw.lock = 1;
+        self.lock_reason = Some(reason.to_string());
+        self
+    }
+
     /// Get a set of raw add options to be used with `git_worktree_add`
     pub fn raw(&self) -> *const raw::git_worktree_add_options {
         &self.raw
     }
 }
 
+/// Implementation of `Repository::worktree`.
+pub(crate) fn add(
+    repo: &Repository,
+    name: &str,
+    path: &Path,
+    opts: Option<&WorktreeAddOptions<'_>>,
+) -> Result<Worktree, Error> {
+    let raw_name = CString::new(name)?;
+    let raw_path = path.into_c_string()?;
+    let default;
+    let opts = match opts {
+        Some(opts) => opts,
+        None => {
+            default = WorktreeAddOptions::new();
+            &default
+        }
+    };
+
+    let mut branch = match &opts.new_branch {
+        Some((branch, target)) => Some(repo.branch(branch, &repo.find_commit(*target)?, false)?),
+        None => None,
+    };
+    // The options only hold plain data and borrowed pointers, so a copy can
+    // point to the new branch without changing the caller's options.
+    let mut raw_opts = unsafe { ptr::read(&opts.raw) };
+    if let Some(branch) = &branch {
+        raw_opts.reference = branch.get().raw();
+    }
+    let mut raw = ptr::null_mut();
+    let rc = unsafe {
+        raw::git_worktree_add(
+            &mut raw,
+            repo.raw(),
+            raw_name.as_ptr(),
+            raw_path.as_ptr(),
+            &raw_opts,
+        )
+    };
+    if rc < 0 {
+        let err = Error::last_error(rc);
+        if let Some(branch) = &mut branch {
+            let _ = branch.delete();
+        }
+        crate::panic::check();
+        return Err(err);
+    }
+    let worktree: Worktree = unsafe { Binding::from_raw(raw) };
+
+    if let Some(reason) = &opts.lock_reason {
+        let locked = repo.commondir().join("worktrees").join(name).join("locked");
+        fs::write(locked, reason)?;
+    }
+    Ok(worktree)
+}
+
 impl WorktreePruneOptions {
     /// Creates a default set of pruning options
     ///
@@ -266,6 +611,7 @@ impl Drop for Worktree {
 mod tests {
     use crate::WorktreeAddOptions;
     use crate::WorktreeLockStatus;
+    use crate::{BranchType, ErrorCode, Repository, WorktreeHead};
 
     use tempfile::TempDir;
 
@@ -334,4 +680,133 @@ mod tests {
         let status = wt.is_locked().unwrap();
         assert_eq!(status, WorktreeLockStatus::Unlocked);
     }
+
+    #[test]
+    fn smoke_add_new_branch_locked() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+
+        let wtdir = TempDir::new().unwrap();
+        let mut opts = WorktreeAddOptions::new();
+        opts.new_branch("feature", head)
+            .lock_reason("on a usb stick");
+        let wt = repo
+            .worktree("feature-tree", &wtdir.path().join("feature"), Some(&opts))
+            .unwrap();
+        assert_eq!(
+            wt.is_locked().unwrap(),
+            WorktreeLockStatus::Locked(Some("on a usb stick".to_string()))
+        );
+        let wt_repo = Repository::open_from_worktree(&wt).unwrap();
+        assert_eq!(wt_repo.head().unwrap().name(), Some("refs/heads/feature"));
+
+        // The branch now exists, and is left alone if adding fails.
+        let err = repo
+            .worktree("other-tree", &wtdir.path().join("other"), Some(&opts))
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), ErrorCode::Exists);
+        assert!(repo.find_branch("feature", BranchType::Local).is_ok());
+    }
+
+    #[test]
+    fn smoke_worktrees_detailed() {
+        let (_td, repo) = crate::test::repo_init();
+        let head = repo.head().unwrap().target().unwrap();
+
+        let wtdir = TempDir::new().unwrap();
+        let mut opts = WorktreeAddOptions::new();
+        opts.new_branch("locked", head).lock_reason("reason");
+        repo.worktree("locked", &wtdir.path().join("locked"), Some(&opts))
+            .unwrap();
+        repo.worktree("gone", &wtdir.path().join("gone"), None)
+            .unwrap();
+        std::fs::remove_dir_all(wtdir.path().join("gone")).unwrap();
+
+        let list = repo.worktrees_detailed().unwrap();
+        assert_eq!(list.len(), 3);
+        assert_eq!(list[0].name(), None);
+        assert_eq!(
+            list[0].head(),
+            Some(&WorktreeHead::Branch("refs/heads/main".to_string()))
+        );
+        let locked = list.iter().find(|w| w.name() == Some("locked")).unwrap();
+        assert_eq!(
+            locked.path().canonicalize().unwrap(),
+            wtdir.path().join("locked").canonicalize().unwrap()
+        );
+        assert_eq!(
+            locked.head(),
+            Some(&WorktreeHead::Branch("refs/heads/locked".to_string()))
+        );
+        assert_eq!(
+            locked.lock_status(),
+            &WorktreeLockStatus::Locked(Some("reason".to_string()))
+        );
+        assert!(!locked.is_prunable());
+        let gone = list.iter().find(|w| w.name() == Some("gone")).unwrap();
+        assert!(gone.is_prunable());
+    }
+
+    #[test]
+    fn smoke_repair() {
+        let (_td, repo) = crate::test::repo_init();
+        let wtdir = TempDir::new().unwrap();
+        let old = wtdir.path().join("old");
+        let new = wtdir.path().join("new");
+        repo.worktree("moved", &old, None).unwrap();
+        assert_eq!(repo.worktree_repair(&[]).unwrap(), Vec::<String>::new());
+
+        std::fs::rename(&old, &new).unwrap();
+        assert!(repo.find_worktree("moved").unwrap().validate().is_err());
+        assert_eq!(repo.worktree_repair(&[&new]).unwrap(), ["moved"]);
+        repo.find_worktree("moved").unwrap().validate().unwrap();
+        Repository::open(&new).unwrap();
+
+        // A `.git` file pointing to a stale location is fixed too.
+        std::fs::write(new.join(".git"), "gitdir: /nowhere/worktrees/moved\n").unwrap();
+        assert_eq!(repo.worktree_repair(&[]).unwrap(), ["moved"]);
+        Repository::open(&new).unwrap();
+
+        // Relative links which are right are left alone, and stay relative
+        // when they are fixed.
+        let gitdir = repo.path().join("worktrees").join("moved");
+        let relative = crate::util::relative_path(&new, &gitdir);
+        let link = format!("gitdir: {}\n", relative.display());
+        std::fs::write(new.join(".git"), &link).unwrap();
+        assert_eq!(repo.worktree_repair(&[]).unwrap(), Vec::<String>::new());
+        std::fs::write(new.join(".git"), "gitdir: ../nowhere/worktrees/moved\n").unwrap();
+        assert_eq!(repo.worktree_repair(&[]).unwrap(), ["moved"]);
+        assert_eq!(std::fs::read_to_string(new.join(".git")).unwrap(), link);
+    }
+
+    #[test]
+    fn smoke_rename_path() {
+        let (_td, repo) = crate::test::repo_init();
+        let wtdir = TempDir::new().unwrap();
+        let old = wtdir.path().join("old");
+        let new = wtdir.path().join("new");
+        let mut wt = repo.worktree("moving", &old, None).unwrap();
+
+        std::fs::write(old.join("untracked"), "").unwrap();
+        let err = wt.rename_path(&new).err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Uncommitted);
+        std::fs::remove_file(old.join("untracked")).unwrap();
+
+        wt.lock(None).unwrap();
+        assert_eq!(
+            wt.rename_path(&new).err().unwrap().code(),
+            ErrorCode::Locked
+        );
+        wt.unlock().unwrap();
+
+        wt.rename_path(&new).unwrap();
+        assert!(!old.exists());
+        assert_eq!(
+            wt.path().canonicalize().unwrap(),
+            new.canonicalize().unwrap()
+        );
+        wt.validate().unwrap();
+        Repository::open(&new).unwrap();
+    }
 }
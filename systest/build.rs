@@ -16,6 +16,8 @@ fn main() {
         .header("git2/sys/repository.h")
         .header("git2/sys/cred.h")
         .header("git2/sys/email.h")
+        .header("git2/sys/merge.h")
+        .header("git2/sys/commit_graph.h")
         .header("git2/cred_helpers.h")
         .type_name(|s, _, _| s.to_string());
     cfg.field_name(|_, f| match f {
@@ -31,6 +33,7 @@ fn main() {
     cfg.skip_signededness(|s| match s {
         s if s.ends_with("_cb") => true,
         s if s.ends_with("_callback") => true,
+        s if s.ends_with("_fn") => true,
         "git_push_transfer_progress" | "git_push_negotiation" | "git_packbuilder_progress" => true,
         _ => false,
     });